@@ -0,0 +1,842 @@
+use crate::config::AppConfig;
+use crate::data::aggregate::{GroupBySpec, ResultTable, Value};
+use crate::data::data_lector::stream_process_csv;
+use crate::data::dataset::Dataset;
+use crate::data::export::{ExportFormat, ExportOptions};
+use crate::data::filters::{self, FileOutput, HistogramSpec, QueryOutputs, TripFilter};
+use crate::data::mmap_reader::stream_process_mmap;
+use crate::data::parallel_scan::{self, MergeOrder, ScanConfig};
+use crate::data::parquet_io;
+use crate::data::sketch::{ApproxStat, SketchOptions};
+use crate::visual;
+use serde_json::json;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Cantidad de destinos que muestra top-destinations por defecto
+const DEFAULT_TOP_DESTINATIONS: usize = 10;
+
+const USAGE: &str = "Uso: practica1 [COMANDO] [OPCIONES]
+
+Sin comando se abre la interfaz gráfica con las rutas configuradas.
+
+Comandos:
+  index build                 Construye los índices del CSV
+  index list                  Viajes del índice hash, uno por línea (JSON)
+  index remove <INDEX>        Quita un viaje del índice hash; get deja de encontrarlo hasta
+                              que el índice se reconstruya
+  filter                      Escribe en stdout (o --output) las filas que cumplen el filtro, en CSV
+  stats                       Estadísticas de las filas que cumplen el filtro (JSON)
+  top-destinations            Destinos más frecuentes entre las filas que cumplen el filtro (JSON)
+  export --output <ARCHIVO>   Guarda las filas que cumplen el filtro (el formato se deduce de la
+                              extensión: .csv, .tsv, .jsonl, .json o .parquet)
+  get <INDEX>                 Viaje con el índice indicado (JSON)
+  analyze                     Estadísticas, destinos más frecuentes, histogramas y (con --output)
+                              archivo de las filas que cumplen el filtro, en un solo recorrido (JSON)
+  bench                       Compara la velocidad de los lectores de CSV sobre el dataset (JSON)
+  help                        Muestra esta ayuda
+
+Opciones:
+  --config <ARCHIVO>          Archivo de configuración JSON (por defecto practica1.config.json)
+  --csv <RUTA>                CSV o Parquet de entrada, directorio o patrón como
+                              'datos/yellow_tripdata_2020-*.csv' (por defecto src/data/data.csv)
+  --tmp-dir <DIR>             Directorio de archivos temporales (por defecto tmp)
+  --index-dir <DIR>           Directorio de índices (por defecto <tmp-dir>/indexes)
+  --threads <N>               Hilos para recorrer los CSV (por defecto todos los núcleos)
+  --query <CONSULTA>          Filtro en el lenguaje de consultas (por defecto todas las filas)
+  --explain                   Muestra en stderr el plan de la consulta antes de ejecutarla
+  --output <ARCHIVO>          Archivo de salida
+  --limit <N>                 Máximo de filas (filter) o de destinos (top-destinations)
+  --format <FORMATO>          Formato de export: csv, tsv, excel (CSV con ; y BOM), jsonl,
+                              json o parquet
+  --columns <A,B,...>         Columnas de export, en ese orden (por defecto todas)
+  --histogram <M:MIN:MAX:N>   Histograma de analyze de la medida M con N cubetas entre MIN y MAX,
+                              por ejemplo total_amount:0:100:20 (se puede repetir)
+  --group-by <K1,K2,...>      Agrupa en analyze por columnas o por pickup_hour, pickup_weekday
+                              o route (par origen-destino)
+  --aggregate <A1,A2,...>     Agregados de cada grupo: count, sum(M), avg(M), min(M), max(M),
+                              median(M), stddev(M) o pNN(M), como p95(trip_distance)
+                              (por defecto count)
+  --approx <E1,E2,...>        Estadísticas de analyze calculadas con resúmenes: median(M),
+                              pNN(M) o distinct(K), como distinct(pu_location_id)
+  --sketch-k <K>              Precisión de los cuantiles aproximados (KLL, por defecto 200)
+  --distinct-precision <P>    Bits de HyperLogLog para contar distintos, de 4 a 18 (por
+                              defecto 14)
+  --exact-limit <N>           Hasta N valores los cuantiles y distintos son exactos (por
+                              defecto 10000)
+  -h, --help                  Muestra esta ayuda
+
+Las rutas y los hilos también se pueden fijar con PRACTICA1_CONFIG, PRACTICA1_DATA,
+PRACTICA1_TMP_DIR, PRACTICA1_INDEX_DIR y PRACTICA1_THREADS; las opciones tienen prioridad.
+
+Los resultados se escriben en stdout y los mensajes de progreso en stderr.";
+
+// Argumentos de un comando sin la interfaz gráfica
+#[derive(Debug, Default)]
+struct CliArgs {
+    command: Vec<String>,
+    help: bool,
+    config_file: Option<PathBuf>,
+    csv_path: Option<PathBuf>,
+    tmp_dir: Option<PathBuf>,
+    index_dir: Option<PathBuf>,
+    threads: Option<usize>,
+    query: Option<String>,
+    explain: bool,
+    output: Option<PathBuf>,
+    limit: Option<usize>,
+    format: Option<ExportFormat>,
+    columns: Option<String>,
+    histograms: Vec<HistogramSpec>,
+    group_by: Option<String>,
+    aggregates: Option<String>,
+    approx: Vec<ApproxStat>,
+    sketch_options: SketchOptions,
+}
+
+impl CliArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = CliArgs::default();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| {
+                iter.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Falta el valor de {}", name))
+            };
+            match arg.as_str() {
+                "--config" => parsed.config_file = Some(PathBuf::from(value(arg)?)),
+                "--csv" => parsed.csv_path = Some(PathBuf::from(value(arg)?)),
+                "--tmp-dir" => parsed.tmp_dir = Some(PathBuf::from(value(arg)?)),
+                "--index-dir" => parsed.index_dir = Some(PathBuf::from(value(arg)?)),
+                "--threads" => {
+                    let threads = value(arg)?;
+                    parsed.threads = Some(
+                        threads
+                            .parse()
+                            .map_err(|_| format!("Cantidad de hilos inválida: {}", threads))?,
+                    );
+                }
+                "--query" => parsed.query = Some(value(arg)?),
+                "--explain" => parsed.explain = true,
+                "-h" | "--help" => parsed.help = true,
+                "--output" => parsed.output = Some(PathBuf::from(value(arg)?)),
+                "--format" => {
+                    let format = value(arg)?;
+                    parsed.format = Some(
+                        ExportFormat::from_name(&format)
+                            .ok_or_else(|| format!("Formato desconocido: {}", format))?,
+                    );
+                }
+                "--columns" => parsed.columns = Some(value(arg)?),
+                "--histogram" => parsed.histograms.push(HistogramSpec::parse(&value(arg)?)?),
+                "--group-by" => parsed.group_by = Some(value(arg)?),
+                "--aggregate" => parsed.aggregates = Some(value(arg)?),
+                "--approx" => parsed.approx.extend(ApproxStat::parse_list(&value(arg)?)?),
+                "--sketch-k" => {
+                    let k = value(arg)?;
+                    parsed.sketch_options.quantile_k = k
+                        .parse()
+                        .map_err(|_| format!("k de cuantiles inválido: {}", k))?;
+                }
+                "--distinct-precision" => {
+                    let precision = value(arg)?;
+                    parsed.sketch_options.distinct_precision = precision
+                        .parse()
+                        .map_err(|_| format!("Precisión de distintos inválida: {}", precision))?;
+                }
+                "--exact-limit" => {
+                    let limit = value(arg)?;
+                    parsed.sketch_options.exact_limit = limit
+                        .parse()
+                        .map_err(|_| format!("Límite de modo exacto inválido: {}", limit))?;
+                }
+                "--limit" => {
+                    let limit = value(arg)?;
+                    parsed.limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| format!("Límite inválido: {}", limit))?,
+                    );
+                }
+                _ if arg.starts_with("--") => return Err(format!("Opción desconocida: {}", arg)),
+                _ => parsed.command.push(arg.clone()),
+            }
+        }
+
+        parsed.sketch_options.validate()?;
+        Ok(parsed)
+    }
+
+    // Configuración final: archivo y variables de entorno, con las opciones encima
+    fn config(&self) -> Result<AppConfig, Box<dyn Error>> {
+        Ok(self.override_config(AppConfig::load(self.config_file.as_deref())?))
+    }
+
+    // Reemplaza en la configuración cargada los valores dados como opciones
+    fn override_config(&self, mut config: AppConfig) -> AppConfig {
+        if let Some(path) = &self.csv_path {
+            config.data_path = path.clone();
+        }
+        if let Some(path) = &self.tmp_dir {
+            config.tmp_dir = path.clone();
+        }
+        if let Some(path) = &self.index_dir {
+            config.index_dir = Some(path.clone());
+        }
+        if let Some(threads) = self.threads {
+            config.threads = Some(threads);
+        }
+        config
+    }
+
+    fn filter(&self) -> Result<TripFilter, Box<dyn Error>> {
+        match &self.query {
+            Some(query) => Ok(filters::parse_query(query)?),
+            None => Ok(TripFilter::And(Vec::new())),
+        }
+    }
+}
+
+// Ejecuta un comando sin interfaz, o la interfaz si no hay comando; devuelve el
+// código de salida del proceso
+pub fn run(args: &[String]) -> i32 {
+    let args = match CliArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    // help solo es un comando en primera posición; en otro lugar es un argumento
+    if args.help
+        || args
+            .command
+            .first()
+            .is_some_and(|command| command == "help")
+    {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    let config = match args.config().and_then(|config| {
+        config.apply()?;
+        Ok(config)
+    }) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+
+    let command: Vec<&str> = args.command.iter().map(String::as_str).collect();
+    let result = match command.as_slice() {
+        [] => visual::run_app(config).map_err(|e| e.to_string().into()),
+        ["index", "build"] => build_index(&config),
+        ["index", "list"] => list_index(&config),
+        ["index", "remove", index] => remove_from_index(&config, index),
+        ["filter"] => filter(&args, &config),
+        ["stats"] => stats(&args, &config),
+        ["top-destinations"] => top_destinations(&args, &config),
+        ["export"] => export(&args, &config),
+        ["get", index] => get(&config, index),
+        ["analyze"] => analyze(&args, &config),
+        ["bench"] => bench(&config),
+        _ => {
+            eprintln!("Comando inválido: {}\n\n{}", args.command.join(" "), USAGE);
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn print_json(value: &serde_json::Value) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn build_index(config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let csv_path = config.data_path.clone();
+    let count = filters::initialize_hash_index(&csv_path)?;
+    print_json(&json!({
+        "csv": csv_path,
+        "indexed_rows": count,
+    }))
+}
+
+// Con --explain, el plan de la consulta en cada archivo del dataset
+fn explain_plan(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    if args.explain {
+        eprint!(
+            "{}",
+            filters::explain_filter(&config.data_path, &args.filter()?)?
+        );
+    }
+    Ok(())
+}
+
+fn filter(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    explain_plan(args, config)?;
+    let filter = args.filter()?;
+    if let Some(output) = &args.output {
+        let count =
+            filters::filter_to_file(config.data_path.clone(), output.clone(), filter, args.limit)?;
+        eprintln!("{} filas escritas en {}", count, output.display());
+        return Ok(());
+    }
+
+    // Sin --output el CSV pasa por un archivo temporal y se copia a stdout
+    let tmp_file =
+        std::env::temp_dir().join(format!("practica1-filter-{}.csv", std::process::id()));
+    let result = filters::filter_to_file(
+        config.data_path.clone(),
+        tmp_file.clone(),
+        filter,
+        args.limit,
+    )
+    .and_then(|count| {
+        let mut reader = BufReader::new(File::open(&tmp_file)?);
+        io::copy(&mut reader, &mut io::stdout().lock())?;
+        Ok(count)
+    });
+    let _ = fs::remove_file(&tmp_file);
+
+    eprintln!("{} filas encontradas", result?);
+    Ok(())
+}
+
+fn stats(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    explain_plan(args, config)?;
+    print_json(&stats_json(args, config)?)
+}
+
+fn stats_json(args: &CliArgs, config: &AppConfig) -> Result<serde_json::Value, Box<dyn Error>> {
+    let stats = filters::get_filter_stats(
+        config.data_path.clone(),
+        args.filter()?,
+        args.sketch_options,
+    )?;
+    // Las claves se ordenan para que la salida sea estable
+    let stats: std::collections::BTreeMap<_, _> = stats.into_iter().collect();
+    Ok(json!(stats))
+}
+
+fn top_destinations(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let limit = args.limit.unwrap_or(DEFAULT_TOP_DESTINATIONS);
+    let destinations =
+        filters::get_popular_destinations(config.data_path.clone(), args.filter()?, limit)?;
+    let destinations: Vec<_> = destinations
+        .into_iter()
+        .map(|(location, trips)| json!({ "do_location_id": location, "trips": trips }))
+        .collect();
+    print_json(&json!(destinations))
+}
+
+// Formato y columnas de --format y --columns; sin --format se deduce de la extensión
+fn export_options(args: &CliArgs, output: &Path) -> Result<ExportOptions, Box<dyn Error>> {
+    let mut options = ExportOptions {
+        format: args
+            .format
+            .unwrap_or_else(|| ExportFormat::from_path(output)),
+        ..ExportOptions::default()
+    };
+    if let Some(columns) = &args.columns {
+        options.columns = ExportOptions::parse_columns(columns)?;
+    }
+    Ok(options)
+}
+
+fn export(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let output = args.output.clone().ok_or("export necesita --output")?;
+    let options = export_options(args, &output)?;
+    explain_plan(args, config)?;
+
+    let count = filters::export_to_file(&config.data_path, &output, args.filter()?, &options)?;
+    print_json(&json!({
+        "output": output,
+        "format": options.format.name(),
+        "rows": count,
+    }))
+}
+
+// Todos los resultados de un filtro con un solo recorrido del dataset
+fn analyze(args: &CliArgs, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    explain_plan(args, config)?;
+    let file = match &args.output {
+        Some(output) => Some(FileOutput {
+            path: output.clone(),
+            options: export_options(args, output)?,
+        }),
+        None => None,
+    };
+    let group_by = match (&args.group_by, &args.aggregates) {
+        (Some(keys), aggregates) => Some(GroupBySpec::parse(
+            keys,
+            aggregates.as_deref().unwrap_or_default(),
+        )?),
+        (None, Some(_)) => return Err("--aggregate necesita --group-by".into()),
+        (None, None) => None,
+    };
+    let outputs = QueryOutputs {
+        file,
+        stats: true,
+        top_destinations: Some(args.limit.unwrap_or(DEFAULT_TOP_DESTINATIONS)),
+        histograms: args.histograms.clone(),
+        group_by,
+        approx: args.approx.clone(),
+        sketch_options: args.sketch_options,
+    };
+    let results = filters::execute_query(&config.data_path, &args.filter()?, &outputs)?;
+
+    let stats: std::collections::BTreeMap<_, _> =
+        results.stats.unwrap_or_default().into_iter().collect();
+    let destinations: Vec<_> = results
+        .top_destinations
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(location, trips)| json!({ "do_location_id": location, "trips": trips }))
+        .collect();
+    let histograms: Vec<_> = results
+        .histograms
+        .iter()
+        .map(|histogram| {
+            let bins: Vec<_> = histogram
+                .counts
+                .iter()
+                .enumerate()
+                .map(|(bin, trips)| {
+                    let (from, to) = histogram.bin_range(bin);
+                    json!({ "from": from, "to": to, "trips": trips })
+                })
+                .collect();
+            json!({
+                "measure": histogram.spec.measure.name(),
+                "bins": bins,
+                "below": histogram.below,
+                "above": histogram.above,
+                "missing": histogram.missing,
+            })
+        })
+        .collect();
+
+    let approx: Vec<_> = results
+        .approx
+        .iter()
+        .map(|approx| {
+            json!({
+                "stat": approx.stat.name(),
+                "value": approx.value,
+                "exact": approx.exact,
+            })
+        })
+        .collect();
+
+    print_json(&json!({
+        "rows": results.matched,
+        "output": args.output,
+        "stats": stats,
+        "top_destinations": destinations,
+        "histograms": histograms,
+        "group_by": results.group_by.as_ref().map(table_json),
+        "approx": approx,
+    }))
+}
+
+// Columnas con su tipo y filas como arreglos de valores
+fn table_json(table: &ResultTable) -> serde_json::Value {
+    let columns: Vec<_> = table
+        .columns
+        .iter()
+        .map(|column| json!({ "name": column.name, "type": column.kind.name() }))
+        .collect();
+    let rows: Vec<Vec<_>> = table
+        .rows
+        .iter()
+        .map(|row| row.iter().map(value_json).collect())
+        .collect();
+    json!({ "columns": columns, "rows": rows })
+}
+
+fn value_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(value) => json!(value),
+        Value::Integer(value) => json!(value),
+        Value::Float(value) => json!(value),
+        Value::Route(from, to) => json!([from, to]),
+        Value::Text(_) | Value::DateTime(_) | Value::Weekday(_) => json!(value.to_string()),
+    }
+}
+
+fn list_index(config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+    let count = filters::for_each_indexed(config.data_path.clone(), |trip| {
+        serde_json::to_writer(&mut stdout, trip)?;
+        writeln!(stdout)?;
+        Ok(())
+    })?;
+    eprintln!("{} viajes en el índice", count);
+    Ok(())
+}
+
+fn remove_from_index(config: &AppConfig, index: &str) -> Result<(), Box<dyn Error>> {
+    if !filters::remove_from_index(config.data_path.clone(), index)? {
+        return Err(format!("No existe un viaje con índice {}", index).into());
+    }
+    print_json(&json!({ "removed": index }))
+}
+
+fn get(config: &AppConfig, index: &str) -> Result<(), Box<dyn Error>> {
+    match filters::find_by_index(config.data_path.clone(), index)? {
+        Some(trip) => print_json(&serde_json::to_value(trip)?),
+        None => Err(format!("No existe un viaje con índice {}", index).into()),
+    }
+}
+
+// Filas leídas y suma de destinos, para comprobar que los lectores leen lo mismo
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct BenchTotals {
+    rows: usize,
+    checksum: u64,
+}
+
+impl BenchTotals {
+    fn add(&mut self, do_location_id: u16) {
+        self.rows += 1;
+        self.checksum += do_location_id as u64;
+    }
+}
+
+// Mide un lector sobre un archivo de `bytes` bytes
+fn measure<F>(bytes: u64, read: F) -> Result<(BenchTotals, serde_json::Value), Box<dyn Error>>
+where
+    F: FnOnce() -> Result<BenchTotals, Box<dyn Error>>,
+{
+    let start = Instant::now();
+    let totals = read()?;
+    let seconds = start.elapsed().as_secs_f64();
+
+    Ok((
+        totals,
+        json!({
+            "rows": totals.rows,
+            "seconds": seconds,
+            "rows_per_sec": totals.rows as f64 / seconds,
+            "mb_per_sec": bytes as f64 / (1024.0 * 1024.0) / seconds,
+        }),
+    ))
+}
+
+// Lee cada CSV del dataset con el lector de csv (un Trip con sus textos copiados por
+// fila), con el mapeado en memoria (TripRef) en un hilo y con el recorrido paralelo
+fn bench(config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let mut results = Vec::new();
+    let mut consistent = true;
+
+    for file in Dataset::open(&config.data_path)?.files() {
+        if parquet_io::is_parquet(file) {
+            eprintln!("{} es Parquet y no se mide", file.display());
+            continue;
+        }
+        let bytes = fs::metadata(file)?.len();
+        eprintln!("Midiendo {}...", file.display());
+
+        let (csv_totals, csv) = measure(bytes, || {
+            let mut totals = BenchTotals::default();
+            stream_process_csv(file, |trip| {
+                totals.add(trip.do_location_id);
+                Ok(())
+            })?;
+            Ok(totals)
+        })?;
+        let (mmap_totals, mmap) = measure(bytes, || {
+            let mut totals = BenchTotals::default();
+            stream_process_mmap(file, |trip| {
+                totals.add(trip.do_location_id);
+                Ok(())
+            })?;
+            Ok(totals)
+        })?;
+        let scan_config = ScanConfig::current();
+        let (parallel_totals, parallel) = measure(bytes, || {
+            let mut totals = BenchTotals::default();
+            parallel_scan::scan_csv(
+                file,
+                &scan_config,
+                MergeOrder::Unordered,
+                BenchTotals::default,
+                |partial, trip| partial.add(trip.do_location_id),
+                |partial| {
+                    totals.rows += partial.rows;
+                    totals.checksum += partial.checksum;
+                    Ok(())
+                },
+            )?;
+            Ok(totals)
+        })?;
+
+        let same = csv_totals == mmap_totals && csv_totals == parallel_totals;
+        if !same {
+            eprintln!(
+                "Los lectores no leyeron los mismos viajes de {}",
+                file.display()
+            );
+            consistent = false;
+        }
+        results.push(json!({
+            "file": file,
+            "bytes": bytes,
+            "threads": scan_config.threads,
+            "consistent": same,
+            "readers": {
+                "csv": csv,
+                "mmap": mmap,
+                "parallel_mmap": parallel,
+            },
+        }));
+    }
+
+    print_json(&json!(results))?;
+    if !consistent {
+        return Err("Los lectores no coinciden".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_support::{TestDir, read_trips, sample_trips_csv};
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn parse(list: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(&args(list))
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        let parsed = parse(&[
+            "filter",
+            "--csv",
+            "viajes.csv",
+            "--threads",
+            "4",
+            "--query",
+            "do_location_id = 5",
+            "--explain",
+            "--output",
+            "salida.csv",
+            "--limit",
+            "7",
+        ])
+        .unwrap();
+        assert_eq!(parsed.command, ["filter"]);
+        assert_eq!(parsed.csv_path, Some(PathBuf::from("viajes.csv")));
+        assert_eq!(parsed.threads, Some(4));
+        assert_eq!(parsed.output, Some(PathBuf::from("salida.csv")));
+        assert_eq!(parsed.limit, Some(7));
+        assert!(parsed.explain);
+        assert_eq!(parsed.filter().unwrap().to_string(), "do_location_id = 5");
+
+        // Sin --csv se usa el de la configuración y sin --query se aceptan todas las filas
+        let parsed = parse(&["get", "42"]).unwrap();
+        assert_eq!(parsed.command, ["get", "42"]);
+        assert_eq!(parsed.csv_path, None);
+        assert_eq!(parsed.filter().unwrap().to_string(), "true");
+
+        for (list, message) in [
+            (&["filter", "--csv"][..], "Falta el valor de --csv"),
+            (
+                &["--threads", "muchos"],
+                "Cantidad de hilos inválida: muchos",
+            ),
+            (&["--limit", "-1"], "Límite inválido: -1"),
+            (&["stats", "--verbose"], "Opción desconocida: --verbose"),
+        ] {
+            assert_eq!(parse(list).unwrap_err(), message);
+        }
+        assert!(
+            parse(&["stats", "--query", "foo = 1"])
+                .unwrap()
+                .filter()
+                .is_err()
+        );
+
+        // Opciones de export, analyze y de los resúmenes
+        let parsed = parse(&[
+            "export",
+            "--format",
+            "excel",
+            "--histogram",
+            "total_amount:0:100:20",
+            "--histogram",
+            "trip_distance:0:10:5",
+            "--exact-limit",
+            "50",
+        ])
+        .unwrap();
+        assert_eq!(
+            parsed.format,
+            Some(ExportFormat::from_name("excel").unwrap())
+        );
+        assert_eq!(parsed.histograms.len(), 2);
+        assert_eq!(parsed.sketch_options.exact_limit, 50);
+        assert_eq!(
+            parse(&["--format", "xls"]).unwrap_err(),
+            "Formato desconocido: xls"
+        );
+        assert!(parse(&["--distinct-precision", "30"]).is_err());
+        assert!(parse(&["analyze", "--histogram", "total_amount"]).is_err());
+    }
+
+    // help es un comando solo en primera posición; -h y --help son opciones
+    #[test]
+    fn help_is_a_command_only_in_first_position() {
+        for list in [
+            &["help"][..],
+            &["--help"],
+            &["stats", "-h"],
+            &["help", "stats"],
+        ] {
+            assert_eq!(run(&args(list)), 0);
+        }
+
+        let parsed = parse(&["get", "help"]).unwrap();
+        assert!(!parsed.help);
+        assert_eq!(parsed.command, ["get", "help"]);
+        let parsed = parse(&[
+            "export",
+            "--output",
+            "help",
+            "--query",
+            "vendor_id = 'help'",
+        ])
+        .unwrap();
+        assert!(!parsed.help);
+        assert_eq!(parsed.output, Some(PathBuf::from("help")));
+        assert_eq!(parsed.filter().unwrap().to_string(), "vendor_id = help");
+
+        let dir = TestDir::with_index_root("cli-help");
+        let tmp_dir = dir.join("tmp");
+        let tmp_dir = tmp_dir.to_str().unwrap();
+        assert_eq!(run(&args(&["index", "help", "--tmp-dir", tmp_dir])), 2);
+    }
+
+    #[test]
+    fn usage_errors_exit_before_reading_the_dataset() {
+        assert_eq!(run(&args(&["--help"])), 0);
+        assert_eq!(run(&args(&["stats", "--verbose"])), 2);
+
+        // Los comandos inválidos se detectan después de cargar la configuración
+        let dir = TestDir::with_index_root("cli-usage");
+        let tmp_dir = dir.join("tmp");
+        let tmp_dir = tmp_dir.to_str().unwrap();
+        assert_eq!(run(&args(&["index", "borrar", "--tmp-dir", tmp_dir])), 2);
+        assert_eq!(run(&args(&["get", "--tmp-dir", tmp_dir])), 2);
+    }
+
+    #[test]
+    fn options_override_config_file_and_env() {
+        let dir = TestDir::new("cli-config");
+        let file = dir.write(
+            "config.json",
+            r#"{ "data_path": "archivo.csv", "tmp_dir": "tmp-archivo", "threads": 2 }"#,
+        );
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("PRACTICA1_INDEX_DIR", "indices-entorno"),
+            ("PRACTICA1_THREADS", "3"),
+        ]);
+        let loaded =
+            AppConfig::load_with(Some(&file), |name| vars.get(name).map(OsString::from)).unwrap();
+
+        // Sin opciones queda lo cargado
+        let config = parse(&["stats"]).unwrap().override_config(loaded.clone());
+        assert_eq!(config.data_path, PathBuf::from("archivo.csv"));
+        assert_eq!(config.index_dir(), PathBuf::from("indices-entorno"));
+        assert_eq!(config.threads, Some(3));
+
+        let parsed = parse(&[
+            "stats",
+            "--csv",
+            "opcion.csv",
+            "--index-dir",
+            "indices-opcion",
+            "--threads",
+            "5",
+        ])
+        .unwrap();
+        let config = parsed.override_config(loaded);
+        assert_eq!(config.data_path, PathBuf::from("opcion.csv"));
+        assert_eq!(config.index_dir(), PathBuf::from("indices-opcion"));
+        assert_eq!(config.threads, Some(5));
+        // Lo que no se pasa como opción se conserva
+        assert_eq!(config.tmp_dir, PathBuf::from("tmp-archivo"));
+    }
+
+    #[test]
+    fn filter_and_stats_end_to_end() {
+        let dir = TestDir::with_index_root("cli-end-to-end");
+        let csv = dir.write("trips.csv", sample_trips_csv(120));
+        let output = dir.join("destino-5.csv");
+        let expected: Vec<_> = read_trips(&csv)
+            .into_iter()
+            .filter(|trip| trip.do_location_id == 5)
+            .collect();
+        assert!(!expected.is_empty());
+
+        let path = |path: &Path| path.to_str().unwrap().to_string();
+        let common = [
+            "--csv".to_string(),
+            path(&csv),
+            "--tmp-dir".to_string(),
+            path(&dir.join("tmp")),
+            "--index-dir".to_string(),
+            path(&dir.join("indexes")),
+            "--query".to_string(),
+            "do_location_id = 5".to_string(),
+        ];
+        let mut filter_args = args(&["filter", "--output", &path(&output)]);
+        filter_args.extend(common.iter().cloned());
+        assert_eq!(run(&filter_args), 0);
+
+        let written = read_trips(&output);
+        assert_eq!(written.len(), expected.len());
+        for (written, expected) in written.iter().zip(&expected) {
+            assert_eq!(written.index, expected.index);
+            assert_eq!(written.total_amount, expected.total_amount);
+        }
+
+        let mut stats_args = args(&["stats"]);
+        stats_args.extend(common.iter().cloned());
+        let parsed = CliArgs::parse(&stats_args).unwrap();
+        let config = parsed.config().unwrap();
+        let stats = stats_json(&parsed, &config).unwrap();
+        assert_eq!(stats["count"], json!(expected.len() as f64));
+        let total: f64 = expected.iter().map(|trip| trip.total_amount).sum();
+        assert!((stats["total_amount"].as_f64().unwrap() - total).abs() < 1e-6);
+
+        // Un CSV que no existe termina con error
+        let mut missing = args(&["stats", "--csv", &path(&dir.join("no-existe.csv"))]);
+        missing.extend(common[2..6].iter().cloned());
+        assert_eq!(run(&missing), 1);
+    }
+}
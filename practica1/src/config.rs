@@ -0,0 +1,169 @@
+use crate::data::{filters, parallel_scan};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Archivo de configuración que se lee del directorio actual si existe
+const DEFAULT_CONFIG_FILE: &str = "practica1.config.json";
+
+// Variables de entorno; tienen prioridad sobre el archivo de configuración
+const ENV_CONFIG_FILE: &str = "PRACTICA1_CONFIG";
+const ENV_DATA_PATH: &str = "PRACTICA1_DATA";
+const ENV_TMP_DIR: &str = "PRACTICA1_TMP_DIR";
+const ENV_INDEX_DIR: &str = "PRACTICA1_INDEX_DIR";
+const ENV_THREADS: &str = "PRACTICA1_THREADS";
+
+// Rutas y opciones de la aplicación. Cada valor se toma, de menor a mayor prioridad, de:
+// los valores por defecto, el archivo de configuración, las variables de entorno
+// y las opciones de línea de comandos
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    // CSV que se abre al iniciar
+    pub data_path: PathBuf,
+    // Resultados temporales y exportaciones
+    pub tmp_dir: PathBuf,
+    // Índices de cada dataset; si falta se usa <tmp_dir>/indexes
+    pub index_dir: Option<PathBuf>,
+    // Hilos para recorrer los CSV; si falta se usan todos los núcleos
+    pub threads: Option<usize>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            data_path: PathBuf::from("src/data/data.csv"),
+            tmp_dir: PathBuf::from("tmp"),
+            index_dir: None,
+            threads: None,
+        }
+    }
+}
+
+impl AppConfig {
+    // Lee el archivo indicado (o el de PRACTICA1_CONFIG, o el archivo por defecto
+    // si existe) y aplica las variables de entorno
+    pub fn load(config_file: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        Self::load_with(config_file, |name| env::var_os(name))
+    }
+
+    // Como load, pero leyendo las variables con `var` en lugar del entorno del proceso
+    pub(crate) fn load_with<F>(config_file: Option<&Path>, var: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let explicit = config_file
+            .map(Path::to_path_buf)
+            .or_else(|| var(ENV_CONFIG_FILE).map(PathBuf::from));
+
+        let mut config = match explicit {
+            Some(path) => Self::read_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::read_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        if let Some(path) = var(ENV_DATA_PATH) {
+            config.data_path = PathBuf::from(path);
+        }
+        if let Some(path) = var(ENV_TMP_DIR) {
+            config.tmp_dir = PathBuf::from(path);
+        }
+        if let Some(path) = var(ENV_INDEX_DIR) {
+            config.index_dir = Some(PathBuf::from(path));
+        }
+        if let Some(threads) = var(ENV_THREADS) {
+            let threads = threads.to_string_lossy();
+            config.threads = Some(
+                threads
+                    .parse()
+                    .map_err(|_| format!("{} inválido: {}", ENV_THREADS, threads))?,
+            );
+        }
+
+        Ok(config)
+    }
+
+    fn read_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("No se pudo leer la configuración {}: {}", path.display(), e))?;
+        let config = serde_json::from_str(&text)
+            .map_err(|e| format!("Configuración inválida en {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    pub fn index_dir(&self) -> PathBuf {
+        self.index_dir
+            .clone()
+            .unwrap_or_else(|| self.tmp_dir.join("indexes"))
+    }
+
+    // Crea el directorio temporal, hace que los índices se guarden en index_dir y fija
+    // los hilos de los recorridos
+    pub fn apply(&self) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.tmp_dir)?;
+        filters::set_index_root(self.index_dir());
+        parallel_scan::set_scan_threads(self.threads.unwrap_or(0));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_support::TestDir;
+    use std::collections::HashMap;
+
+    // Cada fuente pisa solo los valores que define: por defecto < archivo < entorno
+    #[test]
+    fn config_sources_in_order_of_precedence() {
+        let dir = TestDir::new("config");
+        let file = dir.write(
+            "config.json",
+            r#"{ "data_path": "archivo.csv", "tmp_dir": "tmp-archivo", "threads": 2 }"#,
+        );
+
+        let no_env = |_: &str| None;
+        let defaults = AppConfig::load_with(None, no_env).unwrap();
+        assert_eq!(defaults.data_path, PathBuf::from("src/data/data.csv"));
+        assert_eq!(defaults.index_dir(), PathBuf::from("tmp").join("indexes"));
+        assert_eq!(defaults.threads, None);
+
+        let from_file = AppConfig::load_with(Some(&file), no_env).unwrap();
+        assert_eq!(from_file.data_path, PathBuf::from("archivo.csv"));
+        assert_eq!(from_file.tmp_dir, PathBuf::from("tmp-archivo"));
+        assert_eq!(
+            from_file.index_dir(),
+            PathBuf::from("tmp-archivo").join("indexes")
+        );
+        assert_eq!(from_file.threads, Some(2));
+
+        let vars: HashMap<&str, &str> = HashMap::from([
+            (ENV_CONFIG_FILE, file.to_str().unwrap()),
+            (ENV_TMP_DIR, "tmp-entorno"),
+            (ENV_INDEX_DIR, "indices-entorno"),
+            (ENV_THREADS, "3"),
+        ]);
+        let env_var = |name: &str| vars.get(name).map(OsString::from);
+        let from_env = AppConfig::load_with(None, env_var).unwrap();
+        assert_eq!(from_env.data_path, PathBuf::from("archivo.csv"));
+        assert_eq!(from_env.tmp_dir, PathBuf::from("tmp-entorno"));
+        assert_eq!(from_env.index_dir(), PathBuf::from("indices-entorno"));
+        assert_eq!(from_env.threads, Some(3));
+
+        // El archivo indicado explícitamente tiene prioridad sobre PRACTICA1_CONFIG
+        let other = dir.write("otro.json", r#"{ "data_path": "otro.csv" }"#);
+        let explicit = AppConfig::load_with(Some(&other), env_var).unwrap();
+        assert_eq!(explicit.data_path, PathBuf::from("otro.csv"));
+        assert_eq!(explicit.tmp_dir, PathBuf::from("tmp-entorno"));
+
+        let missing = dir.join("no-existe.json");
+        assert!(AppConfig::load_with(Some(&missing), no_env).is_err());
+        let bad_threads = |name: &str| (name == ENV_THREADS).then(|| OsString::from("muchos"));
+        assert!(AppConfig::load_with(None, bad_threads).is_err());
+    }
+}
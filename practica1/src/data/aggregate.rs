@@ -0,0 +1,756 @@
+use super::sketch::{QuantileSketch, SketchOptions};
+use super::trip_struct::{DATETIME_OUTPUT_FORMAT, Measure, TripColumn, TripRef};
+use chrono::{Datelike, NaiveDateTime, Timelike, Weekday};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Valor de una celda de la tabla de resultados
+#[derive(Clone, Debug)]
+pub enum Value {
+    // Agregado sin valores (por ejemplo, promedio de propinas de un grupo sin tarifas)
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    DateTime(NaiveDateTime),
+    Weekday(Weekday),
+    // Par origen → destino
+    Route(u16, u16),
+}
+
+impl Value {
+    // Orden total para ordenar filas: Null primero y los valores de distinto tipo
+    // por su variante
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+            (Value::Weekday(a), Value::Weekday(b)) => {
+                a.num_days_from_monday().cmp(&b.num_days_from_monday())
+            }
+            (Value::Route(a, b), Value::Route(c, d)) => (a, b).cmp(&(c, d)),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Integer(_) => 2,
+            Value::Float(_) => 3,
+            Value::Text(_) => 4,
+            Value::DateTime(_) => 5,
+            Value::Weekday(_) => 6,
+            Value::Route(..) => 7,
+        }
+    }
+}
+
+// Los Float se comparan por sus bits, así pueden ser claves de un grupo
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.compare(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Null => {}
+            Value::Bool(value) => value.hash(state),
+            Value::Integer(value) => value.hash(state),
+            Value::Float(value) => value.to_bits().hash(state),
+            Value::Text(value) => value.hash(state),
+            Value::DateTime(value) => value.hash(state),
+            Value::Weekday(value) => value.num_days_from_monday().hash(state),
+            Value::Route(from, to) => (from, to).hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(value) => write!(f, "{}", if *value { "Y" } else { "N" }),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Text(value) => write!(f, "{}", value),
+            Value::DateTime(value) => write!(f, "{}", value.format(DATETIME_OUTPUT_FORMAT)),
+            Value::Weekday(value) => write!(f, "{}", weekday_name(*value)),
+            Value::Route(from, to) => write!(f, "{} → {}", from, to),
+        }
+    }
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "lunes",
+        Weekday::Tue => "martes",
+        Weekday::Wed => "miércoles",
+        Weekday::Thu => "jueves",
+        Weekday::Fri => "viernes",
+        Weekday::Sat => "sábado",
+        Weekday::Sun => "domingo",
+    }
+}
+
+/// Tipo de los valores de una columna de la tabla de resultados
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    Bool,
+    Integer,
+    Float,
+    Text,
+    DateTime,
+    Weekday,
+    Route,
+}
+
+impl ValueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueKind::Bool => "bool",
+            ValueKind::Integer => "integer",
+            ValueKind::Float => "float",
+            ValueKind::Text => "text",
+            ValueKind::DateTime => "datetime",
+            ValueKind::Weekday => "weekday",
+            ValueKind::Route => "route",
+        }
+    }
+}
+
+/// Clave de agrupación: una columna del viaje o un valor calculado a partir de ellas
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GroupKey {
+    Column(TripColumn),
+    // Hora del día de la recogida (0 a 23)
+    PickupHour,
+    // Día de la semana de la recogida
+    PickupWeekday,
+    // Par de zonas de recogida y destino
+    Route,
+}
+
+impl GroupKey {
+    // Las claves calculadas primero, luego las columnas
+    pub fn all() -> Vec<GroupKey> {
+        [
+            GroupKey::PickupHour,
+            GroupKey::PickupWeekday,
+            GroupKey::Route,
+        ]
+        .into_iter()
+        .chain(TripColumn::ALL.into_iter().map(GroupKey::Column))
+        .collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupKey::Column(column) => column.name(),
+            GroupKey::PickupHour => "pickup_hour",
+            GroupKey::PickupWeekday => "pickup_weekday",
+            GroupKey::Route => "route",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        GroupKey::all()
+            .into_iter()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            GroupKey::PickupHour => ValueKind::Integer,
+            GroupKey::PickupWeekday => ValueKind::Weekday,
+            GroupKey::Route => ValueKind::Route,
+            GroupKey::Column(column) => match column {
+                TripColumn::VendorId | TripColumn::Index | TripColumn::SourceFile => {
+                    ValueKind::Text
+                }
+                TripColumn::PickupDatetime | TripColumn::DropoffDatetime => ValueKind::DateTime,
+                TripColumn::PassengerCount
+                | TripColumn::RatecodeId
+                | TripColumn::PuLocationId
+                | TripColumn::DoLocationId
+                | TripColumn::PaymentType => ValueKind::Integer,
+                TripColumn::StoreAndFwdFlag => ValueKind::Bool,
+                TripColumn::TripDistance
+                | TripColumn::FareAmount
+                | TripColumn::Extra
+                | TripColumn::MtaTax
+                | TripColumn::TipAmount
+                | TripColumn::TollsAmount
+                | TripColumn::ImprovementSurcharge
+                | TripColumn::TotalAmount
+                | TripColumn::CongestionSurcharge => ValueKind::Float,
+            },
+        }
+    }
+
+    // Valor de la clave en el viaje; los códigos de tarifa y de pago como su número y
+    // los valores desconocidos como Null
+    pub fn value(&self, trip: &TripRef) -> Value {
+        fn optional<T>(value: Option<T>, wrap: impl FnOnce(T) -> Value) -> Value {
+            value.map_or(Value::Null, wrap)
+        }
+
+        match self {
+            GroupKey::PickupHour => Value::Integer(trip.tpep_pickup_datetime.hour() as i64),
+            GroupKey::PickupWeekday => Value::Weekday(trip.tpep_pickup_datetime.weekday()),
+            GroupKey::Route => Value::Route(trip.pu_location_id, trip.do_location_id),
+            GroupKey::Column(column) => match column {
+                TripColumn::VendorId => Value::Text(trip.vendor_id.to_string()),
+                TripColumn::PickupDatetime => Value::DateTime(trip.tpep_pickup_datetime),
+                TripColumn::DropoffDatetime => Value::DateTime(trip.tpep_dropoff_datetime),
+                TripColumn::PassengerCount => {
+                    optional(trip.passenger_count, |n| Value::Integer(n as i64))
+                }
+                TripColumn::TripDistance => Value::Float(trip.trip_distance),
+                TripColumn::RatecodeId => {
+                    optional(trip.ratecode_id, |code| Value::Integer(code.code() as i64))
+                }
+                TripColumn::StoreAndFwdFlag => optional(trip.store_and_fwd_flag, Value::Bool),
+                TripColumn::PuLocationId => Value::Integer(trip.pu_location_id as i64),
+                TripColumn::DoLocationId => Value::Integer(trip.do_location_id as i64),
+                TripColumn::PaymentType => optional(trip.payment_type, |payment| {
+                    Value::Integer(payment.code() as i64)
+                }),
+                TripColumn::FareAmount => Value::Float(trip.fare_amount),
+                TripColumn::Extra => Value::Float(trip.extra),
+                TripColumn::MtaTax => Value::Float(trip.mta_tax),
+                TripColumn::TipAmount => Value::Float(trip.tip_amount),
+                TripColumn::TollsAmount => Value::Float(trip.tolls_amount),
+                TripColumn::ImprovementSurcharge => Value::Float(trip.improvement_surcharge),
+                TripColumn::TotalAmount => Value::Float(trip.total_amount),
+                TripColumn::CongestionSurcharge => {
+                    optional(trip.congestion_surcharge, Value::Float)
+                }
+                TripColumn::Index => Value::Text(trip.index.to_string()),
+                TripColumn::SourceFile => match trip.source_file {
+                    Some(file) => Value::Text(file.to_string()),
+                    None => Value::Null,
+                },
+            },
+        }
+    }
+}
+
+/// Función de agregación sobre los viajes de cada grupo
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Sum(Measure),
+    Avg(Measure),
+    Min(Measure),
+    Max(Measure),
+    Median(Measure),
+    // Percentil entre 0 y 100; exacto mientras alcance el presupuesto compartido por los
+    // grupos (ver GroupByAccumulator::exact_values)
+    Percentile(Measure, f64),
+    // Desviación estándar muestral
+    StdDev(Measure),
+}
+
+impl Aggregate {
+    /// Interpreta count, sum(m), avg(m), min(m), max(m), median(m), stddev(m) o pNN(m),
+    /// por ejemplo p95(trip_distance)
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("count") || text.eq_ignore_ascii_case("count(*)") {
+            return Ok(Aggregate::Count);
+        }
+
+        let invalid = || {
+            format!(
+                "Agregado inválido: {} (por ejemplo avg(total_amount))",
+                text
+            )
+        };
+        let (function, rest) = text.split_once('(').ok_or_else(invalid)?;
+        let measure = rest.strip_suffix(')').ok_or_else(invalid)?;
+        let measure = Measure::from_name(measure)
+            .ok_or_else(|| format!("Medida desconocida: {}", measure))?;
+
+        let function = function.trim().to_ascii_lowercase();
+        Ok(match function.as_str() {
+            "sum" => Aggregate::Sum(measure),
+            "avg" | "mean" => Aggregate::Avg(measure),
+            "min" => Aggregate::Min(measure),
+            "max" => Aggregate::Max(measure),
+            "median" => Aggregate::Median(measure),
+            "stddev" => Aggregate::StdDev(measure),
+            _ => {
+                let percentile = function
+                    .strip_prefix('p')
+                    .and_then(|p| p.parse::<f64>().ok())
+                    .filter(|p| (0.0..=100.0).contains(p))
+                    .ok_or_else(|| format!("Función de agregación desconocida: {}", function))?;
+                Aggregate::Percentile(measure, percentile)
+            }
+        })
+    }
+
+    /// Agregados separados por comas
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        text.split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(Aggregate::parse)
+            .collect()
+    }
+
+    // Nombre de la columna en la tabla de resultados
+    pub fn name(&self) -> String {
+        match self {
+            Aggregate::Count => "count".to_string(),
+            Aggregate::Sum(measure) => format!("sum({})", measure.name()),
+            Aggregate::Avg(measure) => format!("avg({})", measure.name()),
+            Aggregate::Min(measure) => format!("min({})", measure.name()),
+            Aggregate::Max(measure) => format!("max({})", measure.name()),
+            Aggregate::Median(measure) => format!("median({})", measure.name()),
+            Aggregate::Percentile(measure, p) => format!("p{}({})", p, measure.name()),
+            Aggregate::StdDev(measure) => format!("stddev({})", measure.name()),
+        }
+    }
+
+    pub fn measure(&self) -> Option<Measure> {
+        match self {
+            Aggregate::Count => None,
+            Aggregate::Sum(measure)
+            | Aggregate::Avg(measure)
+            | Aggregate::Min(measure)
+            | Aggregate::Max(measure)
+            | Aggregate::Median(measure)
+            | Aggregate::Percentile(measure, _)
+            | Aggregate::StdDev(measure) => Some(*measure),
+        }
+    }
+
+    // La mediana y los percentiles necesitan un resumen de los valores del grupo
+    fn needs_quantiles(&self) -> bool {
+        matches!(self, Aggregate::Median(_) | Aggregate::Percentile(..))
+    }
+
+    fn kind(&self) -> ValueKind {
+        match self {
+            Aggregate::Count => ValueKind::Integer,
+            _ => ValueKind::Float,
+        }
+    }
+}
+
+/// Agrupación de los trips por una o más claves, con los agregados de cada grupo
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupBySpec {
+    pub keys: Vec<GroupKey>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+impl GroupBySpec {
+    /// Claves y agregados separados por comas; sin agregados solo se cuentan los trips
+    pub fn parse(keys: &str, aggregates: &str) -> Result<Self, String> {
+        let keys = keys
+            .split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(|name| {
+                GroupKey::from_name(name)
+                    .ok_or_else(|| format!("Clave de agrupación desconocida: {}", name.trim()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err("La agrupación necesita al menos una clave".to_string());
+        }
+
+        let mut aggregates = Aggregate::parse_list(aggregates)?;
+        if aggregates.is_empty() {
+            aggregates.push(Aggregate::Count);
+        }
+        Ok(GroupBySpec { keys, aggregates })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultColumn {
+    pub name: String,
+    pub kind: ValueKind,
+}
+
+/// Tabla con una fila por grupo: primero las claves y luego los agregados, en el orden
+/// de GroupBySpec
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResultTable {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultTable {
+    // Ordena por una columna; a igual valor se mantiene el orden anterior
+    pub fn sort_by_column(&mut self, column: usize, descending: bool) {
+        self.rows.sort_by(|a, b| {
+            let order = a[column].compare(&b[column]);
+            if descending { order.reverse() } else { order }
+        });
+    }
+}
+
+// Valores de una medida en un grupo; Welford para la desviación estándar
+struct MeasureState {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+    quantiles: Option<QuantileSketch>,
+}
+
+impl MeasureState {
+    fn new(quantiles: Option<QuantileSketch>) -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            quantiles,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        if let Some(quantiles) = self.quantiles.as_mut() {
+            quantiles.add(value);
+        }
+    }
+
+    fn exact_len(&self) -> usize {
+        self.quantiles.as_ref().map_or(0, QuantileSketch::exact_len)
+    }
+
+    fn make_approximate(&mut self) {
+        if let Some(quantiles) = self.quantiles.as_mut() {
+            quantiles.make_approximate();
+        }
+    }
+
+    fn percentile(&mut self, p: f64) -> Value {
+        self.quantiles
+            .as_mut()
+            .and_then(|quantiles| quantiles.quantile(p / 100.0))
+            .map_or(Value::Null, Value::Float)
+    }
+
+    fn aggregate(&mut self, aggregate: &Aggregate) -> Value {
+        if self.count == 0 {
+            return match aggregate {
+                Aggregate::Sum(_) => Value::Float(0.0),
+                _ => Value::Null,
+            };
+        }
+        match aggregate {
+            Aggregate::Count => Value::Integer(self.count as i64),
+            Aggregate::Sum(_) => Value::Float(self.sum),
+            Aggregate::Avg(_) => Value::Float(self.mean),
+            Aggregate::Min(_) => Value::Float(self.min),
+            Aggregate::Max(_) => Value::Float(self.max),
+            Aggregate::Median(_) => self.percentile(50.0),
+            Aggregate::Percentile(_, p) => self.percentile(*p),
+            Aggregate::StdDev(_) if self.count < 2 => Value::Null,
+            Aggregate::StdDev(_) => Value::Float((self.m2 / (self.count - 1) as f64).sqrt()),
+        }
+    }
+}
+
+struct GroupState {
+    count: usize,
+    // Una por medida de GroupByAccumulator::measures
+    measures: Vec<MeasureState>,
+}
+
+/// Acumula los grupos viaje a viaje, para calcularlos durante un recorrido del dataset
+pub struct GroupByAccumulator {
+    spec: GroupBySpec,
+    // Medidas distintas que usan los agregados, y si hacen falta sus cuantiles
+    measures: Vec<(Measure, bool)>,
+    groups: HashMap<Vec<Value>, GroupState>,
+    options: SketchOptions,
+    // Valores exactos guardados entre todos los grupos; `exact_limit` acota la suma,
+    // no cada grupo, porque con muchos grupos chicos la memoria no tendría límite
+    exact_values: usize,
+}
+
+impl GroupByAccumulator {
+    pub fn new(spec: GroupBySpec, options: SketchOptions) -> Self {
+        let mut measures: Vec<(Measure, bool)> = Vec::new();
+        for aggregate in &spec.aggregates {
+            let Some(measure) = aggregate.measure() else {
+                continue;
+            };
+            match measures.iter_mut().find(|(m, _)| *m == measure) {
+                Some((_, quantiles)) => *quantiles |= aggregate.needs_quantiles(),
+                None => measures.push((measure, aggregate.needs_quantiles())),
+            }
+        }
+        Self {
+            spec,
+            measures,
+            groups: HashMap::new(),
+            options,
+            exact_values: 0,
+        }
+    }
+
+    pub fn add(&mut self, trip: &TripRef) {
+        let key: Vec<Value> = self.spec.keys.iter().map(|key| key.value(trip)).collect();
+        let (measures, options) = (&self.measures, self.options);
+        // Agotado el presupuesto, los grupos nuevos empiezan aproximados
+        let exact = self.exact_values < options.exact_limit;
+        let group = self.groups.entry(key).or_insert_with(|| GroupState {
+            count: 0,
+            measures: measures
+                .iter()
+                .map(|(_, quantiles)| {
+                    MeasureState::new(quantiles.then(|| {
+                        if exact {
+                            QuantileSketch::new(options)
+                        } else {
+                            QuantileSketch::new_approximate(options)
+                        }
+                    }))
+                })
+                .collect(),
+        });
+
+        group.count += 1;
+        for ((measure, _), state) in self.measures.iter().zip(&mut group.measures) {
+            // Los trips sin valor (propina sin tarifa) no entran en los agregados de la medida
+            if let Some(value) = measure.value(trip) {
+                let before = state.exact_len();
+                state.add(value);
+                self.exact_values = self.exact_values - before + state.exact_len();
+                // Al pasarse del presupuesto se aproxima el grupo que lo superó
+                if self.exact_values > options.exact_limit {
+                    self.exact_values -= state.exact_len();
+                    state.make_approximate();
+                }
+            }
+        }
+    }
+
+    /// Tabla con los grupos ordenados por sus claves
+    pub fn finish(self) -> ResultTable {
+        let columns = self
+            .spec
+            .keys
+            .iter()
+            .map(|key| ResultColumn {
+                name: key.name().to_string(),
+                kind: key.kind(),
+            })
+            .chain(self.spec.aggregates.iter().map(|aggregate| ResultColumn {
+                name: aggregate.name(),
+                kind: aggregate.kind(),
+            }))
+            .collect();
+
+        let mut rows: Vec<Vec<Value>> = self
+            .groups
+            .into_iter()
+            .map(|(mut row, mut group)| {
+                for aggregate in &self.spec.aggregates {
+                    let value = match aggregate.measure() {
+                        None => Value::Integer(group.count as i64),
+                        Some(measure) => {
+                            let position = self
+                                .measures
+                                .iter()
+                                .position(|(m, _)| *m == measure)
+                                .expect("medida registrada en new");
+                            group.measures[position].aggregate(aggregate)
+                        }
+                    };
+                    row.push(value);
+                }
+                row
+            })
+            .collect();
+
+        let key_count = self.spec.keys.len();
+        rows.sort_by(|a, b| {
+            a[..key_count]
+                .iter()
+                .zip(&b[..key_count])
+                .map(|(a, b)| a.compare(b))
+                .find(|order| order.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        ResultTable { columns, rows }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::trip_struct::{PaymentType, RateCode, Trip};
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn trip(day: u32, hour: u32, pu: u16, dest: u16, fare: f64, tip: f64) -> Trip {
+        let pickup = NaiveDate::from_ymd_opt(2020, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap();
+        Trip {
+            vendor_id: "1".to_string(),
+            tpep_pickup_datetime: pickup,
+            tpep_dropoff_datetime: pickup + chrono::Duration::minutes(10),
+            passenger_count: Some(1),
+            trip_distance: fare / 4.0,
+            ratecode_id: Some(RateCode::Standard),
+            store_and_fwd_flag: Some(false),
+            pu_location_id: pu,
+            do_location_id: dest,
+            payment_type: Some(PaymentType::CreditCard),
+            fare_amount: fare,
+            extra: 0.0,
+            mta_tax: 0.0,
+            tip_amount: tip,
+            tolls_amount: 0.0,
+            improvement_surcharge: 0.0,
+            total_amount: fare + tip,
+            congestion_surcharge: Some(0.0),
+            index: format!("{}-{}", day, hour),
+            source_file: None,
+        }
+    }
+
+    // Cada agregado por grupo coincide con el cálculo directo sobre sus valores
+    #[test]
+    fn group_by_computes_each_aggregate() {
+        // 2020-06-01 es lunes y 2020-06-02 martes
+        let trips = [
+            trip(1, 8, 10, 20, 10.0, 1.0),
+            trip(1, 9, 10, 20, 20.0, 2.0),
+            trip(1, 9, 10, 30, 30.0, 0.0),
+            trip(1, 10, 10, 20, 40.0, 4.0),
+            trip(2, 8, 11, 20, 0.0, 5.0),
+            trip(2, 9, 11, 20, 8.0, 0.0),
+        ];
+        let spec = GroupBySpec::parse(
+            "pickup_weekday,route",
+            "count, sum(fare_amount), avg(fare_amount), min(fare_amount), max(fare_amount), \
+             median(fare_amount), p25(fare_amount), stddev(fare_amount), avg(tip_percentage)",
+        )
+        .unwrap();
+        let mut accumulator = GroupByAccumulator::new(spec, SketchOptions::default());
+        for trip in &trips {
+            accumulator.add(&trip.as_trip_ref());
+        }
+        let mut table = accumulator.finish();
+
+        let names: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names[..3], ["pickup_weekday", "route", "count"]);
+        assert_eq!(names[8], "p25(fare_amount)");
+        assert_eq!(table.columns[1].kind, ValueKind::Route);
+        assert_eq!(table.rows.len(), 3);
+
+        // Lunes 10 → 20: tarifas 10, 20 y 40
+        let row = &table.rows[0];
+        assert_eq!(row[0], Value::Weekday(Weekday::Mon));
+        assert_eq!(row[1], Value::Route(10, 20));
+        assert_eq!(row[2], Value::Integer(3));
+        assert_eq!(row[3], Value::Float(70.0));
+        assert_eq!(row[5], Value::Float(10.0));
+        assert_eq!(row[6], Value::Float(40.0));
+        assert_eq!(row[7], Value::Float(20.0));
+        assert_eq!(row[8], Value::Float(15.0));
+        let Value::Float(mean) = row[4] else {
+            panic!("promedio sin valor")
+        };
+        assert!((mean - 70.0 / 3.0).abs() < 1e-9);
+        let expected = [10.0f64, 20.0, 40.0]
+            .iter()
+            .map(|v| (v - mean).powi(2))
+            .sum::<f64>()
+            / 2.0;
+        let Value::Float(stddev) = row[9] else {
+            panic!("desviación sin valor")
+        };
+        assert!((stddev - expected.sqrt()).abs() < 1e-9);
+
+        // Lunes 10 → 30: un solo viaje, sin desviación
+        assert_eq!(table.rows[1][1], Value::Route(10, 30));
+        assert_eq!(table.rows[1][9], Value::Null);
+
+        // Martes: la propina sin tarifa no cuenta en el porcentaje
+        let row = &table.rows[2];
+        assert_eq!(row[0], Value::Weekday(Weekday::Tue));
+        assert_eq!(row[2], Value::Integer(2));
+        assert_eq!(row[10], Value::Float(0.0));
+
+        table.sort_by_column(3, true);
+        assert_eq!(table.rows[0][3], Value::Float(70.0));
+        assert_eq!(table.rows[2][3], Value::Float(8.0));
+
+        assert!(GroupBySpec::parse("hora", "count").is_err());
+        assert!(GroupBySpec::parse("pickup_hour", "p101(fare_amount)").is_err());
+        assert!(GroupBySpec::parse("pickup_hour", "avg(tarifa)").is_err());
+    }
+
+    // El límite de valores exactos vale para todos los grupos juntos
+    #[test]
+    fn exact_budget_is_shared_by_all_groups() {
+        let options = SketchOptions {
+            exact_limit: 100,
+            ..SketchOptions::default()
+        };
+        let spec = GroupBySpec::parse("pu_location_id", "median(fare_amount)").unwrap();
+        let mut accumulator = GroupByAccumulator::new(spec, options);
+        // 50 zonas con 40 viajes cada una, tarifas 1..=40
+        for fare in 1..=40 {
+            for pu in 0..50 {
+                accumulator.add(&trip(1, 8, pu, 1, fare as f64, 0.0).as_trip_ref());
+            }
+            let stored: usize = accumulator
+                .groups
+                .values()
+                .map(|group| group.measures[0].exact_len())
+                .sum();
+            assert_eq!(stored, accumulator.exact_values);
+            assert!(stored <= options.exact_limit);
+        }
+
+        let table = accumulator.finish();
+        assert_eq!(table.rows.len(), 50);
+        for row in &table.rows {
+            let Value::Float(median) = row[1] else {
+                panic!("mediana sin valor")
+            };
+            assert!((median - 20.5).abs() <= 2.0, "mediana {}", median);
+        }
+    }
+}
@@ -1,59 +1,278 @@
-use super::trip_struct::Trip;
+use super::dataset::source_name;
+use super::trip_struct::{Trip, TripParseError, TripRef, TripSchema};
+use csv::StringRecord;
 use std::error::Error;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
 
+// Máximo de errores guardados en el reporte para no crecer sin límite
+const MAX_REPORTED_ERRORS: usize = 100;
+
+// Error de conversión asociado a una línea del CSV
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub line: u64,
+    pub error: TripParseError,
+}
+
+// Resumen de la lectura de un CSV: filas válidas y errores por fila
+#[derive(Debug, Clone, Default)]
+pub struct ParseReport {
+    pub rows_read: usize,
+    pub rows_ok: usize,
+    pub rows_invalid: usize,
+    pub errors: Vec<RowError>,
+}
+
+impl ParseReport {
+    pub fn record_error(&mut self, line: u64, error: TripParseError) {
+        self.rows_invalid += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            eprintln!("Registro inválido en línea {}: {}", line, error);
+            self.errors.push(RowError { line, error });
+        }
+    }
+
+    // Suma el reporte de otra parte del archivo; se conservan los primeros errores
+    pub fn merge(&mut self, other: ParseReport) {
+        self.rows_read += other.rows_read;
+        self.rows_ok += other.rows_ok;
+        self.rows_invalid += other.rows_invalid;
+        let room = MAX_REPORTED_ERRORS.saturating_sub(self.errors.len());
+        self.errors.extend(other.errors.into_iter().take(room));
+    }
+
+    // Imprime un resumen si hubo filas inválidas
+    pub fn print_summary(&self) {
+        if self.rows_invalid > 0 {
+            eprintln!(
+                "Se omitieron {} de {} registros con formato inválido",
+                self.rows_invalid, self.rows_read
+            );
+            if let Some(first) = self.errors.first() {
+                eprintln!("Primer error en línea {}: {}", first.line, first.error);
+            }
+            if self.rows_invalid > self.errors.len() {
+                eprintln!(
+                    "(solo se reportaron los primeros {} errores)",
+                    self.errors.len()
+                );
+            }
+        }
+    }
+}
+
+// Ubicación de una fila dentro del archivo CSV original
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowLocation {
+    pub offset: u64,
+    pub len: u64,
+}
+
 // Función para procesar CSV en streaming con bajo consumo de memoria
-pub fn stream_process_csv<P, F>(filename: P, mut process_trip: F) -> Result<(), Box<dyn Error>>
+pub fn stream_process_csv<P, F>(
+    filename: P,
+    mut process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
 where
     P: AsRef<Path>,
     F: FnMut(&Trip) -> Result<(), Box<dyn Error>>,
 {
+    stream_process_csv_with_locations(filename, |trip, _| process_trip(trip))
+}
+
+// Igual que stream_process_csv pero indicando la posición en bytes de cada fila
+pub fn stream_process_csv_with_locations<P, F>(
+    filename: P,
+    mut process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Trip, RowLocation) -> Result<(), Box<dyn Error>>,
+{
+    let source = source_name(filename.as_ref());
     let file = File::open(filename)?;
     let buf_reader = BufReader::with_capacity(64 * 1024, file);
     let mut csv_reader = csv::ReaderBuilder::new()
         .buffer_capacity(128 * 1024)
         .has_headers(true)
         .from_reader(buf_reader);
+    let schema = TripSchema::from_header(csv_reader.headers()?)?;
 
-    for result in csv_reader.records() {
-        match result {
-            Ok(record) => {
-                if record.len() < 19 {
-                    eprintln!("Registro con formato incorrecto: {:?}", record);
-                    continue;
-                }
+    let mut report = ParseReport::default();
+    let mut record = StringRecord::new();
 
-                let trip = Trip {
-                    vendor_id: record[0].to_string(),
-                    tpep_pickup_datetime: record[1].to_string(),
-                    tpep_dropoff_datetime: record[2].to_string(),
-                    passenger_count: record[3].to_string(),
-                    trip_distance: record[4].to_string(),
-                    ratecode_id: record[5].to_string(),
-                    store_and_fwd_flag: record[6].to_string(),
-                    pu_location_id: record[7].to_string(),
-                    do_location_id: record[8].to_string(),
-                    payment_type: record[9].to_string(),
-                    fare_amount: record[10].to_string(),
-                    extra: record[11].to_string(),
-                    mta_tax: record[12].to_string(),
-                    tip_amount: record[13].to_string(),
-                    tolls_amount: record[14].to_string(),
-                    improvement_surcharge: record[15].to_string(),
-                    total_amount: record[16].to_string(),
-                    congestion_surcharge: record[17].to_string(),
-                    index: record[18].to_string(),
+    loop {
+        match csv_reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                report.rows_read += 1;
+                let (line, offset) = record.position().map_or((0, 0), |p| (p.line(), p.byte()));
+                let location = RowLocation {
+                    offset,
+                    len: csv_reader.position().byte() - offset,
                 };
 
-                process_trip(&trip)?;
+                // Las filas se numeran desde 0 contando también las inválidas
+                let row = report.rows_read as u64 - 1;
+                match schema.parse(&record, Some(row)) {
+                    Ok(mut trip) => {
+                        trip.source_file.get_or_insert_with(|| source.clone());
+                        report.rows_ok += 1;
+                        process_trip(&trip, location)?;
+                    }
+                    Err(e) => report.record_error(line, e),
+                }
+            }
+            Err(e) => {
+                report.rows_read += 1;
+                eprintln!("Error al leer registro: {}", e);
+                report.rows_invalid += 1;
             }
+        }
+    }
+
+    report.print_summary();
+
+    Ok(report)
+}
+
+// Columnas del CSV según su encabezado
+pub fn read_schema<P: AsRef<Path>>(filename: P) -> Result<TripSchema, Box<dyn Error>> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(filename)?;
+    Ok(TripSchema::from_header(csv_reader.headers()?)?)
+}
+
+// Lee una sola fila del CSV a partir de su ubicación en bytes; `row` es su número
+// de fila, necesario como index si el CSV no tiene esa columna
+pub fn read_trip_at<P: AsRef<Path>>(
+    filename: P,
+    location: RowLocation,
+    row: Option<u64>,
+) -> Result<Trip, Box<dyn Error>> {
+    let schema = read_schema(&filename)?;
+    let source = source_name(filename.as_ref());
+    let mut file = File::open(filename)?;
+    file.seek(SeekFrom::Start(location.offset))?;
+
+    let mut bytes = vec![0u8; location.len as usize];
+    file.read_exact(&mut bytes)?;
+
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(bytes.as_slice());
+    let mut record = StringRecord::new();
+    if !csv_reader.read_record(&mut record)? {
+        return Err("No se encontró una fila en la posición indicada".into());
+    }
+
+    let mut trip = schema.parse(&record, row)?;
+    trip.source_file.get_or_insert(source);
+    Ok(trip)
+}
+
+// Lee las filas que empiezan en los bytes indicados (en orden ascendente para
+// aprovechar la lectura secuencial) y entrega cada viaje a process_trip; `rows`
+// trae el número de fila de cada posición si el CSV no tiene columna index
+pub fn read_trips_at_offsets<P, F>(
+    filename: P,
+    offsets: &[u64],
+    rows: Option<&[u64]>,
+    mut process_trip: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
+    let schema = read_schema(&filename)?;
+    let source = source_name(filename.as_ref());
+    let file = File::open(filename)?;
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(BufReader::with_capacity(64 * 1024, file));
+    let mut record = StringRecord::new();
+    let mut read = 0;
+
+    for (i, offset) in offsets.iter().enumerate() {
+        let mut position = csv::Position::new();
+        position.set_byte(*offset);
+        csv_reader.seek(position)?;
+        if !csv_reader.read_record(&mut record)? {
+            return Err(format!("No se encontró una fila en el byte {}", offset).into());
+        }
+
+        let row = rows.and_then(|rows| rows.get(i).copied());
+        let mut trip = schema.parse_fields(|position| record.get(position), row)?;
+        trip.source_file.get_or_insert(&source);
+        process_trip(&trip)?;
+        read += 1;
+    }
+
+    Ok(read)
+}
+
+// Lee hasta `limit` viajes a partir de la fila de datos `skip`, por ejemplo una
+// página de resultados; las filas inválidas se omiten
+pub fn read_trips_range<P: AsRef<Path>>(
+    filename: P,
+    skip: usize,
+    limit: usize,
+) -> Result<Vec<Trip>, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(BufReader::new(file));
+    let schema = TripSchema::from_header(csv_reader.headers()?)?;
+
+    // Los avisos van a stderr como en stream_process_csv: stdout es la salida de la CLI
+    let mut trips = Vec::with_capacity(limit);
+    for (row, result) in csv_reader.records().enumerate().skip(skip).take(limit) {
+        let record = match result {
+            Ok(record) => record,
             Err(e) => {
                 eprintln!("Error al leer registro: {}", e);
+                continue;
+            }
+        };
+        match schema.parse(&record, Some(row as u64)) {
+            Ok(trip) => trips.push(trip),
+            Err(e) => {
+                let line = record.position().map_or(0, |p| p.line());
+                eprintln!("Registro inválido en línea {}: {}", line, e);
             }
         }
     }
 
-    Ok(())
+    Ok(trips)
+}
+
+// Estima las filas del CSV a partir de una muestra del inicio del archivo
+pub fn estimate_row_count<P: AsRef<Path>>(filename: P) -> Result<usize, Box<dyn Error>> {
+    const SAMPLE_SIZE: u64 = 1024 * 1024;
+
+    let file = File::open(filename)?;
+    let size = file.metadata()?.len();
+
+    let mut sample = Vec::new();
+    file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    let lines = sample.iter().filter(|b| **b == b'\n').count();
+
+    // Si la muestra es todo el archivo el conteo es exacto (sin el encabezado)
+    if sample.len() as u64 >= size {
+        let rows = if sample.ends_with(b"\n") {
+            lines
+        } else {
+            lines + 1
+        };
+        return Ok(rows.saturating_sub(1));
+    }
+    if lines == 0 {
+        return Ok(0);
+    }
+
+    let avg_len = sample.len() as f64 / lines as f64;
+    Ok((size as f64 / avg_len) as usize)
 }
@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Extensiones de los archivos que se toman de un directorio
+const DATASET_EXTENSIONS: [&str; 2] = ["csv", "parquet"];
+
+// Conjunto de CSV o Parquet que se consulta como una sola tabla (por ejemplo un
+// yellow_tripdata_AAAA-MM.csv por mes). Los archivos se recorren en orden alfabético
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    files: Vec<PathBuf>,
+}
+
+impl Dataset {
+    // Acepta un archivo, un directorio (todos sus .csv y .parquet) o un patrón con * y ? en el
+    // nombre del archivo, como datos/yellow_tripdata_2020-*.csv
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+
+        let mut files = if path.is_dir() {
+            list_files(path, |name| {
+                Path::new(name).extension().is_some_and(|ext| {
+                    DATASET_EXTENSIONS
+                        .iter()
+                        .any(|dataset_ext| ext.eq_ignore_ascii_case(dataset_ext))
+                })
+            })?
+        } else if is_pattern(path) {
+            let pattern = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let dir = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            if is_pattern(dir) {
+                return Err(format!(
+                    "Solo se admiten comodines en el nombre del archivo: {}",
+                    path.display()
+                )
+                .into());
+            }
+            list_files(dir, |name| matches_pattern(&pattern, name))?
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        if files.is_empty() {
+            return Err(format!(
+                "No se encontraron archivos CSV ni Parquet en {}",
+                path.display()
+            )
+            .into());
+        }
+        files.sort();
+
+        Ok(Self { files })
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn is_multi_file(&self) -> bool {
+        self.files.len() > 1
+    }
+}
+
+// Nombre con el que se registra el archivo de origen de cada viaje
+pub fn source_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+        .into_owned()
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?'])
+}
+
+// Archivos (no directorios) del directorio cuyo nombre cumple `keep`
+fn list_files<F: Fn(&str) -> bool>(dir: &Path, keep: F) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("No se pudo leer el directorio {}: {}", dir.display(), e))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if entry.file_type()?.is_file() && keep(&name.to_string_lossy()) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+// `*` equivale a cualquier secuencia de caracteres y `?` a uno solo
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Última estrella vista y posición del nombre donde se intentó
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // La estrella absorbe un carácter más y se reintenta
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::TestDir;
+    use super::*;
+
+    fn names(dataset: &Dataset) -> Vec<String> {
+        dataset
+            .files()
+            .iter()
+            .map(|file| source_name(file))
+            .collect()
+    }
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(matches_pattern("yellow_*.csv", "yellow_2020-01.csv"));
+        assert!(matches_pattern("yellow_*.csv", "yellow_.csv"));
+        assert!(matches_pattern("*", "datos.parquet"));
+        assert!(matches_pattern("*-0?.csv", "yellow_2020-01.csv"));
+        assert!(matches_pattern("a*b*c", "a-b-b-c"));
+        assert!(matches_pattern("**.csv", "x.csv"));
+        assert!(!matches_pattern("*-0?.csv", "yellow_2020-10.csv"));
+        assert!(!matches_pattern("?.csv", ".csv"));
+        assert!(!matches_pattern("yellow_*.csv", "yellow_2020-01.csv.bak"));
+        assert!(!matches_pattern("a*b*c", "a-b-b-cd"));
+        assert!(matches_pattern("día_?.csv", "día_1.csv"));
+    }
+
+    #[test]
+    fn open_lists_files_in_sorted_order() {
+        let dir = TestDir::new("dataset");
+        for name in [
+            "yellow_2020-10.csv",
+            "yellow_2020-02.csv",
+            "yellow_2020-01.parquet",
+            "yellow_2019-12.CSV",
+            "notas.txt",
+        ] {
+            dir.write(name, "");
+        }
+        fs::create_dir(dir.join("viejos.csv")).unwrap();
+
+        // Un directorio toma sus CSV y Parquet, sin subdirectorios
+        let all = Dataset::open(dir.join("")).unwrap();
+        assert_eq!(
+            names(&all),
+            [
+                "yellow_2019-12.CSV",
+                "yellow_2020-01.parquet",
+                "yellow_2020-02.csv",
+                "yellow_2020-10.csv"
+            ]
+        );
+        assert!(all.is_multi_file());
+
+        let star = Dataset::open(dir.join("yellow_2020-*.csv")).unwrap();
+        assert_eq!(names(&star), ["yellow_2020-02.csv", "yellow_2020-10.csv"]);
+        let question = Dataset::open(dir.join("yellow_2020-0?.*")).unwrap();
+        assert_eq!(
+            names(&question),
+            ["yellow_2020-01.parquet", "yellow_2020-02.csv"]
+        );
+
+        // Un archivo se usa tal cual, aunque no exista todavía
+        let single = Dataset::open(dir.join("otro.csv")).unwrap();
+        assert_eq!(single.files(), [dir.join("otro.csv")]);
+        assert!(!single.is_multi_file());
+    }
+
+    #[test]
+    fn open_reports_bad_patterns() {
+        let dir = TestDir::new("dataset-errors");
+        dir.write("notas.txt", "");
+
+        let error = Dataset::open(dir.join("*.csv")).unwrap_err().to_string();
+        assert!(
+            error.starts_with("No se encontraron archivos CSV ni Parquet en "),
+            "{}",
+            error
+        );
+        assert!(Dataset::open(dir.join("")).is_err());
+
+        let error = Dataset::open(dir.join("2020-*").join("*.csv"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("Solo se admiten comodines en el nombre del archivo"),
+            "{}",
+            error
+        );
+
+        let error = Dataset::open(dir.join("falta").join("*.csv"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("No se pudo leer el directorio"),
+            "{}",
+            error
+        );
+    }
+}
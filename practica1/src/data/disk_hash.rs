@@ -1,180 +1,1598 @@
-use super::trip_struct::Trip;
+use super::data_lector::{ParseReport, RowLocation, read_trip_at};
+use super::trip_struct::{PaymentType, RateCode, Trip};
+use chrono::{DateTime, NaiveDateTime};
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::hash::{Hash, Hasher};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::{self, File, OpenOptions, TryLockError, create_dir_all};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-// Número de buckets para la hash table
-const NUM_BUCKETS: usize = 256;
+// Número de buckets por defecto cuando no se conoce el tamaño del CSV
+const DEFAULT_BUCKETS: usize = 256;
 
-// Estructura para entradas de hash table
-#[derive(Serialize, Deserialize)]
-struct HashEntry {
-    key: String,
-    trip: Trip,
+// Límites para el número inicial de buckets elegido según las filas del CSV
+const MIN_INITIAL_BUCKETS: usize = 16;
+const MAX_INITIAL_BUCKETS: usize = 65536;
+
+// Entradas promedio por bucket a partir de las cuales se divide un bucket
+pub const DEFAULT_MAX_LOAD_FACTOR: f64 = 1024.0;
+
+// Cada registro del bucket es: [largo clave u32][largo datos u32][clave][datos].
+// Un registro sin datos es una lápida que marca la clave como eliminada
+const RECORD_HEADER_LEN: usize = 8;
+
+// Filas agrupadas por escritura al construir el índice
+const BUILD_BATCH_SIZE: usize = 10_000;
+
+// Archivo con la cabecera del índice (modo y origen de los datos)
+const HEADER_FILE: &str = "header.json";
+
+// Bloqueo por operación: compartido al leer y exclusivo al escribir
+const LOCK_FILE: &str = "index.lock";
+// Bloqueo que mantiene el único proceso que puede modificar el índice
+const WRITER_LOCK_FILE: &str = "writer.lock";
+
+// Función de hash usada para ubicar claves en buckets. Debe ser estable entre
+// versiones de Rust; cambiarla obliga a subir INDEX_FORMAT_VERSION
+const HASH_ALGORITHM: &str = "fnv1a-64";
+const INDEX_FORMAT_VERSION: u32 = 3;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Bloques muestreados del CSV para calcular su checksum
+const CHECKSUM_BLOCKS: u64 = 16;
+const CHECKSUM_BLOCK_SIZE: u64 = 64 * 1024;
+
+// Qué se guarda en cada entrada del índice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexMode {
+    // Copia completa del viaje dentro del bucket
+    FullCopy,
+    // Solo la posición en bytes de la fila dentro del CSV original
+    Offsets,
+}
+
+// Parámetros con los que se construye un índice
+#[derive(Debug, Clone, Copy)]
+pub struct IndexConfig {
+    pub mode: IndexMode,
+    // None: se elige a partir del número estimado de filas del CSV
+    pub initial_buckets: Option<usize>,
+    pub max_load_factor: f64,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            mode: IndexMode::Offsets,
+            initial_buckets: None,
+            max_load_factor: DEFAULT_MAX_LOAD_FACTOR,
+        }
+    }
+}
+
+// Elige los buckets iniciales para que el índice arranque a media carga
+fn initial_buckets_for_rows(rows: usize, max_load_factor: f64) -> usize {
+    let target_per_bucket = (max_load_factor / 2.0).max(1.0);
+    let wanted = (rows as f64 / target_per_bucket).ceil() as usize;
+    wanted
+        .next_power_of_two()
+        .clamp(MIN_INITIAL_BUCKETS, MAX_INITIAL_BUCKETS)
+}
+
+// Huella del CSV usado para construir un índice por posiciones
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFingerprint {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+    pub checksum: u64,
+}
+
+impl SourceFingerprint {
+    // Calcula tamaño, fecha de modificación y checksum de un CSV
+    pub fn compute<P: AsRef<Path>>(csv_path: P) -> Result<Self, Box<dyn Error>> {
+        let path = fs::canonicalize(csv_path)?;
+        let (size, modified_secs, modified_nanos) = file_stamp(&path)?;
+        let checksum = sampled_checksum(&path, size)?;
+
+        Ok(Self {
+            path,
+            size,
+            modified_secs,
+            modified_nanos,
+            checksum,
+        })
+    }
+
+    // Chequeo rápido sin checksum: el archivo conserva tamaño y fecha de modificación
+    pub fn is_current(&self) -> Result<bool, Box<dyn Error>> {
+        let (size, secs, nanos) = file_stamp(&self.path)?;
+        Ok(size == self.size && secs == self.modified_secs && nanos == self.modified_nanos)
+    }
+}
+
+// Tamaño y fecha de modificación de un archivo
+fn file_stamp(path: &Path) -> Result<(u64, u64, u32), Box<dyn Error>> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+// FNV-1a de 64 bits: definido por especificación, no cambia entre compiladores
+fn fnv1a_update(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// FNV-1a de 64 bits sobre bloques repartidos por todo el archivo,
+// así no hay que leer los varios GB del CSV cada vez que se abre el índice
+fn sampled_checksum(path: &Path, size: u64) -> Result<u64, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut block = vec![0u8; CHECKSUM_BLOCK_SIZE as usize];
+
+    let step = (size / CHECKSUM_BLOCKS).max(CHECKSUM_BLOCK_SIZE);
+    let mut offset = 0;
+    while offset < size {
+        file.seek(SeekFrom::Start(offset))?;
+        let len = CHECKSUM_BLOCK_SIZE.min(size - offset) as usize;
+        file.read_exact(&mut block[..len])?;
+        hash = fnv1a_update(hash, &block[..len]);
+        offset += step;
+    }
+
+    // Incluir siempre el final del archivo, donde se agregan filas nuevas
+    if size > CHECKSUM_BLOCK_SIZE {
+        file.seek(SeekFrom::Start(size - CHECKSUM_BLOCK_SIZE))?;
+        file.read_exact(&mut block)?;
+        hash = fnv1a_update(hash, &block);
+    }
+
+    Ok(hash)
+}
+
+// Cabecera guardada junto a los buckets. Los buckets crecen con hashing lineal:
+// hay initial_buckets * 2^level buckets más los ya divididos en la ronda actual
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexHeader {
+    // Los índices anteriores no tienen estos campos y quedan vacíos al leerlos
+    #[serde(default)]
+    hash_algorithm: String,
+    #[serde(default)]
+    format_version: u32,
+    mode: IndexMode,
+    source: Option<SourceFingerprint>,
+    initial_buckets: usize,
+    level: u32,
+    split_pointer: usize,
+    entries: u64,
+    max_load_factor: f64,
+}
+
+impl IndexHeader {
+    fn new(
+        mode: IndexMode,
+        source: Option<SourceFingerprint>,
+        initial_buckets: usize,
+        max_load_factor: f64,
+    ) -> Self {
+        Self {
+            hash_algorithm: HASH_ALGORITHM.to_string(),
+            format_version: INDEX_FORMAT_VERSION,
+            mode,
+            source,
+            initial_buckets,
+            level: 0,
+            split_pointer: 0,
+            entries: 0,
+            max_load_factor,
+        }
+    }
+
+    // Buckets de la ronda actual antes de dividir
+    fn round_size(&self) -> usize {
+        self.initial_buckets << self.level
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.round_size() + self.split_pointer
+    }
+
+    // Bucket de un hash según el nivel y el puntero de división actuales
+    fn bucket_for_hash(&self, hash: u64) -> usize {
+        let bucket = (hash % self.round_size() as u64) as usize;
+        if bucket < self.split_pointer {
+            (hash % (self.round_size() as u64 * 2)) as usize
+        } else {
+            bucket
+        }
+    }
+
+    fn bucket_for_key(&self, key: &[u8]) -> usize {
+        self.bucket_for_hash(calculate_hash(key))
+    }
+
+    fn load_factor(&self) -> f64 {
+        self.entries as f64 / self.bucket_count() as f64
+    }
+
+    fn read(dir: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = dir.join(HEADER_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        let header: Self = serde_json::from_reader(reader)?;
+        header.check_compatible()?;
+        Ok(Some(header))
+    }
+
+    // Rechaza índices construidos con otra función de hash o formato
+    fn check_compatible(&self) -> Result<(), Box<dyn Error>> {
+        if self.hash_algorithm != HASH_ALGORITHM || self.format_version != INDEX_FORMAT_VERSION {
+            return Err(format!(
+                "Índice incompatible: hash '{}' versión {} (se esperaba '{}' versión {})",
+                self.hash_algorithm, self.format_version, HASH_ALGORITHM, INDEX_FORMAT_VERSION
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // Se escribe a un temporal y se renombra para no dejar una cabecera a medias
+    fn write(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let path = dir.join(HEADER_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
 }
 
 // Calcula el hash para una clave
-fn calculate_hash(key: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    key.hash(&mut hasher);
-    hasher.finish()
+pub fn calculate_hash(key: &[u8]) -> u64 {
+    fnv1a_update(FNV_OFFSET_BASIS, key)
 }
 
-// Determina el bucket para una clave
-fn get_bucket_index(key: &str) -> usize {
-    (calculate_hash(key) % NUM_BUCKETS as u64) as usize
+// Nombre del archivo binario de un bucket
+fn bucket_file_name(bucket_idx: usize) -> String {
+    format!("bucket_{}.bin", bucket_idx)
 }
 
-// Implementación de hash table basada en disco
+// Codificación binaria compacta de un viaje (little endian)
+fn encode_trip(trip: &Trip, buf: &mut Vec<u8>) {
+    fn put_str(buf: &mut Vec<u8>, value: &str) {
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+    // Los valores desconocidos llevan antes un byte 0 y los conocidos un 1
+    fn put_option<T>(buf: &mut Vec<u8>, value: Option<T>, put: impl FnOnce(&mut Vec<u8>, T)) {
+        match value {
+            Some(value) => {
+                buf.push(1);
+                put(buf, value);
+            }
+            None => buf.push(0),
+        }
+    }
+    fn put_datetime(buf: &mut Vec<u8>, value: &NaiveDateTime) {
+        let utc = value.and_utc();
+        buf.extend_from_slice(&utc.timestamp().to_le_bytes());
+        buf.extend_from_slice(&utc.timestamp_subsec_nanos().to_le_bytes());
+    }
+
+    put_str(buf, &trip.vendor_id);
+    put_datetime(buf, &trip.tpep_pickup_datetime);
+    put_datetime(buf, &trip.tpep_dropoff_datetime);
+    put_option(buf, trip.passenger_count, |buf, passengers| {
+        buf.push(passengers)
+    });
+    buf.extend_from_slice(&trip.trip_distance.to_le_bytes());
+    put_option(buf, trip.ratecode_id, |buf, code| buf.push(code.code()));
+    put_option(buf, trip.store_and_fwd_flag, |buf, flag| {
+        buf.push(flag as u8)
+    });
+    buf.extend_from_slice(&trip.pu_location_id.to_le_bytes());
+    buf.extend_from_slice(&trip.do_location_id.to_le_bytes());
+    put_option(buf, trip.payment_type, |buf, payment| {
+        buf.push(payment.code())
+    });
+    for amount in [
+        trip.fare_amount,
+        trip.extra,
+        trip.mta_tax,
+        trip.tip_amount,
+        trip.tolls_amount,
+        trip.improvement_surcharge,
+        trip.total_amount,
+    ] {
+        buf.extend_from_slice(&amount.to_le_bytes());
+    }
+    put_option(buf, trip.congestion_surcharge, |buf, surcharge| {
+        buf.extend_from_slice(&surcharge.to_le_bytes())
+    });
+    put_str(buf, &trip.index);
+}
+
+// Lector secuencial sobre los bytes de un viaje codificado
+struct ByteCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err("Registro binario truncado".into());
+        }
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, Box<dyn Error>> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, Box<dyn Error>> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    // Valor precedido por el byte que indica si es conocido (ver encode_trip)
+    fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            _ => Err("Marca de valor opcional inválida".into()),
+        }
+    }
+
+    fn datetime(&mut self) -> Result<NaiveDateTime, Box<dyn Error>> {
+        let secs = self.i64()?;
+        let nanos = self.u32()?;
+        DateTime::from_timestamp(secs, nanos)
+            .map(|dt| dt.naive_utc())
+            .ok_or_else(|| "Fecha inválida en registro binario".into())
+    }
+}
+
+fn encode_location(location: RowLocation, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&location.offset.to_le_bytes());
+    buf.extend_from_slice(&location.len.to_le_bytes());
+}
+
+fn decode_location(data: &[u8]) -> Result<RowLocation, Box<dyn Error>> {
+    if data.len() != 16 {
+        return Err("Registro de posición con tamaño inválido".into());
+    }
+    Ok(RowLocation {
+        offset: u64::from_le_bytes(data[0..8].try_into()?),
+        len: u64::from_le_bytes(data[8..16].try_into()?),
+    })
+}
+
+fn decode_trip(data: &[u8]) -> Result<Trip, Box<dyn Error>> {
+    let mut cursor = ByteCursor { data, pos: 0 };
+
+    Ok(Trip {
+        vendor_id: cursor.string()?,
+        tpep_pickup_datetime: cursor.datetime()?,
+        tpep_dropoff_datetime: cursor.datetime()?,
+        passenger_count: cursor.option(ByteCursor::u8)?,
+        trip_distance: cursor.f64()?,
+        ratecode_id: cursor.option(|cursor| {
+            Ok(RateCode::from_code(cursor.u8()?).ok_or("Código de tarifa inválido")?)
+        })?,
+        store_and_fwd_flag: cursor.option(|cursor| Ok(cursor.u8()? != 0))?,
+        pu_location_id: cursor.u16()?,
+        do_location_id: cursor.u16()?,
+        payment_type: cursor.option(|cursor| {
+            Ok(PaymentType::from_code(cursor.u8()?).ok_or("Tipo de pago inválido")?)
+        })?,
+        fare_amount: cursor.f64()?,
+        extra: cursor.f64()?,
+        mta_tax: cursor.f64()?,
+        tip_amount: cursor.f64()?,
+        tolls_amount: cursor.f64()?,
+        improvement_surcharge: cursor.f64()?,
+        total_amount: cursor.f64()?,
+        congestion_surcharge: cursor.option(ByteCursor::f64)?,
+        index: cursor.string()?,
+        source_file: None,
+    })
+}
+
+// Registro de un bucket: (clave, datos)
+type BucketRecord = (Vec<u8>, Vec<u8>);
+
+// Serializa un registro completo del bucket
+fn encode_record(key: &[u8], data: &[u8], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(data);
+}
+
+// Deja solo la versión más reciente de cada clave, sin las eliminadas ni las que
+// no pertenecen al bucket (restos de una división interrumpida)
+fn live_records(
+    records: Vec<BucketRecord>,
+    header: &IndexHeader,
+    bucket_idx: usize,
+) -> Vec<BucketRecord> {
+    let mut seen = HashSet::new();
+    let mut latest: Vec<BucketRecord> = records
+        .into_iter()
+        .rev()
+        .filter(|(key, _)| header.bucket_for_key(key) == bucket_idx)
+        .filter(|(key, _)| seen.insert(key.clone()))
+        .filter(|(_, data)| !data.is_empty())
+        .collect();
+    latest.reverse();
+    latest
+}
+
+// Recorre los registros de un bucket. Un registro incompleto al final del archivo
+// (una escritura interrumpida) se ignora
+struct BucketScanner {
+    reader: BufReader<File>,
+    file_len: u64,
+    position: u64,
+    unread: u64,
+}
+
+impl BucketScanner {
+    // None si el bucket no existe
+    fn open(path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let file_len = file.metadata()?.len();
+
+        Ok(Some(Self {
+            reader: BufReader::new(file),
+            file_len,
+            position: 0,
+            unread: 0,
+        }))
+    }
+
+    // Lee la clave del siguiente registro completo y devuelve el largo de sus datos
+    fn next_key(&mut self, key: &mut Vec<u8>) -> Result<Option<usize>, Box<dyn Error>> {
+        if self.unread > 0 {
+            self.reader.seek_relative(self.unread as i64)?;
+            self.unread = 0;
+        }
+        if self.position + RECORD_HEADER_LEN as u64 > self.file_len {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        self.reader.read_exact(&mut header)?;
+        let key_len = u32::from_le_bytes(header[0..4].try_into()?) as usize;
+        let data_len = u32::from_le_bytes(header[4..8].try_into()?) as usize;
+
+        let end = self.position + (RECORD_HEADER_LEN + key_len + data_len) as u64;
+        if end > self.file_len {
+            return Ok(None);
+        }
+
+        key.resize(key_len, 0);
+        self.reader.read_exact(key)?;
+        self.position = end;
+        self.unread = data_len as u64;
+        Ok(Some(data_len))
+    }
+
+    // Posición de los datos del último registro leído
+    fn data_offset(&self) -> u64 {
+        self.position - self.unread
+    }
+
+    // Lee los datos del último registro leído
+    fn read_data(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; self.unread as usize];
+        self.reader.read_exact(&mut data)?;
+        self.unread = 0;
+        Ok(data)
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; len];
+        self.reader.read_exact(&mut data)?;
+        self.position = offset + len as u64;
+        self.unread = 0;
+        Ok(data)
+    }
+}
+
+fn open_lock_file(dir: &Path, name: &str) -> Result<File, Box<dyn Error>> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(name))?)
+}
+
+// Los bloqueos se liberan al soltar el archivo devuelto
+fn lock_shared(dir: &Path) -> Result<File, Box<dyn Error>> {
+    let file = open_lock_file(dir, LOCK_FILE)?;
+    file.lock_shared()?;
+    Ok(file)
+}
+
+fn lock_exclusive(dir: &Path) -> Result<File, Box<dyn Error>> {
+    let file = open_lock_file(dir, LOCK_FILE)?;
+    file.lock()?;
+    Ok(file)
+}
+
+// Solo un proceso a la vez puede abrir el índice para escritura
+fn acquire_writer(dir: &Path) -> Result<File, Box<dyn Error>> {
+    let file = open_lock_file(dir, WRITER_LOCK_FILE)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "Otro proceso está escribiendo el índice en {}",
+            dir.display()
+        )
+        .into()),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+// Convierte los datos guardados en un viaje según el modo del índice. La clave es
+// el index del viaje, que en un CSV sin esa columna es su número de fila
+fn decode_payload(header: &IndexHeader, key: &str, data: &[u8]) -> Result<Trip, Box<dyn Error>> {
+    match header.mode {
+        IndexMode::FullCopy => decode_trip(data),
+        IndexMode::Offsets => {
+            let source = header
+                .source
+                .as_ref()
+                .ok_or("El índice no registra el CSV de origen")?;
+
+            // Chequeo rápido: tamaño y fecha deben coincidir antes de leer la fila
+            if !source.is_current()? {
+                return Err("El CSV cambió desde que se construyó el índice".into());
+            }
+
+            read_trip_at(&source.path, decode_location(data)?, key.parse().ok())
+        }
+    }
+}
+
+// Implementación de hash table basada en disco. Varios procesos pueden leerla a
+// la vez; solo el que la abrió para escritura (new o create) puede modificarla
 pub struct DiskHashTable {
     bucket_dir: PathBuf,
+    header: IndexHeader,
+    // Bloqueo de escritor; None si la tabla es solo de lectura
+    writer: Option<File>,
 }
 
 impl DiskHashTable {
-    // Crear nueva hash table
+    // Crear nueva hash table (o abrir una existente con su cabecera) para escritura
     pub fn new<P: AsRef<Path>>(dir_path: P) -> Result<Self, Box<dyn Error>> {
         let bucket_dir = dir_path.as_ref().to_path_buf();
         create_dir_all(&bucket_dir)?;
+        let writer = acquire_writer(&bucket_dir)?;
+        let _lock = lock_exclusive(&bucket_dir)?;
+
+        let header = match IndexHeader::read(&bucket_dir)? {
+            Some(header) => header,
+            None => {
+                let header = IndexHeader::new(
+                    IndexMode::FullCopy,
+                    None,
+                    DEFAULT_BUCKETS,
+                    DEFAULT_MAX_LOAD_FACTOR,
+                );
+                header.write(&bucket_dir)?;
+                header
+            }
+        };
+
+        Self::init_buckets(&bucket_dir, header.bucket_count())?;
+
+        let table = Self {
+            bucket_dir,
+            header,
+            writer: Some(writer),
+        };
+        table.repair_buckets()?;
+        Ok(table)
+    }
+
+    // Abrir una hash table existente solo para lectura; None si no hay cabecera
+    pub fn open<P: AsRef<Path>>(dir_path: P) -> Result<Option<Self>, Box<dyn Error>> {
+        let bucket_dir = dir_path.as_ref().to_path_buf();
+        if !bucket_dir.join(HEADER_FILE).exists() {
+            return Ok(None);
+        }
+
+        let _lock = lock_shared(&bucket_dir)?;
+        Ok(IndexHeader::read(&bucket_dir)?.map(|header| Self {
+            bucket_dir,
+            header,
+            writer: None,
+        }))
+    }
 
-        // Inicializa buckets vacíos
-        for i in 0..NUM_BUCKETS {
-            let bucket_path = bucket_dir.join(format!("bucket_{}.json", i));
+    // Crear una hash table vacía para el CSV indicado, registrando su huella.
+    // Si ya había un índice en el directorio se reemplaza
+    pub fn create<P: AsRef<Path>, Q: AsRef<Path>>(
+        dir_path: P,
+        csv_path: Q,
+        config: IndexConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        let bucket_dir = dir_path.as_ref().to_path_buf();
+        create_dir_all(&bucket_dir)?;
+        let writer = acquire_writer(&bucket_dir)?;
+
+        let initial_buckets = match config.initial_buckets {
+            Some(buckets) => buckets.max(1),
+            None => {
+                let rows = super::data_lector::estimate_row_count(&csv_path)?;
+                initial_buckets_for_rows(rows, config.max_load_factor)
+            }
+        };
+
+        let header = IndexHeader::new(
+            config.mode,
+            Some(SourceFingerprint::compute(csv_path)?),
+            initial_buckets,
+            config.max_load_factor,
+        );
+
+        // Los lectores esperan mientras se borra el índice anterior
+        let _lock = lock_exclusive(&bucket_dir)?;
+        Self::clear_dir(&bucket_dir)?;
+        header.write(&bucket_dir)?;
+        Self::init_buckets(&bucket_dir, header.bucket_count())?;
+
+        Ok(Self {
+            bucket_dir,
+            header,
+            writer: Some(writer),
+        })
+    }
+
+    // Borra todo lo del directorio menos los archivos de bloqueo
+    fn clear_dir(bucket_dir: &Path) -> Result<(), Box<dyn Error>> {
+        for entry in fs::read_dir(bucket_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name == LOCK_FILE || name == WRITER_LOCK_FILE {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Inicializa buckets vacíos
+    fn init_buckets(bucket_dir: &Path, bucket_count: usize) -> Result<(), Box<dyn Error>> {
+        for i in 0..bucket_count {
+            let bucket_path = bucket_dir.join(bucket_file_name(i));
             if !bucket_path.exists() {
                 File::create(bucket_path)?;
             }
         }
+        Ok(())
+    }
 
-        Ok(Self { bucket_dir })
+    fn bucket_path(&self, bucket_idx: usize) -> PathBuf {
+        self.bucket_dir.join(bucket_file_name(bucket_idx))
     }
 
-    // Insertar en la hash table
-    pub fn insert(&self, key: String, trip: Trip) -> Result<(), Box<dyn Error>> {
-        let bucket_idx = get_bucket_index(&key);
-        let bucket_path = self.bucket_dir.join(format!("bucket_{}.json", bucket_idx));
+    pub fn mode(&self) -> IndexMode {
+        self.header.mode
+    }
 
-        // Crear archivo si no existe
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&bucket_path)?;
+    pub fn bucket_count(&self) -> usize {
+        self.header.bucket_count()
+    }
 
-        let mut contents = String::new();
-        let mut reader = BufReader::new(&file);
-        reader.read_to_string(&mut contents)?;
+    // Registros guardados (incluye versiones anteriores de claves actualizadas)
+    pub fn stored_records(&self) -> u64 {
+        self.header.entries
+    }
 
-        // Cargar entradas existentes
-        let mut entries: Vec<HashEntry> = if contents.is_empty() {
-            Vec::new()
-        } else {
-            serde_json::from_str(&contents)?
+    // Guarda la cabecera con los contadores actuales
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        self.check_writer()?;
+        let _lock = lock_exclusive(&self.bucket_dir)?;
+        self.header.write(&self.bucket_dir)
+    }
+
+    fn check_writer(&self) -> Result<(), Box<dyn Error>> {
+        if self.writer.is_none() {
+            return Err("El índice se abrió solo para lectura".into());
+        }
+        Ok(())
+    }
+
+    // Cabecera vigente: el escritor tiene la suya en memoria y los lectores la
+    // releen porque otro proceso pudo haber modificado el índice
+    fn current_header(&self) -> Result<Cow<'_, IndexHeader>, Box<dyn Error>> {
+        if self.writer.is_some() {
+            return Ok(Cow::Borrowed(&self.header));
+        }
+        match IndexHeader::read(&self.bucket_dir)? {
+            Some(header) => Ok(Cow::Owned(header)),
+            None => Err("El índice fue eliminado".into()),
+        }
+    }
+
+    // Recorta registros incompletos que dejó una escritura interrumpida,
+    // para que los siguientes se agreguen sobre un final válido
+    fn repair_buckets(&self) -> Result<(), Box<dyn Error>> {
+        let mut key = Vec::new();
+        for i in 0..self.header.bucket_count() {
+            let path = self.bucket_path(i);
+            let Some(mut scanner) = BucketScanner::open(&path)? else {
+                continue;
+            };
+            while scanner.next_key(&mut key)?.is_some() {}
+
+            if scanner.position < scanner.file_len {
+                eprintln!("Bucket {} con un registro incompleto, se recorta", i);
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)?
+                    .set_len(scanner.position)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Verifica que el CSV sea el mismo y no haya cambiado desde que se construyó el índice
+    pub fn validate_source<P: AsRef<Path>>(&self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        let Some(source) = &self.header.source else {
+            return Err("El índice no registra el CSV de origen".into());
         };
 
-        // Buscar si la clave ya existe
-        let entry_idx = entries.iter().position(|e| e.key == key);
+        let current = SourceFingerprint::compute(csv_path)?;
+        if current.path != source.path {
+            return Err(format!(
+                "El índice fue construido para {} y no para {}",
+                source.path.display(),
+                current.path.display()
+            )
+            .into());
+        }
+        if current != *source {
+            return Err("El CSV cambió desde que se construyó el índice".into());
+        }
+
+        Ok(())
+    }
 
-        // Crear nueva entrada o actualizar existente
-        let entry = HashEntry { key, trip };
+    // Agrega registros al final de sus buckets con una sola escritura por bucket,
+    // y divide buckets si la carga supera el máximo
+    fn append_records(&mut self, records: &[(String, Vec<u8>)]) -> Result<(), Box<dyn Error>> {
+        self.check_writer()?;
+        let _lock = lock_exclusive(&self.bucket_dir)?;
 
-        if let Some(idx) = entry_idx {
-            entries[idx] = entry;
-        } else {
-            entries.push(entry);
+        let mut per_bucket: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for (key, payload) in records {
+            let buf = per_bucket
+                .entry(self.header.bucket_for_key(key.as_bytes()))
+                .or_default();
+            encode_record(key.as_bytes(), payload, buf);
         }
 
-        // Guardar actualizaciones
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&bucket_path)?;
+        for (bucket_idx, buf) in per_bucket {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.bucket_path(bucket_idx))?;
+            file.write_all(&buf)?;
+        }
 
-        let mut writer = BufWriter::new(file);
-        serde_json::to_writer(&mut writer, &entries)?;
+        self.header.entries += records.len() as u64;
+        while self.header.load_factor() > self.header.max_load_factor {
+            self.split_next_bucket()?;
+        }
+
+        Ok(())
+    }
+
+    // Lee todos los registros de un bucket en orden de escritura
+    fn read_bucket(&self, bucket_idx: usize) -> Result<Vec<BucketRecord>, Box<dyn Error>> {
+        let Some(mut scanner) = BucketScanner::open(&self.bucket_path(bucket_idx))? else {
+            return Ok(Vec::new());
+        };
+
+        let mut records = Vec::new();
+        let mut key = Vec::new();
+        while scanner.next_key(&mut key)?.is_some() {
+            records.push((key.clone(), scanner.read_data()?));
+        }
+
+        Ok(records)
+    }
+
+    // Reescribe un bucket completo a través de un archivo temporal
+    fn write_bucket(
+        &self,
+        bucket_idx: usize,
+        records: &[BucketRecord],
+    ) -> Result<(), Box<dyn Error>> {
+        let bucket_path = self.bucket_path(bucket_idx);
+        let tmp_path = bucket_path.with_extension("bin.tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let mut buf = Vec::new();
+        for (key, data) in records {
+            buf.clear();
+            encode_record(key, data, &mut buf);
+            writer.write_all(&buf)?;
+        }
         writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
 
+        fs::rename(&tmp_path, &bucket_path)?;
         Ok(())
     }
 
-    // Obtener de la hash table
-    pub fn get(&self, key: &str) -> Result<Option<Trip>, Box<dyn Error>> {
-        let bucket_idx = get_bucket_index(key);
-        let bucket_path = self.bucket_dir.join(format!("bucket_{}.json", bucket_idx));
+    // Hashing lineal: divide el bucket apuntado por split_pointer en dos
+    fn split_next_bucket(&mut self) -> Result<(), Box<dyn Error>> {
+        let old_idx = self.header.split_pointer;
+        let new_idx = old_idx + self.header.round_size();
+        let modulus = self.header.round_size() as u64 * 2;
 
-        if !bucket_path.exists() {
-            return Ok(None);
+        // Al dividir se descartan las versiones viejas y las claves eliminadas
+        let records = self.read_bucket(old_idx)?;
+        let total = records.len();
+        let latest = live_records(records, &self.header, old_idx);
+
+        let mut stay = Vec::new();
+        let mut moved = Vec::new();
+        for (key, data) in latest {
+            let hash = calculate_hash(&key);
+            if (hash % modulus) as usize == old_idx {
+                stay.push((key, data));
+            } else {
+                moved.push((key, data));
+            }
         }
 
-        let file = File::open(&bucket_path)?;
-        let mut reader = BufReader::new(file);
-        let mut contents = String::new();
-        reader.read_to_string(&mut contents)?;
+        // Orden seguro ante una caída: primero el bucket nuevo, luego la cabecera
+        // que lo activa y al final el bucket viejo. Si se corta antes de reescribir
+        // el viejo, sus registros movidos quedan sobrando y se ignoran al leer
+        let kept = (stay.len() + moved.len()) as u64;
+        self.write_bucket(new_idx, &moved)?;
 
-        if contents.is_empty() {
-            return Ok(None);
+        self.header.entries -= total as u64 - kept;
+        self.header.split_pointer += 1;
+        if self.header.split_pointer == self.header.round_size() {
+            self.header.level += 1;
+            self.header.split_pointer = 0;
+        }
+        self.header.write(&self.bucket_dir)?;
+
+        self.write_bucket(old_idx, &stay)?;
+
+        Ok(())
+    }
+
+    // Insertar en la hash table: se agrega el registro al final del bucket,
+    // si la clave ya existía el registro más reciente es el que vale
+    #[allow(dead_code)]
+    pub fn insert(&mut self, key: String, trip: Trip) -> Result<(), Box<dyn Error>> {
+        self.insert_many(std::iter::once((key, trip)))
+    }
+
+    // Insertar varios viajes agrupando las escrituras por bucket
+    pub fn insert_many<I>(&mut self, entries: I) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = (String, Trip)>,
+    {
+        if self.header.mode != IndexMode::FullCopy {
+            return Err("Este índice guarda posiciones, use insert_location".into());
+        }
+
+        let records: Vec<(String, Vec<u8>)> = entries
+            .into_iter()
+            .map(|(key, trip)| {
+                let mut payload = Vec::with_capacity(160);
+                encode_trip(&trip, &mut payload);
+                (key, payload)
+            })
+            .collect();
+        self.append_records(&records)
+    }
+
+    // Insertar la posición de una fila del CSV de origen
+    #[allow(dead_code)]
+    pub fn insert_location(
+        &mut self,
+        key: String,
+        location: RowLocation,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_many_locations(std::iter::once((key, location)))
+    }
+
+    // Insertar varias posiciones agrupando las escrituras por bucket
+    pub fn insert_many_locations<I>(&mut self, entries: I) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = (String, RowLocation)>,
+    {
+        if self.header.mode != IndexMode::Offsets {
+            return Err("Este índice guarda viajes completos, use insert".into());
+        }
+
+        let records: Vec<(String, Vec<u8>)> = entries
+            .into_iter()
+            .map(|(key, location)| {
+                let mut payload = Vec::with_capacity(16);
+                encode_location(location, &mut payload);
+                (key, payload)
+            })
+            .collect();
+        self.append_records(&records)
+    }
+
+    // Vacía el lote pendiente de la construcción según el modo del índice
+    fn insert_batch(
+        &mut self,
+        mode: IndexMode,
+        trips: &mut Vec<(String, Trip)>,
+        locations: &mut Vec<(String, RowLocation)>,
+    ) -> Result<(), Box<dyn Error>> {
+        match mode {
+            IndexMode::FullCopy => self.insert_many(trips.drain(..)),
+            IndexMode::Offsets => self.insert_many_locations(locations.drain(..)),
+        }
+    }
+
+    // Eliminar una clave; devuelve si existía
+    pub fn remove(&mut self, key: &str) -> Result<bool, Box<dyn Error>> {
+        if !self.contains(key)? {
+            return Ok(false);
         }
 
-        let entries: Vec<HashEntry> = serde_json::from_str(&contents)?;
+        self.append_records(&[(key.to_string(), Vec::new())])?;
+        Ok(true)
+    }
+
+    pub fn contains(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+        let _lock = lock_shared(&self.bucket_dir)?;
+        let header = self.current_header()?;
+        Ok(self.find_payload(&header, key)?.is_some())
+    }
+
+    // Recorre todas las entradas vigentes, un bucket a la vez. Usa la cabecera
+    // del momento en que empieza; no ve cambios hechos durante el recorrido
+    pub fn iter(&self) -> DiskHashIter<'_> {
+        DiskHashIter {
+            table: self,
+            header: None,
+            next_bucket: 0,
+            pending: Vec::new().into_iter(),
+        }
+    }
 
-        for entry in entries {
-            if entry.key == key {
-                return Ok(Some(entry.trip));
+    // Busca los datos de una clave: solo se leen las claves y los datos del registro encontrado
+    fn find_payload(
+        &self,
+        header: &IndexHeader,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let bucket_path = self.bucket_path(header.bucket_for_key(key.as_bytes()));
+        let Some(mut scanner) = BucketScanner::open(&bucket_path)? else {
+            return Ok(None);
+        };
+
+        let mut found: Option<(u64, usize)> = None;
+        let mut key_buf = Vec::new();
+        while let Some(data_len) = scanner.next_key(&mut key_buf)? {
+            if key_buf == key.as_bytes() {
+                found = Some((scanner.data_offset(), data_len));
             }
         }
 
-        Ok(None)
+        match found {
+            // Una lápida indica que la clave fue eliminada
+            Some((_, 0)) => Ok(None),
+            Some((offset, len)) => Ok(Some(scanner.read_at(offset, len)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Posición en el CSV de la fila con esta clave (solo en modo Offsets)
+    pub fn get_location(&self, key: &str) -> Result<Option<RowLocation>, Box<dyn Error>> {
+        let _lock = lock_shared(&self.bucket_dir)?;
+        let header = self.current_header()?;
+        if header.mode != IndexMode::Offsets {
+            return Err("Este índice guarda viajes completos, no posiciones".into());
+        }
+        if let Some(source) = &header.source
+            && !source.is_current()?
+        {
+            return Err("El CSV cambió desde que se construyó el índice".into());
+        }
+        match self.find_payload(&header, key)? {
+            Some(data) => Ok(Some(decode_location(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Obtener de la hash table
+    #[allow(dead_code)]
+    pub fn get(&self, key: &str) -> Result<Option<Trip>, Box<dyn Error>> {
+        let _lock = lock_shared(&self.bucket_dir)?;
+        let header = self.current_header()?;
+        match self.find_payload(&header, key)? {
+            Some(data) => Ok(Some(decode_payload(&header, key, &data)?)),
+            None => Ok(None),
+        }
     }
 
     // Contar el número total de entradas
+    #[allow(dead_code)]
     pub fn count_entries(&self) -> Result<usize, Box<dyn Error>> {
+        let _lock = lock_shared(&self.bucket_dir)?;
+        let header = self.current_header()?;
         let mut total_entries = 0;
 
-        for i in 0..NUM_BUCKETS {
-            let bucket_path = self.bucket_dir.join(format!("bucket_{}.json", i));
-
-            if !bucket_path.exists() {
+        for i in 0..header.bucket_count() {
+            let Some(mut scanner) = BucketScanner::open(&self.bucket_path(i))? else {
                 continue;
-            }
-
-            let file = File::open(&bucket_path)?;
-            let mut reader = BufReader::new(file);
-            let mut contents = String::new();
-            reader.read_to_string(&mut contents)?;
+            };
 
-            if contents.is_empty() {
-                continue;
+            // Las claves repetidas solo cuentan una vez y las eliminadas no cuentan
+            let mut keys: HashMap<Vec<u8>, bool> = HashMap::new();
+            let mut key_buf = Vec::new();
+            while let Some(data_len) = scanner.next_key(&mut key_buf)? {
+                if header.bucket_for_key(&key_buf) == i {
+                    keys.insert(key_buf.clone(), data_len > 0);
+                }
             }
 
-            let entries: Vec<HashEntry> = serde_json::from_str(&contents)?;
-            total_entries += entries.len();
+            total_entries += keys.values().filter(|live| **live).count();
         }
 
         Ok(total_entries)
     }
 }
 
+// Iterador sobre las entradas de una DiskHashTable
+pub struct DiskHashIter<'a> {
+    table: &'a DiskHashTable,
+    header: Option<IndexHeader>,
+    next_bucket: usize,
+    pending: std::vec::IntoIter<BucketRecord>,
+}
+
+impl DiskHashIter<'_> {
+    // Carga el siguiente bucket con bloqueo compartido
+    fn load_bucket(&mut self) -> Result<bool, Box<dyn Error>> {
+        // Tras un error el recorrido termina
+        if self.next_bucket == usize::MAX {
+            return Ok(false);
+        }
+        let _lock = lock_shared(&self.table.bucket_dir)?;
+        if self.header.is_none() {
+            self.header = Some(self.table.current_header()?.into_owned());
+        }
+        let Some(header) = &self.header else {
+            return Ok(false);
+        };
+
+        if self.next_bucket >= header.bucket_count() {
+            return Ok(false);
+        }
+        let bucket_idx = self.next_bucket;
+        self.next_bucket += 1;
+
+        let records = self.table.read_bucket(bucket_idx)?;
+        self.pending = live_records(records, header, bucket_idx).into_iter();
+        Ok(true)
+    }
+}
+
+impl Iterator for DiskHashIter<'_> {
+    type Item = Result<(String, Trip), Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, data)) = self.pending.next() {
+                let header = self.header.as_ref()?;
+                let entry = String::from_utf8(key)
+                    .map_err(|e| e.into())
+                    .and_then(|key| {
+                        let trip = decode_payload(header, &key, &data)?;
+                        Ok((key, trip))
+                    });
+                return Some(entry);
+            }
+
+            match self.load_bucket() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.next_bucket = usize::MAX;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
 // Construir la hash table desde CSV
-pub fn build_hash_table_from_csv<P: AsRef<Path>>(
+#[allow(dead_code)]
+pub fn build_hash_table_from_csv<P: AsRef<Path>, Q: AsRef<Path>>(
     csv_path: P,
-    hash_dir: P,
+    hash_dir: Q,
+    config: IndexConfig,
 ) -> Result<usize, Box<dyn Error>> {
-    let hash_table = DiskHashTable::new(&hash_dir)?;
+    let report = build_hash_table_from_csv_with(csv_path, hash_dir, config, |_, _| Ok(()))?;
+    Ok(report.rows_ok)
+}
+
+// Igual que build_hash_table_from_csv, pero entrega cada fila a on_row para
+// construir otros índices en la misma pasada sobre el CSV
+pub fn build_hash_table_from_csv_with<P, Q, F>(
+    csv_path: P,
+    hash_dir: Q,
+    config: IndexConfig,
+    mut on_row: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(&Trip, RowLocation) -> Result<(), Box<dyn Error>>,
+{
+    let mut hash_table = DiskHashTable::create(&hash_dir, &csv_path, config)?;
     let mut count = 0;
+    let mut trips: Vec<(String, Trip)> = Vec::new();
+    let mut locations: Vec<(String, RowLocation)> = Vec::new();
+
+    let report =
+        super::data_lector::stream_process_csv_with_locations(csv_path, |trip, location| {
+            let key = trip.index.clone();
+            match config.mode {
+                IndexMode::FullCopy => trips.push((key, trip.clone())),
+                // Solo se guarda dónde está la fila, no se duplica el viaje en disco
+                IndexMode::Offsets => locations.push((key, location)),
+            }
+            on_row(trip, location)?;
+            count += 1;
+
+            if count % BUILD_BATCH_SIZE == 0 {
+                hash_table.insert_batch(config.mode, &mut trips, &mut locations)?;
+                eprintln!("Procesados {} registros...", count);
+            }
 
-    super::data_lector::stream_process_csv(csv_path, |trip| {
-        let key = trip.index.clone();
-        hash_table.insert(key, trip.clone())?;
-        count += 1;
+            Ok(())
+        })?;
+
+    hash_table.insert_batch(config.mode, &mut trips, &mut locations)?;
+    hash_table.flush()?;
+    eprintln!(
+        "Índice con {} buckets (carga promedio {:.1})",
+        hash_table.bucket_count(),
+        hash_table.header.load_factor()
+    );
+
+    Ok(report)
+}
 
-        if count % 10000 == 0 {
-            println!("Procesados {} registros...", count);
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{TestDir, read_trips, sample_trips_csv};
+    use super::*;
+
+    fn offsets_config(initial_buckets: usize, max_load_factor: f64) -> IndexConfig {
+        IndexConfig {
+            mode: IndexMode::Offsets,
+            initial_buckets: Some(initial_buckets),
+            max_load_factor,
         }
+    }
 
-        Ok(())
-    })?;
+    fn full_copy_config(initial_buckets: usize, max_load_factor: f64) -> IndexConfig {
+        IndexConfig {
+            mode: IndexMode::FullCopy,
+            ..offsets_config(initial_buckets, max_load_factor)
+        }
+    }
+
+    // Viajes como los guarda una copia completa, que no incluye el archivo de origen
+    fn read_copies(csv: &Path) -> Vec<Trip> {
+        read_trips(csv)
+            .into_iter()
+            .map(|trip| Trip {
+                source_file: None,
+                ..trip
+            })
+            .collect()
+    }
+
+    fn build(csv: &Path, hash_dir: &Path, config: IndexConfig) -> DiskHashTable {
+        build_hash_table_from_csv(csv, hash_dir, config).unwrap();
+        DiskHashTable::open(hash_dir).unwrap().unwrap()
+    }
+
+    #[test]
+    fn trip_record_round_trip() {
+        let dir = TestDir::new("hash-record");
+        let csv = dir.write("trips.csv", sample_trips_csv(3));
+
+        for trip in read_copies(&csv) {
+            let mut payload = Vec::new();
+            encode_trip(&trip, &mut payload);
+            assert_eq!(decode_trip(&payload).unwrap(), trip);
+            assert!(decode_trip(&payload[..payload.len() - 1]).is_err());
+        }
+
+        // [largo clave u32][largo datos u32][clave][datos]
+        let mut record = Vec::new();
+        encode_record(b"42", &[7, 8, 9], &mut record);
+        assert_eq!(record, [2, 0, 0, 0, 3, 0, 0, 0, b'4', b'2', 7, 8, 9]);
+
+        let location = RowLocation {
+            offset: 1 << 40,
+            len: 97,
+        };
+        let mut payload = Vec::new();
+        encode_location(location, &mut payload);
+        assert_eq!(payload.len(), 16);
+        assert_eq!(decode_location(&payload).unwrap(), location);
+        assert!(decode_location(&payload[..15]).is_err());
+    }
+
+    #[test]
+    fn latest_record_wins() {
+        let dir = TestDir::new("hash-append");
+        let csv = dir.write("trips.csv", sample_trips_csv(20));
+        let hash_dir = dir.join("hash");
+        let trips = read_copies(&csv);
+        build(&csv, &hash_dir, full_copy_config(4, 1000.0));
+
+        let mut table = DiskHashTable::new(&hash_dir).unwrap();
+        for trip in &trips {
+            assert_eq!(table.get(&trip.index).unwrap().as_ref(), Some(trip));
+        }
+        assert!(table.get("20").unwrap().is_none());
+
+        // Volver a insertar una clave agrega un registro; vale el último
+        let mut updated = trips[4].clone();
+        updated.total_amount = 99.5;
+        table.insert("4".to_string(), updated.clone()).unwrap();
+        assert_eq!(table.get("4").unwrap(), Some(updated));
+        assert_eq!(table.count_entries().unwrap(), 20);
+        assert_eq!(table.stored_records(), 21);
+    }
+
+    #[test]
+    fn offsets_and_full_copy_modes_read_the_same_trips() {
+        let dir = TestDir::new("hash-modes");
+        let csv = dir.write("trips.csv", sample_trips_csv(30));
+        let mut offsets = build(&csv, &dir.join("offsets"), offsets_config(4, 1000.0));
+        let mut full_copy = build(&csv, &dir.join("full"), full_copy_config(4, 1000.0));
+        assert_eq!(offsets.mode(), IndexMode::Offsets);
+        assert_eq!(full_copy.mode(), IndexMode::FullCopy);
+
+        // Por posición se vuelve a leer el CSV, que agrega el archivo de origen
+        for (trip, copy) in read_trips(&csv).into_iter().zip(read_copies(&csv)) {
+            assert_eq!(offsets.get(&trip.index).unwrap(), Some(trip));
+            assert_eq!(full_copy.get(&copy.index).unwrap(), Some(copy));
+        }
+        assert!(offsets.get("30").unwrap().is_none());
+
+        // Cada modo acepta solo su tipo de entrada
+        let trip = read_trips(&csv).remove(0);
+        assert!(offsets.insert("0".to_string(), trip).is_err());
+        let location = RowLocation { offset: 0, len: 1 };
+        assert!(
+            full_copy
+                .insert_location("0".to_string(), location)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn stale_source_is_rejected() {
+        let dir = TestDir::new("hash-stale");
+        let csv = dir.write("trips.csv", sample_trips_csv(10));
+        let other = dir.write("other.csv", sample_trips_csv(10));
+        let table = build(&csv, &dir.join("hash"), offsets_config(2, 1000.0));
+        table.validate_source(&csv).unwrap();
+        assert!(table.get("3").unwrap().is_some());
+
+        // Otro archivo con el mismo contenido no es el CSV del índice
+        let error = table.validate_source(&other).unwrap_err().to_string();
+        assert!(error.contains("fue construido para"), "{}", error);
+
+        // Al agregar una fila cambian tamaño y checksum
+        let mut file = OpenOptions::new().append(true).open(&csv).unwrap();
+        file.write_all(sample_trips_csv(11).lines().last().unwrap().as_bytes())
+            .unwrap();
+        drop(file);
+        assert!(table.validate_source(&csv).is_err());
+        assert!(table.get("3").is_err());
+    }
+
+    #[test]
+    fn splits_keep_every_key_reachable() {
+        let dir = TestDir::new("hash-split");
+        let csv = dir.write("trips.csv", sample_trips_csv(1));
+        let mut table =
+            DiskHashTable::create(dir.join("hash"), &csv, offsets_config(2, 2.0)).unwrap();
+        let location = |key: u64, version: u64| RowLocation {
+            offset: key * 1000 + version,
+            len: 10,
+        };
+
+        for batch in (0..300).collect::<Vec<u64>>().chunks(37) {
+            table
+                .insert_many_locations(batch.iter().map(|key| (key.to_string(), location(*key, 0))))
+                .unwrap();
+        }
+        // Actualizar claves: la versión vieja se descarta al dividir su bucket
+        table
+            .insert_many_locations(
+                (0..300)
+                    .step_by(3)
+                    .map(|key| (key.to_string(), location(key, 1))),
+            )
+            .unwrap();
+        table.flush().unwrap();
+        assert!(table.bucket_count() > 100);
+        assert!(table.header.load_factor() <= 2.0);
+
+        let reader = DiskHashTable::open(dir.join("hash")).unwrap().unwrap();
+        assert_eq!(reader.bucket_count(), table.bucket_count());
+        for key in 0..300 {
+            let version = if key % 3 == 0 { 1 } else { 0 };
+            let found = reader
+                .find_payload(&reader.header, &key.to_string())
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                decode_location(&found).unwrap(),
+                location(key, version),
+                "clave {}",
+                key
+            );
+        }
+        assert!(
+            reader
+                .find_payload(&reader.header, "300")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn initial_buckets_follow_the_row_estimate() {
+        assert_eq!(initial_buckets_for_rows(0, 1024.0), MIN_INITIAL_BUCKETS);
+        assert_eq!(initial_buckets_for_rows(512 * 100, 1024.0), 128);
+        assert_eq!(
+            initial_buckets_for_rows(usize::MAX / 2, 1.0),
+            MAX_INITIAL_BUCKETS
+        );
+    }
+
+    #[test]
+    fn key_hash_is_stable() {
+        // Vectores de referencia de FNV-1a de 64 bits
+        assert_eq!(calculate_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(calculate_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(calculate_hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn incompatible_header_is_rejected() {
+        let dir = TestDir::new("hash-header");
+        let csv = dir.write("trips.csv", sample_trips_csv(5));
+        let hash_dir = dir.join("hash");
+        build(&csv, &hash_dir, offsets_config(2, 1000.0));
+        let header_path = hash_dir.join(HEADER_FILE);
+        let header: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&header_path).unwrap()).unwrap();
+
+        let mut old_hasher = header.clone();
+        old_hasher["hash_algorithm"] = "siphash".into();
+        let mut old_format = header.clone();
+        old_format["format_version"] = (INDEX_FORMAT_VERSION - 1).into();
+        // Los índices anteriores a la versión no tienen esos campos
+        let mut unversioned = header.clone();
+        let fields = unversioned.as_object_mut().unwrap();
+        fields.remove("hash_algorithm");
+        fields.remove("format_version");
+
+        for old in [old_hasher, old_format, unversioned] {
+            fs::write(&header_path, old.to_string()).unwrap();
+            let error = DiskHashTable::open(&hash_dir).err().unwrap().to_string();
+            assert!(error.starts_with("Índice incompatible"), "{}", error);
+        }
+
+        fs::write(&header_path, header.to_string()).unwrap();
+        assert!(DiskHashTable::open(&hash_dir).unwrap().is_some());
+    }
 
-    Ok(count)
+    #[test]
+    fn remove_contains_and_iter_see_the_live_entries() {
+        let dir = TestDir::new("hash-remove");
+        let csv = dir.write("trips.csv", sample_trips_csv(40));
+        let trips = read_copies(&csv);
+        build(&csv, &dir.join("hash"), full_copy_config(2, 8.0));
+        let mut table = DiskHashTable::new(dir.join("hash")).unwrap();
+
+        assert!(table.contains("7").unwrap());
+        assert!(table.remove("7").unwrap());
+        assert!(!table.remove("7").unwrap());
+        assert!(!table.contains("7").unwrap());
+        assert!(table.get("7").unwrap().is_none());
+        assert!(!table.remove("40").unwrap());
+
+        let mut updated = trips[9].clone();
+        updated.total_amount = 99.5;
+        table.insert("9".to_string(), updated.clone()).unwrap();
+        // Las lápidas y las versiones viejas sobreviven a las divisiones de buckets
+        let buckets = table.bucket_count();
+        table
+            .insert_many((40..80).map(|key| (key.to_string(), trips[0].clone())))
+            .unwrap();
+        assert!(table.bucket_count() > buckets);
+        assert!(!table.contains("7").unwrap());
+        assert_eq!(table.count_entries().unwrap(), 79);
+
+        let mut entries: Vec<(String, Trip)> = table.iter().map(Result::unwrap).collect();
+        entries.sort_by_key(|(key, _)| key.parse::<u32>().unwrap());
+        assert_eq!(entries.len(), 79);
+        assert!(entries.iter().all(|(key, _)| key != "7"));
+        assert_eq!(entries[8], ("9".to_string(), updated));
+        assert_eq!(entries[0], ("0".to_string(), trips[0].clone()));
+
+        // Una clave eliminada se puede volver a insertar
+        table.insert("7".to_string(), trips[7].clone()).unwrap();
+        assert_eq!(table.get("7").unwrap().as_ref(), Some(&trips[7]));
+        assert_eq!(table.iter().count(), 80);
+    }
+
+    #[test]
+    fn truncated_bucket_tail_is_ignored() {
+        let dir = TestDir::new("hash-truncated");
+        let csv = dir.write("trips.csv", sample_trips_csv(20));
+        let hash_dir = dir.join("hash");
+        // Un solo bucket con todas las filas
+        let table = build(&csv, &hash_dir, offsets_config(1, 1000.0));
+        let bucket = hash_dir.join(bucket_file_name(0));
+        let complete_len = fs::metadata(&bucket).unwrap().len();
+
+        // Una escritura cortada: la cabecera anuncia una clave y datos que no están
+        let mut file = OpenOptions::new().append(true).open(&bucket).unwrap();
+        file.write_all(&[2, 0, 0, 0, 16, 0, 0, 0, b'9']).unwrap();
+        drop(file);
+
+        let mut scanner = BucketScanner::open(&bucket).unwrap().unwrap();
+        let mut key = Vec::new();
+        let mut keys = 0;
+        while scanner.next_key(&mut key).unwrap().is_some() {
+            keys += 1;
+        }
+        assert_eq!(keys, 20);
+        assert_eq!(scanner.position, complete_len);
+        assert!(scanner.position < scanner.file_len);
+
+        for row in 0..20 {
+            assert!(table.get(&row.to_string()).unwrap().is_some());
+        }
+        assert!(table.get("20").unwrap().is_none());
+    }
+
+    #[test]
+    fn reopening_for_write_repairs_a_truncated_bucket() {
+        let dir = TestDir::new("hash-repair");
+        let csv = dir.write("trips.csv", sample_trips_csv(20));
+        let hash_dir = dir.join("hash");
+        build(&csv, &hash_dir, full_copy_config(1, 1000.0));
+        let bucket = hash_dir.join(bucket_file_name(0));
+
+        // Un corte en medio del último registro, como el que deja un proceso interrumpido
+        let complete_len = fs::metadata(&bucket).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&bucket).unwrap();
+        file.set_len(complete_len - 5).unwrap();
+        drop(file);
+        let trips = read_copies(&csv);
+        let reader = DiskHashTable::open(&hash_dir).unwrap().unwrap();
+        let lost: Vec<&Trip> = trips
+            .iter()
+            .filter(|trip| reader.get(&trip.index).unwrap().is_none())
+            .collect();
+        assert_eq!(lost.len(), 1);
+        drop(reader);
+
+        // Al reabrirlo para escritura se recorta el registro incompleto, así lo que
+        // se agrega después queda legible
+        let mut table = DiskHashTable::new(&hash_dir).unwrap();
+        assert!(fs::metadata(&bucket).unwrap().len() < complete_len - 5);
+        table
+            .insert(lost[0].index.clone(), lost[0].clone())
+            .unwrap();
+        table.insert("nuevo".to_string(), trips[3].clone()).unwrap();
+        table.flush().unwrap();
+        drop(table);
+
+        let reader = DiskHashTable::open(&hash_dir).unwrap().unwrap();
+        for trip in &trips {
+            assert_eq!(reader.get(&trip.index).unwrap().as_ref(), Some(trip));
+        }
+        assert_eq!(reader.get("nuevo").unwrap().as_ref(), Some(&trips[3]));
+    }
+
+    #[test]
+    fn only_one_writer_at_a_time() {
+        let dir = TestDir::new("hash-lock");
+        let csv = dir.write("trips.csv", sample_trips_csv(5));
+        let hash_dir = dir.join("hash");
+        let writer = DiskHashTable::create(&hash_dir, &csv, offsets_config(2, 1000.0)).unwrap();
+
+        let error = DiskHashTable::create(&hash_dir, &csv, offsets_config(2, 1000.0))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.starts_with("Otro proceso"), "{}", error);
+        assert!(DiskHashTable::new(&hash_dir).is_err());
+        assert!(build_hash_table_from_csv(&csv, &hash_dir, offsets_config(2, 1000.0)).is_err());
+
+        // Los lectores no esperan al escritor, solo a cada escritura
+        let reader = DiskHashTable::open(&hash_dir).unwrap().unwrap();
+        assert!(reader.get("0").unwrap().is_none());
+        assert!(reader.flush().is_err());
+
+        drop(writer);
+        build(&csv, &hash_dir, offsets_config(2, 1000.0));
+    }
 }
@@ -0,0 +1,421 @@
+use super::parquet_io::ParquetTripWriter;
+use super::trip_struct::{Trip, TripColumn};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Marca de orden de bytes para que Excel reconozca el archivo como UTF-8
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Formatos de exportación de los viajes filtrados
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Tsv,
+    // CSV separado por `;` y con BOM, como lo esperan las hojas de cálculo en español
+    ExcelCsv,
+    JsonLines,
+    Json,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 6] = [
+        ExportFormat::Csv,
+        ExportFormat::Tsv,
+        ExportFormat::ExcelCsv,
+        ExportFormat::JsonLines,
+        ExportFormat::Json,
+        ExportFormat::Parquet,
+    ];
+
+    // Nombre usado en la línea de comandos
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::ExcelCsv => "excel",
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Json => "json",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Tsv => "TSV",
+            ExportFormat::ExcelCsv => "CSV para Excel (;)",
+            ExportFormat::JsonLines => "JSON Lines",
+            ExportFormat::Json => "JSON",
+            ExportFormat::Parquet => "Parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::ExcelCsv => "csv",
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Json => "json",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ExportFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == name)
+    }
+
+    // Formato según la extensión del archivo; CSV si no se reconoce
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "tsv" | "tab" => ExportFormat::Tsv,
+            "jsonl" | "ndjson" => ExportFormat::JsonLines,
+            "json" => ExportFormat::Json,
+            "parquet" => ExportFormat::Parquet,
+            _ => ExportFormat::Csv,
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+// Formato y columnas de una exportación
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub columns: Vec<TripColumn>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Csv,
+            columns: TripColumn::ALL.to_vec(),
+        }
+    }
+}
+
+impl ExportOptions {
+    // Interpreta una lista de columnas separadas por comas
+    pub fn parse_columns(list: &str) -> Result<Vec<TripColumn>, String> {
+        let columns = list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| TripColumn::from_name(name).ok_or(format!("Columna desconocida: {}", name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if columns.is_empty() {
+            return Err("Hay que elegir al menos una columna".to_string());
+        }
+        Ok(columns)
+    }
+}
+
+// Escribe los viajes exportados en el formato elegido
+pub struct TripExporter {
+    columns: Vec<TripColumn>,
+    output: ExportOutput,
+}
+
+enum ExportOutput {
+    Delimited(Box<csv::Writer<BufWriter<File>>>),
+    JsonLines(BufWriter<File>),
+    // `written` indica si ya hay elementos en el arreglo, para las comas
+    Json {
+        writer: BufWriter<File>,
+        written: bool,
+    },
+    Parquet(Box<ParquetTripWriter>),
+}
+
+impl TripExporter {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        options: &ExportOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        if options.columns.is_empty() {
+            return Err("Hay que elegir al menos una columna".into());
+        }
+
+        let output = match options.format {
+            ExportFormat::Parquet => {
+                ExportOutput::Parquet(Box::new(ParquetTripWriter::create(path, &options.columns)?))
+            }
+            ExportFormat::Csv | ExportFormat::Tsv | ExportFormat::ExcelCsv => {
+                let mut file = BufWriter::new(File::create(path)?);
+                let delimiter = match options.format {
+                    ExportFormat::Tsv => b'\t',
+                    ExportFormat::ExcelCsv => {
+                        file.write_all(UTF8_BOM)?;
+                        b';'
+                    }
+                    _ => b',',
+                };
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(delimiter)
+                    .from_writer(file);
+                writer.write_record(options.columns.iter().map(TripColumn::name))?;
+                ExportOutput::Delimited(Box::new(writer))
+            }
+            ExportFormat::JsonLines => ExportOutput::JsonLines(BufWriter::new(File::create(path)?)),
+            ExportFormat::Json => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write!(writer, "[")?;
+                ExportOutput::Json {
+                    writer,
+                    written: false,
+                }
+            }
+        };
+
+        Ok(Self {
+            columns: options.columns.clone(),
+            output,
+        })
+    }
+
+    pub fn write(&mut self, trip: &Trip) -> Result<(), Box<dyn Error>> {
+        match &mut self.output {
+            ExportOutput::Delimited(writer) => {
+                writer.write_record(self.columns.iter().map(|column| column.format(trip)))?;
+            }
+            ExportOutput::JsonLines(writer) => {
+                writeln!(writer, "{}", json_object(trip, &self.columns, false)?)?;
+            }
+            ExportOutput::Json { writer, written } => {
+                let separator = if *written { "," } else { "" };
+                write!(
+                    writer,
+                    "{}\n  {}",
+                    separator,
+                    json_object(trip, &self.columns, true)?
+                )?;
+                *written = true;
+            }
+            ExportOutput::Parquet(writer) => writer.write(trip)?,
+        }
+        Ok(())
+    }
+
+    // Cierra el archivo; hay que llamarlo para que JSON y Parquet queden completos
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.output {
+            ExportOutput::Delimited(mut writer) => writer.flush()?,
+            ExportOutput::JsonLines(mut writer) => writer.flush()?,
+            ExportOutput::Json {
+                mut writer,
+                written,
+            } => {
+                writeln!(writer, "{}]", if written { "\n" } else { "" })?;
+                writer.flush()?;
+            }
+            ExportOutput::Parquet(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+// Objeto JSON con las columnas en el orden elegido; `pretty` lo escribe con una
+// columna por línea, sangrado para ir dentro del arreglo
+fn json_object(
+    trip: &Trip,
+    columns: &[TripColumn],
+    pretty: bool,
+) -> Result<String, Box<dyn Error>> {
+    let mut fields = Vec::with_capacity(columns.len());
+    for column in columns {
+        let text = column.format(trip);
+        let value = if !column.is_numeric() {
+            serde_json::to_string(&text)?
+        } else if text.parse::<f64>().is_ok_and(f64::is_finite) {
+            text
+        } else {
+            // NaN e infinito no existen en JSON
+            "null".to_string()
+        };
+        let separator = if pretty { ": " } else { ":" };
+        fields.push(format!(
+            "{}{}{}",
+            serde_json::to_string(column.name())?,
+            separator,
+            value
+        ));
+    }
+
+    Ok(if pretty {
+        format!("{{\n    {}\n  }}", fields.join(",\n    "))
+    } else {
+        format!("{{{}}}", fields.join(","))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{TestDir, read_trips, sample_trips_csv};
+    use super::*;
+    use serde_json::Value;
+
+    const COLUMNS: [TripColumn; 3] = [
+        TripColumn::Index,
+        TripColumn::TotalAmount,
+        TripColumn::VendorId,
+    ];
+
+    // Exporta los viajes con el formato y columnas dados y devuelve el archivo
+    fn export(dir: &TestDir, format: ExportFormat, trips: &[Trip]) -> Vec<u8> {
+        let path = dir.join(format!("salida.{}", format.extension()));
+        let options = ExportOptions {
+            format,
+            columns: COLUMNS.to_vec(),
+        };
+        let mut exporter = TripExporter::create(&path, &options).unwrap();
+        for trip in trips {
+            exporter.write(trip).unwrap();
+        }
+        exporter.finish().unwrap();
+        std::fs::read(path).unwrap()
+    }
+
+    fn sample_trips(dir: &TestDir) -> Vec<Trip> {
+        read_trips(&dir.write("trips.csv", sample_trips_csv(3)))
+    }
+
+    // Líneas del archivo delimitado esperado con las columnas de prueba
+    fn delimited_lines(trips: &[Trip], delimiter: &str) -> Vec<String> {
+        let mut lines = vec![COLUMNS.map(|column| column.name()).join(delimiter)];
+        for trip in trips {
+            lines.push(COLUMNS.map(|column| column.format(trip)).join(delimiter));
+        }
+        lines
+    }
+
+    #[test]
+    fn formats_come_from_names_and_extensions() {
+        for format in ExportFormat::ALL {
+            assert_eq!(ExportFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(ExportFormat::from_name("xls"), None);
+
+        for (path, format) in [
+            ("a.TSV", ExportFormat::Tsv),
+            ("a.ndjson", ExportFormat::JsonLines),
+            ("a.json", ExportFormat::Json),
+            ("a.parquet", ExportFormat::Parquet),
+            ("a.txt", ExportFormat::Csv),
+            ("sin_extension", ExportFormat::Csv),
+        ] {
+            assert_eq!(ExportFormat::from_path(Path::new(path)), format, "{}", path);
+        }
+    }
+
+    #[test]
+    fn columns_keep_the_chosen_order() {
+        assert_eq!(
+            ExportOptions::parse_columns(" total_amount,index ,do_location_id,").unwrap(),
+            [
+                TripColumn::TotalAmount,
+                TripColumn::Index,
+                TripColumn::DoLocationId
+            ]
+        );
+        assert_eq!(
+            ExportOptions::parse_columns("index,precio").unwrap_err(),
+            "Columna desconocida: precio"
+        );
+        assert_eq!(
+            ExportOptions::parse_columns(" , ").unwrap_err(),
+            "Hay que elegir al menos una columna"
+        );
+
+        let dir = TestDir::new("export-columns");
+        let options = ExportOptions {
+            format: ExportFormat::Csv,
+            columns: Vec::new(),
+        };
+        assert!(TripExporter::create(dir.join("vacio.csv"), &options).is_err());
+    }
+
+    #[test]
+    fn delimited_formats_use_their_separator() {
+        let dir = TestDir::new("export-delimited");
+        let trips = sample_trips(&dir);
+
+        let csv = String::from_utf8(export(&dir, ExportFormat::Csv, &trips)).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            delimited_lines(&trips, ",")
+        );
+
+        let tsv = String::from_utf8(export(&dir, ExportFormat::Tsv, &trips)).unwrap();
+        assert_eq!(
+            tsv.lines().collect::<Vec<_>>(),
+            delimited_lines(&trips, "\t")
+        );
+
+        // Excel necesita el BOM para leer UTF-8 y `;` como separador
+        let excel = export(&dir, ExportFormat::ExcelCsv, &trips);
+        let text = std::str::from_utf8(excel.strip_prefix(UTF8_BOM).unwrap()).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            delimited_lines(&trips, ";")
+        );
+    }
+
+    #[test]
+    fn json_lines_write_one_object_per_line() {
+        let dir = TestDir::new("export-jsonl");
+        let trips = sample_trips(&dir);
+        let text = String::from_utf8(export(&dir, ExportFormat::JsonLines, &trips)).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), trips.len());
+        for (line, trip) in lines.iter().zip(&trips) {
+            assert_eq!(
+                *line,
+                format!(
+                    "{{\"index\":\"{}\",\"total_amount\":{},\"vendor_id\":\"{}\"}}",
+                    trip.index, trip.total_amount, trip.vendor_id
+                )
+            );
+            let object: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(object["total_amount"], trip.total_amount);
+        }
+    }
+
+    #[test]
+    fn pretty_json_is_a_valid_array() {
+        let dir = TestDir::new("export-json");
+        let trips = sample_trips(&dir);
+
+        let array: Value =
+            serde_json::from_slice(&export(&dir, ExportFormat::Json, &trips)).unwrap();
+        let array = array.as_array().unwrap();
+        assert_eq!(array.len(), trips.len());
+        for (object, trip) in array.iter().zip(&trips) {
+            assert_eq!(object["index"], trip.index.as_str());
+            assert_eq!(object["total_amount"], trip.total_amount);
+            assert_eq!(object["vendor_id"], trip.vendor_id.as_str());
+        }
+
+        // Sin viajes el archivo sigue siendo un arreglo válido
+        let empty = export(&dir, ExportFormat::Json, &[]);
+        assert_eq!(empty, b"[]\n");
+        let empty: Value = serde_json::from_slice(&empty).unwrap();
+        assert_eq!(empty, Value::Array(Vec::new()));
+    }
+}
@@ -1,42 +1,163 @@
-use super::trip_struct::Trip;
-use super::disk_hash::{DiskHashTable, build_hash_table_from_csv};
+use super::aggregate::{GroupByAccumulator, GroupBySpec, ResultTable, Value};
+use super::data_lector::{ParseReport, RowLocation, read_schema};
+use super::dataset::{Dataset, source_name};
+use super::disk_hash::{
+    DEFAULT_MAX_LOAD_FACTOR, DiskHashTable, IndexConfig, IndexMode, build_hash_table_from_csv_with,
+    calculate_hash,
+};
+use super::export::{ExportFormat, ExportOptions, TripExporter};
+use super::parallel_scan::{self, ScanConfig};
+use super::parquet_io;
+use super::planner::{self, IndexCatalog};
+use super::row_numbers::{RowNumbers, RowNumbersBuilder};
+use super::secondary_index::{SecondaryIndexBuilder, SecondaryIndexes};
+use super::sketch::{
+    ApproxAccumulator, ApproxStat, ApproxValue, DistinctCounter, QuantileSketch, SketchOptions,
+};
+use super::sorted_index::{SortedIndexBuilder, SortedIndexes};
+use super::trip_struct::{Measure, PaymentType, Trip, TripRef};
+use chrono::NaiveDateTime;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Once;
-use std::sync::Mutex;
 use std::sync::LazyLock;
+use std::sync::Mutex;
+
+mod query;
+pub use query::{QueryError, parse_query};
+
+// Directorio por defecto con los índices de todos los datasets
+const DEFAULT_INDEX_ROOT: &str = "tmp/indexes";
+// Subdirectorio del índice hash dentro del directorio de un dataset
+const HASH_DIR: &str = "hash_index";
+// El índice guarda posiciones en el CSV para no duplicar los viajes en disco;
+// el número de buckets se elige según el tamaño del CSV
+const HASH_INDEX_CONFIG: IndexConfig = IndexConfig {
+    mode: IndexMode::Offsets,
+    initial_buckets: None,
+    max_load_factor: DEFAULT_MAX_LOAD_FACTOR,
+};
+// Índices secundarios (destino, origen y fecha de recogida)
+const SECONDARY_DIR: &str = "secondary_index";
+// Índices ordenados para rangos de total_amount y trip_distance
+const SORTED_DIR: &str = "sorted_index";
+// Número de fila de cada posición, solo para CSV sin columna index
+const ROW_NUMBERS_FILE: &str = "row_numbers.idx";
+// Error usado para cortar el recorrido al llegar a max_results
+const RESULT_LIMIT_REACHED: &str = "Límite de resultados alcanzado";
+static INDEX_ROOT: LazyLock<Mutex<PathBuf>> =
+    LazyLock::new(|| Mutex::new(PathBuf::from(DEFAULT_INDEX_ROOT)));
+// Índices cargados de cada archivo de un dataset (una partición por CSV), por su
+// directorio; el mapa se mantiene bloqueado mientras se usan para que otro hilo
+// no los reemplace al reconstruirlos
+static PARTITIONS: LazyLock<Mutex<HashMap<PathBuf, PartitionIndexes>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+// Solo un hilo construye índices a la vez; PARTITIONS queda libre mientras tanto
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+// Índices de un archivo del dataset; los que falten quedan vacíos
+struct PartitionIndexes {
+    hash: Option<DiskHashTable>,
+    secondary: Option<SecondaryIndexes>,
+    sorted: Option<SortedIndexes>,
+    row_numbers: Option<RowNumbers>,
+}
 
-// Constantes para el directorio de hash
-const HASH_DIR: &str = "tmp/hash_index";
-static HASH_INIT: Once = Once::new();
-static HASH_TABLE: LazyLock<Mutex<Option<DiskHashTable>>> = LazyLock::new(|| Mutex::new(None));
+impl PartitionIndexes {
+    fn catalog(&self) -> IndexCatalog<'_> {
+        IndexCatalog {
+            hash: self.hash.as_ref(),
+            secondary: self.secondary.as_ref(),
+            sorted: self.sorted.as_ref(),
+        }
+    }
 
+    // Filas inválidas registradas al construir los índices
+    fn invalid_rows(&self) -> usize {
+        self.secondary
+            .as_ref()
+            .map_or(0, |indexes| indexes.invalid_rows())
+    }
+}
+
+// Filas de un archivo que pueden cumplir un filtro según sus índices
+struct Candidates {
+    offsets: Vec<u64>,
+    // Número de fila de cada posición si el CSV no tiene columna index
+    rows: Option<Vec<u64>>,
+    invalid_rows: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TripFilter {
-    Price { min: Option<f64>, max: Option<f64> },
+    Price {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
     Index(String),
-    Destination(String),
+    Destination(u16),
+    PickupTime {
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    },
+    PickupLocation(u16),
+    PaymentType(PaymentType),
+    Vendor(String),
+    Passengers {
+        min: Option<u8>,
+        max: Option<u8>,
+    },
+    Distance {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    // Propina como porcentaje de fare_amount
+    TipPercentage {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    // Duración del viaje en minutos
+    Duration {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
     And(Vec<TripFilter>),
     Or(Vec<TripFilter>),
+    Not(Box<TripFilter>),
 }
 
 impl TripFilter {
-    pub fn matches(&self, trip: &Trip) -> bool {
+    // Se evalúa sobre el viaje prestado; un Trip se compara con as_trip_ref
+    pub fn matches(&self, trip: &TripRef) -> bool {
         match self {
             TripFilter::Price { min, max } => {
-                // Convertir el precio a f64, usar 0.0 si hay error
-                let price = trip.total_amount.parse::<f64>().unwrap_or(0.0);
+                let price = trip.total_amount;
 
                 // Verificar límites mínimo y máximo si existen
-                let min_check = min.map_or(true, |min_val| price >= min_val);
-                let max_check = max.map_or(true, |max_val| price <= max_val);
+                let min_check = min.is_none_or(|min_val| price >= min_val);
+                let max_check = max.is_none_or(|max_val| price <= max_val);
 
                 min_check && max_check
             }
             TripFilter::Index(target_index) => trip.index == *target_index,
             TripFilter::Destination(target_dest) => trip.do_location_id == *target_dest,
+            TripFilter::PickupTime { from, to } => in_range(trip.tpep_pickup_datetime, *from, *to),
+            TripFilter::PickupLocation(target_pickup) => trip.pu_location_id == *target_pickup,
+            TripFilter::PaymentType(payment) => trip.payment_type == Some(*payment),
+            TripFilter::Vendor(vendor) => trip.vendor_id == *vendor,
+            TripFilter::Passengers { min, max } => {
+                // Un viaje sin número de pasajeros no está en ningún rango
+                trip.passenger_count
+                    .is_some_and(|passengers| in_range(passengers, *min, *max))
+            }
+            TripFilter::Distance { min, max } => in_range(trip.trip_distance, *min, *max),
+            TripFilter::TipPercentage { min, max } => {
+                // Sin tarifa no hay porcentaje de propina que comparar
+                trip.tip_percentage()
+                    .is_some_and(|percentage| in_range(percentage, *min, *max))
+            }
+            TripFilter::Duration { min, max } => in_range(trip.duration_minutes(), *min, *max),
             TripFilter::And(filters) => {
                 // Todos los filtros deben cumplirse (AND lógico)
                 filters.iter().all(|filter| filter.matches(trip))
@@ -45,321 +166,1254 @@ impl TripFilter {
                 // Al menos un filtro debe cumplirse (OR lógico)
                 filters.iter().any(|filter| filter.matches(trip))
             }
+            TripFilter::Not(filter) => !filter.matches(trip),
         }
     }
 }
 
-// Nueva función para inicializar o recuperar la tabla hash
-fn get_or_initialize_hash_table<P: AsRef<Path>>(csv_path: P) -> Result<&'static Mutex<Option<DiskHashTable>>, Box<dyn Error>> {
-    HASH_INIT.call_once(|| {
-        println!("Inicializando tabla hash en disco...");
-        let hash_path = PathBuf::from(HASH_DIR);
-        
-        // Verificar si ya existe la tabla hash
-        let mut needs_build = true;
-        if hash_path.exists() {
-            // Si existe al menos un archivo de bucket, asumimos que la tabla está construida
-            for i in 0..256 { // NUM_BUCKETS from disk_hash.rs
-                let bucket_path = hash_path.join(format!("bucket_{}.json", i));
-                if bucket_path.exists() && fs::metadata(&bucket_path).map(|m| m.len() > 0).unwrap_or(false) {
-                    needs_build = false;
-                    break;
-                }
-            }
+// Verifica que el valor esté entre los límites (inclusive) que existan
+fn in_range<T: PartialOrd>(value: T, min: Option<T>, max: Option<T>) -> bool {
+    min.is_none_or(|min_val| value >= min_val) && max.is_none_or(|max_val| value <= max_val)
+}
+
+/// Cambia el directorio donde se guardan los índices de cada dataset
+pub fn set_index_root<P: AsRef<Path>>(dir: P) {
+    *INDEX_ROOT.lock().unwrap() = dir.as_ref().to_path_buf();
+}
+
+/// Directorio de índices de un CSV: el nombre del archivo más un hash de su ruta
+/// absoluta, para que dos datasets con el mismo nombre no compartan índices
+pub fn dataset_index_dir<P: AsRef<Path>>(csv_path: P) -> PathBuf {
+    let csv_path = csv_path.as_ref();
+    let absolute = fs::canonicalize(csv_path).unwrap_or_else(|_| csv_path.to_path_buf());
+    let stem = csv_path
+        .file_stem()
+        .map_or("dataset".into(), |stem| stem.to_string_lossy());
+    let hash = calculate_hash(absolute.to_string_lossy().as_bytes());
+
+    INDEX_ROOT
+        .lock()
+        .unwrap()
+        .join(format!("{}-{:08x}", stem, hash as u32))
+}
+
+// Nueva función para cargar los índices de un archivo del dataset; se construyen la
+// primera vez que se usa el archivo si no existen o no son válidos. Se llama con
+// BUILD_LOCK tomado y sin bloquear PARTITIONS
+fn load_indexes<P: AsRef<Path>>(csv_path: P, dataset_dir: &Path) -> PartitionIndexes {
+    eprintln!(
+        "Inicializando tabla hash en disco en {}...",
+        dataset_dir.display()
+    );
+    let hash_path = dataset_dir.join(HASH_DIR);
+
+    // Verificar si ya existe la tabla hash: solo se reutiliza si tiene entradas,
+    // fue construida con el mismo modo y el CSV no ha cambiado
+    let mut needs_build = true;
+    match DiskHashTable::open(&hash_path) {
+        Ok(Some(table)) if table.mode() != HASH_INDEX_CONFIG.mode => {
+            eprintln!("El índice hash existente usa otro modo, se reconstruirá");
         }
-        
-        // Inicializar la tabla hash
-        match DiskHashTable::new(&hash_path) {
-            Ok(hash_table) => {
-                let mut table_ref = HASH_TABLE.lock().unwrap();
-                *table_ref = Some(hash_table);
-                
-                // Si necesitamos construir la tabla, hacerlo ahora
-                if needs_build {
-                    println!("Construyendo índice hash desde CSV...");
-                    match build_hash_table_from_csv(&csv_path, &hash_path) {
-                        Ok(count) => println!("Índice hash construido con {} registros", count),
-                        Err(e) => eprintln!("Error al construir índice hash: {}", e),
-                    }
-                } else {
-                    println!("Usando índice hash existente");
-                }
-            },
-            Err(e) => {
-                eprintln!("Error al inicializar tabla hash: {}", e);
-            }
+        Ok(Some(table)) if table.stored_records() > 0 => match table.validate_source(&csv_path) {
+            Ok(()) => needs_build = false,
+            Err(e) => eprintln!("El índice hash existente no es válido: {}", e),
+        },
+        Ok(_) => {}
+        Err(e) => eprintln!(
+            "No se puede usar el índice hash existente ({}), se reconstruirá",
+            e
+        ),
+    }
+
+    // Si necesitamos construir la tabla, hacerlo ahora (reemplaza el índice anterior)
+    if needs_build {
+        eprintln!("Construyendo índices desde CSV...");
+        match build_all_indexes(&csv_path, dataset_dir) {
+            Ok(count) => eprintln!("Índices construidos con {} registros", count),
+            Err(e) => eprintln!("Error al construir índices: {}", e),
         }
-    });
-    
-    Ok(&HASH_TABLE)
-}
-
-/// Determina si un filtro puede beneficiarse del uso de hash table
-fn can_use_hash_index(filter: &TripFilter) -> Option<String> {
-    match filter {
-        // Si es un filtro directo por índice, podemos usar hash
-        TripFilter::Index(idx) => Some(idx.clone()),
-        
-        // Si es un AND con un filtro de índice, podemos usarlo para la primera búsqueda
-        TripFilter::And(filters) => {
-            // Buscamos un filtro de índice en la lista
-            for f in filters {
-                if let TripFilter::Index(idx) = f {
-                    return Some(idx.clone());
+    } else {
+        eprintln!("Usando índice hash existente");
+
+        // Los índices secundarios y ordenados pueden faltar aunque el primario sea válido
+        let secondary_ok = match SecondaryIndexes::open(dataset_dir.join(SECONDARY_DIR)) {
+            Ok(Some(indexes)) => indexes.validate_source(&csv_path).is_ok(),
+            _ => false,
+        };
+        let sorted_ok = match SortedIndexes::open(dataset_dir.join(SORTED_DIR)) {
+            Ok(Some(indexes)) => indexes.validate_source(&csv_path).is_ok(),
+            _ => false,
+        };
+        let row_numbers_ok = match read_schema(&csv_path) {
+            Ok(schema) if schema.synthesizes_index() => {
+                match RowNumbers::open(dataset_dir.join(ROW_NUMBERS_FILE)) {
+                    Ok(Some(rows)) => rows.validate_source(&csv_path).is_ok(),
+                    _ => false,
                 }
             }
+            _ => true,
+        };
+        if !secondary_ok || !sorted_ok || !row_numbers_ok {
+            eprintln!("Construyendo índices secundarios y ordenados desde CSV...");
+            if let Err(e) = build_side_indexes(&csv_path, dataset_dir) {
+                eprintln!("Error al construir índices secundarios y ordenados: {}", e);
+            }
+        }
+    }
+
+    open_indexes(dataset_dir)
+}
+
+/// Carga en memoria los índices de un archivo; los que falten quedan vacíos
+fn open_indexes(dataset_dir: &Path) -> PartitionIndexes {
+    // Inicializar la tabla hash en modo lectura: otros procesos pueden compartirla
+    let hash_path = dataset_dir.join(HASH_DIR);
+    let hash = match DiskHashTable::open(&hash_path) {
+        Ok(Some(hash_table)) => Some(hash_table),
+        Ok(None) => {
+            eprintln!("No se encontró el índice hash en {}", hash_path.display());
             None
+        }
+        Err(e) => {
+            eprintln!("Error al inicializar tabla hash: {}", e);
+            None
+        }
+    };
+
+    let secondary = SecondaryIndexes::open(dataset_dir.join(SECONDARY_DIR)).unwrap_or_else(|e| {
+        eprintln!("Error al abrir índices secundarios: {}", e);
+        None
+    });
+    let sorted = SortedIndexes::open(dataset_dir.join(SORTED_DIR)).unwrap_or_else(|e| {
+        eprintln!("Error al abrir índices ordenados: {}", e);
+        None
+    });
+    let row_numbers = RowNumbers::open(dataset_dir.join(ROW_NUMBERS_FILE)).unwrap_or_else(|e| {
+        eprintln!("Error al abrir los números de fila: {}", e);
+        None
+    });
+
+    PartitionIndexes {
+        hash,
+        secondary,
+        sorted,
+        row_numbers,
+    }
+}
+
+/// Construye el índice hash, los secundarios y los ordenados en una sola pasada sobre el CSV
+fn build_all_indexes<P: AsRef<Path>>(
+    csv_path: P,
+    dataset_dir: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    let mut secondary = SecondaryIndexBuilder::create(dataset_dir.join(SECONDARY_DIR))?;
+    let mut sorted = SortedIndexBuilder::create(dataset_dir.join(SORTED_DIR))?;
+    let mut rows = row_numbers_builder(&csv_path, dataset_dir)?;
+    let report = build_hash_table_from_csv_with(
+        &csv_path,
+        dataset_dir.join(HASH_DIR),
+        HASH_INDEX_CONFIG,
+        |trip, location| {
+            secondary.add(trip, location)?;
+            sorted.add(trip, location)?;
+            add_row_number(&mut rows, trip, location)
+        },
+    )?;
+    secondary.write(&csv_path, report.rows_invalid)?;
+    sorted.write(&csv_path)?;
+    write_row_numbers(rows, &csv_path, dataset_dir)?;
+
+    Ok(report.rows_ok)
+}
+
+/// Construye solo los índices secundarios y ordenados con una pasada sobre el CSV
+fn build_side_indexes<P: AsRef<Path>>(
+    csv_path: P,
+    dataset_dir: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    let mut secondary = SecondaryIndexBuilder::create(dataset_dir.join(SECONDARY_DIR))?;
+    let mut sorted = SortedIndexBuilder::create(dataset_dir.join(SORTED_DIR))?;
+    let mut rows = row_numbers_builder(&csv_path, dataset_dir)?;
+    let report =
+        super::data_lector::stream_process_csv_with_locations(&csv_path, |trip, location| {
+            secondary.add(trip, location)?;
+            sorted.add(trip, location)?;
+            add_row_number(&mut rows, trip, location)
+        })?;
+    secondary.write(&csv_path, report.rows_invalid)?;
+    sorted.write(&csv_path)?;
+    write_row_numbers(rows, &csv_path, dataset_dir)?;
+
+    Ok(report.rows_ok)
+}
+
+/// Los números de fila solo se guardan si el CSV no tiene columna index
+fn row_numbers_builder<P: AsRef<Path>>(
+    csv_path: P,
+    dataset_dir: &Path,
+) -> Result<Option<RowNumbersBuilder>, Box<dyn Error>> {
+    if !read_schema(csv_path)?.synthesizes_index() {
+        return Ok(None);
+    }
+    Ok(Some(RowNumbersBuilder::create(
+        dataset_dir.join(ROW_NUMBERS_FILE),
+    )?))
+}
+
+/// En un CSV sin columna index el index del viaje es su número de fila
+fn add_row_number(
+    rows: &mut Option<RowNumbersBuilder>,
+    trip: &Trip,
+    location: RowLocation,
+) -> Result<(), Box<dyn Error>> {
+    match rows {
+        Some(rows) => rows.add(location, trip.index.parse()?),
+        None => Ok(()),
+    }
+}
+
+/// Escribe los números de fila, o borra los que queden de una versión del CSV sin index
+fn write_row_numbers<P: AsRef<Path>>(
+    rows: Option<RowNumbersBuilder>,
+    csv_path: P,
+    dataset_dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let path = dataset_dir.join(ROW_NUMBERS_FILE);
+    match rows {
+        Some(rows) => rows.write(csv_path),
+        None if path.exists() => Ok(fs::remove_file(&path)?),
+        None => Ok(()),
+    }
+}
+
+/// Ejecuta `f` con los índices cargados de un archivo del dataset
+fn with_partition<P, R, F>(csv_path: P, f: F) -> Result<R, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&PartitionIndexes) -> R,
+{
+    let dataset_dir = dataset_index_dir(&csv_path);
+    {
+        let partitions = PARTITIONS.lock().unwrap();
+        if let Some(partition) = partitions.get(&dataset_dir) {
+            return Ok(f(partition));
+        }
+    }
+
+    // Los índices se construyen sin bloquear PARTITIONS, así las consultas sobre otros
+    // archivos no esperan; otro hilo pudo cargarlos mientras esperábamos BUILD_LOCK
+    let _build = BUILD_LOCK.lock().unwrap();
+    if !PARTITIONS.lock().unwrap().contains_key(&dataset_dir) {
+        let partition = load_indexes(&csv_path, &dataset_dir);
+        PARTITIONS
+            .lock()
+            .unwrap()
+            .insert(dataset_dir.clone(), partition);
+    }
+
+    let partitions = PARTITIONS.lock().unwrap();
+    Ok(f(&partitions[&dataset_dir]))
+}
+
+/// Ejecuta `f` con los índices de un archivo y las filas inválidas registradas al construirlos
+fn with_index_catalog<P, R, F>(csv_path: P, f: F) -> Result<R, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&IndexCatalog, usize) -> R,
+{
+    with_partition(csv_path, |partition| {
+        f(&partition.catalog(), partition.invalid_rows())
+    })
+}
+
+/// Filas candidatas de un archivo según el plan del filtro (en orden del archivo) junto
+/// con sus filas inválidas; None si conviene un escaneo completo
+fn indexed_candidates<P: AsRef<Path>>(csv_path: P, filter: &TripFilter) -> Option<Candidates> {
+    let synthesizes_index = read_schema(&csv_path).is_ok_and(|schema| schema.synthesizes_index());
+    let result = with_partition(
+        csv_path,
+        |partition| -> Result<Option<Candidates>, Box<dyn Error>> {
+            let catalog = partition.catalog();
+            let plan = planner::plan(filter, &catalog);
+            let Some(offsets) = plan.candidates(&catalog)? else {
+                return Ok(None);
+            };
+
+            // Sin columna index hace falta el número de fila de cada candidata
+            let rows = match (&partition.row_numbers, synthesizes_index) {
+                (Some(row_numbers), true) => Some(row_numbers.rows_at(&offsets)?),
+                (None, true) => return Err("faltan los números de fila".into()),
+                (_, false) => None,
+            };
+            Ok(Some(Candidates {
+                offsets,
+                rows,
+                invalid_rows: partition.invalid_rows(),
+            }))
         },
-        
-        // Para otros tipos de filtros, no podemos usar hash directamente
-        _ => None
+    );
+
+    match result {
+        Ok(Ok(candidates)) => candidates,
+        Ok(Err(e)) | Err(e) => {
+            eprintln!(
+                "No se pudieron usar los índices ({}), se hará un escaneo completo",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Describe cómo se resolvería un filtro (índices usados y filas estimadas); en un
+/// dataset de varios archivos hay un plan por archivo
+pub fn explain_filter<P: AsRef<Path>>(
+    csv_path: P,
+    filter: &TripFilter,
+) -> Result<String, Box<dyn Error>> {
+    let dataset = Dataset::open(csv_path)?;
+    let mut explain = String::new();
+
+    for file in dataset.files() {
+        if dataset.is_multi_file() {
+            explain.push_str(&format!("Archivo {}:\n", source_name(file)));
+        }
+        if parquet_io::is_parquet(file) {
+            explain.push_str(&parquet_io::explain_row_groups(file, filter)?);
+        } else {
+            explain.push_str(&with_index_catalog(file, |catalog, _| {
+                planner::plan(filter, catalog).explain()
+            })?);
+        }
+    }
+
+    Ok(explain)
+}
+
+/// Recorre un archivo completo del dataset y entrega en orden los viajes que cumplen el
+/// filtro (todos si no hay), prestados y sin copiarlos; en Parquet el filtro sirve para
+/// omitir los grupos de filas que no pueden cumplirlo
+fn stream_file<F>(
+    file: &Path,
+    filter: Option<&TripFilter>,
+    mut process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
+    let keep = |trip: &TripRef| filter.is_none_or(|filter| filter.matches(trip));
+    if parquet_io::is_parquet(file) {
+        parquet_io::stream_process_parquet(file, filter, |trip| {
+            let trip = trip.as_trip_ref();
+            if keep(&trip) {
+                process_trip(&trip)
+            } else {
+                Ok(())
+            }
+        })
+    } else {
+        parallel_scan::scan_csv_matching(file, &ScanConfig::current(), keep, process_trip)
     }
 }
 
-/// Filtrar trips y guardar resultados en un archivo
+/// Recorre las filas de todos los archivos del dataset que pueden cumplir el filtro:
+/// de cada CSV se leen solo las candidatas si su plan usa índices, o el archivo
+/// completo si no. Devuelve la cantidad de filas inválidas encontradas
+fn scan_dataset<P, F>(
+    csv_path: P,
+    filter: &TripFilter,
+    purpose: &str,
+    mut process_trip: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
+    let dataset = Dataset::open(csv_path)?;
+    let mut invalid_rows = 0;
+
+    for file in dataset.files() {
+        // Los índices guardan posiciones en bytes de un CSV; los Parquet no los usan
+        if parquet_io::is_parquet(file) {
+            invalid_rows += stream_file(file, Some(filter), &mut process_trip)?.rows_invalid;
+        } else if let Some(candidates) = indexed_candidates(file, filter) {
+            super::data_lector::read_trips_at_offsets(
+                file,
+                &candidates.offsets,
+                candidates.rows.as_deref(),
+                &mut process_trip,
+            )?;
+            invalid_rows += candidates.invalid_rows;
+        } else {
+            // Si no podemos usar índices, caemos al método tradicional
+            eprintln!(
+                "Usando escaneo secuencial de {} para {}",
+                file.display(),
+                purpose
+            );
+            invalid_rows += stream_file(file, Some(filter), &mut process_trip)?.rows_invalid;
+        }
+    }
+
+    Ok(invalid_rows)
+}
+
+/// Ignora el error usado para cortar un recorrido al llegar al límite de resultados
+fn ignore_result_limit<T: Default>(result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    result.or_else(|e| {
+        if e.to_string() == RESULT_LIMIT_REACHED {
+            Ok(Default::default())
+        } else {
+            Err(e)
+        }
+    })
+}
+
+/// Filtrar trips y guardar resultados en un archivo CSV con todas las columnas de
+/// TripColumn, o Parquet si termina en .parquet
 pub fn filter_to_file<P: AsRef<Path>>(
-    csv_path: P, 
+    csv_path: P,
     output_file: P,
-    filter: TripFilter, 
-    max_results: Option<usize>
+    filter: TripFilter,
+    max_results: Option<usize>,
 ) -> Result<usize, Box<dyn Error>> {
     let output_file = output_file.as_ref();
-    
+
     // Crear directorio padre si no existe
     if let Some(parent) = output_file.parent() {
         fs::create_dir_all(parent)?;
     }
-    
-    let file = File::create(output_file)?;
-    let mut writer = BufWriter::new(file);
-    
-    // Escribir encabezado CSV
-    writeln!(
-        writer,
-        "vendor_id,tpep_pickup_datetime,tpep_dropoff_datetime,passenger_count,trip_distance,ratecode_id,store_and_fwd_flag,pu_location_id,do_location_id,payment_type,fare_amount,extra,mta_tax,tip_amount,tolls_amount,improvement_surcharge,total_amount,congestion_surcharge,index"
-    )?;
-    
+
+    let options = ExportOptions {
+        format: ExportFormat::from_path(output_file),
+        ..ExportOptions::default()
+    };
+    let mut output = TripExporter::create(output_file, &options)?;
+    let count = write_filtered(csv_path, &filter, max_results, |trip| {
+        output.write(&trip.to_trip())
+    })?;
+    output.finish()?;
+
+    Ok(count)
+}
+
+/// Exporta los trips que cumplen el filtro en el formato y con las columnas indicadas
+pub fn export_to_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    csv_path: P,
+    output_file: Q,
+    filter: TripFilter,
+    options: &ExportOptions,
+) -> Result<usize, Box<dyn Error>> {
+    let outputs = QueryOutputs {
+        file: Some(FileOutput {
+            path: output_file.as_ref().to_path_buf(),
+            options: options.clone(),
+        }),
+        ..QueryOutputs::default()
+    };
+
+    Ok(execute_query(csv_path, &filter, &outputs)?.matched)
+}
+
+/// Entrega a `write` los trips que cumplen el filtro, hasta max_results; devuelve cuántos fueron
+fn write_filtered<P, F>(
+    csv_path: P,
+    filter: &TripFilter,
+    max_results: Option<usize>,
+    mut write: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
     let mut count = 0;
-    
-    // Verificar si podemos usar un índice hash para este filtro
-    if let Some(index) = can_use_hash_index(&filter) {
-        println!("Usando índice hash para búsqueda rápida por índice: {}", index);
-        
-        // Obtener la tabla hash
-        let hash_table_ref = get_or_initialize_hash_table(&csv_path)?;
-        if let Some(hash_table) = hash_table_ref.lock().unwrap().as_ref() {
-            // Buscar directamente por índice
-            if let Ok(Some(trip)) = hash_table.get(&index) {
-                // Verificar si el trip completo cumple con todos los criterios del filtro
-                if filter.matches(&trip) {
-                    // Escribir el trip en el archivo de salida
-                    writeln!(
-                        writer,
-                        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-                        trip.vendor_id,
-                        trip.tpep_pickup_datetime,
-                        trip.tpep_dropoff_datetime,
-                        trip.passenger_count,
-                        trip.trip_distance,
-                        trip.ratecode_id,
-                        trip.store_and_fwd_flag,
-                        trip.pu_location_id,
-                        trip.do_location_id,
-                        trip.payment_type,
-                        trip.fare_amount,
-                        trip.extra,
-                        trip.mta_tax,
-                        trip.tip_amount,
-                        trip.tolls_amount,
-                        trip.improvement_surcharge,
-                        trip.total_amount,
-                        trip.congestion_surcharge,
-                        trip.index
-                    )?;
-                    count = 1;
-                }
-            }
-            writer.flush()?;
-            return Ok(count);
-        }
-    }
-    
-    // Si no podemos usar hash o la búsqueda hash falló, caemos al método tradicional
-    println!("Usando escaneo secuencial de CSV para filtrado");
-    
-    // Procesar CSV en streaming y aplicar filtros
-    super::data_lector::stream_process_csv(csv_path, |trip| {
+
+    let mut write_matching = |trip: &TripRef| -> Result<(), Box<dyn Error>> {
         if filter.matches(trip) {
             // Escribir el viaje filtrado al archivo de salida
-            writeln!(
-                writer,
-                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-                trip.vendor_id,
-                trip.tpep_pickup_datetime,
-                trip.tpep_dropoff_datetime,
-                trip.passenger_count,
-                trip.trip_distance,
-                trip.ratecode_id,
-                trip.store_and_fwd_flag,
-                trip.pu_location_id,
-                trip.do_location_id,
-                trip.payment_type,
-                trip.fare_amount,
-                trip.extra,
-                trip.mta_tax,
-                trip.tip_amount,
-                trip.tolls_amount,
-                trip.improvement_surcharge,
-                trip.total_amount,
-                trip.congestion_surcharge,
-                trip.index
-            )?;
-            
+            write(trip)?;
+
             count += 1;
-            
+
             // Verificar si hemos alcanzado el máximo de resultados
-            if let Some(max) = max_results {
-                if count >= max {
-                    return Err("Límite de resultados alcanzado".into());
-                }
+            if let Some(max) = max_results
+                && count >= max
+            {
+                return Err(RESULT_LIMIT_REACHED.into());
             }
         }
-        
+
         Ok(())
-    })
-    .or_else(|e| {
-        // Ignorar el error específico de límite alcanzado
-        if e.to_string() == "Límite de resultados alcanzado" {
-            Ok(())
+    };
+
+    // El límite corta el recorrido de todos los archivos restantes
+    ignore_result_limit(scan_dataset(
+        &csv_path,
+        filter,
+        "filtrado",
+        &mut write_matching,
+    ))?;
+
+    Ok(count)
+}
+
+/// Archivo con los trips que cumplen el filtro, en el formato y con las columnas indicadas
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileOutput {
+    pub path: PathBuf,
+    pub options: ExportOptions,
+}
+
+/// Histograma de una medida con `bins` cubetas del mismo ancho entre min y max
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramSpec {
+    pub measure: Measure,
+    pub min: f64,
+    pub max: f64,
+    pub bins: usize,
+}
+
+impl HistogramSpec {
+    /// Interpreta "medida:min:max:cubetas", por ejemplo total_amount:0:100:20
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = text.split(':').collect();
+        let [measure, min, max, bins] = parts.as_slice() else {
+            return Err(format!(
+                "Histograma inválido: {} (se espera medida:min:max:cubetas)",
+                text
+            ));
+        };
+        let measure = Measure::from_name(measure)
+            .ok_or_else(|| format!("Medida desconocida: {}", measure))?;
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Límite inválido en {}: {}", text, value))
+        };
+        let spec = HistogramSpec {
+            measure,
+            min: number(min)?,
+            max: number(max)?,
+            bins: bins
+                .trim()
+                .parse()
+                .map_err(|_| format!("Cantidad de cubetas inválida: {}", bins))?,
+        };
+
+        if spec.bins == 0 || !spec.min.is_finite() || !spec.max.is_finite() || spec.min >= spec.max
+        {
+            return Err(format!(
+                "Histograma inválido: {} (hace falta min < max y al menos una cubeta)",
+                text
+            ));
+        }
+        Ok(spec)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    pub spec: HistogramSpec,
+    pub counts: Vec<usize>,
+    // Valores fuera de [min, max]
+    pub below: usize,
+    pub above: usize,
+    // Trips sin valor para la medida (propina sin tarifa)
+    pub missing: usize,
+}
+
+impl Histogram {
+    fn new(spec: HistogramSpec) -> Self {
+        Self {
+            counts: vec![0; spec.bins],
+            spec,
+            below: 0,
+            above: 0,
+            missing: 0,
+        }
+    }
+
+    fn add(&mut self, value: Option<f64>) {
+        let Some(value) = value else {
+            self.missing += 1;
+            return;
+        };
+        let HistogramSpec { min, max, bins, .. } = self.spec;
+        if value < min {
+            self.below += 1;
+        } else if value > max {
+            self.above += 1;
         } else {
-            Err(e)
+            // El máximo entra en la última cubeta
+            let bin = ((value - min) / (max - min) * bins as f64) as usize;
+            self.counts[bin.min(bins - 1)] += 1;
         }
-    })?;
-    
-    writer.flush()?;
-    
-    Ok(count)
+    }
+
+    // Límites de una cubeta: incluye el inicio y no el final, salvo la última
+    pub fn bin_range(&self, bin: usize) -> (f64, f64) {
+        let width = (self.spec.max - self.spec.min) / self.spec.bins as f64;
+        (
+            self.spec.min + width * bin as f64,
+            self.spec.min + width * (bin + 1) as f64,
+        )
+    }
+}
+
+/// Resultados que puede calcular execute_query; los que no se piden no se calculan
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryOutputs {
+    pub file: Option<FileOutput>,
+    pub stats: bool,
+    // Cantidad de destinos más frecuentes entre los trips que cumplen el filtro
+    pub top_destinations: Option<usize>,
+    pub histograms: Vec<HistogramSpec>,
+    pub group_by: Option<GroupBySpec>,
+    // Cuantiles y valores distintos calculados con resúmenes
+    pub approx: Vec<ApproxStat>,
+    // Precisión de los resúmenes de approx, de las estadísticas y de la agrupación
+    pub sketch_options: SketchOptions,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryResults {
+    // Trips que cumplen el filtro (las filas escritas en el archivo, si se pidió)
+    pub matched: usize,
+    pub invalid_rows: usize,
+    pub stats: Option<HashMap<String, f64>>,
+    pub top_destinations: Option<Vec<(u16, usize)>>,
+    pub histograms: Vec<Histogram>,
+    pub group_by: Option<ResultTable>,
+    pub approx: Vec<ApproxValue>,
+}
+
+// Sumas y resúmenes para las estadísticas de get_filter_stats
+struct StatsAccumulator {
+    count: usize,
+    total_distance: f64,
+    total_amount: f64,
+    total_passengers: u64,
+    // Viajes con número de pasajeros conocido
+    passenger_trips: usize,
+    fares: QuantileSketch,
+    distances: QuantileSketch,
+    pickup_zones: DistinctCounter,
+}
+
+impl StatsAccumulator {
+    fn new(options: SketchOptions) -> Self {
+        Self {
+            count: 0,
+            total_distance: 0.0,
+            total_amount: 0.0,
+            total_passengers: 0,
+            passenger_trips: 0,
+            fares: QuantileSketch::new(options),
+            distances: QuantileSketch::new(options),
+            pickup_zones: DistinctCounter::new(options),
+        }
+    }
+
+    fn add(&mut self, trip: &TripRef) {
+        self.count += 1;
+        self.total_distance += trip.trip_distance;
+        self.total_amount += trip.total_amount;
+        if let Some(passengers) = trip.passenger_count {
+            self.total_passengers += passengers as u64;
+            self.passenger_trips += 1;
+        }
+        self.fares.add(trip.fare_amount);
+        self.distances.add(trip.trip_distance);
+        self.pickup_zones
+            .add(Value::Integer(trip.pu_location_id as i64));
+    }
+
+    fn finish(mut self, invalid_rows: usize) -> HashMap<String, f64> {
+        let mut stats = HashMap::new();
+
+        // Calcular promedios y almacenar estadísticas
+        stats.insert("count".to_string(), self.count as f64);
+        stats.insert("invalid_rows".to_string(), invalid_rows as f64);
+
+        if self.count > 0 {
+            let count = self.count as f64;
+            stats.insert("avg_distance".to_string(), self.total_distance / count);
+            stats.insert("avg_amount".to_string(), self.total_amount / count);
+            stats.insert("total_amount".to_string(), self.total_amount);
+            if self.passenger_trips > 0 {
+                stats.insert(
+                    "avg_passengers".to_string(),
+                    self.total_passengers as f64 / self.passenger_trips as f64,
+                );
+            }
+            // Exactos hasta SketchOptions::exact_limit viajes, aproximados desde ahí
+            stats.extend(
+                self.fares
+                    .quantile(0.5)
+                    .map(|median| ("median_fare".to_string(), median)),
+            );
+            stats.extend(
+                self.distances
+                    .quantile(0.95)
+                    .map(|p95| ("p95_distance".to_string(), p95)),
+            );
+            stats.insert(
+                "distinct_pickup_zones".to_string(),
+                self.pickup_zones.count(),
+            );
+        }
+
+        stats
+    }
+}
+
+/// Calcula en un solo recorrido del dataset todos los resultados pedidos para un filtro:
+/// el archivo filtrado, las estadísticas, los destinos más frecuentes, los histogramas,
+/// la agrupación y los cuantiles y valores distintos aproximados.
+/// Los trips se reciben prestados y en orden del archivo, así cada resultado es idéntico
+/// al de calcularlo por separado; solo se copian los que se escriben en el archivo
+pub fn execute_query<P: AsRef<Path>>(
+    csv_path: P,
+    filter: &TripFilter,
+    outputs: &QueryOutputs,
+) -> Result<QueryResults, Box<dyn Error>> {
+    let mut exporter = match &outputs.file {
+        Some(file) => {
+            if let Some(parent) = file.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Some(TripExporter::create(&file.path, &file.options)?)
+        }
+        None => None,
+    };
+    let mut matched = 0;
+    let mut stats = StatsAccumulator::new(outputs.sketch_options);
+    let mut destinations: HashMap<u16, usize> = HashMap::new();
+    let mut histograms: Vec<Histogram> = outputs
+        .histograms
+        .iter()
+        .cloned()
+        .map(Histogram::new)
+        .collect();
+    let mut groups = outputs
+        .group_by
+        .clone()
+        .map(|spec| GroupByAccumulator::new(spec, outputs.sketch_options));
+    let mut approx = ApproxAccumulator::new(&outputs.approx, outputs.sketch_options);
+
+    let mut accumulate = |trip: &TripRef| -> Result<(), Box<dyn Error>> {
+        // Las filas candidatas de los índices pueden no cumplir el filtro
+        if !filter.matches(trip) {
+            return Ok(());
+        }
+
+        matched += 1;
+        // Solo el archivo de salida necesita una copia del viaje
+        if let Some(exporter) = exporter.as_mut() {
+            exporter.write(&trip.to_trip())?;
+        }
+        if outputs.stats {
+            stats.add(trip);
+        }
+        if outputs.top_destinations.is_some() {
+            *destinations.entry(trip.do_location_id).or_insert(0) += 1;
+        }
+        for histogram in &mut histograms {
+            histogram.add(histogram.spec.measure.value(trip));
+        }
+        if let Some(groups) = groups.as_mut() {
+            groups.add(trip);
+        }
+        approx.add(trip);
+
+        Ok(())
+    };
+
+    let invalid_rows = scan_dataset(&csv_path, filter, "consulta", &mut accumulate)?;
+    if let Some(exporter) = exporter {
+        exporter.finish()?;
+    }
+
+    Ok(QueryResults {
+        matched,
+        invalid_rows,
+        stats: outputs.stats.then(|| stats.finish(invalid_rows)),
+        top_destinations: outputs
+            .top_destinations
+            .map(|limit| most_frequent(destinations, limit)),
+        histograms,
+        group_by: groups.map(GroupByAccumulator::finish),
+        approx: approx.finish(),
+    })
 }
 
-/// Obtiene estadísticas de los trips que cumplen con un filtro
+/// Obtiene estadísticas de los trips que cumplen con un filtro; la mediana de la tarifa,
+/// el p95 de la distancia y las zonas de origen distintas usan la precisión de `sketch_options`
 pub fn get_filter_stats<P: AsRef<Path>>(
     csv_path: P,
     filter: TripFilter,
+    sketch_options: SketchOptions,
 ) -> Result<HashMap<String, f64>, Box<dyn Error>> {
-    let mut stats = HashMap::new();
-    let mut count = 0;
-    let mut total_distance = 0.0;
-    let mut total_amount = 0.0;
-    let mut total_passengers = 0;
-    
-    // Verificar si podemos usar un índice hash para este filtro
-    if let Some(index) = can_use_hash_index(&filter) {
-        println!("Usando índice hash para estadísticas por índice: {}", index);
-        
-        // Obtener la tabla hash
-        let hash_table_ref = get_or_initialize_hash_table(&csv_path)?;
-        if let Some(hash_table) = hash_table_ref.lock().unwrap().as_ref() {
-            // Buscar directamente por índice
-            if let Ok(Some(trip)) = hash_table.get(&index) {
-                // Verificar si el trip completo cumple con todos los criterios del filtro
-                if filter.matches(&trip) {
-                    count = 1;
-                    total_distance = trip.trip_distance.parse::<f64>().unwrap_or(0.0);
-                    total_amount = trip.total_amount.parse::<f64>().unwrap_or(0.0);
-                    total_passengers = trip.passenger_count.parse::<i32>().unwrap_or(0);
-                    
-                    // Calcular estadísticas
-                    stats.insert("count".to_string(), count as f64);
-                    stats.insert("avg_distance".to_string(), total_distance);
-                    stats.insert("avg_amount".to_string(), total_amount);
-                    stats.insert("avg_passengers".to_string(), total_passengers as f64);
-                    stats.insert("total_amount".to_string(), total_amount);
-                    
-                    return Ok(stats);
+    let outputs = QueryOutputs {
+        stats: true,
+        sketch_options,
+        ..QueryOutputs::default()
+    };
+    let results = execute_query(csv_path, &filter, &outputs)?;
+
+    Ok(results.stats.unwrap_or_default())
+}
+
+// Los `limit` destinos con más viajes; a igual frecuencia, por destino
+fn most_frequent(counts: HashMap<u16, usize>, limit: usize) -> Vec<(u16, usize)> {
+    let mut counts: Vec<(u16, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|d| (std::cmp::Reverse(d.1), d.0));
+    counts.truncate(limit);
+    counts
+}
+
+/// Obtiene una lista de los destinos más populares entre los trips que cumplen un filtro
+pub fn get_popular_destinations<P: AsRef<Path>>(
+    csv_path: P,
+    filter: TripFilter,
+    limit: usize,
+) -> Result<Vec<(u16, usize)>, Box<dyn Error>> {
+    // Solo cuenta los viajes que cumplen el filtro, con el mismo recorrido de
+    // execute_query (y sus índices si el plan los usa)
+    let outputs = QueryOutputs {
+        top_destinations: Some(limit),
+        ..QueryOutputs::default()
+    };
+    let results = execute_query(csv_path, &filter, &outputs)?;
+
+    Ok(results.top_destinations.unwrap_or_default())
+}
+
+/// Busca un viaje por su índice con la tabla hash de cada archivo, sin recorrer los CSV
+pub fn find_by_index<P: AsRef<Path>>(
+    csv_path: P,
+    index: &str,
+) -> Result<Option<Trip>, Box<dyn Error>> {
+    for file in Dataset::open(csv_path)?.files() {
+        // Los Parquet no tienen índice hash y se recorren completos
+        if parquet_io::is_parquet(file) {
+            let filter = TripFilter::Index(index.to_string());
+            let mut found = None;
+            ignore_result_limit(stream_file(file, Some(&filter), |trip| {
+                if filter.matches(trip) {
+                    found = Some(trip.to_trip());
+                    return Err(RESULT_LIMIT_REACHED.into());
                 }
+                Ok(())
+            }))?;
+            if found.is_some() {
+                return Ok(found);
             }
+            continue;
+        }
+
+        let location = with_partition(file, |partition| match &partition.hash {
+            Some(table) => table.get_location(index),
+            None => Err("No hay índice hash disponible".into()),
+        })??;
+
+        if let Some(location) = location {
+            // En un CSV sin columna index la clave es el número de fila
+            return Ok(Some(super::data_lector::read_trip_at(
+                file,
+                location,
+                index.parse().ok(),
+            )?));
         }
     }
-    
-    // Si no podemos usar hash o la búsqueda hash falló, caemos al método tradicional
-    println!("Usando escaneo secuencial de CSV para estadísticas");
-    
-    // Procesar CSV en streaming y acumular estadísticas
-    super::data_lector::stream_process_csv(csv_path, |trip| {
-        if filter.matches(trip) {
+
+    Ok(None)
+}
+
+/// Directorio del índice hash de un archivo del dataset, construyéndolo si hace falta
+fn hash_index_dir(file: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    if !with_partition(file, |partition| partition.hash.is_some())? {
+        return Err("No hay índice hash disponible".into());
+    }
+    Ok(dataset_index_dir(file).join(HASH_DIR))
+}
+
+/// Quita un viaje del índice hash de su archivo; find_by_index deja de encontrarlo
+/// hasta que el índice se reconstruya. Devuelve si estaba indexado
+pub fn remove_from_index<P: AsRef<Path>>(csv_path: P, index: &str) -> Result<bool, Box<dyn Error>> {
+    for file in Dataset::open(csv_path)?.files() {
+        if parquet_io::is_parquet(file) {
+            continue;
+        }
+
+        // Los índices cargados son de solo lectura y ven el cambio en la próxima búsqueda
+        let mut table = DiskHashTable::new(hash_index_dir(file)?)?;
+        if table.remove(index)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Recorre los viajes de los índices hash del dataset, archivo por archivo y en el
+/// orden de sus buckets
+pub fn for_each_indexed<P, F>(csv_path: P, mut f: F) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Trip) -> Result<(), Box<dyn Error>>,
+{
+    let mut count = 0;
+    for file in Dataset::open(csv_path)?.files() {
+        if parquet_io::is_parquet(file) {
+            continue;
+        }
+
+        let table =
+            DiskHashTable::open(hash_index_dir(file)?)?.ok_or("No se encontró el índice hash")?;
+        for entry in table.iter() {
+            let (_, trip) = entry?;
+            f(&trip)?;
             count += 1;
-            total_distance += trip.trip_distance.parse::<f64>().unwrap_or(0.0);
-            total_amount += trip.total_amount.parse::<f64>().unwrap_or(0.0);
-            total_passengers += trip.passenger_count.parse::<i32>().unwrap_or(0);
         }
-        
-        Ok(())
-    })?;
-    
-    // Calcular promedios y almacenar estadísticas
-    stats.insert("count".to_string(), count as f64);
-    
-    if count > 0 {
-        stats.insert("avg_distance".to_string(), total_distance / count as f64);
-        stats.insert("avg_amount".to_string(), total_amount / count as f64);
-        stats.insert(
-            "avg_passengers".to_string(),
-            total_passengers as f64 / count as f64,
-        );
-        stats.insert("total_amount".to_string(), total_amount);
     }
-    
-    Ok(stats)
+
+    Ok(count)
 }
 
-/// Obtiene una lista de los destinos más populares
-pub fn get_popular_destinations<P: AsRef<Path>>(
-    csv_path: P,
-    limit: usize,
-) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
-    // Para destinos populares, necesitamos procesar todos los registros
-    // así que no hay ventaja en usar la hash table aquí
-    let mut dest_counts: HashMap<String, usize> = HashMap::new();
-    
-    // Contar ocurrencias de cada destino
-    super::data_lector::stream_process_csv(csv_path, |trip| {
-        let dest = &trip.do_location_id;
-        *dest_counts.entry(dest.clone()).or_insert(0) += 1;
-        
-        Ok(())
-    })?;
-    
-    // Convertir a vector para ordenar
-    let mut dest_vec: Vec<(String, usize)> = dest_counts.into_iter().collect();
-    dest_vec.sort_by(|a, b| b.1.cmp(&a.1)); // Ordenar por frecuencia descendente
-    
-    // Limitar resultados
-    let result = dest_vec.into_iter().take(limit).collect();
-    
-    Ok(result)
-}
-
-/// Nueva función: Inicializar manualmente el índice hash
+/// Nueva función: Inicializar manualmente los índices de todos los archivos del dataset
 pub fn initialize_hash_index<P: AsRef<Path>>(csv_path: P) -> Result<usize, Box<dyn Error>> {
-    println!("Inicializando índice hash manualmente...");
-    let hash_path = PathBuf::from(HASH_DIR);
-    
-    // Limpiar índice existente si existe
-    if hash_path.exists() {
-        println!("Eliminando índice hash existente...");
-        fs::remove_dir_all(&hash_path)?;
-    }
-    
-    // Construir nuevo índice
-    println!("Construyendo nuevo índice hash...");
-    let count = build_hash_table_from_csv(csv_path, &hash_path)?;
-    
-    // Reinicializar la referencia estática
-    let hash_table = DiskHashTable::new(&hash_path)?;
-    let mut table_ref = HASH_TABLE.lock().unwrap();
-    *table_ref = Some(hash_table);
-    
-    println!("Índice hash inicializado con {} registros", count);
-    
+    eprintln!("Inicializando índice hash manualmente...");
+    let dataset = Dataset::open(csv_path)?;
+    let _build = BUILD_LOCK.lock().unwrap();
+    let mut count = 0;
+
+    for file in dataset.files() {
+        if parquet_io::is_parquet(file) {
+            eprintln!("{} es Parquet y no usa índices", file.display());
+            continue;
+        }
+
+        // Construir nuevos índices (reemplazan los existentes); mientras tanto las
+        // consultas de este archivo esperan en BUILD_LOCK y las de los demás siguen
+        let dataset_dir = dataset_index_dir(file);
+        PARTITIONS.lock().unwrap().remove(&dataset_dir);
+        eprintln!(
+            "Construyendo nuevos índices en {}...",
+            dataset_dir.display()
+        );
+        count += build_all_indexes(file, &dataset_dir)?;
+
+        // Reemplazar los índices cargados de este archivo
+        let partition = open_indexes(&dataset_dir);
+        if partition.hash.is_none() {
+            return Err("No se encontró el índice hash recién construido".into());
+        }
+        PARTITIONS.lock().unwrap().insert(dataset_dir, partition);
+    }
+
+    eprintln!("Índice hash inicializado con {} registros", count);
+
     Ok(count)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::aggregate::Value;
+    use super::super::test_support::{
+        TestDir, read_trips, read_trips_allowing_errors, sample_trips_csv,
+    };
+    use super::super::trip_struct::TripColumn;
+    use super::*;
+
+    // Los valores con comas, comillas y saltos de línea se vuelven a leer igual
+    #[test]
+    fn filtered_csv_round_trip() {
+        let dir = TestDir::with_index_root("round-trip");
+
+        let input = dir.join("input.csv");
+        let mut writer = csv::Writer::from_path(&input).unwrap();
+        writer
+            .write_record(TripColumn::ALL[..19].iter().map(TripColumn::name))
+            .unwrap();
+        let vendors = [
+            "1",
+            "2,a",
+            "dice \"hola\"",
+            "varias\nlíneas",
+            " con espacios ",
+        ];
+        for (i, vendor) in vendors.iter().enumerate() {
+            let fare = 10.1 + i as f64 * 0.7;
+            writer
+                .write_record([
+                    vendor.to_string(),
+                    format!("2020-06-0{} 10:00:00", i + 1),
+                    format!("2020-06-0{} 10:15:00", i + 1),
+                    (i % 4 + 1).to_string(),
+                    (i as f64 / 3.0).to_string(),
+                    "1".to_string(),
+                    "N".to_string(),
+                    (10 + i).to_string(),
+                    (20 + i).to_string(),
+                    "1".to_string(),
+                    fare.to_string(),
+                    "0.5".to_string(),
+                    "0.5".to_string(),
+                    "1.1".to_string(),
+                    "0".to_string(),
+                    "0.3".to_string(),
+                    (fare + 2.4).to_string(),
+                    "2.5".to_string(),
+                    format!("{},{}", i, i),
+                ])
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let original = read_trips(&input);
+        assert_eq!(original.len(), vendors.len());
+
+        // Escaneo completo
+        let output = dir.join("output.csv");
+        let count = filter_to_file(&input, &output, TripFilter::And(Vec::new()), None).unwrap();
+        assert_eq!(count, original.len());
+        assert_eq!(read_trips(&output), original);
+
+        // Búsqueda con el índice hash, que lee la fila por su posición en bytes
+        let count =
+            filter_to_file(&input, &output, TripFilter::Index("3,3".to_string()), None).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(read_trips(&output), vec![original[3].clone()]);
+    }
+
+    // Un CSV de TLC con otro orden de columnas, airport_fee y sin index se lee por
+    // el encabezado; las lecturas con índices usan el mismo número de fila como index
+    #[test]
+    fn csv_mapped_by_header() {
+        let dir = TestDir::with_index_root("header");
+
+        let input = dir.join("yellow_tripdata.csv");
+        let mut rows = vec![
+            "tpep_pickup_datetime,VendorID,tpep_dropoff_datetime,trip_distance,PULocationID,DOLocationID,payment_type,fare_amount,total_amount,passenger_count,Airport_fee".to_string(),
+        ];
+        for i in 0..200 {
+            let passengers = if i % 7 == 0 {
+                String::new()
+            } else {
+                (i % 4 + 1).to_string()
+            };
+            rows.push(format!(
+                "2023-01-01 10:{:02}:00,2,2023-01-01 11:00:00,{}.5,{},{},1,{}.0,{}.5,{},1.25",
+                i % 60,
+                i % 9,
+                100 + i % 3,
+                1 + i % 50,
+                10 + i,
+                12 + i,
+                passengers
+            ));
+        }
+        // Fila inválida (la fila de datos 50): también cuenta para numerar las siguientes
+        rows.insert(51, "x,2,x,x,x,x,x,x,x,x,x".to_string());
+        fs::write(&input, rows.join("\n") + "\n").unwrap();
+
+        let original = read_trips_allowing_errors(&input);
+        assert_eq!(original.len(), 200);
+        assert_eq!(original[0].index, "0");
+        assert_eq!(original[49].index, "49");
+        assert_eq!(original[50].index, "51");
+        // Pasajeros vacíos y congestion_surcharge ausente son desconocidos
+        assert_eq!(original[0].passenger_count, None);
+        assert_eq!(original[0].congestion_surcharge, None);
+        assert_eq!(original[1].passenger_count, Some(2));
+        assert_eq!(original[1].extra, 0.0);
+
+        // El destino 1 es poco frecuente, así que el plan usa el índice secundario
+        initialize_hash_index(&input).unwrap();
+        let output = dir.join("output.csv");
+        let count = filter_to_file(&input, &output, TripFilter::Destination(1), None).unwrap();
+        let expected: Vec<Trip> = original
+            .iter()
+            .filter(|trip| trip.do_location_id == 1)
+            .cloned()
+            .collect();
+        assert_eq!(count, expected.len());
+        assert_eq!(read_trips(&output), expected);
+
+        let found = find_by_index(&input, "51").unwrap();
+        assert_eq!(found.as_ref(), Some(&original[50]));
+    }
+
+    // Un viaje quitado del índice hash ya no se encuentra ni se recorre, y el índice
+    // se conserva mientras el CSV no cambie
+    #[test]
+    fn removed_trips_leave_the_hash_index() {
+        let dir = TestDir::with_index_root("remove");
+        let input = dir.write("trips.csv", sample_trips_csv(50));
+        let original = read_trips(&input);
+
+        assert!(find_by_index(&input, "17").unwrap().is_some());
+        assert!(remove_from_index(&input, "17").unwrap());
+        assert!(!remove_from_index(&input, "17").unwrap());
+        assert!(!remove_from_index(&input, "no-existe").unwrap());
+        assert_eq!(find_by_index(&input, "17").unwrap(), None);
+
+        let mut indexed = Vec::new();
+        let count = for_each_indexed(&input, |trip| {
+            indexed.push(trip.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 49);
+        indexed.sort_by_key(|trip| trip.index.parse::<usize>().unwrap());
+        let expected: Vec<Trip> = original
+            .into_iter()
+            .filter(|trip| trip.index != "17")
+            .collect();
+        assert_eq!(indexed, expected);
+
+        // Otra carga de los índices reutiliza el mismo índice hash
+        PARTITIONS.lock().unwrap().clear();
+        assert_eq!(find_by_index(&input, "17").unwrap(), None);
+        assert!(find_by_index(&input, "18").unwrap().is_some());
+    }
+
+    // Un hilo que construye los índices de un archivo no bloquea las consultas sobre
+    // los archivos ya cargados, y los que esperan la construcción reciben sus índices
+    #[test]
+    fn indexes_are_built_outside_the_partitions_lock() {
+        let dir = TestDir::with_index_root("concurrent");
+        let loaded = dir.write("loaded.csv", sample_trips_csv(40));
+        let fresh = dir.write("fresh.csv", sample_trips_csv(60));
+        assert!(find_by_index(&loaded, "3").unwrap().is_some());
+
+        // Con BUILD_LOCK tomado los hilos de fresh.csv quedan esperando para construir
+        let build = BUILD_LOCK.lock().unwrap();
+        std::thread::scope(|scope| {
+            let waiting: Vec<_> = (0..3)
+                .map(|_| scope.spawn(|| find_by_index(&fresh, "59").unwrap()))
+                .collect();
+            assert!(find_by_index(&loaded, "5").unwrap().is_some());
+            drop(build);
+
+            for thread in waiting {
+                assert_eq!(thread.join().unwrap().unwrap().index, "59");
+            }
+        });
+    }
+
+    // Un solo recorrido da los mismos resultados que calcular cada uno por separado,
+    // con un escaneo completo y con el índice secundario
+    #[test]
+    fn single_pass_query_matches_separate_calls() {
+        let dir = TestDir::with_index_root("single-pass");
+        let input = dir.write("trips.csv", sample_trips_csv(300));
+        let original = read_trips(&input);
+
+        let output = dir.join("single_pass.csv");
+        let outputs = QueryOutputs {
+            file: Some(FileOutput {
+                path: output.clone(),
+                options: ExportOptions::default(),
+            }),
+            stats: true,
+            top_destinations: Some(5),
+            histograms: vec![
+                HistogramSpec::parse("total_amount:0:40:8").unwrap(),
+                HistogramSpec::parse("tip_percentage:0:50:5").unwrap(),
+            ],
+            group_by: Some(GroupBySpec::parse("do_location_id", "count").unwrap()),
+            approx: ApproxStat::parse_list("median(total_amount), distinct(do_location_id)")
+                .unwrap(),
+            sketch_options: SketchOptions::default(),
+        };
+        let filter = parse_query("total_amount >= 12 and not do_location_id = 3").unwrap();
+        let results = execute_query(&input, &filter, &outputs).unwrap();
+
+        let expected: Vec<Trip> = original
+            .iter()
+            .filter(|trip| filter.matches(&trip.as_trip_ref()))
+            .cloned()
+            .collect();
+        assert_eq!(results.matched, expected.len());
+        assert_eq!(read_trips(&output), expected);
+
+        let separate = dir.join("separate.csv");
+        filter_to_file(&input, &separate, filter.clone(), None).unwrap();
+        assert_eq!(fs::read(&separate).unwrap(), fs::read(&output).unwrap());
+
+        let stats = results.stats.unwrap();
+        let total: f64 = expected.iter().map(|trip| trip.total_amount).sum();
+        assert_eq!(stats["count"], expected.len() as f64);
+        assert_eq!(stats["total_amount"], total);
+
+        // Con pocos trips los cuantiles y los distintos son exactos
+        let mut amounts: Vec<f64> = expected.iter().map(|trip| trip.total_amount).collect();
+        amounts.sort_by(f64::total_cmp);
+        let middle = amounts.len() / 2;
+        let median = if amounts.len().is_multiple_of(2) {
+            (amounts[middle - 1] + amounts[middle]) / 2.0
+        } else {
+            amounts[middle]
+        };
+        assert_eq!(results.approx[0].value, Some(median));
+        assert!(results.approx[0].exact);
+        let zones: std::collections::HashSet<u16> =
+            expected.iter().map(|trip| trip.pu_location_id).collect();
+        assert_eq!(stats["distinct_pickup_zones"], zones.len() as f64);
+
+        let mut counts = HashMap::new();
+        for trip in &expected {
+            *counts.entry(trip.do_location_id).or_insert(0) += 1;
+        }
+        let groups = results.group_by.unwrap();
+        assert_eq!(groups.rows.len(), counts.len());
+        for row in &groups.rows {
+            let [Value::Integer(dest), Value::Integer(count)] = row.as_slice() else {
+                panic!("fila con tipos inesperados: {:?}", row);
+            };
+            assert_eq!(counts[&(*dest as u16)], *count as usize);
+        }
+        let top = most_frequent(counts, 5);
+        assert_eq!(results.top_destinations.unwrap(), top);
+        assert_eq!(
+            get_popular_destinations(&input, filter.clone(), 5).unwrap(),
+            top
+        );
+
+        let amounts = &results.histograms[0];
+        assert_eq!(
+            amounts.counts.iter().sum::<usize>() + amounts.below + amounts.above,
+            expected.len()
+        );
+        let third_bin = expected
+            .iter()
+            .filter(|trip| (10.0..15.0).contains(&trip.total_amount))
+            .count();
+        assert!(third_bin > 0);
+        assert_eq!(amounts.counts[2], third_bin);
+        let tips = &results.histograms[1];
+        let without_fare = expected
+            .iter()
+            .filter(|trip| trip.fare_amount <= 0.0)
+            .count();
+        assert!(without_fare > 0);
+        assert_eq!(tips.missing, without_fare);
+
+        // El destino 7 es poco frecuente y se lee con el índice secundario
+        initialize_hash_index(&input).unwrap();
+        let filter = TripFilter::Destination(7);
+        let results = execute_query(&input, &filter, &outputs).unwrap();
+        let expected: Vec<Trip> = original
+            .iter()
+            .filter(|trip| filter.matches(&trip.as_trip_ref()))
+            .cloned()
+            .collect();
+        assert_eq!(results.matched, expected.len());
+        assert_eq!(read_trips(&output), expected);
+        assert_eq!(results.top_destinations.unwrap(), vec![(7, expected.len())]);
+    }
+}
@@ -0,0 +1,901 @@
+// Lenguaje de consultas en texto para TripFilter, por ejemplo:
+//   total_amount between 10 and 50 and (do_location_id = 236 or not payment_type = 2)
+//
+// Precedencia de menor a mayor: or, and, not. Los campos de rango admiten
+// =, !=, <, <=, >, >= y between; los demás solo = y !=.
+use super::TripFilter;
+use crate::data::trip_struct::{DATETIME_OUTPUT_FORMAT, PaymentType, parse_datetime_str};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use std::error::Error;
+use std::fmt;
+
+// Error de sintaxis con la posición (en caracteres) donde se detectó
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "posición {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl Error for QueryError {}
+
+// Compila una consulta en texto a un TripFilter
+pub fn parse_query(query: &str) -> Result<TripFilter, QueryError> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser { tokens, next: 0 };
+    let filter = parser.parse_or()?;
+    let token = parser.peek();
+    if token.kind != TokenKind::End {
+        return Err(parser.error_at(token, "se esperaba 'and', 'or' o el final de la consulta"));
+    }
+    Ok(filter)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Op(CompareOp),
+    // Palabra sin comillas: campos, palabras clave y valores simples
+    Word(String),
+    // Valor entre comillas
+    Text(String),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    // Posición en caracteres dentro de la consulta
+    position: usize,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+            TokenKind::Op(_) => "un operador".to_string(),
+            TokenKind::Word(word) => format!("'{}'", word),
+            TokenKind::Text(text) => format!("\"{}\"", text),
+            TokenKind::End => "el final de la consulta".to_string(),
+        }
+    }
+}
+
+const KEYWORDS: [&str; 6] = ["and", "or", "not", "between", "true", "false"];
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '+' | ':')
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                TokenKind::LParen
+            }
+            ')' => {
+                i += 1;
+                TokenKind::RParen
+            }
+            '=' | '!' | '<' | '>' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('=', Some('=')) => (CompareOp::Eq, 2),
+                    ('=', _) => (CompareOp::Eq, 1),
+                    ('!', Some('=')) => (CompareOp::NotEq, 2),
+                    ('<', Some('>')) => (CompareOp::NotEq, 2),
+                    ('<', Some('=')) => (CompareOp::LtEq, 2),
+                    ('<', _) => (CompareOp::Lt, 1),
+                    ('>', Some('=')) => (CompareOp::GtEq, 2),
+                    ('>', _) => (CompareOp::Gt, 1),
+                    _ => {
+                        return Err(QueryError {
+                            position: start,
+                            message: "se esperaba '!='".to_string(),
+                        });
+                    }
+                };
+                i += len;
+                TokenKind::Op(op)
+            }
+            '"' | '\'' => {
+                // Texto entre comillas; la barra invertida escapa el siguiente carácter
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(QueryError {
+                                position: start,
+                                message: "comillas sin cerrar".to_string(),
+                            });
+                        }
+                        Some(&q) if q == c => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&other) => {
+                            text.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                TokenKind::Text(text)
+            }
+            _ if is_word_char(c) => {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                TokenKind::Word(chars[start..i].iter().collect())
+            }
+            _ => {
+                return Err(QueryError {
+                    position: start,
+                    message: format!("carácter inesperado '{}'", c),
+                });
+            }
+        };
+        tokens.push(Token {
+            kind,
+            position: start,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        position: chars.len(),
+    });
+    Ok(tokens)
+}
+
+// Campos que se pueden usar en una consulta
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    TotalAmount,
+    Index,
+    DropoffLocation,
+    PickupLocation,
+    PickupDatetime,
+    PaymentType,
+    Vendor,
+    Passengers,
+    Distance,
+    TipPercentage,
+    Duration,
+}
+
+impl Field {
+    const ALL: [Field; 11] = [
+        Field::TotalAmount,
+        Field::Index,
+        Field::DropoffLocation,
+        Field::PickupLocation,
+        Field::PickupDatetime,
+        Field::PaymentType,
+        Field::Vendor,
+        Field::Passengers,
+        Field::Distance,
+        Field::TipPercentage,
+        Field::Duration,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Field::TotalAmount => "total_amount",
+            Field::Index => "index",
+            Field::DropoffLocation => "do_location_id",
+            Field::PickupLocation => "pu_location_id",
+            Field::PickupDatetime => "tpep_pickup_datetime",
+            Field::PaymentType => "payment_type",
+            Field::Vendor => "vendor_id",
+            Field::Passengers => "passenger_count",
+            Field::Distance => "trip_distance",
+            Field::TipPercentage => "tip_percentage",
+            Field::Duration => "duration_minutes",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Field::ALL
+            .into_iter()
+            .find(|field| field.name().eq_ignore_ascii_case(name))
+    }
+
+    fn is_range(&self) -> bool {
+        matches!(
+            self,
+            Field::TotalAmount
+                | Field::PickupDatetime
+                | Field::Passengers
+                | Field::Distance
+                | Field::TipPercentage
+                | Field::Duration
+        )
+    }
+}
+
+// Valor de un campo de rango. Un valor puede abarcar un intervalo (una fecha
+// sin hora es el día completo) y los límites estrictos se convierten en
+// inclusivos con el valor representable siguiente
+trait RangeValue: Copy + PartialOrd {
+    // Primer y último valor que representa el texto
+    fn parse_span(text: &str) -> Option<(Self, Self)>;
+    fn next(self) -> Option<Self>;
+    fn prev(self) -> Option<Self>;
+}
+
+impl RangeValue for f64 {
+    fn parse_span(text: &str) -> Option<(Self, Self)> {
+        let value = text.parse::<f64>().ok().filter(|v| !v.is_nan())?;
+        Some((value, value))
+    }
+
+    fn next(self) -> Option<Self> {
+        Some(self.next_up())
+    }
+
+    fn prev(self) -> Option<Self> {
+        Some(self.next_down())
+    }
+}
+
+impl RangeValue for u8 {
+    fn parse_span(text: &str) -> Option<(Self, Self)> {
+        let value = text.parse::<u8>().ok()?;
+        Some((value, value))
+    }
+
+    fn next(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+
+    fn prev(self) -> Option<Self> {
+        self.checked_sub(1)
+    }
+}
+
+// Las fechas del CSV tienen precisión de segundos
+impl RangeValue for NaiveDateTime {
+    fn parse_span(text: &str) -> Option<(Self, Self)> {
+        if let Some(datetime) = parse_datetime_str(text) {
+            return Some((datetime, datetime));
+        }
+        let date = NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d").ok()?;
+        Some((date.and_hms_opt(0, 0, 0)?, date.and_hms_opt(23, 59, 59)?))
+    }
+
+    fn next(self) -> Option<Self> {
+        self.checked_add_signed(TimeDelta::seconds(1))
+    }
+
+    fn prev(self) -> Option<Self> {
+        self.checked_sub_signed(TimeDelta::seconds(1))
+    }
+}
+
+// Límites inclusivos de un rango; None si ningún valor cumple la condición
+type Bounds<T> = Option<(Option<T>, Option<T>)>;
+
+fn compare_bounds<T: RangeValue>(op: CompareOp, (first, last): (T, T)) -> Bounds<T> {
+    match op {
+        CompareOp::Eq | CompareOp::NotEq => Some((Some(first), Some(last))),
+        CompareOp::GtEq => Some((Some(first), None)),
+        CompareOp::Gt => Some((Some(last.next()?), None)),
+        CompareOp::LtEq => Some((None, Some(last))),
+        CompareOp::Lt => Some((None, Some(first.prev()?))),
+    }
+}
+
+// Un valor de la consulta junto con su posición para reportar errores
+struct Value {
+    text: String,
+    position: usize,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next.min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().is_keyword(keyword) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error_at(&self, token: &Token, message: impl Into<String>) -> QueryError {
+        QueryError {
+            position: token.position,
+            message: format!("{} (se encontró {})", message.into(), token.describe()),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<TripFilter, QueryError> {
+        let mut filters = vec![self.parse_and()?];
+        while self.eat_keyword("or") {
+            filters.push(self.parse_and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            TripFilter::Or(filters)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<TripFilter, QueryError> {
+        let mut filters = vec![self.parse_unary()?];
+        while self.eat_keyword("and") {
+            filters.push(self.parse_unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            TripFilter::And(filters)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<TripFilter, QueryError> {
+        if self.eat_keyword("not") {
+            return Ok(TripFilter::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<TripFilter, QueryError> {
+        let token = self.advance();
+        match &token.kind {
+            TokenKind::LParen => {
+                let filter = self.parse_or()?;
+                let close = self.advance();
+                if close.kind != TokenKind::RParen {
+                    return Err(self.error_at(&close, "se esperaba ')'"));
+                }
+                Ok(filter)
+            }
+            // Un AND vacío acepta todo y un OR vacío no acepta nada
+            TokenKind::Word(_) if token.is_keyword("true") => Ok(TripFilter::And(Vec::new())),
+            TokenKind::Word(_) if token.is_keyword("false") => Ok(TripFilter::Or(Vec::new())),
+            TokenKind::Word(name) => {
+                let field = Field::from_name(name).ok_or_else(|| QueryError {
+                    position: token.position,
+                    message: format!(
+                        "campo desconocido '{}'; campos válidos: {}",
+                        name,
+                        Field::ALL.map(|f| f.name()).join(", ")
+                    ),
+                })?;
+                self.parse_comparison(field)
+            }
+            _ => Err(self.error_at(&token, "se esperaba un campo, 'not' o '('")),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, QueryError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Word(ref word) if !KEYWORDS.iter().any(|k| token.is_keyword(k)) => {
+                Ok(Value {
+                    text: word.clone(),
+                    position: token.position,
+                })
+            }
+            TokenKind::Text(text) => Ok(Value {
+                text,
+                position: token.position,
+            }),
+            _ => Err(self.error_at(&token, "se esperaba un valor")),
+        }
+    }
+
+    fn parse_comparison(&mut self, field: Field) -> Result<TripFilter, QueryError> {
+        let op_token = self.advance();
+        let comparison = match op_token.kind {
+            TokenKind::Op(op) => {
+                if !field.is_range() && !matches!(op, CompareOp::Eq | CompareOp::NotEq) {
+                    return Err(QueryError {
+                        position: op_token.position,
+                        message: format!("{} solo admite = y !=", field.name()),
+                    });
+                }
+                Comparison::Compare(op, self.parse_value()?)
+            }
+            TokenKind::Word(_) if op_token.is_keyword("between") => {
+                if !field.is_range() {
+                    return Err(QueryError {
+                        position: op_token.position,
+                        message: format!("{} no admite 'between'", field.name()),
+                    });
+                }
+                let low = self.parse_value()?;
+                let and = self.advance();
+                if !and.is_keyword("and") {
+                    return Err(self.error_at(&and, "se esperaba 'and' en 'between'"));
+                }
+                Comparison::Between(low, self.parse_value()?)
+            }
+            _ => {
+                return Err(self.error_at(
+                    &op_token,
+                    format!("se esperaba un operador después de {}", field.name()),
+                ));
+            }
+        };
+
+        let filter = comparison_filter(field, &comparison)?;
+        Ok(match comparison {
+            Comparison::Compare(CompareOp::NotEq, _) => TripFilter::Not(Box::new(filter)),
+            _ => filter,
+        })
+    }
+}
+
+// Comparación de un campo ya leída
+enum Comparison {
+    Compare(CompareOp, Value),
+    Between(Value, Value),
+}
+
+fn invalid_value(field: Field, value: &Value) -> QueryError {
+    QueryError {
+        position: value.position,
+        message: format!("valor inválido para {}: '{}'", field.name(), value.text),
+    }
+}
+
+fn parse_span<T: RangeValue>(field: Field, value: &Value) -> Result<(T, T), QueryError> {
+    T::parse_span(&value.text).ok_or_else(|| invalid_value(field, value))
+}
+
+// Construye el filtro de rango del campo; sin valores posibles nada coincide
+fn range_filter<T: RangeValue>(
+    field: Field,
+    comparison: &Comparison,
+    make: impl FnOnce(Option<T>, Option<T>) -> TripFilter,
+) -> Result<TripFilter, QueryError> {
+    let bounds = match comparison {
+        Comparison::Compare(op, value) => compare_bounds(*op, parse_span(field, value)?),
+        Comparison::Between(low, high) => {
+            let (first, _) = parse_span::<T>(field, low)?;
+            let (_, last) = parse_span::<T>(field, high)?;
+            Some((Some(first), Some(last)))
+        }
+    };
+    Ok(match bounds {
+        Some((min, max)) => make(min, max),
+        None => TripFilter::Or(Vec::new()),
+    })
+}
+
+// Los campos de igualdad solo llegan aquí con = o !=
+fn equality_value(comparison: &Comparison) -> &Value {
+    match comparison {
+        Comparison::Compare(_, value) | Comparison::Between(value, _) => value,
+    }
+}
+
+fn comparison_filter(field: Field, comparison: &Comparison) -> Result<TripFilter, QueryError> {
+    let value = equality_value(comparison);
+    match field {
+        Field::Index => Ok(TripFilter::Index(value.text.clone())),
+        Field::Vendor => Ok(TripFilter::Vendor(value.text.clone())),
+        Field::DropoffLocation => value
+            .text
+            .parse()
+            .map(TripFilter::Destination)
+            .map_err(|_| invalid_value(field, value)),
+        Field::PickupLocation => value
+            .text
+            .parse()
+            .map(TripFilter::PickupLocation)
+            .map_err(|_| invalid_value(field, value)),
+        Field::PaymentType => value
+            .text
+            .parse()
+            .ok()
+            .and_then(PaymentType::from_code)
+            .map(TripFilter::PaymentType)
+            .ok_or_else(|| invalid_value(field, value)),
+        Field::TotalAmount => {
+            range_filter(field, comparison, |min, max| TripFilter::Price { min, max })
+        }
+        Field::Distance => range_filter(field, comparison, |min, max| TripFilter::Distance {
+            min,
+            max,
+        }),
+        Field::TipPercentage => range_filter(field, comparison, |min, max| {
+            TripFilter::TipPercentage { min, max }
+        }),
+        Field::Duration => range_filter(field, comparison, |min, max| TripFilter::Duration {
+            min,
+            max,
+        }),
+        Field::Passengers => range_filter(field, comparison, |min, max| TripFilter::Passengers {
+            min,
+            max,
+        }),
+        Field::PickupDatetime => range_filter(field, comparison, |from, to| {
+            TripFilter::PickupTime { from, to }
+        }),
+    }
+}
+
+// Escribe un valor sin comillas si se puede leer de vuelta como una sola palabra
+fn quote(text: &str) -> String {
+    let bare = !text.is_empty()
+        && text.chars().all(is_word_char)
+        && !KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(text));
+    if bare {
+        text.to_string()
+    } else {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn format_datetime(datetime: NaiveDateTime) -> String {
+    quote(&datetime.format(DATETIME_OUTPUT_FORMAT).to_string())
+}
+
+fn fmt_range<T: PartialEq>(
+    f: &mut fmt::Formatter<'_>,
+    field: Field,
+    min: Option<T>,
+    max: Option<T>,
+    format: impl Fn(T) -> String,
+) -> fmt::Result {
+    let name = field.name();
+    match (min, max) {
+        (Some(min), Some(max)) if min == max => write!(f, "{} = {}", name, format(min)),
+        (Some(min), Some(max)) => write!(f, "{} between {} and {}", name, format(min), format(max)),
+        (Some(min), None) => write!(f, "{} >= {}", name, format(min)),
+        (None, Some(max)) => write!(f, "{} <= {}", name, format(max)),
+        // Un rango sin límites acepta todas las filas
+        (None, None) => write!(f, "true"),
+    }
+}
+
+// Los AND y OR anidados van entre paréntesis para conservar la estructura
+fn fmt_operand(f: &mut fmt::Formatter<'_>, filter: &TripFilter) -> fmt::Result {
+    match filter {
+        TripFilter::And(filters) | TripFilter::Or(filters) if !filters.is_empty() => {
+            write!(f, "({})", filter)
+        }
+        _ => write!(f, "{}", filter),
+    }
+}
+
+fn fmt_list(f: &mut fmt::Formatter<'_>, filters: &[TripFilter], separator: &str) -> fmt::Result {
+    for (i, filter) in filters.iter().enumerate() {
+        if i > 0 {
+            write!(f, " {} ", separator)?;
+        }
+        fmt_operand(f, filter)?;
+    }
+    Ok(())
+}
+
+// Escribe el filtro en el lenguaje de consultas; parse_query lo lee de vuelta
+impl fmt::Display for TripFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let number = |value: f64| value.to_string();
+        match self {
+            TripFilter::Price { min, max } => fmt_range(f, Field::TotalAmount, *min, *max, number),
+            TripFilter::Index(index) => write!(f, "{} = {}", Field::Index.name(), quote(index)),
+            TripFilter::Destination(dest) => {
+                write!(f, "{} = {}", Field::DropoffLocation.name(), dest)
+            }
+            TripFilter::PickupTime { from, to } => {
+                fmt_range(f, Field::PickupDatetime, *from, *to, format_datetime)
+            }
+            TripFilter::PickupLocation(pickup) => {
+                write!(f, "{} = {}", Field::PickupLocation.name(), pickup)
+            }
+            TripFilter::PaymentType(payment) => {
+                write!(f, "{} = {}", Field::PaymentType.name(), payment.code())
+            }
+            TripFilter::Vendor(vendor) => write!(f, "{} = {}", Field::Vendor.name(), quote(vendor)),
+            TripFilter::Passengers { min, max } => {
+                fmt_range(f, Field::Passengers, *min, *max, |value: u8| {
+                    value.to_string()
+                })
+            }
+            TripFilter::Distance { min, max } => fmt_range(f, Field::Distance, *min, *max, number),
+            TripFilter::TipPercentage { min, max } => {
+                fmt_range(f, Field::TipPercentage, *min, *max, number)
+            }
+            TripFilter::Duration { min, max } => fmt_range(f, Field::Duration, *min, *max, number),
+            TripFilter::And(filters) if filters.is_empty() => write!(f, "true"),
+            TripFilter::Or(filters) if filters.is_empty() => write!(f, "false"),
+            TripFilter::And(filters) => fmt_list(f, filters, "and"),
+            TripFilter::Or(filters) => fmt_list(f, filters, "or"),
+            TripFilter::Not(filter) => {
+                write!(f, "not ")?;
+                fmt_operand(f, filter)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(text: &str) -> NaiveDateTime {
+        parse_datetime_str(text).unwrap()
+    }
+
+    fn error(query: &str) -> QueryError {
+        parse_query(query).unwrap_err()
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let unknown = error("total_amount > 5 and foo = 3");
+        assert_eq!(unknown.position, 21);
+        assert!(unknown.message.starts_with("campo desconocido 'foo'"));
+        assert!(unknown.to_string().starts_with("posición 22: "));
+
+        let unclosed = error("vendor_id = 'abc");
+        assert_eq!(unclosed.position, 12);
+        assert_eq!(unclosed.message, "comillas sin cerrar");
+
+        let missing_paren = error("(do_location_id = 5 or index = 3");
+        assert_eq!(missing_paren.position, 32);
+        assert_eq!(
+            missing_paren.message,
+            "se esperaba ')' (se encontró el final de la consulta)"
+        );
+        let missing_or = error("(do_location_id = 5 index = 3)");
+        assert_eq!(missing_or.position, 20);
+        assert_eq!(missing_or.message, "se esperaba ')' (se encontró 'index')");
+
+        for (query, position) in [
+            ("do_location_id < 5", 15),
+            ("vendor_id between 1 and 2", 10),
+            ("passenger_count = many", 18),
+            ("passenger_count > 256", 18),
+            ("total_amount between 5 or 6", 23),
+            ("tpep_pickup_datetime >= '2023-02-30'", 24),
+            ("payment_type = 9", 15),
+            ("total_amount = 5 extra", 17),
+            ("not", 3),
+            ("total_amount ! 5", 13),
+            ("index = 5 # 3", 10),
+        ] {
+            assert_eq!(error(query).position, position, "{}", query);
+        }
+    }
+
+    #[test]
+    fn strict_bounds_become_inclusive() {
+        // u8: los extremos no tienen siguiente ni anterior
+        assert_eq!(
+            compare_bounds(CompareOp::Gt, (3u8, 3)),
+            Some((Some(4), None))
+        );
+        assert_eq!(
+            compare_bounds(CompareOp::Lt, (3u8, 3)),
+            Some((None, Some(2)))
+        );
+        assert_eq!(compare_bounds(CompareOp::Gt, (255u8, 255)), None);
+        assert_eq!(compare_bounds(CompareOp::Lt, (0u8, 0)), None);
+        assert_eq!(
+            compare_bounds(CompareOp::LtEq, (255u8, 255)),
+            Some((None, Some(255)))
+        );
+        assert_eq!(
+            compare_bounds(CompareOp::GtEq, (0u8, 0)),
+            Some((Some(0), None))
+        );
+
+        // Una fecha sin hora abarca el día completo
+        let day = NaiveDateTime::parse_span("2023-03-01").unwrap();
+        assert_eq!(
+            day,
+            (
+                datetime("2023-03-01 00:00:00"),
+                datetime("2023-03-01 23:59:59")
+            )
+        );
+        assert_eq!(
+            compare_bounds(CompareOp::Gt, day),
+            Some((Some(datetime("2023-03-02 00:00:00")), None))
+        );
+        assert_eq!(
+            compare_bounds(CompareOp::Lt, day),
+            Some((None, Some(datetime("2023-02-28 23:59:59"))))
+        );
+        assert_eq!(
+            compare_bounds(CompareOp::Eq, day),
+            Some((Some(day.0), Some(day.1)))
+        );
+        let last = NaiveDateTime::MAX;
+        assert_eq!(compare_bounds(CompareOp::Gt, (last, last)), None);
+        let first = NaiveDateTime::MIN;
+        assert_eq!(compare_bounds(CompareOp::Lt, (first, first)), None);
+
+        assert_eq!(
+            compare_bounds(CompareOp::Gt, (10.0, 10.0)),
+            Some((Some(10.0f64.next_up()), None))
+        );
+        assert!(f64::parse_span("NaN").is_none());
+
+        // Lo mismo desde el texto de la consulta
+        for (query, expected) in [
+            ("passenger_count > 2", "passenger_count >= 3"),
+            ("passenger_count < 1", "passenger_count <= 0"),
+            ("passenger_count > 255", "false"),
+            ("passenger_count < 0", "false"),
+            (
+                "passenger_count between 2 and 4",
+                "passenger_count between 2 and 4",
+            ),
+            (
+                "tpep_pickup_datetime = '2023-03-01'",
+                "tpep_pickup_datetime between \"2023-03-01 00:00:00\" and \"2023-03-01 23:59:59\"",
+            ),
+            (
+                "tpep_pickup_datetime between '2023-03-01' and '2023-03-02'",
+                "tpep_pickup_datetime between \"2023-03-01 00:00:00\" and \"2023-03-02 23:59:59\"",
+            ),
+            (
+                "tpep_pickup_datetime > '2023-03-01 10:00:00'",
+                "tpep_pickup_datetime >= \"2023-03-01 10:00:01\"",
+            ),
+            ("total_amount <= 10", "total_amount <= 10"),
+        ] {
+            assert_eq!(
+                parse_query(query).unwrap().to_string(),
+                expected,
+                "{}",
+                query
+            );
+        }
+        assert!(matches!(
+            parse_query("total_amount < 10").unwrap(),
+            TripFilter::Price { min: None, max: Some(max) } if max == 10.0f64.next_down()
+        ));
+    }
+
+    #[test]
+    fn every_filter_round_trips_through_text() {
+        let day_start = datetime("2023-03-01 08:30:00");
+        let day_end = datetime("2023-03-01 17:45:59");
+        let filters = vec![
+            TripFilter::Price {
+                min: Some(10.5),
+                max: Some(50.0),
+            },
+            TripFilter::Price {
+                min: Some(-5.25),
+                max: None,
+            },
+            TripFilter::Price {
+                min: None,
+                max: Some(0.1),
+            },
+            TripFilter::Index("42".to_string()),
+            TripFilter::Index("fila \"7\" \\ b".to_string()),
+            TripFilter::Destination(236),
+            TripFilter::PickupTime {
+                from: Some(day_start),
+                to: Some(day_end),
+            },
+            TripFilter::PickupTime {
+                from: None,
+                to: Some(day_end),
+            },
+            TripFilter::PickupLocation(0),
+            TripFilter::PaymentType(PaymentType::VoidedTrip),
+            TripFilter::PaymentType(PaymentType::Dispute),
+            TripFilter::Vendor("and".to_string()),
+            TripFilter::Vendor("CMT".to_string()),
+            TripFilter::Passengers {
+                min: Some(3),
+                max: Some(3),
+            },
+            TripFilter::Passengers {
+                min: Some(0),
+                max: Some(255),
+            },
+            TripFilter::Distance {
+                min: Some(1e-7),
+                max: Some(1e10),
+            },
+            TripFilter::TipPercentage {
+                min: Some(15.0),
+                max: None,
+            },
+            TripFilter::Duration {
+                min: None,
+                max: Some(90.5),
+            },
+            TripFilter::And(Vec::new()),
+            TripFilter::Or(Vec::new()),
+            TripFilter::Not(Box::new(TripFilter::Destination(1))),
+            TripFilter::Not(Box::new(TripFilter::And(vec![
+                TripFilter::Destination(1),
+                TripFilter::PickupLocation(2),
+            ]))),
+            TripFilter::Not(Box::new(TripFilter::Not(Box::new(TripFilter::Vendor(
+                "2".to_string(),
+            ))))),
+            TripFilter::Or(vec![
+                TripFilter::Or(vec![TripFilter::Destination(1), TripFilter::Destination(2)]),
+                TripFilter::And(vec![
+                    TripFilter::Price {
+                        min: Some(1.0),
+                        max: Some(2.0),
+                    },
+                    TripFilter::Or(Vec::new()),
+                ]),
+                TripFilter::Index("3".to_string()),
+            ]),
+        ];
+
+        for filter in filters {
+            let text = filter.to_string();
+            let parsed = parse_query(&text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", filter), "{}", text);
+            assert_eq!(parsed.to_string(), text);
+        }
+
+        // Un rango sin límites acepta todo y se escribe como true
+        let unbounded = TripFilter::Distance {
+            min: None,
+            max: None,
+        };
+        assert_eq!(unbounded.to_string(), "true");
+    }
+}
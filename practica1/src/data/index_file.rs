@@ -0,0 +1,191 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+// Registros que se ordenan en memoria antes de escribir una corrida (unos 16 MB)
+pub const RUN_RECORDS: usize = 1 << 20;
+
+/// Registro de tamaño fijo que se puede guardar en una corrida de ExternalSorter
+pub trait RunRecord: Ord + Copy {
+    const LEN: usize;
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+/// Ordena más registros de los que conviene tener en memoria: se acumulan hasta
+/// `run_records`, cada tanda se ordena y se escribe como una corrida en un archivo
+/// temporal, y al terminar las corridas se mezclan leyéndolas en paralelo
+pub struct ExternalSorter<T> {
+    buffer: Vec<T>,
+    run_records: usize,
+    // Las corridas son `<run_prefix>.runN.tmp`
+    run_prefix: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl<T: RunRecord> ExternalSorter<T> {
+    pub fn new(run_prefix: PathBuf, run_records: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            run_records: run_records.max(1),
+            run_prefix,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: T) -> Result<(), Box<dyn Error>> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.run_records {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Ordena la tanda en memoria y la escribe como una corrida
+    fn spill(&mut self) -> Result<(), Box<dyn Error>> {
+        self.buffer.sort_unstable();
+        let mut path = self.run_prefix.clone().into_os_string();
+        path.push(format!(".run{}.tmp", self.runs.len()));
+        let path = PathBuf::from(path);
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.runs.push(path);
+        let mut buf = Vec::with_capacity(T::LEN);
+        for record in self.buffer.drain(..) {
+            buf.clear();
+            record.encode(&mut buf);
+            writer.write_all(&buf)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Registros en orden; si todo cupo en una tanda no se escribe ninguna corrida
+    pub fn finish(mut self) -> Result<SortedRecords<T>, Box<dyn Error>> {
+        if self.runs.is_empty() {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.sort_unstable();
+            return Ok(SortedRecords {
+                memory: buffer.into_iter(),
+                runs: Vec::new(),
+                heap: BinaryHeap::new(),
+            });
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut records = SortedRecords {
+            memory: Vec::new().into_iter(),
+            runs: Vec::new(),
+            heap: BinaryHeap::new(),
+        };
+        for path in std::mem::take(&mut self.runs) {
+            let reader = BufReader::new(File::open(&path)?);
+            records.runs.push(RunReader { reader, path });
+            let run = records.runs.len() - 1;
+            if let Some(record) = records.runs[run].next()? {
+                records.heap.push(Reverse((record, run)));
+            }
+        }
+        Ok(records)
+    }
+}
+
+// Si el ordenamiento no termina (un error al leer el CSV) se borran las corridas
+impl<T> Drop for ExternalSorter<T> {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+struct RunReader {
+    reader: BufReader<File>,
+    path: PathBuf,
+}
+
+impl RunReader {
+    fn next<T: RunRecord>(&mut self) -> Result<Option<T>, Box<dyn Error>> {
+        let mut buf = vec![0u8; T::LEN];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => Ok(Some(T::decode(&buf))),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for RunReader {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Registros de un ExternalSorter en orden: de la memoria, o mezclando las corridas con
+/// un montículo que guarda el siguiente registro de cada una
+pub struct SortedRecords<T> {
+    memory: std::vec::IntoIter<T>,
+    runs: Vec<RunReader>,
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+}
+
+impl<T: RunRecord> Iterator for SortedRecords<T> {
+    type Item = Result<T, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.runs.is_empty() {
+            return self.memory.next().map(Ok);
+        }
+
+        let Reverse((record, run)) = self.heap.pop()?;
+        match self.runs[run].next() {
+            Ok(Some(next)) => self.heap.push(Reverse((next, run))),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(record))
+    }
+}
+
+/// Escribe al final del archivo su cabecera en JSON seguida de su largo (u64); así los
+/// datos se escriben antes de conocer todo lo que describe la cabecera
+pub fn write_trailer<W: Write, H: Serialize>(
+    writer: &mut W,
+    header: &H,
+) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_vec(header)?;
+    writer.write_all(&json)?;
+    writer.write_all(&(json.len() as u64).to_le_bytes())?;
+    Ok(())
+}
+
+/// Lee la cabecera escrita por write_trailer y el largo de los datos que la preceden;
+/// None si el archivo no termina en una cabecera válida (de un formato anterior, por ejemplo)
+pub fn read_trailer<H: DeserializeOwned>(
+    file: &mut File,
+) -> Result<Option<(H, u64)>, Box<dyn Error>> {
+    let file_len = file.metadata()?.len();
+    if file_len < 8 {
+        return Ok(None);
+    }
+    let mut len_buf = [0u8; 8];
+    file.seek(SeekFrom::Start(file_len - 8))?;
+    file.read_exact(&mut len_buf)?;
+    let Some(data_len) = (file_len - 8).checked_sub(u64::from_le_bytes(len_buf)) else {
+        return Ok(None);
+    };
+
+    let mut json = vec![0u8; (file_len - 8 - data_len) as usize];
+    file.seek(SeekFrom::Start(data_len))?;
+    file.read_exact(&mut json)?;
+    Ok(serde_json::from_slice(&json)
+        .ok()
+        .map(|header| (header, data_len)))
+}
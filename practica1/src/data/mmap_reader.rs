@@ -0,0 +1,454 @@
+use super::data_lector::ParseReport;
+use super::dataset::source_name;
+use super::trip_struct::{TripRef, TripSchema};
+use csv::StringRecord;
+use memchr::{memchr, memchr2, memchr3};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+// Encabezado del CSV y dónde empiezan los datos
+pub struct CsvHeader {
+    pub schema: TripSchema,
+    pub fields: usize,
+    pub data_start: u64,
+    pub data_line: u64,
+}
+
+/// CSV mapeado en memoria. Los viajes se convierten directo desde los bytes del
+/// archivo y se entregan como TripRef, sin copiar sus textos
+pub struct MappedCsv {
+    mmap: Mmap,
+    source: String,
+}
+
+impl MappedCsv {
+    pub fn open<P: AsRef<Path>>(filename: P) -> Result<Self, Box<dyn Error>> {
+        let path = filename.as_ref();
+        let file = File::open(path)?;
+        // SAFETY: el CSV no se modifica mientras se lee; los índices ya suponen lo mismo
+        // y verifican con su huella que el archivo no cambió
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        Ok(Self {
+            mmap,
+            source: source_name(path),
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    pub fn len(&self) -> u64 {
+        self.mmap.len() as u64
+    }
+
+    // Lee el encabezado; un archivo vacío no tiene columnas
+    pub fn header(&self) -> Result<CsvHeader, Box<dyn Error>> {
+        let mut reader = RecordReader::new(self.bytes(), 1);
+        let mut fields = Vec::new();
+        let names = match reader.next_record(&mut fields) {
+            Some(Ok(_)) => {
+                StringRecord::from(fields.iter().map(|f| f.as_ref()).collect::<Vec<_>>())
+            }
+            Some(Err(e)) => return Err(format!("Error al leer el encabezado: {}", e).into()),
+            None => StringRecord::new(),
+        };
+
+        Ok(CsvHeader {
+            schema: TripSchema::from_header(&names)?,
+            fields: names.len(),
+            data_start: reader.pos as u64,
+            data_line: reader.line,
+        })
+    }
+
+    // Registros entre dos límites de registro, contando también los inválidos
+    pub fn count_records(&self, range: Range<u64>) -> u64 {
+        let bytes = &self.bytes()[range.start as usize..range.end as usize];
+        let mut reader = RecordReader::new(bytes, 1);
+        let mut fields = Vec::new();
+        let mut count = 0;
+        while reader.next_record(&mut fields).is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Convierte los registros entre dos límites de registro igual que stream_process_csv.
+    /// `first_line` es la línea del archivo donde empieza el rango y `first_row` el número
+    /// de su primera fila de datos, usado como index si el CSV no tiene esa columna
+    pub fn scan_range<E, F>(
+        &self,
+        header: &CsvHeader,
+        range: Range<u64>,
+        first_line: u64,
+        first_row: u64,
+        mut process_trip: F,
+    ) -> Result<ParseReport, E>
+    where
+        F: FnMut(&TripRef) -> Result<(), E>,
+    {
+        self.scan_range_with_positions(header, range, first_line, first_row, |trip, _| {
+            process_trip(trip)
+        })
+    }
+
+    /// Igual que scan_range pero indicando también la posición de cada registro, para
+    /// volver a leerlo después con read_record
+    pub fn scan_range_with_positions<E, F>(
+        &self,
+        header: &CsvHeader,
+        range: Range<u64>,
+        first_line: u64,
+        first_row: u64,
+        mut process_trip: F,
+    ) -> Result<ParseReport, E>
+    where
+        F: FnMut(&TripRef, RecordPosition) -> Result<(), E>,
+    {
+        let bytes = &self.bytes()[range.start as usize..range.end as usize];
+        let mut reader = RecordReader::new(bytes, first_line);
+        let mut fields = Vec::with_capacity(header.fields);
+        let mut report = ParseReport::default();
+
+        loop {
+            let (start, start_line) = (reader.pos, reader.line);
+            let Some(record) = reader.next_record(&mut fields) else {
+                break;
+            };
+            report.rows_read += 1;
+            let line = match record {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Error al leer registro: {}", e);
+                    report.rows_invalid += 1;
+                    continue;
+                }
+            };
+            if fields.len() != header.fields {
+                eprintln!(
+                    "Error al leer registro: la línea {} tiene {} campos y el encabezado {}",
+                    line,
+                    fields.len(),
+                    header.fields
+                );
+                report.rows_invalid += 1;
+                continue;
+            }
+
+            // Las filas se numeran desde 0 contando también las inválidas
+            let row = first_row + report.rows_read as u64 - 1;
+            let field = |position: usize| fields.get(position).map(|f| f.as_ref());
+            match header.schema.parse_fields(field, Some(row)) {
+                Ok(mut trip) => {
+                    trip.source_file.get_or_insert(&self.source);
+                    report.rows_ok += 1;
+                    let position = RecordPosition {
+                        start: range.start + start as u64,
+                        end: range.start + reader.pos as u64,
+                        line: start_line,
+                        row,
+                    };
+                    process_trip(&trip, position)?;
+                }
+                Err(e) => report.record_error(line, e),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Vuelve a convertir un registro válido leído antes por scan_range_with_positions
+    pub fn read_record<E, F>(
+        &self,
+        header: &CsvHeader,
+        position: RecordPosition,
+        process_trip: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(&TripRef) -> Result<(), E>,
+    {
+        self.scan_range(
+            header,
+            position.start..position.end,
+            position.line,
+            position.row,
+            process_trip,
+        )
+        .map(|_| ())
+    }
+}
+
+/// Ubicación de un registro en el archivo: sus bytes, la línea donde empieza y su número
+/// de fila de datos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordPosition {
+    pub start: u64,
+    pub end: u64,
+    pub line: u64,
+    pub row: u64,
+}
+
+/// Igual que stream_process_csv pero leyendo el archivo mapeado en memoria y entregando
+/// viajes prestados; para los recorridos que solo filtran o acumulan
+pub fn stream_process_mmap<P, F>(
+    filename: P,
+    process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
+    let mapped = MappedCsv::open(filename)?;
+    let header = mapped.header()?;
+    let data = header.data_start..mapped.len();
+    let report = mapped.scan_range(&header, data, header.data_line, 0, process_trip)?;
+    report.print_summary();
+
+    Ok(report)
+}
+
+// Separa registros CSV como el lector de csv con sus opciones por defecto: campos
+// separados por ',', comillas dobles con "" como escape, fin de registro \n, \r o \r\n
+// y líneas vacías omitidas. Los campos se toman prestados de los bytes salvo los que
+// tienen comillas escapadas. Las líneas también se cuentan como csv: la de un registro
+// es la posición al terminar el primer byte del fin del anterior, sin contar el \n de
+// un \r\n ni las líneas vacías que siguen
+struct RecordReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // Línea del archivo en la posición actual
+    line: u64,
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(data: &'a [u8], line: u64) -> Self {
+        Self { data, pos: 0, line }
+    }
+
+    // Lee el siguiente registro en `fields`; devuelve la línea donde empieza, o un
+    // error si no es UTF-8 válido (el registro se consume igual)
+    fn next_record(&mut self, fields: &mut Vec<Cow<'a, str>>) -> Option<Result<u64, String>> {
+        let data = self.data;
+        let line = self.line;
+        // Omitir líneas vacías
+        loop {
+            match data.get(self.pos)? {
+                b'\n' => self.line += 1,
+                b'\r' => {}
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        fields.clear();
+
+        // Camino rápido: un registro sin comillas se separa por comas sin copiar nada
+        let rest = &data[self.pos..];
+        let end = memchr2(b'\n', b'\r', rest).unwrap_or(rest.len());
+        let record = &rest[..end];
+        if memchr(b'"', record).is_none() {
+            self.pos += end;
+            self.skip_terminator();
+            return Some(match std::str::from_utf8(record) {
+                Ok(text) => {
+                    fields.extend(text.split(',').map(Cow::Borrowed));
+                    Ok(line)
+                }
+                Err(_) => Err(invalid_utf8(line)),
+            });
+        }
+
+        let mut valid = true;
+        loop {
+            let field = self.read_field();
+            match field {
+                Cow::Borrowed(bytes) => match std::str::from_utf8(bytes) {
+                    Ok(text) => fields.push(Cow::Borrowed(text)),
+                    Err(_) => valid = false,
+                },
+                Cow::Owned(bytes) => match String::from_utf8(bytes) {
+                    Ok(text) => fields.push(Cow::Owned(text)),
+                    Err(_) => valid = false,
+                },
+            }
+
+            if data.get(self.pos) == Some(&b',') {
+                self.pos += 1;
+            } else {
+                self.skip_terminator();
+                break;
+            }
+        }
+
+        Some(if valid {
+            Ok(line)
+        } else {
+            Err(invalid_utf8(line))
+        })
+    }
+
+    // Lee un campo hasta la coma o el fin de registro que lo termina, sin consumirlos
+    fn read_field(&mut self) -> Cow<'a, [u8]> {
+        let data = self.data;
+        if data.get(self.pos) != Some(&b'"') {
+            let end = self.field_end(self.pos);
+            let field = &data[self.pos..end];
+            self.pos = end;
+            return Cow::Borrowed(field);
+        }
+
+        // Campo entre comillas: puede tener comas, saltos de línea y "" por cada comilla
+        self.pos += 1;
+        let mut unescaped: Option<Vec<u8>> = None;
+        let mut segment_start = self.pos;
+        let content_end = loop {
+            let close = memchr(b'"', &data[self.pos..]).map_or(data.len(), |i| self.pos + i);
+            self.line += memchr::memchr_iter(b'\n', &data[self.pos..close]).count() as u64;
+            if data.get(close + 1) == Some(&b'"') {
+                unescaped
+                    .get_or_insert_with(Vec::new)
+                    .extend_from_slice(&data[segment_start..=close]);
+                self.pos = close + 2;
+                segment_start = self.pos;
+            } else {
+                self.pos = (close + 1).min(data.len());
+                break close;
+            }
+        };
+
+        // Lo que sigue a la comilla de cierre se agrega tal cual, como hace csv
+        let tail_end = self.field_end(self.pos);
+        let tail = &data[self.pos..tail_end];
+        self.pos = tail_end;
+
+        match unescaped {
+            None if tail.is_empty() => Cow::Borrowed(&data[segment_start..content_end]),
+            unescaped => {
+                let mut field = unescaped.unwrap_or_default();
+                field.extend_from_slice(&data[segment_start..content_end]);
+                field.extend_from_slice(tail);
+                Cow::Owned(field)
+            }
+        }
+    }
+
+    fn field_end(&self, from: usize) -> usize {
+        memchr3(b',', b'\n', b'\r', &self.data[from..]).map_or(self.data.len(), |i| from + i)
+    }
+
+    // Consume solo el primer byte del fin de registro; el resto se omite como línea vacía
+    fn skip_terminator(&mut self) {
+        match self.data.get(self.pos) {
+            Some(b'\n') => self.line += 1,
+            Some(b'\r') => {}
+            _ => return,
+        }
+        self.pos += 1;
+    }
+}
+
+fn invalid_utf8(line: u64) -> String {
+    format!("la línea {} no es UTF-8 válido", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::data_lector::stream_process_csv;
+    use super::super::parallel_scan::{MergeOrder, ScanConfig, scan_csv};
+    use super::super::test_support::TestDir;
+    use super::super::trip_struct::Trip;
+    use super::*;
+    use std::fs;
+
+    // Los mismos viajes, filas y errores que el lector de csv, también en porciones, con
+    // saltos de línea de Windows, líneas vacías, BOM, comillas escapadas, bytes inválidos
+    // y filas cortas
+    #[test]
+    fn mmap_reader_matches_csv_reader() {
+        let dir = TestDir::new("mmap");
+        let input = dir.join("trips.csv");
+
+        let mut bytes = "\u{feff}VendorID,tpep_pickup_datetime,tpep_dropoff_datetime,trip_distance,PULocationID,DOLocationID,payment_type,fare_amount,total_amount,Index\r\n"
+            .as_bytes()
+            .to_vec();
+        for i in 0..200 {
+            let vendor = match i % 10 {
+                0 => "\"con \"\"comillas\"\"\r\ny salto\"".to_string(),
+                1 => "\"a,b\"c".to_string(),
+                2 => "\"simple\"".to_string(),
+                _ => (i % 2 + 1).to_string(),
+            };
+            bytes.extend_from_slice(
+                format!(
+                    "{},2023-01-01 10:00:00,2023-01-01 10:{:02}:00,{}.5,{},{},1,{}.25,{}.75,{}\r\n",
+                    vendor,
+                    i % 60,
+                    i % 7,
+                    i % 50,
+                    i % 40,
+                    i % 11,
+                    i % 23,
+                    i * 3
+                )
+                .as_bytes(),
+            );
+            match i % 37 {
+                5 => bytes.extend_from_slice(b"\r\n\n"),
+                6 => bytes.extend_from_slice(b"1,\xff\xfe,x,1,1,1,1,1,1,1\n"),
+                7 => bytes.extend_from_slice(b"1,corta\n"),
+                8 => bytes.extend_from_slice(b"1,fecha,mala,1,1,1,1,1,1,1\r"),
+                _ => {}
+            }
+        }
+        // El último registro sin fin de línea
+        bytes.extend_from_slice(b"2,2023-01-02 00:00:00,2023-01-02 00:30:00,3,4,5,2,6,7,final");
+        fs::write(&input, bytes).unwrap();
+
+        let mut expected: Vec<Trip> = Vec::new();
+        let expected_report = stream_process_csv(&input, |trip| {
+            expected.push(trip.clone());
+            Ok(())
+        })
+        .unwrap();
+
+        let mut trips = Vec::new();
+        let report = stream_process_mmap(&input, |trip| {
+            trips.push(trip.to_trip());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(trips, expected);
+        assert_eq!(report.rows_read, expected_report.rows_read);
+        assert_eq!(report.rows_invalid, expected_report.rows_invalid);
+        let lines = |report: &ParseReport| report.errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines(&report), lines(&expected_report));
+        assert!(report.rows_invalid > 0);
+
+        // Repartido en porciones chicas entre varios hilos numera igual las líneas
+        let config = ScanConfig {
+            threads: 3,
+            chunk_bytes: 200,
+        };
+        let mut trips = Vec::new();
+        let report = scan_csv(
+            &input,
+            &config,
+            MergeOrder::Ordered,
+            Vec::new,
+            |acc: &mut Vec<Trip>, trip| acc.push(trip.to_trip()),
+            |acc| {
+                trips.extend(acc);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(trips, expected);
+        assert_eq!(lines(&report), lines(&expected_report));
+    }
+}
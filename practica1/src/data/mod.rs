@@ -1,5 +1,18 @@
+pub mod aggregate;
 pub mod data_lector;
+pub mod dataset;
 pub mod disk_hash;
+pub mod export;
 pub mod filters;
+pub mod index_file;
+pub mod mmap_reader;
+pub mod parallel_scan;
+pub mod parquet_io;
+pub mod planner;
+pub mod row_numbers;
+pub mod secondary_index;
+pub mod sketch;
+pub mod sorted_index;
+#[cfg(test)]
+pub mod test_support;
 pub mod trip_struct;
-
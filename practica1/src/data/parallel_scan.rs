@@ -0,0 +1,749 @@
+use super::data_lector::ParseReport;
+use super::mmap_reader::{CsvHeader, MappedCsv};
+use super::trip_struct::TripRef;
+use memchr::{memchr, memchr_iter};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+// Tamaño aproximado de cada porción del CSV que procesa un hilo
+const CHUNK_BYTES: u64 = 4 * 1024 * 1024;
+
+// Filas que se acumulan antes de entregarlas en el recorrido de un solo hilo
+const SEQUENTIAL_BATCH_ROWS: usize = 4096;
+
+// Hilos de los recorridos paralelos; 0 usa todos los núcleos disponibles
+static SCAN_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+// Los errores de los hilos tienen que poder cruzar entre ellos
+type WorkerError = Box<dyn Error + Send + Sync>;
+
+/// Cambia la cantidad de hilos de los recorridos paralelos (0 = todos los núcleos)
+pub fn set_scan_threads(threads: usize) {
+    SCAN_THREADS.store(threads, Ordering::Relaxed);
+}
+
+/// Hilos que usan los recorridos paralelos
+pub fn scan_threads() -> usize {
+    match SCAN_THREADS.load(Ordering::Relaxed) {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
+    }
+}
+
+// Orden en que se entregan los resultados de cada porción
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeOrder {
+    // En orden del archivo, así el resultado es idéntico al del recorrido secuencial
+    Ordered,
+    // Apenas termina cada porción, para agregados que no dependen del orden
+    Unordered,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScanConfig {
+    pub threads: usize,
+    pub chunk_bytes: u64,
+}
+
+impl ScanConfig {
+    // Hilos configurados y porciones del tamaño por defecto
+    pub fn current() -> Self {
+        Self {
+            threads: scan_threads(),
+            chunk_bytes: CHUNK_BYTES,
+        }
+    }
+}
+
+// Porción del CSV que empieza y termina en un límite de registro
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chunk {
+    start: u64,
+    end: u64,
+    // Línea del archivo donde empieza, para reportar errores
+    first_line: u64,
+    // Número de la primera fila de datos; solo se calcula si el CSV no tiene index
+    first_row: u64,
+}
+
+/// Recorre un CSV mapeado en memoria repartiéndolo en porciones entre varios hilos. Cada
+/// hilo convierte las filas de su porción a TripRef y las acumula con `fold` en un valor
+/// nuevo de `init`; `consume` recibe el acumulado de cada porción en el hilo que llamó,
+/// en orden del archivo o según terminen (ver MergeOrder). Con un solo hilo o un archivo
+/// chico se recorre entero en el hilo que llamó
+pub fn scan_csv<P, A, I, F, C>(
+    filename: P,
+    config: &ScanConfig,
+    order: MergeOrder,
+    init: I,
+    fold: F,
+    mut consume: C,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    A: Send,
+    I: Fn() -> A + Sync,
+    F: Fn(&mut A, &TripRef) + Sync,
+    C: FnMut(A) -> Result<(), Box<dyn Error>>,
+{
+    let mapped = MappedCsv::open(filename)?;
+    let header = mapped.header()?;
+    let chunks = plan_chunks(&mapped, &header, config)?;
+    if chunks.len() <= 1 {
+        return scan_sequential(&mapped, &header, init, fold, consume);
+    }
+
+    let mut report = ParseReport::default();
+    run_pool(
+        &chunks,
+        config.threads,
+        order,
+        |chunk| Ok(scan_chunk(&mapped, chunk, &header, &init, &fold)),
+        |(acc, chunk_report)| {
+            report.merge(chunk_report);
+            consume(acc)
+        },
+    )?;
+
+    report.print_summary();
+    Ok(report)
+}
+
+/// Recorre un CSV como scan_csv y entrega a `process_trip`, en orden del archivo, las
+/// filas que cumplen `keep`. Los hilos solo eligen las filas de su porción y el hilo que
+/// llamó vuelve a convertir las elegidas desde el archivo mapeado, así ningún viaje se
+/// copia ni se guarda mientras esperan las porciones anteriores
+pub fn scan_csv_matching<P, K, F>(
+    filename: P,
+    config: &ScanConfig,
+    keep: K,
+    mut process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    K: Fn(&TripRef) -> bool + Sync,
+    F: FnMut(&TripRef) -> Result<(), Box<dyn Error>>,
+{
+    let mapped = MappedCsv::open(filename)?;
+    let header = mapped.header()?;
+    let chunks = plan_chunks(&mapped, &header, config)?;
+
+    let report = if chunks.len() <= 1 {
+        // En un solo hilo cada fila se entrega apenas se convierte
+        let data = header.data_start..mapped.len();
+        mapped.scan_range(&header, data, header.data_line, 0, |trip| {
+            if keep(trip) {
+                process_trip(trip)
+            } else {
+                Ok(())
+            }
+        })?
+    } else {
+        let mut report = ParseReport::default();
+        run_pool(
+            &chunks,
+            config.threads,
+            MergeOrder::Ordered,
+            |chunk| {
+                let mut positions = Vec::new();
+                let data = chunk.start..chunk.end;
+                let Ok(report) = mapped.scan_range_with_positions(
+                    &header,
+                    data,
+                    chunk.first_line,
+                    chunk.first_row,
+                    |trip, position| {
+                        if keep(trip) {
+                            positions.push(position);
+                        }
+                        Ok::<_, Infallible>(())
+                    },
+                );
+                Ok((positions, report))
+            },
+            |(positions, chunk_report)| {
+                report.merge(chunk_report);
+                positions.into_iter().try_for_each(|position| {
+                    mapped.read_record(&header, position, &mut process_trip)
+                })
+            },
+        )?;
+        report
+    };
+
+    report.print_summary();
+    Ok(report)
+}
+
+// Porciones en que se reparte el CSV entre los hilos; con un solo hilo o un archivo
+// chico queda a lo sumo una y se recorre entero en el hilo que llamó
+fn plan_chunks(
+    mapped: &MappedCsv,
+    header: &CsvHeader,
+    config: &ScanConfig,
+) -> Result<Vec<Chunk>, Box<dyn Error>> {
+    if config.threads <= 1 {
+        return Ok(Vec::new());
+    }
+    let mut chunks = split_chunks(
+        mapped.bytes(),
+        header.data_start,
+        header.data_line,
+        config.chunk_bytes,
+    );
+    if chunks.len() <= 1 {
+        return Ok(chunks);
+    }
+
+    // split_chunks solo cuenta la paridad de comillas, que se equivoca con una comilla
+    // suelta dentro de un campo sin comillas; si alguna porción no termina en un fin de
+    // registro el CSV se recorre en un solo hilo
+    let mut on_records = true;
+    run_pool(
+        &chunks[..chunks.len() - 1],
+        config.threads,
+        MergeOrder::Unordered,
+        |chunk| {
+            Ok(ends_on_record(
+                &mapped.bytes()[chunk.start as usize..chunk.end as usize],
+            ))
+        },
+        |ends| {
+            on_records &= ends;
+            Ok(())
+        },
+    )?;
+    if !on_records {
+        eprintln!(
+            "El CSV tiene comillas dentro de campos sin comillas: se recorre en un solo hilo"
+        );
+        return Ok(Vec::new());
+    }
+
+    // Sin columna index el index es el número de fila, así que hay que saber cuántas
+    // filas tiene cada porción antes de convertirlas
+    if header.schema.synthesizes_index() {
+        let mut counts = Vec::with_capacity(chunks.len());
+        run_pool(
+            &chunks,
+            config.threads,
+            MergeOrder::Ordered,
+            |chunk| Ok(mapped.count_records(chunk.start..chunk.end)),
+            |count| {
+                counts.push(count);
+                Ok(())
+            },
+        )?;
+        let mut first_row = 0;
+        for (chunk, count) in chunks.iter_mut().zip(counts) {
+            chunk.first_row = first_row;
+            first_row += count;
+        }
+    }
+    Ok(chunks)
+}
+
+// Recorrido de un solo hilo; los acumulados se entregan cada SEQUENTIAL_BATCH_ROWS filas
+fn scan_sequential<A, I, F, C>(
+    mapped: &MappedCsv,
+    header: &CsvHeader,
+    init: I,
+    fold: F,
+    mut consume: C,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    I: Fn() -> A,
+    F: Fn(&mut A, &TripRef),
+    C: FnMut(A) -> Result<(), Box<dyn Error>>,
+{
+    let mut acc = init();
+    let mut rows = 0;
+    let data = header.data_start..mapped.len();
+    let report = mapped.scan_range(header, data, header.data_line, 0, |trip| {
+        fold(&mut acc, trip);
+        rows += 1;
+        if rows % SEQUENTIAL_BATCH_ROWS == 0 {
+            consume(std::mem::replace(&mut acc, init()))?;
+        }
+        Ok::<_, Box<dyn Error>>(())
+    })?;
+    consume(acc)?;
+
+    report.print_summary();
+    Ok(report)
+}
+
+// Divide los datos en porciones de unos chunk_bytes que terminan en un fin de registro
+// fuera de comillas. Se cuentan las comillas y saltos de línea desde el inicio, así un
+// campo entre comillas con saltos de línea nunca queda partido. Cada porción termina en
+// el primer byte del fin de registro, como el lector de csv, para numerar igual las líneas
+fn split_chunks(bytes: &[u8], data_start: u64, data_line: u64, chunk_bytes: u64) -> Vec<Chunk> {
+    let len = bytes.len();
+    let chunk_bytes = chunk_bytes.max(1) as usize;
+    let mut chunks = Vec::new();
+    let (mut start, mut line) = (data_start as usize, data_line);
+    let mut quoted = false;
+
+    while start + chunk_bytes < len {
+        // Antes del objetivo solo importa la paridad de comillas y las líneas
+        let target = start + chunk_bytes;
+        let span = &bytes[start..target];
+        quoted ^= memchr_iter(b'"', span).count() % 2 == 1;
+        let mut end_line = line + memchr_iter(b'\n', span).count() as u64;
+
+        let mut end = len;
+        for (i, byte) in bytes[target..].iter().enumerate() {
+            match byte {
+                b'"' => quoted = !quoted,
+                b'\n' | b'\r' => {
+                    end_line += (*byte == b'\n') as u64;
+                    if !quoted {
+                        end = target + i + 1;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        chunks.push(Chunk {
+            start: start as u64,
+            end: end as u64,
+            first_line: line,
+            first_row: 0,
+        });
+        (start, line) = (end, end_line);
+    }
+
+    if start < len {
+        chunks.push(Chunk {
+            start: start as u64,
+            end: len as u64,
+            first_line: line,
+            first_row: 0,
+        });
+    }
+    chunks
+}
+
+// Sigue las comillas de una porción como RecordReader: una comilla abre un campo solo
+// al inicio del campo, en medio de un campo sin comillas es un carácter más, y dentro
+// de un campo entre comillas "" es una comilla escapada. Devuelve si la porción termina
+// fuera de comillas, es decir, en un fin de registro
+fn ends_on_record(bytes: &[u8]) -> bool {
+    let mut pos = 0;
+    while let Some(i) = memchr(b'"', &bytes[pos..]) {
+        let open = pos + i;
+        pos = open + 1;
+        if open > 0 && !matches!(bytes[open - 1], b',' | b'\n' | b'\r') {
+            continue;
+        }
+        // Buscar la comilla de cierre saltando las escapadas
+        loop {
+            let Some(i) = memchr(b'"', &bytes[pos..]) else {
+                return false;
+            };
+            let close = pos + i;
+            if bytes.get(close + 1) == Some(&b'"') {
+                pos = close + 2;
+            } else {
+                pos = close + 1;
+                break;
+            }
+        }
+    }
+    true
+}
+
+// Convierte las filas de una porción igual que stream_process_csv
+fn scan_chunk<A, I, F>(
+    mapped: &MappedCsv,
+    chunk: &Chunk,
+    header: &CsvHeader,
+    init: &I,
+    fold: &F,
+) -> (A, ParseReport)
+where
+    I: Fn() -> A,
+    F: Fn(&mut A, &TripRef),
+{
+    let mut acc = init();
+    let data = chunk.start..chunk.end;
+    let Ok(report) = mapped.scan_range(header, data, chunk.first_line, chunk.first_row, |trip| {
+        fold(&mut acc, trip);
+        Ok::<_, Infallible>(())
+    });
+    (acc, report)
+}
+
+// Porciones entregadas en orden por merge; los hilos esperan aquí para no adelantarse
+#[derive(Default)]
+struct Delivered {
+    count: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl Delivered {
+    // Espera hasta que la porción `i` quede dentro de la ventana o se cancele el recorrido
+    fn wait_for(&self, i: usize, window: usize, abort: &AtomicBool) {
+        let count = self.count.lock().unwrap();
+        let _count = self
+            .changed
+            .wait_while(count, |count| {
+                i >= *count + window && !abort.load(Ordering::Relaxed)
+            })
+            .unwrap();
+    }
+
+    fn set(&self, count: usize) {
+        *self.count.lock().unwrap() = count;
+        self.changed.notify_all();
+    }
+
+    // Despierta a los hilos que esperan para que vean que se canceló
+    fn wake_all(&self) {
+        let _count = self.count.lock().unwrap();
+        self.changed.notify_all();
+    }
+}
+
+// Si un hilo entra en pánico los demás no pueden quedar esperando su porción
+struct AbortOnPanic<'a> {
+    abort: &'a AtomicBool,
+    delivered: &'a Delivered,
+}
+
+impl Drop for AbortOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.abort.store(true, Ordering::Relaxed);
+            self.delivered.wake_all();
+        }
+    }
+}
+
+// Ejecuta `task` sobre cada porción con `threads` hilos, que toman las porciones en
+// orden, y entrega los resultados a `consume` en el hilo que llamó. Si `consume` falla
+// los hilos dejan de tomar porciones. En orden del archivo ningún hilo toma una porción
+// más de `threads * 2` adelante de la que espera merge, así una porción lenta no deja
+// acumular los resultados de todas las siguientes
+fn run_pool<R, T, C>(
+    chunks: &[Chunk],
+    threads: usize,
+    order: MergeOrder,
+    task: T,
+    mut consume: C,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Send,
+    T: Fn(&Chunk) -> Result<R, WorkerError> + Sync,
+    C: FnMut(R) -> Result<(), Box<dyn Error>>,
+{
+    let next = AtomicUsize::new(0);
+    let abort = AtomicBool::new(false);
+    let delivered = Delivered::default();
+    let window = threads * 2;
+
+    thread::scope(|scope| {
+        // Capacidad acotada para que los hilos no se adelanten demasiado al consumidor
+        let (sender, receiver) = mpsc::sync_channel(threads * 2);
+        for _ in 0..threads.min(chunks.len()) {
+            let sender = sender.clone();
+            let (next, abort, task, delivered) = (&next, &abort, &task, &delivered);
+            scope.spawn(move || {
+                let _guard = AbortOnPanic { abort, delivered };
+                while !abort.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(chunk) = chunks.get(i) else {
+                        break;
+                    };
+                    if order == MergeOrder::Ordered {
+                        delivered.wait_for(i, window, abort);
+                        if abort.load(Ordering::Relaxed) {
+                            break;
+                        }
+                    }
+                    if sender.send((i, task(chunk))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let result = merge(receiver, order, &delivered, &mut consume);
+        if result.is_err() {
+            abort.store(true, Ordering::Relaxed);
+            delivered.wake_all();
+        }
+        result
+    })
+}
+
+// Entrega los resultados de las porciones según `order`; al terminar se suelta el
+// receptor y los hilos que sigan enviando se detienen
+fn merge<R, C>(
+    receiver: Receiver<(usize, Result<R, WorkerError>)>,
+    order: MergeOrder,
+    delivered: &Delivered,
+    consume: &mut C,
+) -> Result<(), Box<dyn Error>>
+where
+    C: FnMut(R) -> Result<(), Box<dyn Error>>,
+{
+    let mut pending = BTreeMap::new();
+    let mut expected = 0;
+
+    for (i, result) in receiver {
+        match order {
+            MergeOrder::Unordered => consume(result.map_err(|e| e as Box<dyn Error>)?)?,
+            MergeOrder::Ordered => {
+                // Las porciones que llegan antes de tiempo esperan a las anteriores; son
+                // a lo sumo las de la ventana de run_pool
+                pending.insert(i, result);
+                while let Some(result) = pending.remove(&expected) {
+                    consume(result.map_err(|e| e as Box<dyn Error>)?)?;
+                    expected += 1;
+                    delivered.set(expected);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::data_lector::stream_process_csv;
+    use super::super::test_support::TestDir;
+    use super::super::trip_struct::Trip;
+    use super::*;
+    use std::fs;
+
+    fn collect_sequential(path: &Path) -> (Vec<Trip>, ParseReport) {
+        let mut trips = Vec::new();
+        let report = stream_process_csv(path, |trip| {
+            trips.push(trip.clone());
+            Ok(())
+        })
+        .unwrap();
+        (trips, report)
+    }
+
+    // Porciones chicas con campos entre comillas que cruzan saltos de línea, filas
+    // inválidas y sin columna index: el resultado ordenado es el del recorrido secuencial
+    #[test]
+    fn parallel_scan_matches_sequential() {
+        let dir = TestDir::new("parallel");
+        let input = dir.join("trips.csv");
+
+        let mut text = String::from(
+            "VendorID,tpep_pickup_datetime,tpep_dropoff_datetime,trip_distance,PULocationID,DOLocationID,payment_type,fare_amount,total_amount\n",
+        );
+        for i in 0..500 {
+            let vendor = match i % 25 {
+                0 => "\"varias\nlíneas, \"\"comillas\"\"\"".to_string(),
+                1 => "\"a,b\"".to_string(),
+                _ => (i % 2 + 1).to_string(),
+            };
+            if i % 97 == 0 {
+                text.push_str("1,fecha,inválida,1,1,1,1,1,1\n");
+            }
+            if i % 131 == 0 {
+                text.push_str("1,faltan,campos\n");
+            }
+            text.push_str(&format!(
+                "{},2023-01-01 10:00:00,2023-01-01 10:20:00,{}.1,{},{},1,{}.3,{}.7\n",
+                vendor,
+                i % 13,
+                i % 50,
+                i % 40,
+                i % 17,
+                i % 29
+            ));
+        }
+        fs::write(&input, text).unwrap();
+
+        let (expected, expected_report) = collect_sequential(&input);
+        for threads in [1, 2, 4] {
+            let config = ScanConfig {
+                threads,
+                chunk_bytes: 300,
+            };
+
+            let mut trips = Vec::new();
+            let report = scan_csv(
+                &input,
+                &config,
+                MergeOrder::Ordered,
+                Vec::new,
+                |acc: &mut Vec<Trip>, trip| acc.push(trip.to_trip()),
+                |acc| {
+                    trips.extend(acc);
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(trips, expected);
+            assert_eq!(report.rows_read, expected_report.rows_read);
+            assert_eq!(report.rows_invalid, expected_report.rows_invalid);
+            let lines =
+                |report: &ParseReport| report.errors.iter().map(|e| e.line).collect::<Vec<_>>();
+            assert_eq!(lines(&report), lines(&expected_report));
+
+            let mut total = 0;
+            scan_csv(
+                &input,
+                &config,
+                MergeOrder::Unordered,
+                || 0,
+                |count: &mut usize, _| *count += 1,
+                |count| {
+                    total += count;
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(total, expected.len());
+
+            // Las filas elegidas se vuelven a leer desde su posición, con el mismo index
+            let mut matched = Vec::new();
+            let report = scan_csv_matching(
+                &input,
+                &config,
+                |trip| trip.do_location_id % 3 == 0,
+                |trip| {
+                    matched.push(trip.to_trip());
+                    Ok(())
+                },
+            )
+            .unwrap();
+            let expected_matched: Vec<Trip> = expected
+                .iter()
+                .filter(|trip| trip.do_location_id % 3 == 0)
+                .cloned()
+                .collect();
+            assert_eq!(matched, expected_matched);
+            assert_eq!(report.rows_invalid, expected_report.rows_invalid);
+        }
+
+        // Las porciones terminan en un límite de registro
+        let mapped = MappedCsv::open(&input).unwrap();
+        let header = mapped.header().unwrap();
+        let chunks = split_chunks(mapped.bytes(), header.data_start, header.data_line, 300);
+        assert!(chunks.len() > 10);
+        assert!(chunks.windows(2).all(|pair| pair[0].end == pair[1].start));
+        let bytes = mapped.bytes();
+        assert!(
+            chunks[..chunks.len() - 1]
+                .iter()
+                .all(|chunk| ends_on_record(&bytes[chunk.start as usize..chunk.end as usize]))
+        );
+    }
+
+    // Una comilla suelta en un campo sin comillas invierte la paridad que usa
+    // split_chunks; esas porciones se detectan y el recorrido da lo mismo que el secuencial
+    #[test]
+    fn stray_quotes_fall_back_to_sequential() {
+        let dir = TestDir::new("parallel-quotes");
+        let input = dir.join("trips.csv");
+
+        let mut text = String::from(
+            "VendorID,tpep_pickup_datetime,tpep_dropoff_datetime,trip_distance,PULocationID,DOLocationID,payment_type,fare_amount,total_amount\n",
+        );
+        for i in 0..300 {
+            let vendor = match i % 20 {
+                3 => "2\"x".to_string(),
+                7 => "\"varias\nlíneas\"".to_string(),
+                11 => "\"a\"b,\"c\"".to_string(),
+                _ => (i % 2 + 1).to_string(),
+            };
+            text.push_str(&format!(
+                "{},2023-01-01 10:00:00,2023-01-01 10:20:00,{}.1,{},{},1,{}.3,{}.7\n",
+                vendor,
+                i % 13,
+                i % 50,
+                i % 40,
+                i % 17,
+                i % 29
+            ));
+        }
+        fs::write(&input, text).unwrap();
+
+        let mapped = MappedCsv::open(&input).unwrap();
+        let header = mapped.header().unwrap();
+        let bytes = mapped.bytes();
+        let chunks = split_chunks(bytes, header.data_start, header.data_line, 300);
+        assert!(
+            chunks[..chunks.len() - 1]
+                .iter()
+                .any(|chunk| !ends_on_record(&bytes[chunk.start as usize..chunk.end as usize]))
+        );
+
+        let (expected, expected_report) = collect_sequential(&input);
+        let config = ScanConfig {
+            threads: 4,
+            chunk_bytes: 300,
+        };
+        let mut trips = Vec::new();
+        let report = scan_csv(
+            &input,
+            &config,
+            MergeOrder::Ordered,
+            Vec::new,
+            |acc: &mut Vec<Trip>, trip| acc.push(trip.to_trip()),
+            |acc| {
+                trips.extend(acc);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(trips, expected);
+        assert_eq!(report.rows_invalid, expected_report.rows_invalid);
+    }
+
+    // Con una porción lenta al principio los hilos no se adelantan más que la ventana
+    #[test]
+    fn ordered_pool_waits_for_slow_chunks() {
+        let chunks: Vec<Chunk> = (0..40)
+            .map(|i| Chunk {
+                start: i,
+                end: i + 1,
+                first_line: 0,
+                first_row: 0,
+            })
+            .collect();
+        let first_done = AtomicBool::new(false);
+        let furthest = AtomicUsize::new(0);
+
+        let mut seen = Vec::new();
+        run_pool(
+            &chunks,
+            4,
+            MergeOrder::Ordered,
+            |chunk| {
+                if chunk.start == 0 {
+                    thread::sleep(std::time::Duration::from_millis(100));
+                } else if !first_done.load(Ordering::SeqCst) {
+                    furthest.fetch_max(chunk.start as usize, Ordering::SeqCst);
+                }
+                Ok(chunk.start)
+            },
+            |start| {
+                first_done.store(true, Ordering::SeqCst);
+                seen.push(start);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(seen, (0..40).collect::<Vec<_>>());
+        assert!(furthest.load(Ordering::SeqCst) < 4 * 2);
+    }
+}
@@ -0,0 +1,830 @@
+use super::data_lector::ParseReport;
+use super::dataset::source_name;
+use super::filters::TripFilter;
+use super::trip_struct::{PaymentType, RateCode, Trip, TripColumn, TripParseError};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, TimestampMicrosecondType};
+use arrow_array::{
+    Array, ArrayRef, Float64Array, Int32Array, Int64Array, RecordBatch, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDateTime};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::ProjectionMask;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::basic::Compression;
+use parquet::file::metadata::RowGroupMetaData;
+use parquet::file::properties::WriterProperties;
+use parquet::file::statistics::Statistics;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+// Filas por lote al leer y al escribir
+const BATCH_ROWS: usize = 8192;
+// Filas por grupo al escribir; grupos chicos permiten saltar más al filtrar
+const ROW_GROUP_ROWS: usize = 64 * 1024;
+
+pub fn is_parquet(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("parquet"))
+}
+
+// Columnas del archivo que corresponden a Trip: nombre de Trip -> (posición, nombre original)
+type ColumnMap = HashMap<&'static str, (usize, String)>;
+
+// En los archivos de TLC los nombres cambian de formato (VendorID, PULocationID...),
+// así que se comparan como los del encabezado de un CSV. Las columnas que faltan
+// siguen la misma regla que en un CSV: solo las obligatorias son un error
+fn column_map(schema: &Schema) -> Result<ColumnMap, Box<dyn Error>> {
+    let mut columns = HashMap::new();
+    for column in TripColumn::ALL {
+        if let Some(position) = schema
+            .fields()
+            .iter()
+            .position(|field| TripColumn::from_header(field.name()) == Some(column))
+        {
+            columns.insert(
+                column.name(),
+                (position, schema.field(position).name().clone()),
+            );
+        } else if column.default_value().is_none() {
+            return Err(format!("El archivo Parquet no tiene la columna {}", column.name()).into());
+        }
+    }
+    Ok(columns)
+}
+
+// Rango de valores (mínimo, máximo) de una columna en un grupo de filas según sus
+// estadísticas; las fechas se expresan en segundos desde 1970
+fn column_range(
+    group: &RowGroupMetaData,
+    column: &str,
+    data_type: &DataType,
+) -> Option<(f64, f64)> {
+    let chunk = group
+        .columns()
+        .iter()
+        .find(|chunk| chunk.column_descr().name() == column)?;
+    let (min, max) = match chunk.statistics()? {
+        Statistics::Int32(stats) => (*stats.min_opt()? as f64, *stats.max_opt()? as f64),
+        Statistics::Int64(stats) => (*stats.min_opt()? as f64, *stats.max_opt()? as f64),
+        Statistics::Float(stats) => (*stats.min_opt()? as f64, *stats.max_opt()? as f64),
+        Statistics::Double(stats) => (*stats.min_opt()?, *stats.max_opt()?),
+        _ => return None,
+    };
+
+    let scale = match data_type {
+        DataType::Timestamp(TimeUnit::Second, _) => 1.0,
+        DataType::Timestamp(TimeUnit::Millisecond, _) => 1e3,
+        DataType::Timestamp(TimeUnit::Microsecond, _) => 1e6,
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => 1e9,
+        _ => 1.0,
+    };
+    (!min.is_nan() && !max.is_nan()).then_some((min / scale, max / scale))
+}
+
+fn seconds(datetime: NaiveDateTime) -> f64 {
+    let utc = datetime.and_utc();
+    utc.timestamp() as f64 + utc.timestamp_subsec_micros() as f64 / 1e6
+}
+
+// Falso solo si las estadísticas del grupo garantizan que ninguna fila cumple el filtro
+fn row_group_may_match(filter: &TripFilter, ranges: &HashMap<&'static str, (f64, f64)>) -> bool {
+    let overlaps = |column: &str, min: Option<f64>, max: Option<f64>| match ranges.get(column) {
+        Some((low, high)) => {
+            min.is_none_or(|min| min <= *high) && max.is_none_or(|max| max >= *low)
+        }
+        None => true,
+    };
+    let equals = |column: &str, value: f64| overlaps(column, Some(value), Some(value));
+
+    match filter {
+        TripFilter::Price { min, max } => overlaps("total_amount", *min, *max),
+        TripFilter::Distance { min, max } => overlaps("trip_distance", *min, *max),
+        TripFilter::Passengers { min, max } => {
+            overlaps("passenger_count", min.map(f64::from), max.map(f64::from))
+        }
+        TripFilter::Destination(id) => equals("do_location_id", *id as f64),
+        TripFilter::PickupLocation(id) => equals("pu_location_id", *id as f64),
+        TripFilter::PaymentType(payment) => equals("payment_type", payment.code() as f64),
+        TripFilter::PickupTime { from, to } => {
+            overlaps("tpep_pickup_datetime", from.map(seconds), to.map(seconds))
+        }
+        TripFilter::And(filters) => filters
+            .iter()
+            .all(|filter| row_group_may_match(filter, ranges)),
+        TripFilter::Or(filters) => filters
+            .iter()
+            .any(|filter| row_group_may_match(filter, ranges)),
+        _ => true,
+    }
+}
+
+// Grupos de filas que hay que leer para el filtro, y el total del archivo
+fn select_row_groups(
+    builder: &ParquetRecordBatchReaderBuilder<File>,
+    columns: &ColumnMap,
+    filter: Option<&TripFilter>,
+) -> (Vec<usize>, usize) {
+    let schema = builder.schema();
+    let groups = builder.metadata().row_groups();
+    let selected = (0..groups.len())
+        .filter(|&i| {
+            let Some(filter) = filter else {
+                return true;
+            };
+            let ranges = columns
+                .iter()
+                .filter_map(|(name, (position, original))| {
+                    let data_type = schema.field(*position).data_type();
+                    column_range(&groups[i], original, data_type).map(|range| (*name, range))
+                })
+                .collect();
+            row_group_may_match(filter, &ranges)
+        })
+        .collect();
+    (selected, groups.len())
+}
+
+/// Describe cuántos grupos de filas del archivo se leerían con el filtro
+pub fn explain_row_groups<P: AsRef<Path>>(
+    filename: P,
+    filter: &TripFilter,
+) -> Result<String, Box<dyn Error>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(filename)?)?;
+    let columns = column_map(builder.schema())?;
+    let (selected, total) = select_row_groups(&builder, &columns, Some(filter));
+    let groups = builder.metadata().row_groups();
+    let rows: i64 = selected.iter().map(|&i| groups[i].num_rows()).sum();
+    let total_rows: i64 = groups.iter().map(|group| group.num_rows()).sum();
+
+    Ok(format!(
+        "Plan de consulta: ~{} de {} filas\n  Archivo Parquet: se leen {} de {} grupos de filas según sus estadísticas\n",
+        rows,
+        total_rows,
+        selected.len(),
+        total
+    ))
+}
+
+/// Lee los viajes de un archivo Parquet. Solo se leen las columnas de Trip y, si hay
+/// filtro, se omiten los grupos de filas cuyas estadísticas lo descartan (el filtro
+/// igual hay que aplicarlo a cada viaje)
+pub fn stream_process_parquet<P, F>(
+    filename: P,
+    filter: Option<&TripFilter>,
+    mut process_trip: F,
+) -> Result<ParseReport, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Trip) -> Result<(), Box<dyn Error>>,
+{
+    let source = source_name(filename.as_ref());
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(filename)?)?;
+    let columns = column_map(builder.schema())?;
+    let (selected, _) = select_row_groups(&builder, &columns, filter);
+
+    // Número de la primera fila de cada grupo, para el índice sintético y los errores
+    let metadata = builder.metadata().clone();
+    let groups = metadata.row_groups();
+    let mut first_rows = Vec::with_capacity(selected.len());
+    let mut row = 0;
+    for (i, group) in groups.iter().enumerate() {
+        if selected.contains(&i) {
+            first_rows.push(row);
+        }
+        row += group.num_rows() as u64;
+    }
+
+    let projection = ProjectionMask::roots(
+        builder.parquet_schema(),
+        columns.values().map(|(position, _)| *position),
+    );
+    let reader = builder
+        .with_row_groups(selected.clone())
+        .with_projection(projection)
+        .with_batch_size(BATCH_ROWS)
+        .build()?;
+
+    let mut report = ParseReport::default();
+    let mut group = 0;
+    let mut group_rows_left = selected.first().map_or(0, |&i| groups[i].num_rows() as u64);
+    let mut row = first_rows.first().copied().unwrap_or_default();
+
+    for batch in reader {
+        let batch = TripColumns::new(&batch?, &columns)?;
+        for i in 0..batch.len {
+            // Un lote puede incluir filas de varios grupos seleccionados
+            while group_rows_left == 0 && group + 1 < selected.len() {
+                group += 1;
+                group_rows_left = groups[selected[group]].num_rows() as u64;
+                row = first_rows[group];
+            }
+
+            report.rows_read += 1;
+            match batch.trip(i, row, &source) {
+                Ok(trip) => {
+                    report.rows_ok += 1;
+                    process_trip(&trip)?;
+                }
+                Err(e) => report.record_error(row + 1, e),
+            }
+            row += 1;
+            group_rows_left = group_rows_left.saturating_sub(1);
+        }
+    }
+
+    report.print_summary();
+
+    Ok(report)
+}
+
+// Columnas de un lote ya convertidas a los tipos con que se arma un Trip
+struct TripColumns {
+    len: usize,
+    text: HashMap<&'static str, StringArray>,
+    numbers: HashMap<&'static str, Float64Array>,
+    datetimes: HashMap<&'static str, DatetimeColumn>,
+}
+
+// Las fechas pueden venir como timestamp o como texto
+enum DatetimeColumn {
+    Timestamp(TimestampMicrosecondArray),
+    Text(StringArray),
+}
+
+const TEXT_COLUMNS: [&str; 4] = ["vendor_id", "store_and_fwd_flag", "index", "source_file"];
+const DATETIME_COLUMNS: [&str; 2] = ["tpep_pickup_datetime", "tpep_dropoff_datetime"];
+
+impl TripColumns {
+    fn new(batch: &RecordBatch, columns: &ColumnMap) -> Result<Self, Box<dyn Error>> {
+        let mut text = HashMap::new();
+        let mut numbers = HashMap::new();
+        let mut datetimes = HashMap::new();
+
+        for (name, (_, original)) in columns {
+            let array = batch
+                .column_by_name(original)
+                .ok_or_else(|| format!("Falta la columna {} en el lote", original))?;
+            if TEXT_COLUMNS.contains(name) {
+                let array = arrow_cast::cast(array, &DataType::Utf8)?;
+                text.insert(*name, array.as_string::<i32>().clone());
+            } else if DATETIME_COLUMNS.contains(name) {
+                let column = if matches!(array.data_type(), DataType::Timestamp(_, _)) {
+                    let array =
+                        arrow_cast::cast(array, &DataType::Timestamp(TimeUnit::Microsecond, None))?;
+                    DatetimeColumn::Timestamp(
+                        array.as_primitive::<TimestampMicrosecondType>().clone(),
+                    )
+                } else {
+                    let array = arrow_cast::cast(array, &DataType::Utf8)?;
+                    DatetimeColumn::Text(array.as_string::<i32>().clone())
+                };
+                datetimes.insert(*name, column);
+            } else {
+                let array = arrow_cast::cast(array, &DataType::Float64)?;
+                numbers.insert(*name, array.as_primitive::<Float64Type>().clone());
+            }
+        }
+
+        Ok(Self {
+            len: batch.num_rows(),
+            text,
+            numbers,
+            datetimes,
+        })
+    }
+
+    fn text(&self, field: &'static str, row: usize) -> Result<Option<&str>, TripParseError> {
+        match self.text.get(field) {
+            Some(array) if array.is_null(row) => Err(null_value(field)),
+            Some(array) => Ok(Some(array.value(row))),
+            None => Ok(None),
+        }
+    }
+
+    // Texto de una columna que puede faltar o ser nulo, como un valor vacío en un CSV
+    fn nullable_text(&self, field: &'static str, row: usize) -> Option<&str> {
+        self.text
+            .get(field)
+            .filter(|array| !array.is_null(row) && !array.value(row).is_empty())
+            .map(|array| array.value(row))
+    }
+
+    // Un nulo es un error; si la columna no está en el archivo vale 0 (las
+    // obligatorias ya se comprobaron en column_map)
+    fn number(&self, field: &'static str, row: usize) -> Result<f64, TripParseError> {
+        match self.numbers.get(field) {
+            Some(array) if array.is_null(row) => Err(null_value(field)),
+            Some(array) => Ok(array.value(row)),
+            None => Ok(0.0),
+        }
+    }
+
+    // Número de una columna que admite nulos; None si es nulo o falta la columna
+    fn nullable_number(&self, field: &'static str, row: usize) -> Option<f64> {
+        self.numbers
+            .get(field)
+            .filter(|array| !array.is_null(row))
+            .map(|array| array.value(row))
+    }
+
+    fn integer<T: TryFrom<u64>>(
+        &self,
+        field: &'static str,
+        row: usize,
+    ) -> Result<T, TripParseError> {
+        whole_number(field, self.number(field, row)?)
+    }
+
+    fn nullable_integer<T: TryFrom<u64>>(
+        &self,
+        field: &'static str,
+        row: usize,
+    ) -> Result<Option<T>, TripParseError> {
+        self.nullable_number(field, row)
+            .map(|value| whole_number(field, value))
+            .transpose()
+    }
+
+    fn datetime(&self, field: &'static str, row: usize) -> Result<NaiveDateTime, TripParseError> {
+        match self.datetimes.get(field) {
+            Some(DatetimeColumn::Timestamp(array)) if !array.is_null(row) => {
+                let micros = array.value(row);
+                DateTime::from_timestamp_micros(micros)
+                    .map(|datetime| datetime.naive_utc())
+                    .ok_or_else(|| {
+                        TripParseError::new(field, &micros.to_string(), "fecha fuera de rango")
+                    })
+            }
+            Some(DatetimeColumn::Text(array)) if !array.is_null(row) => {
+                let value = array.value(row);
+                super::trip_struct::parse_datetime_str(value).ok_or_else(|| {
+                    TripParseError::new(field, value, "fecha con formato desconocido")
+                })
+            }
+            _ => Err(null_value(field)),
+        }
+    }
+
+    // Viaje de la fila `row` del lote; `file_row` es su número de fila en el archivo.
+    // Los nulos siguen la regla de los valores vacíos de un CSV (ver TripSchema::parse)
+    fn trip(&self, row: usize, file_row: u64, source: &str) -> Result<Trip, TripParseError> {
+        let payment_code: Option<u8> = self.nullable_integer("payment_type", row)?;
+        let ratecode: Option<u8> = self.nullable_integer("ratecode_id", row)?;
+
+        Ok(Trip {
+            vendor_id: self
+                .nullable_text("vendor_id", row)
+                .unwrap_or_default()
+                .to_string(),
+            tpep_pickup_datetime: self.datetime("tpep_pickup_datetime", row)?,
+            tpep_dropoff_datetime: self.datetime("tpep_dropoff_datetime", row)?,
+            passenger_count: self.nullable_integer("passenger_count", row)?,
+            trip_distance: self.number("trip_distance", row)?,
+            ratecode_id: ratecode
+                .map(|code| {
+                    RateCode::from_code(code).ok_or_else(|| {
+                        TripParseError::new(
+                            "ratecode_id",
+                            &code.to_string(),
+                            "código de tarifa desconocido",
+                        )
+                    })
+                })
+                .transpose()?,
+            store_and_fwd_flag: self
+                .nullable_text("store_and_fwd_flag", row)
+                .map(|flag| match flag {
+                    "Y" | "y" | "true" => Ok(true),
+                    "N" | "n" | "false" => Ok(false),
+                    _ => Err(TripParseError::new(
+                        "store_and_fwd_flag",
+                        flag,
+                        "se esperaba 'Y' o 'N'",
+                    )),
+                })
+                .transpose()?,
+            pu_location_id: self.integer("pu_location_id", row)?,
+            do_location_id: self.integer("do_location_id", row)?,
+            payment_type: payment_code
+                .map(|code| {
+                    PaymentType::from_code(code).ok_or_else(|| {
+                        TripParseError::new(
+                            "payment_type",
+                            &code.to_string(),
+                            "tipo de pago desconocido",
+                        )
+                    })
+                })
+                .transpose()?,
+            fare_amount: self.number("fare_amount", row)?,
+            extra: self.number("extra", row)?,
+            mta_tax: self.number("mta_tax", row)?,
+            tip_amount: self.number("tip_amount", row)?,
+            tolls_amount: self.number("tolls_amount", row)?,
+            improvement_surcharge: self.number("improvement_surcharge", row)?,
+            total_amount: self.number("total_amount", row)?,
+            congestion_surcharge: self.nullable_number("congestion_surcharge", row),
+            index: match self.text("index", row)? {
+                Some(index) => index.to_string(),
+                None => file_row.to_string(),
+            },
+            source_file: Some(
+                self.nullable_text("source_file", row)
+                    .unwrap_or(source)
+                    .to_string(),
+            ),
+        })
+    }
+}
+
+// Enteros guardados como double en los archivos de TLC (passenger_count, RatecodeID)
+fn whole_number<T: TryFrom<u64>>(field: &'static str, value: f64) -> Result<T, TripParseError> {
+    if value < 0.0 || value.fract() != 0.0 {
+        return Err(TripParseError::new(
+            field,
+            &value.to_string(),
+            "no es un entero válido",
+        ));
+    }
+    T::try_from(value as u64)
+        .map_err(|_| TripParseError::new(field, &value.to_string(), "fuera de rango"))
+}
+
+fn null_value(field: &'static str) -> TripParseError {
+    TripParseError::new(field, "", "valor nulo")
+}
+
+// Esquema de los archivos que escribe la aplicación: las columnas del CSV con
+// fechas como timestamp y el archivo de origen, en el orden de TripColumn::ALL
+fn output_schema() -> SchemaRef {
+    let amount = |name: &str| Field::new(name, DataType::Float64, false);
+    Arc::new(Schema::new(vec![
+        Field::new("vendor_id", DataType::Utf8, false),
+        Field::new(
+            "tpep_pickup_datetime",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new(
+            "tpep_dropoff_datetime",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            false,
+        ),
+        Field::new("passenger_count", DataType::Int64, true),
+        amount("trip_distance"),
+        Field::new("ratecode_id", DataType::Int64, true),
+        Field::new("store_and_fwd_flag", DataType::Utf8, true),
+        Field::new("pu_location_id", DataType::Int32, false),
+        Field::new("do_location_id", DataType::Int32, false),
+        Field::new("payment_type", DataType::Int64, true),
+        amount("fare_amount"),
+        amount("extra"),
+        amount("mta_tax"),
+        amount("tip_amount"),
+        amount("tolls_amount"),
+        amount("improvement_surcharge"),
+        amount("total_amount"),
+        Field::new("congestion_surcharge", DataType::Float64, true),
+        Field::new("index", DataType::Utf8, false),
+        Field::new("source_file", DataType::Utf8, true),
+    ]))
+}
+
+// Escribe viajes en un archivo Parquet comprimido con Snappy; los viajes se
+// acumulan en lotes de BATCH_ROWS filas
+pub struct ParquetTripWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    // Posición en TripColumn::ALL de cada columna escrita
+    positions: Vec<usize>,
+    pending: Vec<Trip>,
+}
+
+impl ParquetTripWriter {
+    // Solo se guardan las columnas indicadas (un archivo sin todas las columnas de
+    // Trip no se puede volver a abrir como dataset)
+    pub fn create<P: AsRef<Path>>(path: P, columns: &[TripColumn]) -> Result<Self, Box<dyn Error>> {
+        let positions: Vec<usize> = columns
+            .iter()
+            .filter_map(|column| TripColumn::ALL.iter().position(|c| c == column))
+            .collect();
+        let schema = Arc::new(output_schema().project(&positions)?);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_ROWS)
+            .build();
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))?;
+
+        Ok(Self {
+            writer,
+            schema,
+            positions,
+            pending: Vec::with_capacity(BATCH_ROWS),
+        })
+    }
+
+    pub fn write(&mut self, trip: &Trip) -> Result<(), Box<dyn Error>> {
+        self.pending.push(trip.clone());
+        if self.pending.len() >= BATCH_ROWS {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let trips = std::mem::take(&mut self.pending);
+        let micros = |datetime: NaiveDateTime| datetime.and_utc().timestamp_micros();
+        let amount = |get: fn(&Trip) -> f64| -> ArrayRef {
+            Arc::new(trips.iter().map(get).collect::<Float64Array>())
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                trips.iter().map(|t| &t.vendor_id),
+            )),
+            Arc::new(TimestampMicrosecondArray::from_iter_values(
+                trips.iter().map(|t| micros(t.tpep_pickup_datetime)),
+            )),
+            Arc::new(TimestampMicrosecondArray::from_iter_values(
+                trips.iter().map(|t| micros(t.tpep_dropoff_datetime)),
+            )),
+            Arc::new(Int64Array::from_iter(
+                trips.iter().map(|t| t.passenger_count.map(i64::from)),
+            )),
+            amount(|t| t.trip_distance),
+            Arc::new(Int64Array::from_iter(
+                trips
+                    .iter()
+                    .map(|t| t.ratecode_id.map(|code| code.code() as i64)),
+            )),
+            Arc::new(StringArray::from_iter(
+                trips.iter().map(|t| t.store_and_fwd_flag_str()),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                trips.iter().map(|t| t.pu_location_id as i32),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                trips.iter().map(|t| t.do_location_id as i32),
+            )),
+            Arc::new(Int64Array::from_iter(
+                trips
+                    .iter()
+                    .map(|t| t.payment_type.map(|payment| payment.code() as i64)),
+            )),
+            amount(|t| t.fare_amount),
+            amount(|t| t.extra),
+            amount(|t| t.mta_tax),
+            amount(|t| t.tip_amount),
+            amount(|t| t.tolls_amount),
+            amount(|t| t.improvement_surcharge),
+            amount(|t| t.total_amount),
+            Arc::new(Float64Array::from_iter(
+                trips.iter().map(|t| t.congestion_surcharge),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                trips.iter().map(|t| &t.index),
+            )),
+            Arc::new(StringArray::from_iter(
+                trips.iter().map(|t| t.source_file.as_deref()),
+            )),
+        ];
+
+        let columns = self.positions.iter().map(|&i| columns[i].clone()).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+
+        // Se reutiliza la capacidad del vector para el próximo lote
+        self.pending = trips;
+        self.pending.clear();
+        Ok(())
+    }
+
+    // Escribe los viajes pendientes y el pie del archivo
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.write_pending()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{TestDir, read_trips};
+    use super::*;
+
+    // Pasajeros por fila: grupos de 4 filas con 1-2, 5-6 y solo nulos
+    const PASSENGERS: [Option<f64>; 12] = [
+        Some(1.0),
+        Some(2.0),
+        None,
+        Some(1.0),
+        Some(5.0),
+        Some(6.0),
+        None,
+        Some(5.0),
+        None,
+        None,
+        None,
+        None,
+    ];
+
+    fn read_parquet(path: &Path, filter: Option<&TripFilter>) -> Vec<Trip> {
+        let mut trips = Vec::new();
+        let report = stream_process_parquet(path, filter, |trip| {
+            trips.push(trip.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(report.rows_invalid, 0);
+        trips
+    }
+
+    // Archivo como los de TLC, con nulos en las columnas que los admiten y sin
+    // extra, mta_tax ni los otros recargos
+    fn write_tlc_parquet(path: &Path) {
+        let rows = PASSENGERS.len();
+        let nullable = |i: usize| i % 3 != 2;
+        let pickup = |i: usize| {
+            NaiveDateTime::parse_from_str(
+                &format!("2023-03-01 10:{:02}:00", i),
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .unwrap()
+            .and_utc()
+            .timestamp_micros()
+        };
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("VendorID", DataType::Int32, false),
+            Field::new(
+                "tpep_pickup_datetime",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+            Field::new(
+                "tpep_dropoff_datetime",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+            Field::new("passenger_count", DataType::Float64, true),
+            Field::new("trip_distance", DataType::Float64, false),
+            Field::new("RatecodeID", DataType::Float64, true),
+            Field::new("store_and_fwd_flag", DataType::Utf8, true),
+            Field::new("PULocationID", DataType::Int32, false),
+            Field::new("DOLocationID", DataType::Int32, false),
+            Field::new("payment_type", DataType::Int64, true),
+            Field::new("fare_amount", DataType::Float64, false),
+            Field::new("total_amount", DataType::Float64, false),
+            Field::new("congestion_surcharge", DataType::Float64, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from_iter_values(
+                (0..rows).map(|i| i as i32 % 2 + 1),
+            )),
+            Arc::new(TimestampMicrosecondArray::from_iter_values(
+                (0..rows).map(pickup),
+            )),
+            Arc::new(TimestampMicrosecondArray::from_iter_values(
+                (0..rows).map(|i| pickup(i + 20)),
+            )),
+            Arc::new(Float64Array::from_iter(PASSENGERS)),
+            Arc::new(Float64Array::from_iter_values(
+                (0..rows).map(|i| i as f64 + 0.5),
+            )),
+            Arc::new(Float64Array::from_iter(
+                (0..rows).map(|i| nullable(i).then_some(1.0)),
+            )),
+            Arc::new(StringArray::from_iter(
+                (0..rows).map(|i| nullable(i).then_some("N")),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                (0..rows).map(|i| i as i32 + 10),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                (0..rows).map(|i| i as i32 + 30),
+            )),
+            Arc::new(Int64Array::from_iter(
+                (0..rows).map(|i| nullable(i).then_some(i as i64 % 2)),
+            )),
+            Arc::new(Float64Array::from_iter_values((0..rows).map(|i| i as f64))),
+            Arc::new(Float64Array::from_iter_values(
+                (0..rows).map(|i| i as f64 + 3.0),
+            )),
+            Arc::new(Float64Array::from_iter(
+                (0..rows).map(|i| nullable(i).then_some(2.5)),
+            )),
+        ];
+        let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(4)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), schema, Some(properties)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    // Los nulos se leen como los valores vacíos del mismo archivo en CSV, los grupos
+    // de filas se descartan por sus estadísticas y el archivo escrito se vuelve a
+    // leer igual
+    #[test]
+    fn parquet_nulls_pruning_and_round_trip() {
+        let dir = TestDir::new("parquet-nulls");
+        let input = dir.join("tlc.parquet");
+        write_tlc_parquet(&input);
+
+        let trips = read_parquet(&input, None);
+        assert_eq!(trips.len(), PASSENGERS.len());
+        assert_eq!(trips[2].passenger_count, None);
+        assert_eq!(trips[2].ratecode_id, None);
+        assert_eq!(trips[2].store_and_fwd_flag, None);
+        assert_eq!(trips[2].payment_type, None);
+        assert_eq!(trips[2].congestion_surcharge, None);
+        assert_eq!(trips[3].payment_type, Some(PaymentType::CreditCard));
+        assert_eq!(trips[4].payment_type, Some(PaymentType::FlexFare));
+        assert_eq!(trips[3].extra, 0.0);
+
+        // El mismo archivo en CSV, con los nulos como valores vacíos
+        let mut csv = "VendorID,tpep_pickup_datetime,tpep_dropoff_datetime,passenger_count,trip_distance,RatecodeID,store_and_fwd_flag,PULocationID,DOLocationID,payment_type,fare_amount,total_amount,congestion_surcharge\n".to_string();
+        for trip in &trips {
+            let columns = [
+                TripColumn::VendorId,
+                TripColumn::PickupDatetime,
+                TripColumn::DropoffDatetime,
+                TripColumn::PassengerCount,
+                TripColumn::TripDistance,
+                TripColumn::RatecodeId,
+                TripColumn::StoreAndFwdFlag,
+                TripColumn::PuLocationId,
+                TripColumn::DoLocationId,
+                TripColumn::PaymentType,
+                TripColumn::FareAmount,
+                TripColumn::TotalAmount,
+                TripColumn::CongestionSurcharge,
+            ];
+            let values: Vec<String> = columns.iter().map(|column| column.format(trip)).collect();
+            csv.push_str(&values.join(","));
+            csv.push('\n');
+        }
+        // Solo cambia el archivo de origen
+        let without_source = |trips: &[Trip]| -> Vec<Trip> {
+            trips
+                .iter()
+                .map(|trip| Trip {
+                    source_file: None,
+                    ..trip.clone()
+                })
+                .collect()
+        };
+        let from_csv = read_trips(&dir.write("tlc.csv", csv));
+        assert_eq!(without_source(&from_csv), without_source(&trips));
+
+        // Solo el grupo de 5-6 pasajeros y el de nulos (sin estadísticas) pueden cumplir
+        let filter = TripFilter::Passengers {
+            min: Some(5),
+            max: None,
+        };
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&input).unwrap()).unwrap();
+        let columns = column_map(builder.schema()).unwrap();
+        assert_eq!(
+            select_row_groups(&builder, &columns, Some(&filter)),
+            (vec![1, 2], 3)
+        );
+        let payment = TripFilter::PaymentType(PaymentType::FlexFare);
+        assert_eq!(
+            select_row_groups(&builder, &columns, Some(&payment)).0,
+            vec![0, 1, 2]
+        );
+        let matched = read_parquet(&input, Some(&filter));
+        let expected: Vec<Trip> = trips
+            .iter()
+            .filter(|trip| filter.matches(&trip.as_trip_ref()))
+            .cloned()
+            .collect();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(
+            matched
+                .into_iter()
+                .filter(|trip| filter.matches(&trip.as_trip_ref()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        let output = dir.join("round-trip.parquet");
+        let mut writer = ParquetTripWriter::create(&output, &TripColumn::ALL).unwrap();
+        for trip in &trips {
+            writer.write(trip).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(read_parquet(&output, None), trips);
+    }
+}
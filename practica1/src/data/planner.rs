@@ -0,0 +1,634 @@
+use super::disk_hash::{DiskHashTable, IndexMode};
+use super::filters::TripFilter;
+use super::secondary_index::{SecondaryField, SecondaryIndexes, date_key};
+use super::sorted_index::{SortedField, SortedIndexes};
+use std::error::Error;
+use std::fmt::Write;
+
+// Un plan con índices se usa solo si lee a lo sumo esta fracción de las filas;
+// si no, recorrer el CSV completo en orden es más rápido que saltar entre filas
+pub const MAX_INDEX_SELECTIVITY: f64 = 0.25;
+
+// Índices disponibles para planificar una consulta
+pub struct IndexCatalog<'a> {
+    pub hash: Option<&'a DiskHashTable>,
+    pub secondary: Option<&'a SecondaryIndexes>,
+    pub sorted: Option<&'a SortedIndexes>,
+}
+
+impl IndexCatalog<'_> {
+    // Filas válidas del CSV según las estadísticas de los índices
+    fn total_rows(&self) -> u64 {
+        if let Some(sorted) = self.sorted {
+            return sorted.rows();
+        }
+        if let Some(secondary) = self.secondary {
+            return secondary.rows() as u64;
+        }
+        self.hash.map_or(0, |hash| hash.stored_records())
+    }
+}
+
+// Operación de un paso del plan
+#[derive(Debug, Clone)]
+pub enum PlanKind {
+    HashLookup(String),
+    PostingList {
+        field: SecondaryField,
+        min: u32,
+        max: u32,
+    },
+    RangeScan {
+        field: SortedField,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Union(Vec<PlanNode>),
+    Intersection(Vec<PlanNode>),
+    FullScan,
+}
+
+// Paso del plan con las filas que se estima que produce
+#[derive(Debug, Clone)]
+pub struct PlanNode {
+    pub kind: PlanKind,
+    pub estimated_rows: u64,
+}
+
+impl PlanNode {
+    fn new(kind: PlanKind, estimated_rows: u64) -> Self {
+        Self {
+            kind,
+            estimated_rows,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.kind {
+            PlanKind::HashLookup(key) => format!("Búsqueda en índice hash: index = {}", key),
+            PlanKind::PostingList { field, min, max } if min == max => {
+                format!("Índice secundario: {} = {}", field, field.format_key(*min))
+            }
+            PlanKind::PostingList { field, min, max } => format!(
+                "Índice secundario: {} <= {} <= {}",
+                if *min == 0 {
+                    "-∞".to_string()
+                } else {
+                    field.format_key(*min)
+                },
+                field,
+                if *max == u32::MAX {
+                    "∞".to_string()
+                } else {
+                    field.format_key(*max)
+                }
+            ),
+            PlanKind::RangeScan { field, min, max } => format!(
+                "Rango en índice ordenado: {} <= {} <= {}",
+                min.map_or("-∞".to_string(), |v| v.to_string()),
+                field,
+                max.map_or("∞".to_string(), |v| v.to_string())
+            ),
+            PlanKind::Union(_) => "Unión de posiciones (OR)".to_string(),
+            PlanKind::Intersection(_) => "Intersección de posiciones (AND)".to_string(),
+            PlanKind::FullScan => "Escaneo completo del CSV".to_string(),
+        }
+    }
+
+    fn explain_into(&self, out: &mut String, depth: usize) {
+        let _ = writeln!(
+            out,
+            "{}{} (~{} filas)",
+            "  ".repeat(depth),
+            self.describe(),
+            self.estimated_rows
+        );
+        if let PlanKind::Union(children) | PlanKind::Intersection(children) = &self.kind {
+            for child in children {
+                child.explain_into(out, depth + 1);
+            }
+        }
+    }
+
+    // Posiciones de las filas candidatas, ordenadas y sin repetir
+    fn execute(&self, catalog: &IndexCatalog) -> Result<Vec<u64>, Box<dyn Error>> {
+        match &self.kind {
+            PlanKind::HashLookup(key) => {
+                let hash = catalog.hash.ok_or("No hay índice hash")?;
+                Ok(hash
+                    .get_location(key)?
+                    .map(|location| location.offset)
+                    .into_iter()
+                    .collect())
+            }
+            PlanKind::PostingList { field, min, max } => catalog
+                .secondary
+                .ok_or("No hay índices secundarios")?
+                .lookup_range(*field, *min, *max),
+            PlanKind::RangeScan { field, min, max } => catalog
+                .sorted
+                .ok_or("No hay índices ordenados")?
+                .range(*field, *min, *max),
+            PlanKind::Union(children) => {
+                let mut offsets = Vec::new();
+                for child in children {
+                    offsets.extend(child.execute(catalog)?);
+                }
+                offsets.sort_unstable();
+                offsets.dedup();
+                Ok(offsets)
+            }
+            PlanKind::Intersection(children) => {
+                // Los hijos están ordenados de más a menos selectivo
+                let Some((first, rest)) = children.split_first() else {
+                    return Ok(Vec::new());
+                };
+                let mut offsets = first.execute(catalog)?;
+                for child in rest {
+                    if offsets.is_empty() {
+                        break;
+                    }
+                    let other = child.execute(catalog)?;
+                    offsets.retain(|offset| other.binary_search(offset).is_ok());
+                }
+                Ok(offsets)
+            }
+            PlanKind::FullScan => Err("Un escaneo completo no tiene filas candidatas".into()),
+        }
+    }
+}
+
+// Plan elegido para un filtro
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    pub root: PlanNode,
+    pub total_rows: u64,
+    // Mejor plan con índices cuando se descartó por poco selectivo
+    pub discarded: Option<PlanNode>,
+}
+
+impl QueryPlan {
+    pub fn uses_index(&self) -> bool {
+        !matches!(self.root.kind, PlanKind::FullScan)
+    }
+
+    // Posiciones de las filas a leer; None si el plan es un escaneo completo.
+    // Las filas candidatas se deben verificar igual con el filtro completo
+    pub fn candidates(&self, catalog: &IndexCatalog) -> Result<Option<Vec<u64>>, Box<dyn Error>> {
+        if !self.uses_index() {
+            return Ok(None);
+        }
+        Ok(Some(self.root.execute(catalog)?))
+    }
+
+    // Descripción del plan en texto, con un paso por línea
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        let percent = if self.total_rows > 0 {
+            self.root.estimated_rows as f64 * 100.0 / self.total_rows as f64
+        } else {
+            0.0
+        };
+        let _ = writeln!(
+            out,
+            "Plan de consulta: ~{} de {} filas ({:.1}%)",
+            self.root.estimated_rows, self.total_rows, percent
+        );
+        self.root.explain_into(&mut out, 1);
+
+        if let Some(discarded) = &self.discarded {
+            let _ = writeln!(
+                out,
+                "Plan con índices descartado (lee más del {:.0}% de las filas):",
+                MAX_INDEX_SELECTIVITY * 100.0
+            );
+            discarded.explain_into(&mut out, 1);
+        }
+        out
+    }
+}
+
+// Elige el plan más barato para un filtro con los índices disponibles
+pub fn plan(filter: &TripFilter, catalog: &IndexCatalog) -> QueryPlan {
+    let total_rows = catalog.total_rows();
+    let full_scan = PlanNode::new(PlanKind::FullScan, total_rows);
+
+    match plan_node(filter, catalog, total_rows) {
+        Some(node) if node.estimated_rows as f64 <= total_rows as f64 * MAX_INDEX_SELECTIVITY => {
+            QueryPlan {
+                root: node,
+                total_rows,
+                discarded: None,
+            }
+        }
+        discarded => QueryPlan {
+            root: full_scan,
+            total_rows,
+            discarded,
+        },
+    }
+}
+
+// Plan con índices para un subárbol del filtro; None si necesita un escaneo completo
+fn plan_node(filter: &TripFilter, catalog: &IndexCatalog, total_rows: u64) -> Option<PlanNode> {
+    match filter {
+        TripFilter::Index(key) => {
+            let hash = catalog.hash?;
+            if hash.mode() != IndexMode::Offsets {
+                return None;
+            }
+            Some(PlanNode::new(PlanKind::HashLookup(key.clone()), 1))
+        }
+        TripFilter::Destination(dest) => posting_node(
+            catalog,
+            SecondaryField::DropoffLocation,
+            *dest as u32,
+            *dest as u32,
+        ),
+        TripFilter::PickupLocation(pickup) => posting_node(
+            catalog,
+            SecondaryField::PickupLocation,
+            *pickup as u32,
+            *pickup as u32,
+        ),
+        TripFilter::PickupTime { from, to } => {
+            if from.is_none() && to.is_none() {
+                return None;
+            }
+            // El índice es por día: se leen los días completos y el filtro
+            // descarta las horas fuera del rango
+            let min = from.map_or(0, |from| date_key(from.date()));
+            let max = to.map_or(u32::MAX, |to| date_key(to.date()));
+            posting_node(catalog, SecondaryField::PickupDate, min, max)
+        }
+        TripFilter::Distance { min, max } => {
+            if min.is_none() && max.is_none() {
+                return None;
+            }
+            range_node(catalog, SortedField::TripDistance, *min, *max)
+        }
+        // Columnas sin índice; una negación acepta casi todas las filas
+        TripFilter::Not(_)
+        | TripFilter::PaymentType(_)
+        | TripFilter::Vendor(_)
+        | TripFilter::Passengers { .. }
+        | TripFilter::TipPercentage { .. }
+        | TripFilter::Duration { .. } => None,
+        TripFilter::Price { min, max } => {
+            // Sin límites el filtro acepta todas las filas
+            if min.is_none() && max.is_none() {
+                return None;
+            }
+            range_node(catalog, SortedField::TotalAmount, *min, *max)
+        }
+        TripFilter::And(filters) => {
+            let mut children: Vec<PlanNode> = filters
+                .iter()
+                .filter_map(|f| plan_node(f, catalog, total_rows))
+                .collect();
+            children.sort_by_key(|child| child.estimated_rows);
+
+            // Leer las posiciones de un hijo poco selectivo cuesta más de lo que ahorra
+            let limit = total_rows as f64 * MAX_INDEX_SELECTIVITY;
+            let mut kept = Vec::new();
+            for (i, child) in children.into_iter().enumerate() {
+                if i == 0 || child.estimated_rows as f64 <= limit {
+                    kept.push(child);
+                }
+            }
+
+            if kept.len() <= 1 {
+                return kept.pop();
+            }
+
+            // Se asume independencia entre columnas
+            let selectivity: f64 = kept
+                .iter()
+                .map(|child| child.estimated_rows as f64 / total_rows.max(1) as f64)
+                .product();
+            let estimated =
+                ((total_rows as f64 * selectivity).ceil() as u64).min(kept[0].estimated_rows);
+            Some(PlanNode::new(PlanKind::Intersection(kept), estimated))
+        }
+        TripFilter::Or(filters) => {
+            // Basta con que una rama necesite escaneo para que lo necesite todo el OR
+            let mut children = filters
+                .iter()
+                .map(|f| plan_node(f, catalog, total_rows))
+                .collect::<Option<Vec<PlanNode>>>()?;
+
+            if children.len() == 1 {
+                return children.pop();
+            }
+            let estimated = children
+                .iter()
+                .map(|child| child.estimated_rows)
+                .sum::<u64>()
+                .min(total_rows);
+            Some(PlanNode::new(PlanKind::Union(children), estimated))
+        }
+    }
+}
+
+fn posting_node(
+    catalog: &IndexCatalog,
+    field: SecondaryField,
+    min: u32,
+    max: u32,
+) -> Option<PlanNode> {
+    let secondary = catalog.secondary?;
+    Some(PlanNode::new(
+        PlanKind::PostingList { field, min, max },
+        secondary.count_range(field, min, max),
+    ))
+}
+
+fn range_node(
+    catalog: &IndexCatalog,
+    field: SortedField,
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Option<PlanNode> {
+    let sorted = catalog.sorted?;
+    match sorted.count_range(field, min, max) {
+        Ok(count) => Some(PlanNode::new(
+            PlanKind::RangeScan { field, min, max },
+            count,
+        )),
+        Err(e) => {
+            eprintln!("No se pudo estimar el rango de {} ({})", field, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::data_lector::stream_process_csv_with_locations;
+    use super::super::disk_hash::{IndexConfig, build_hash_table_from_csv_with};
+    use super::super::filters::parse_query;
+    use super::super::secondary_index::SecondaryIndexBuilder;
+    use super::super::sorted_index::SortedIndexBuilder;
+    use super::super::test_support::{TestDir, sample_trips_csv};
+    use super::super::trip_struct::Trip;
+    use super::*;
+
+    // Índices de un CSV de prueba junto con sus viajes y la posición de cada uno
+    struct Fixture {
+        _dir: TestDir,
+        hash: DiskHashTable,
+        secondary: SecondaryIndexes,
+        sorted: SortedIndexes,
+        trips: Vec<(Trip, u64)>,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = TestDir::new(name);
+            let csv = dir.write("trips.csv", sample_trips_csv(400));
+
+            build_hash_table_from_csv_with(
+                &csv,
+                dir.join("hash"),
+                IndexConfig::default(),
+                |_, _| Ok(()),
+            )
+            .unwrap();
+            let mut secondary = SecondaryIndexBuilder::create(dir.join("secondary")).unwrap();
+            let mut sorted = SortedIndexBuilder::create(dir.join("sorted")).unwrap();
+            let mut trips = Vec::new();
+            let report = stream_process_csv_with_locations(&csv, |trip, location| {
+                trips.push((trip.clone(), location.offset));
+                secondary.add(trip, location)?;
+                sorted.add(trip, location)
+            })
+            .unwrap();
+            secondary.write(&csv, report.rows_invalid).unwrap();
+            sorted.write(&csv).unwrap();
+
+            Self {
+                hash: DiskHashTable::open(dir.join("hash")).unwrap().unwrap(),
+                secondary: SecondaryIndexes::open(dir.join("secondary"))
+                    .unwrap()
+                    .unwrap(),
+                sorted: SortedIndexes::open(dir.join("sorted")).unwrap().unwrap(),
+                trips,
+                _dir: dir,
+            }
+        }
+
+        fn catalog(&self) -> IndexCatalog<'_> {
+            IndexCatalog {
+                hash: Some(&self.hash),
+                secondary: Some(&self.secondary),
+                sorted: Some(&self.sorted),
+            }
+        }
+
+        fn plan(&self, query: &str) -> QueryPlan {
+            plan(&parse_query(query).unwrap(), &self.catalog())
+        }
+
+        // Las candidatas del plan incluyen todas las filas que cumplen la consulta
+        fn assert_candidates(&self, query: &str, exact: bool) {
+            let filter = parse_query(query).unwrap();
+            let plan = plan(&filter, &self.catalog());
+            let candidates = plan.candidates(&self.catalog()).unwrap().unwrap();
+            let matching: Vec<u64> = self
+                .trips
+                .iter()
+                .filter(|(trip, _)| filter.matches(&trip.as_trip_ref()))
+                .map(|(_, offset)| *offset)
+                .collect();
+            if exact {
+                assert_eq!(candidates, matching, "{}", query);
+            } else {
+                assert!(
+                    matching.iter().all(|offset| candidates.contains(offset)),
+                    "{}",
+                    query
+                );
+            }
+        }
+    }
+
+    fn count(fixture: &Fixture, query: &str) -> u64 {
+        let filter = parse_query(query).unwrap();
+        fixture
+            .trips
+            .iter()
+            .filter(|(trip, _)| filter.matches(&trip.as_trip_ref()))
+            .count() as u64
+    }
+
+    #[test]
+    fn selective_filters_use_indexes() {
+        let fixture = Fixture::new("planner-selective");
+
+        let plan = fixture.plan("do_location_id = 5");
+        assert!(matches!(
+            plan.root.kind,
+            PlanKind::PostingList {
+                field: SecondaryField::DropoffLocation,
+                min: 5,
+                max: 5
+            }
+        ));
+        assert_eq!(
+            plan.root.estimated_rows,
+            count(&fixture, "do_location_id = 5")
+        );
+        assert_eq!(plan.total_rows, 400);
+        fixture.assert_candidates("do_location_id = 5", true);
+
+        let plan = fixture.plan("index = 17");
+        assert!(matches!(&plan.root.kind, PlanKind::HashLookup(key) if key == "17"));
+        fixture.assert_candidates("index = 17", true);
+
+        // Un día son ~14 % de las filas; el índice es por día y puede traer de más
+        let day = "tpep_pickup_datetime between '2023-03-02 10:00:00' and '2023-03-02 12:00:00'";
+        assert!(matches!(
+            fixture.plan(day).root.kind,
+            PlanKind::PostingList {
+                field: SecondaryField::PickupDate,
+                ..
+            }
+        ));
+        fixture.assert_candidates(day, false);
+
+        let price = "total_amount between 10 and 12";
+        let plan = fixture.plan(price);
+        assert!(matches!(plan.root.kind, PlanKind::RangeScan { .. }));
+        assert_eq!(plan.root.estimated_rows, count(&fixture, price));
+        assert!(plan.uses_index());
+        fixture.assert_candidates(price, true);
+    }
+
+    #[test]
+    fn unselective_filters_use_a_full_scan() {
+        let fixture = Fixture::new("planner-full-scan");
+
+        // Más del 25 % de las filas: el índice se descarta pero queda en el plan
+        for query in [
+            "total_amount >= 0",
+            "tpep_pickup_datetime < '2023-03-04'",
+            "do_location_id = 5 or total_amount > 20",
+        ] {
+            let plan = fixture.plan(query);
+            assert!(!plan.uses_index(), "{}", query);
+            assert_eq!(plan.root.estimated_rows, 400);
+            let discarded = plan.discarded.as_ref().unwrap();
+            assert!(
+                discarded.estimated_rows as f64 > 400.0 * MAX_INDEX_SELECTIVITY,
+                "{}",
+                query
+            );
+            assert!(plan.candidates(&fixture.catalog()).unwrap().is_none());
+        }
+
+        // Columnas sin índice y negaciones no tienen plan con índices
+        for query in [
+            "passenger_count = 2",
+            "not do_location_id = 5",
+            "do_location_id = 5 or passenger_count = 2",
+        ] {
+            let plan = fixture.plan(query);
+            assert!(!plan.uses_index(), "{}", query);
+            assert!(plan.discarded.is_none(), "{}", query);
+        }
+
+        // Sin índices todo es un escaneo completo
+        let empty = IndexCatalog {
+            hash: None,
+            secondary: None,
+            sorted: None,
+        };
+        let plan = plan(&parse_query("do_location_id = 5").unwrap(), &empty);
+        assert!(!plan.uses_index());
+    }
+
+    #[test]
+    fn and_or_combine_plans() {
+        let fixture = Fixture::new("planner-combine");
+
+        // AND: intersección empezando por el hijo más selectivo
+        let query = "pu_location_id = 3 and do_location_id = 5";
+        let plan = fixture.plan(query);
+        let PlanKind::Intersection(children) = &plan.root.kind else {
+            panic!("{:?}", plan.root.kind);
+        };
+        assert_eq!(children.len(), 2);
+        assert!(children[0].estimated_rows <= children[1].estimated_rows);
+        assert!(plan.root.estimated_rows <= children[0].estimated_rows);
+        fixture.assert_candidates(query, true);
+
+        // Los hijos sin índice o poco selectivos se verifican al leer las filas
+        for query in [
+            "do_location_id = 5 and passenger_count = 2",
+            "do_location_id = 5 and total_amount >= 0",
+            "do_location_id = 5 and not pu_location_id = 3",
+        ] {
+            let plan = fixture.plan(query);
+            assert!(
+                matches!(plan.root.kind, PlanKind::PostingList { min: 5, .. }),
+                "{}",
+                query
+            );
+            fixture.assert_candidates(query, false);
+        }
+
+        // OR: unión de las ramas, con la suma de sus filas
+        let query = "do_location_id = 5 or do_location_id = 6 or index = 3";
+        let plan = fixture.plan(query);
+        let PlanKind::Union(children) = &plan.root.kind else {
+            panic!("{:?}", plan.root.kind);
+        };
+        assert_eq!(children.len(), 3);
+        assert_eq!(
+            plan.root.estimated_rows,
+            children.iter().map(|c| c.estimated_rows).sum::<u64>()
+        );
+        fixture.assert_candidates(query, true);
+
+        // Anidados
+        let query = "(do_location_id = 5 or do_location_id = 6) and total_amount between 10 and 14";
+        let plan = fixture.plan(query);
+        assert!(matches!(plan.root.kind, PlanKind::Intersection(_)));
+        fixture.assert_candidates(query, true);
+    }
+
+    #[test]
+    fn explain_lists_each_step() {
+        let fixture = Fixture::new("planner-explain");
+        let (five, six) = (
+            count(&fixture, "do_location_id = 5"),
+            count(&fixture, "do_location_id = 6"),
+        );
+
+        let plan = fixture.plan("do_location_id = 5 or do_location_id = 6");
+        let total = five + six;
+        assert_eq!(
+            plan.explain(),
+            format!(
+                "Plan de consulta: ~{} de 400 filas ({:.1}%)\n  Unión de posiciones (OR) (~{} filas)\n    Índice secundario: do_location_id = 5 (~{} filas)\n    Índice secundario: do_location_id = 6 (~{} filas)\n",
+                total,
+                total as f64 * 100.0 / 400.0,
+                total,
+                five,
+                six
+            )
+        );
+
+        let plan = fixture.plan("total_amount >= 0");
+        assert_eq!(
+            plan.explain(),
+            "Plan de consulta: ~400 de 400 filas (100.0%)\n  Escaneo completo del CSV (~400 filas)\nPlan con índices descartado (lee más del 25% de las filas):\n  Rango en índice ordenado: 0 <= total_amount <= ∞ (~400 filas)\n"
+        );
+
+        let plan = fixture.plan("tpep_pickup_datetime between '2023-03-02' and '2023-03-03'");
+        assert!(
+            plan.explain()
+                .contains("Índice secundario: 2023-03-02 <= fecha de recogida <= 2023-03-03")
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use super::data_lector::RowLocation;
+use super::disk_hash::SourceFingerprint;
+use super::index_file::{read_trailer, write_trailer};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, create_dir_all};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Cambiar el formato del archivo obliga a subir esta versión
+const ROW_NUMBERS_FORMAT_VERSION: u32 = 2;
+
+// Cada entrada es: [byte de la fila en el CSV u64][número de fila u64], en orden del archivo.
+// Las entradas van al inicio y la cabecera al final, seguida de su largo (u64), así se
+// escriben mientras se recorre el CSV
+const ENTRY_LEN: u64 = 16;
+
+// Entradas leídas por bloque al recorrer el archivo
+const READ_BATCH_ENTRIES: u64 = 4096;
+
+// Cabecera al final del archivo de números de fila
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RowNumbersHeader {
+    format_version: u32,
+    source: SourceFingerprint,
+    entries: u64,
+}
+
+// Guarda el número de fila de cada posición de un CSV sin columna index. Ese número
+// es el index de los viajes, y al leer una fila por su posición no se conoce. Las
+// entradas se escriben en un archivo temporal a medida que llegan
+pub struct RowNumbersBuilder {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: BufWriter<File>,
+    entries: u64,
+    last_offset: Option<u64>,
+}
+
+impl RowNumbersBuilder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("idx.tmp");
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+
+        Ok(Self {
+            path,
+            tmp_path,
+            writer,
+            entries: 0,
+            last_offset: None,
+        })
+    }
+
+    // Las filas llegan en orden del archivo, que es el orden en que las busca rows_at
+    pub fn add(&mut self, location: RowLocation, row: u64) -> Result<(), Box<dyn Error>> {
+        if self.last_offset.is_some_and(|last| last >= location.offset) {
+            return Err(format!(
+                "Números de fila fuera de orden en el byte {}",
+                location.offset
+            )
+            .into());
+        }
+        self.writer.write_all(&location.offset.to_le_bytes())?;
+        self.writer.write_all(&row.to_le_bytes())?;
+        self.last_offset = Some(location.offset);
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Agrega la cabecera y reemplaza el archivo con un rename
+    pub fn write<P: AsRef<Path>>(self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        let header = RowNumbersHeader {
+            format_version: ROW_NUMBERS_FORMAT_VERSION,
+            source: SourceFingerprint::compute(csv_path)?,
+            entries: self.entries,
+        };
+        let mut writer = self.writer;
+        write_trailer(&mut writer, &header)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&self.tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+// Números de fila abiertos; las entradas se leen del archivo al consultarlas
+pub struct RowNumbers {
+    file: File,
+    header: RowNumbersHeader,
+}
+
+impl RowNumbers {
+    // Abre el archivo; None si no existe
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut file = File::open(path)?;
+        let invalid = || format!("Números de fila incompatibles en {}", path.display());
+        // Un archivo del formato anterior no termina en una cabecera
+        let (header, entries_len): (RowNumbersHeader, u64) =
+            read_trailer(&mut file)?.ok_or_else(invalid)?;
+
+        if header.format_version != ROW_NUMBERS_FORMAT_VERSION
+            || header.entries * ENTRY_LEN != entries_len
+        {
+            return Err(invalid().into());
+        }
+
+        Ok(Some(Self { file, header }))
+    }
+
+    // Verifica que el archivo corresponda al CSV y que no haya cambiado
+    pub fn validate_source<P: AsRef<Path>>(&self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        if self.header.source != SourceFingerprint::compute(csv_path)? {
+            return Err("Los números de fila no corresponden al CSV actual".into());
+        }
+        Ok(())
+    }
+
+    fn read_entries(&self, first: u64, count: u64) -> Result<Vec<(u64, u64)>, Box<dyn Error>> {
+        let mut buf = vec![0u8; (count * ENTRY_LEN) as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(first * ENTRY_LEN))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf
+            .chunks_exact(ENTRY_LEN as usize)
+            .map(|entry| {
+                let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap_or_default());
+                let row = u64::from_le_bytes(entry[8..16].try_into().unwrap_or_default());
+                (offset, row)
+            })
+            .collect())
+    }
+
+    // Número de fila de cada posición (en orden ascendente, como las entrega el
+    // planificador); se recorre el archivo una sola vez junto con las posiciones
+    pub fn rows_at(&self, offsets: &[u64]) -> Result<Vec<u64>, Box<dyn Error>> {
+        if !self.header.source.is_current()? {
+            return Err("El CSV cambió desde que se guardaron los números de fila".into());
+        }
+
+        let mut rows = Vec::with_capacity(offsets.len());
+        let mut batch: Vec<(u64, u64)> = Vec::new();
+        let (mut next, mut in_batch) = (0, 0);
+
+        for offset in offsets {
+            loop {
+                if in_batch == batch.len() {
+                    if next >= self.header.entries {
+                        return Err(format!("No hay número de fila para el byte {}", offset).into());
+                    }
+                    let count = (self.header.entries - next).min(READ_BATCH_ENTRIES);
+                    batch = self.read_entries(next, count)?;
+                    next += count;
+                    in_batch = 0;
+                }
+
+                let (entry_offset, row) = batch[in_batch];
+                if entry_offset < *offset {
+                    in_batch += 1;
+                } else if entry_offset == *offset {
+                    rows.push(row);
+                    break;
+                } else {
+                    return Err(format!("No hay número de fila para el byte {}", offset).into());
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::TestDir;
+    use super::*;
+
+    #[test]
+    fn row_numbers_round_trip() {
+        let dir = TestDir::new("row-numbers");
+        let csv = dir.write("trips.csv", "a\n1\n2\n");
+        let path = dir.join("row_numbers.idx");
+
+        let mut builder = RowNumbersBuilder::create(&path).unwrap();
+        for (offset, row) in [(2, 0), (4, 1), (9, 3)] {
+            builder.add(RowLocation { offset, len: 2 }, row).unwrap();
+        }
+        // Las filas tienen que llegar en orden del archivo
+        assert!(builder.add(RowLocation { offset: 4, len: 2 }, 4).is_err());
+        builder.write(&csv).unwrap();
+        assert!(!dir.join("row_numbers.idx.tmp").exists());
+
+        let rows = RowNumbers::open(&path).unwrap().unwrap();
+        rows.validate_source(&csv).unwrap();
+        assert_eq!(rows.rows_at(&[2, 9]).unwrap(), vec![0, 3]);
+        assert!(rows.rows_at(&[5]).is_err());
+    }
+}
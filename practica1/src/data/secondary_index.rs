@@ -0,0 +1,420 @@
+use super::data_lector::RowLocation;
+use super::disk_hash::SourceFingerprint;
+use super::index_file::{ExternalSorter, RUN_RECORDS, RunRecord, read_trailer, write_trailer};
+use super::trip_struct::Trip;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, create_dir_all};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Cambiar el formato de los archivos obliga a subir esta versión
+const SECONDARY_FORMAT_VERSION: u32 = 2;
+
+// Cada posición en una lista es el byte donde empieza la fila en el CSV (u64). Las
+// listas van al inicio del archivo y el directorio al final
+const POSTING_LEN: u64 = 8;
+
+// Columnas con índice secundario
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SecondaryField {
+    DropoffLocation,
+    PickupLocation,
+    PickupDate,
+}
+
+impl SecondaryField {
+    pub const ALL: [SecondaryField; 3] = [
+        SecondaryField::DropoffLocation,
+        SecondaryField::PickupLocation,
+        SecondaryField::PickupDate,
+    ];
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            SecondaryField::DropoffLocation => "do_location_id.idx",
+            SecondaryField::PickupLocation => "pu_location_id.idx",
+            SecondaryField::PickupDate => "pickup_date.idx",
+        }
+    }
+
+    // Clave del viaje en este índice
+    fn key_for(&self, trip: &Trip) -> u32 {
+        match self {
+            SecondaryField::DropoffLocation => trip.do_location_id as u32,
+            SecondaryField::PickupLocation => trip.pu_location_id as u32,
+            SecondaryField::PickupDate => date_key(trip.tpep_pickup_datetime.date()),
+        }
+    }
+
+    // Clave en texto legible (las fechas como AAAA-MM-DD)
+    pub fn format_key(&self, key: u32) -> String {
+        match self {
+            SecondaryField::PickupDate => i32::try_from(key)
+                .ok()
+                .and_then(NaiveDate::from_num_days_from_ce_opt)
+                .map_or(key.to_string(), |date| date.to_string()),
+            _ => key.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SecondaryField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SecondaryField::DropoffLocation => "do_location_id",
+            SecondaryField::PickupLocation => "pu_location_id",
+            SecondaryField::PickupDate => "fecha de recogida",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Las fechas se guardan como días desde el 1 de enero del año 1
+pub fn date_key(date: NaiveDate) -> u32 {
+    date.num_days_from_ce().max(0) as u32
+}
+
+// Ubicación de la lista de una clave dentro de la sección de posiciones
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct PostingRef {
+    offset: u64,
+    count: u64,
+}
+
+// Directorio al final de cada archivo de índice secundario
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PostingDirectory {
+    format_version: u32,
+    field: SecondaryField,
+    source: SourceFingerprint,
+    rows: usize,
+    invalid_rows: usize,
+    postings: BTreeMap<u32, PostingRef>,
+}
+
+// Posición de una fila con su clave; ordenar por (clave, byte) deja las listas
+// agrupadas por clave y cada una en orden del archivo
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Posting {
+    key: u32,
+    offset: u64,
+}
+
+impl RunRecord for Posting {
+    const LEN: usize = 12;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.key.to_le_bytes());
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self {
+            key: u32::from_le_bytes(bytes[0..4].try_into().unwrap_or_default()),
+            offset: u64::from_le_bytes(bytes[4..12].try_into().unwrap_or_default()),
+        }
+    }
+}
+
+// Ordena las posiciones de cada columna mientras se recorre el CSV; las que no
+// caben en memoria se guardan en corridas temporales dentro del directorio
+pub struct SecondaryIndexBuilder {
+    dir: PathBuf,
+    sorters: Vec<ExternalSorter<Posting>>,
+    rows: usize,
+}
+
+impl SecondaryIndexBuilder {
+    pub fn create<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        Self::with_run_records(dir, RUN_RECORDS)
+    }
+
+    // Permite corridas pequeñas en las pruebas
+    fn with_run_records<P: AsRef<Path>>(
+        dir: P,
+        run_records: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(&dir)?;
+        let sorters = SecondaryField::ALL
+            .iter()
+            .map(|field| ExternalSorter::new(dir.join(field.file_name()), run_records))
+            .collect();
+
+        Ok(Self {
+            dir,
+            sorters,
+            rows: 0,
+        })
+    }
+
+    pub fn add(&mut self, trip: &Trip, location: RowLocation) -> Result<(), Box<dyn Error>> {
+        for (field, sorter) in SecondaryField::ALL.iter().zip(self.sorters.iter_mut()) {
+            sorter.push(Posting {
+                key: field.key_for(trip),
+                offset: location.offset,
+            })?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    // Escribe un archivo por columna mezclando sus corridas: primero las posiciones y
+    // al final el directorio; cada archivo se reemplaza completo con un rename
+    pub fn write<P: AsRef<Path>>(
+        self,
+        csv_path: P,
+        invalid_rows: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let source = SourceFingerprint::compute(csv_path)?;
+
+        for (field, sorter) in SecondaryField::ALL.into_iter().zip(self.sorters) {
+            let path = self.dir.join(field.file_name());
+            let tmp_path = path.with_extension("idx.tmp");
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+            let mut postings: BTreeMap<u32, PostingRef> = BTreeMap::new();
+            let mut offset = 0;
+            for posting in sorter.finish()? {
+                let posting = posting?;
+                writer.write_all(&posting.offset.to_le_bytes())?;
+                postings
+                    .entry(posting.key)
+                    .or_insert(PostingRef { offset, count: 0 })
+                    .count += 1;
+                offset += POSTING_LEN;
+            }
+
+            let directory = PostingDirectory {
+                format_version: SECONDARY_FORMAT_VERSION,
+                field,
+                source: source.clone(),
+                rows: self.rows,
+                invalid_rows,
+                postings,
+            };
+            write_trailer(&mut writer, &directory)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            drop(writer);
+
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Índice secundario abierto: el directorio se mantiene en memoria y las listas
+// se leen del archivo al consultarlas
+struct OpenIndex {
+    file: File,
+    directory: PostingDirectory,
+}
+
+// Índices secundarios de un CSV (destino, origen y fecha de recogida)
+pub struct SecondaryIndexes {
+    indexes: Vec<OpenIndex>,
+}
+
+impl SecondaryIndexes {
+    // Abre los índices del directorio; None si falta alguno
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Option<Self>, Box<dyn Error>> {
+        let dir = dir.as_ref();
+        let mut indexes = Vec::new();
+
+        for field in SecondaryField::ALL {
+            let path: PathBuf = dir.join(field.file_name());
+            if !path.exists() {
+                return Ok(None);
+            }
+
+            // El archivo queda abierto: si se reconstruye el índice seguimos
+            // leyendo la versión que corresponde a este directorio
+            let mut file = File::open(&path)?;
+            let invalid = || format!("Índice secundario incompatible en {}", path.display());
+            // Un archivo del formato anterior no termina en un directorio
+            let (directory, postings_len): (PostingDirectory, u64) =
+                read_trailer(&mut file)?.ok_or_else(invalid)?;
+            let entries: u64 = directory.postings.values().map(|p| p.count).sum();
+
+            if directory.format_version != SECONDARY_FORMAT_VERSION
+                || directory.field != field
+                || entries * POSTING_LEN != postings_len
+            {
+                return Err(invalid().into());
+            }
+
+            indexes.push(OpenIndex { file, directory });
+        }
+
+        Ok(Some(Self { indexes }))
+    }
+
+    fn index(&self, field: SecondaryField) -> &OpenIndex {
+        let position = SecondaryField::ALL
+            .iter()
+            .position(|f| *f == field)
+            .unwrap_or_default();
+        &self.indexes[position]
+    }
+
+    // Verifica que todos los índices correspondan al CSV y que no haya cambiado
+    pub fn validate_source<P: AsRef<Path>>(&self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        let current = SourceFingerprint::compute(csv_path)?;
+        for index in &self.indexes {
+            if index.directory.source != current {
+                return Err(format!(
+                    "El índice de {} no corresponde al CSV actual",
+                    index.directory.field
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // Filas con formato inválido que se omitieron al construir los índices
+    pub fn invalid_rows(&self) -> usize {
+        self.indexes
+            .first()
+            .map_or(0, |index| index.directory.invalid_rows)
+    }
+
+    // Filas indexadas (las válidas del CSV)
+    pub fn rows(&self) -> usize {
+        self.indexes.first().map_or(0, |index| index.directory.rows)
+    }
+
+    // Cantidad de filas con clave entre min y max (inclusive), sin leer las listas
+    pub fn count_range(&self, field: SecondaryField, min: u32, max: u32) -> u64 {
+        if min > max {
+            return 0;
+        }
+        self.index(field)
+            .directory
+            .postings
+            .range(min..=max)
+            .map(|(_, posting)| posting.count)
+            .sum()
+    }
+
+    // Posiciones de las filas con clave entre min y max (inclusive), en orden del archivo
+    pub fn lookup_range(
+        &self,
+        field: SecondaryField,
+        min: u32,
+        max: u32,
+    ) -> Result<Vec<u64>, Box<dyn Error>> {
+        let index = self.index(field);
+        if min > max {
+            return Ok(Vec::new());
+        }
+        if !index.directory.source.is_current()? {
+            return Err("El CSV cambió desde que se construyó el índice secundario".into());
+        }
+
+        let mut offsets = Vec::new();
+        let mut file = &index.file;
+        for posting in index.directory.postings.range(min..=max).map(|(_, p)| p) {
+            let mut buf = vec![0u8; (posting.count * POSTING_LEN) as usize];
+            file.seek(SeekFrom::Start(posting.offset))?;
+            file.read_exact(&mut buf)?;
+            offsets.extend(
+                buf.chunks_exact(POSTING_LEN as usize)
+                    .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or_default())),
+            );
+        }
+
+        // Las listas de varias claves se mezclan para leer el CSV en orden
+        if min != max {
+            offsets.sort_unstable();
+        }
+        Ok(offsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::data_lector::stream_process_csv_with_locations;
+    use super::super::test_support::{TestDir, sample_trips_csv};
+    use super::*;
+
+    #[test]
+    fn spilled_runs_merge_into_lookups() {
+        let dir = TestDir::new("secondary-index");
+        let csv = dir.write("trips.csv", sample_trips_csv(100));
+        let index_dir = dir.join("secondary");
+
+        // Con corridas de 7 posiciones cada columna se ordena en varios archivos
+        let mut builder = SecondaryIndexBuilder::with_run_records(&index_dir, 7).unwrap();
+        let mut trips = Vec::new();
+        let report = stream_process_csv_with_locations(&csv, |trip, location| {
+            trips.push((trip.clone(), location.offset));
+            builder.add(trip, location)
+        })
+        .unwrap();
+        assert!(fs::read_dir(&index_dir).unwrap().any(|entry| {
+            entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .contains(".run")
+        }));
+        builder.write(&csv, report.rows_invalid).unwrap();
+
+        // Solo quedan los índices: las corridas y los temporales se borraron
+        let mut files: Vec<_> = fs::read_dir(&index_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "do_location_id.idx",
+                "pickup_date.idx",
+                "pu_location_id.idx"
+            ]
+        );
+
+        let indexes = SecondaryIndexes::open(&index_dir).unwrap().unwrap();
+        indexes.validate_source(&csv).unwrap();
+        assert_eq!(indexes.rows(), 100);
+
+        let expected = |field: SecondaryField, min: u32, max: u32| -> Vec<u64> {
+            trips
+                .iter()
+                .filter(|(trip, _)| (min..=max).contains(&field.key_for(trip)))
+                .map(|(_, offset)| *offset)
+                .collect()
+        };
+        for (field, min, max) in [
+            (SecondaryField::PickupLocation, 3, 3),
+            (SecondaryField::PickupLocation, 10, 14),
+            (SecondaryField::DropoffLocation, 0, 20),
+            (SecondaryField::DropoffLocation, 41, 50),
+        ] {
+            let offsets = indexes.lookup_range(field, min, max).unwrap();
+            assert_eq!(offsets, expected(field, min, max));
+            assert_eq!(indexes.count_range(field, min, max), offsets.len() as u64);
+        }
+
+        let day = date_key(NaiveDate::from_ymd_opt(2023, 3, 2).unwrap());
+        let offsets = indexes
+            .lookup_range(SecondaryField::PickupDate, day, day)
+            .unwrap();
+        assert_eq!(offsets.len(), 15);
+        assert_eq!(offsets, expected(SecondaryField::PickupDate, day, day));
+        assert!(
+            indexes
+                .lookup_range(SecondaryField::PickupLocation, 5, 4)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,526 @@
+use super::aggregate::{GroupKey, Value};
+use super::trip_struct::{Measure, TripRef};
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Precisión de los resúmenes aproximados
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SketchOptions {
+    // Tamaño de los compactadores de KLL; con 200 el error de rango es de ~1,65 %
+    pub quantile_k: usize,
+    // Bits de HyperLogLog: 2^precision registros, error relativo de ~1,04 / √(2^precision)
+    pub distinct_precision: u8,
+    // Hasta esta cantidad de valores los resultados son exactos
+    pub exact_limit: usize,
+}
+
+impl Default for SketchOptions {
+    fn default() -> Self {
+        Self {
+            quantile_k: 200,
+            distinct_precision: 14,
+            exact_limit: 10_000,
+        }
+    }
+}
+
+impl SketchOptions {
+    pub const MIN_QUANTILE_K: usize = 8;
+    pub const DISTINCT_PRECISION_RANGE: std::ops::RangeInclusive<u8> = 4..=18;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.quantile_k < Self::MIN_QUANTILE_K {
+            return Err(format!(
+                "k de cuantiles inválido: {} (mínimo {})",
+                self.quantile_k,
+                Self::MIN_QUANTILE_K
+            ));
+        }
+        if !Self::DISTINCT_PRECISION_RANGE.contains(&self.distinct_precision) {
+            return Err(format!(
+                "Precisión de distintos inválida: {} (entre {} y {})",
+                self.distinct_precision,
+                Self::DISTINCT_PRECISION_RANGE.start(),
+                Self::DISTINCT_PRECISION_RANGE.end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Resumen KLL (Karnin, Lang y Liberty): niveles de valores donde cada valor del nivel h
+// representa 2^h valores. Al llenarse un nivel se ordena y la mitad de sus valores,
+// los pares o los impares al azar, pasa al siguiente
+struct Kll {
+    k: usize,
+    compactors: Vec<Vec<f64>>,
+    size: usize,
+    max_size: usize,
+    // xorshift con semilla fija, para que dos recorridos den el mismo resultado
+    rng: u64,
+}
+
+impl Kll {
+    fn new(k: usize) -> Self {
+        let mut kll = Self {
+            k,
+            compactors: Vec::new(),
+            size: 0,
+            max_size: 0,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        };
+        kll.grow();
+        kll
+    }
+
+    // Los niveles más bajos son más chicos: su error pesa menos
+    fn capacity(&self, level: usize) -> usize {
+        let depth = self.compactors.len() - level - 1;
+        (self.k as f64 * (2.0f64 / 3.0).powi(depth as i32)).ceil() as usize + 1
+    }
+
+    fn grow(&mut self) {
+        self.compactors.push(Vec::new());
+        self.max_size = (0..self.compactors.len())
+            .map(|level| self.capacity(level))
+            .sum();
+    }
+
+    fn next_bit(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng & 1) as usize
+    }
+
+    fn add(&mut self, value: f64) {
+        self.compactors[0].push(value);
+        self.size += 1;
+        if self.size >= self.max_size {
+            self.compress();
+        }
+    }
+
+    fn compress(&mut self) {
+        for level in 0..self.compactors.len() {
+            if self.compactors[level].len() < self.capacity(level) {
+                continue;
+            }
+            if level + 1 == self.compactors.len() {
+                self.grow();
+            }
+
+            let mut items = std::mem::take(&mut self.compactors[level]);
+            items.sort_by(f64::total_cmp);
+            // Con una cantidad impar el menor se queda en el nivel
+            if items.len() % 2 == 1 {
+                self.compactors[level].push(items.remove(0));
+            }
+            let offset = self.next_bit();
+            self.compactors[level + 1].extend(items.into_iter().skip(offset).step_by(2));
+
+            self.size = self.compactors.iter().map(Vec::len).sum();
+            if self.size < self.max_size {
+                break;
+            }
+        }
+    }
+
+    fn quantile(&self, q: f64) -> f64 {
+        let mut items: Vec<(f64, u64)> = self
+            .compactors
+            .iter()
+            .enumerate()
+            .flat_map(|(level, items)| items.iter().map(move |&value| (value, 1u64 << level)))
+            .collect();
+        items.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total: u64 = items.iter().map(|(_, weight)| weight).sum();
+        let target = q * total as f64;
+        let mut cumulative = 0;
+        for &(value, weight) in &items {
+            cumulative += weight;
+            if cumulative as f64 >= target {
+                return value;
+            }
+        }
+        items.last().map_or(f64::NAN, |(value, _)| *value)
+    }
+}
+
+/// Cuantiles de una serie de valores sin guardarlos todos: exactos hasta
+/// `exact_limit` valores y desde ahí aproximados con KLL
+pub struct QuantileSketch {
+    options: SketchOptions,
+    count: usize,
+    exact: Vec<f64>,
+    sorted: bool,
+    kll: Option<Kll>,
+}
+
+impl QuantileSketch {
+    pub fn new(options: SketchOptions) -> Self {
+        Self {
+            options,
+            count: 0,
+            exact: Vec::new(),
+            sorted: true,
+            kll: None,
+        }
+    }
+
+    /// Como new, pero aproximado desde el primer valor
+    pub fn new_approximate(options: SketchOptions) -> Self {
+        let mut sketch = Self::new(options);
+        sketch.kll = Some(Kll::new(options.quantile_k));
+        sketch
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        if let Some(kll) = self.kll.as_mut() {
+            kll.add(value);
+            return;
+        }
+
+        self.exact.push(value);
+        self.sorted = false;
+        if self.exact.len() > self.options.exact_limit {
+            self.make_approximate();
+        }
+    }
+
+    /// Valores guardados en modo exacto (0 una vez aproximado)
+    pub fn exact_len(&self) -> usize {
+        self.exact.len()
+    }
+
+    /// Pasa a KLL con los valores guardados hasta ahora
+    pub fn make_approximate(&mut self) {
+        if self.kll.is_some() {
+            return;
+        }
+        let mut kll = Kll::new(self.options.quantile_k);
+        for value in self.exact.drain(..) {
+            kll.add(value);
+        }
+        self.exact.shrink_to_fit();
+        self.kll = Some(kll);
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.kll.is_none()
+    }
+
+    /// Cuantil q entre 0 y 1; None sin valores. En modo exacto interpola entre los dos
+    /// valores más cercanos (ordena los valores guardados)
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        if let Some(kll) = &self.kll {
+            return Some(kll.quantile(q));
+        }
+
+        if !self.sorted {
+            self.exact.sort_by(f64::total_cmp);
+            self.sorted = true;
+        }
+        let rank = q * (self.exact.len() - 1) as f64;
+        let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+        Some(self.exact[low] + (self.exact[high] - self.exact[low]) * (rank - low as f64))
+    }
+}
+
+// HyperLogLog: cada valor se asigna a un registro por los primeros bits de su hash, y el
+// registro guarda la mayor cantidad de ceros iniciales vista en el resto
+struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new(precision: u8) -> Self {
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    fn add_hash(&mut self, hash: u64) {
+        let precision = self.precision as u32;
+        let register = (hash >> (64 - precision)) as usize;
+        let rank = ((hash << precision).leading_zeros() + 1).min(64 - precision + 1) as u8;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-(rank as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        // Con pocos valores se cuentan los registros vacíos (linear counting)
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if raw <= 2.5 * m && empty > 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+fn hash_value(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Cantidad de valores distintos: exacta hasta `exact_limit` valores y desde ahí
+/// aproximada con HyperLogLog
+pub struct DistinctCounter {
+    options: SketchOptions,
+    exact: HashSet<Value>,
+    hll: Option<HyperLogLog>,
+}
+
+impl DistinctCounter {
+    pub fn new(options: SketchOptions) -> Self {
+        Self {
+            options,
+            exact: HashSet::new(),
+            hll: None,
+        }
+    }
+
+    pub fn add(&mut self, value: Value) {
+        if let Some(hll) = self.hll.as_mut() {
+            hll.add_hash(hash_value(&value));
+            return;
+        }
+
+        self.exact.insert(value);
+        if self.exact.len() > self.options.exact_limit {
+            let mut hll = HyperLogLog::new(self.options.distinct_precision);
+            for value in self.exact.drain() {
+                hll.add_hash(hash_value(&value));
+            }
+            self.exact.shrink_to_fit();
+            self.hll = Some(hll);
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.hll.is_none()
+    }
+
+    pub fn count(&self) -> f64 {
+        match &self.hll {
+            Some(hll) => hll.estimate().round(),
+            None => self.exact.len() as f64,
+        }
+    }
+}
+
+/// Estadística calculada con un resumen durante el recorrido
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApproxStat {
+    // Cuantil entre 0 y 1 de una medida
+    Quantile(Measure, f64),
+    // Valores distintos de una clave (una columna o un valor calculado)
+    Distinct(GroupKey),
+}
+
+impl ApproxStat {
+    /// Interpreta median(M), pNN(M) o distinct(K), por ejemplo p95(trip_distance) o
+    /// distinct(pu_location_id)
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let invalid = || {
+            format!(
+                "Estadística inválida: {} (median(M), pNN(M) o distinct(K))",
+                text
+            )
+        };
+        let (function, rest) = text.split_once('(').ok_or_else(invalid)?;
+        let argument = rest.strip_suffix(')').ok_or_else(invalid)?;
+
+        let function = function.trim().to_ascii_lowercase();
+        if function == "distinct" {
+            let key = GroupKey::from_name(argument)
+                .ok_or_else(|| format!("Clave desconocida: {}", argument))?;
+            return Ok(ApproxStat::Distinct(key));
+        }
+        let measure = Measure::from_name(argument)
+            .ok_or_else(|| format!("Medida desconocida: {}", argument))?;
+        let percentile = match function.as_str() {
+            "median" => 50.0,
+            _ => function
+                .strip_prefix('p')
+                .and_then(|p| p.parse::<f64>().ok())
+                .filter(|p| (0.0..=100.0).contains(p))
+                .ok_or_else(invalid)?,
+        };
+        Ok(ApproxStat::Quantile(measure, percentile / 100.0))
+    }
+
+    /// Estadísticas separadas por comas
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        text.split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(ApproxStat::parse)
+            .collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ApproxStat::Quantile(measure, q) if *q == 0.5 => format!("median({})", measure.name()),
+            ApproxStat::Quantile(measure, q) => format!("p{}({})", q * 100.0, measure.name()),
+            ApproxStat::Distinct(key) => format!("distinct({})", key.name()),
+        }
+    }
+}
+
+/// Resultado de una ApproxStat; `exact` indica si no hizo falta aproximar
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApproxValue {
+    pub stat: ApproxStat,
+    // None si ningún trip tenía valor para la medida
+    pub value: Option<f64>,
+    pub exact: bool,
+}
+
+enum StatSketch {
+    Quantile(QuantileSketch),
+    Distinct(DistinctCounter),
+}
+
+/// Resúmenes de varias ApproxStat, actualizados viaje a viaje
+pub struct ApproxAccumulator {
+    stats: Vec<(ApproxStat, StatSketch)>,
+}
+
+impl ApproxAccumulator {
+    pub fn new(stats: &[ApproxStat], options: SketchOptions) -> Self {
+        let stats = stats
+            .iter()
+            .map(|stat| {
+                let sketch = match stat {
+                    ApproxStat::Quantile(..) => StatSketch::Quantile(QuantileSketch::new(options)),
+                    ApproxStat::Distinct(_) => StatSketch::Distinct(DistinctCounter::new(options)),
+                };
+                (*stat, sketch)
+            })
+            .collect();
+        Self { stats }
+    }
+
+    pub fn add(&mut self, trip: &TripRef) {
+        for (stat, sketch) in &mut self.stats {
+            match (stat, sketch) {
+                (ApproxStat::Quantile(measure, _), StatSketch::Quantile(sketch)) => {
+                    if let Some(value) = measure.value(trip) {
+                        sketch.add(value);
+                    }
+                }
+                (ApproxStat::Distinct(key), StatSketch::Distinct(counter)) => {
+                    counter.add(key.value(trip));
+                }
+                _ => unreachable!("cada estadística se crea con su resumen"),
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<ApproxValue> {
+        self.stats
+            .into_iter()
+            .map(|(stat, sketch)| match sketch {
+                StatSketch::Quantile(mut sketch) => {
+                    let ApproxStat::Quantile(_, q) = stat else {
+                        unreachable!("cada estadística se crea con su resumen")
+                    };
+                    ApproxValue {
+                        stat,
+                        value: sketch.quantile(q),
+                        exact: sketch.is_exact(),
+                    }
+                }
+                StatSketch::Distinct(counter) => ApproxValue {
+                    stat,
+                    value: Some(counter.count()),
+                    exact: counter.is_exact(),
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Con pocos valores el resultado es exacto; con muchos queda dentro del error esperado
+    #[test]
+    fn sketches_are_exact_below_limit_and_close_above() {
+        let options = SketchOptions {
+            exact_limit: 1000,
+            ..SketchOptions::default()
+        };
+
+        let mut small = QuantileSketch::new(options);
+        let mut distinct_small = DistinctCounter::new(options);
+        for value in [5.0, 1.0, 4.0, 2.0, 3.0] {
+            small.add(value);
+            distinct_small.add(Value::Float(value));
+            distinct_small.add(Value::Float(value));
+        }
+        assert!(small.is_exact() && distinct_small.is_exact());
+        assert_eq!(small.quantile(0.5), Some(3.0));
+        assert_eq!(small.quantile(0.25), Some(2.0));
+        assert_eq!(small.quantile(0.9), Some(4.6));
+        assert_eq!(distinct_small.count(), 5.0);
+        assert_eq!(QuantileSketch::new(options).quantile(0.5), None);
+
+        // Una permutación de 0..n, para que el orden de llegada no sea el de los valores
+        let n = 200_000u64;
+        let mut large = QuantileSketch::new(options);
+        let mut distinct = DistinctCounter::new(options);
+        for i in 0..n {
+            let value = (i * 7919) % n;
+            large.add(value as f64);
+            distinct.add(Value::Integer((value % 50_000) as i64));
+        }
+        assert!(!large.is_exact() && !distinct.is_exact());
+        for q in [0.01, 0.25, 0.5, 0.95, 0.99] {
+            let estimate = large.quantile(q).unwrap();
+            let rank_error = (estimate / n as f64 - q).abs();
+            assert!(
+                rank_error < 0.02,
+                "q {}: {} (error {})",
+                q,
+                estimate,
+                rank_error
+            );
+        }
+        let relative_error = (distinct.count() - 50_000.0).abs() / 50_000.0;
+        assert!(relative_error < 0.03, "distintos: {}", distinct.count());
+
+        let stats =
+            ApproxStat::parse_list("median(fare_amount), p95(trip_distance), distinct(route)")
+                .unwrap();
+        assert_eq!(stats[1], ApproxStat::Quantile(Measure::TripDistance, 0.95));
+        assert_eq!(stats[2], ApproxStat::Distinct(GroupKey::Route));
+        assert_eq!(stats[0].name(), "median(fare_amount)");
+        assert!(ApproxStat::parse("avg(fare_amount)").is_err());
+        assert!(ApproxStat::parse("distinct(zona)").is_err());
+    }
+}
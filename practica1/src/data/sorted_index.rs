@@ -0,0 +1,430 @@
+use super::data_lector::RowLocation;
+use super::disk_hash::SourceFingerprint;
+use super::index_file::{ExternalSorter, RUN_RECORDS, RunRecord, read_trailer, write_trailer};
+use super::trip_struct::Trip;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, create_dir_all};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Cambiar el formato de los archivos obliga a subir esta versión
+const SORTED_FORMAT_VERSION: u32 = 2;
+
+// Cada entrada es: [valor f64][byte de la fila en el CSV u64], ordenadas por valor.
+// Las entradas van al inicio del archivo y la cabecera al final
+const ENTRY_LEN: u64 = 16;
+
+// Entradas leídas por bloque al recorrer un rango
+const READ_BATCH_ENTRIES: u64 = 4096;
+
+// Columnas con índice ordenado
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortedField {
+    TotalAmount,
+    TripDistance,
+}
+
+impl SortedField {
+    pub const ALL: [SortedField; 2] = [SortedField::TotalAmount, SortedField::TripDistance];
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            SortedField::TotalAmount => "total_amount.idx",
+            SortedField::TripDistance => "trip_distance.idx",
+        }
+    }
+
+    fn key_for(&self, trip: &Trip) -> f64 {
+        match self {
+            SortedField::TotalAmount => trip.total_amount,
+            SortedField::TripDistance => trip.trip_distance,
+        }
+    }
+}
+
+impl fmt::Display for SortedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SortedField::TotalAmount => "total_amount",
+            SortedField::TripDistance => "trip_distance",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Cabecera al final de cada archivo de índice ordenado
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SortedHeader {
+    format_version: u32,
+    field: SortedField,
+    source: SourceFingerprint,
+    entries: u64,
+}
+
+// -0.0 y 0.0 son el mismo valor para los filtros, pero total_cmp los separa; se
+// guarda y se busca siempre 0.0
+fn normalize(value: f64) -> f64 {
+    if value == 0.0 { 0.0 } else { value }
+}
+
+// Entrada del índice; a igual valor se conserva el orden del archivo
+#[derive(Clone, Copy)]
+struct SortedEntry {
+    value: f64,
+    offset: u64,
+}
+
+impl Ord for SortedEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then(self.offset.cmp(&other.offset))
+    }
+}
+
+impl PartialOrd for SortedEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortedEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for SortedEntry {}
+
+impl RunRecord for SortedEntry {
+    const LEN: usize = ENTRY_LEN as usize;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.value.to_le_bytes());
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self {
+            value: f64::from_le_bytes(bytes[0..8].try_into().unwrap_or_default()),
+            offset: u64::from_le_bytes(bytes[8..16].try_into().unwrap_or_default()),
+        }
+    }
+}
+
+// Ordena los valores de cada columna mientras se recorre el CSV; los que no caben
+// en memoria se guardan en corridas temporales dentro del directorio
+pub struct SortedIndexBuilder {
+    dir: PathBuf,
+    sorters: Vec<ExternalSorter<SortedEntry>>,
+}
+
+impl SortedIndexBuilder {
+    pub fn create<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        Self::with_run_records(dir, RUN_RECORDS)
+    }
+
+    // Permite corridas pequeñas en las pruebas
+    fn with_run_records<P: AsRef<Path>>(
+        dir: P,
+        run_records: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(&dir)?;
+        let sorters = SortedField::ALL
+            .iter()
+            .map(|field| ExternalSorter::new(dir.join(field.file_name()), run_records))
+            .collect();
+
+        Ok(Self { dir, sorters })
+    }
+
+    pub fn add(&mut self, trip: &Trip, location: RowLocation) -> Result<(), Box<dyn Error>> {
+        for (field, sorter) in SortedField::ALL.iter().zip(self.sorters.iter_mut()) {
+            sorter.push(SortedEntry {
+                value: normalize(field.key_for(trip)),
+                offset: location.offset,
+            })?;
+        }
+        Ok(())
+    }
+
+    // Escribe un archivo por columna mezclando sus corridas, con la cabecera al
+    // final; cada archivo se reemplaza completo con un rename
+    pub fn write<P: AsRef<Path>>(self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        let source = SourceFingerprint::compute(csv_path)?;
+
+        for (field, sorter) in SortedField::ALL.into_iter().zip(self.sorters) {
+            let path = self.dir.join(field.file_name());
+            let tmp_path = path.with_extension("idx.tmp");
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+            let mut entries = 0;
+            let mut buf = Vec::with_capacity(SortedEntry::LEN);
+            for entry in sorter.finish()? {
+                buf.clear();
+                entry?.encode(&mut buf);
+                writer.write_all(&buf)?;
+                entries += 1;
+            }
+
+            let header = SortedHeader {
+                format_version: SORTED_FORMAT_VERSION,
+                field,
+                source: source.clone(),
+                entries,
+            };
+            write_trailer(&mut writer, &header)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            drop(writer);
+
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Índice ordenado abierto; las entradas se leen del archivo al consultarlo
+struct OpenSortedIndex {
+    file: File,
+    header: SortedHeader,
+}
+
+impl OpenSortedIndex {
+    fn read_entries(&self, first: u64, count: u64) -> Result<Vec<(f64, u64)>, Box<dyn Error>> {
+        let mut buf = vec![0u8; (count * ENTRY_LEN) as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(first * ENTRY_LEN))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf
+            .chunks_exact(ENTRY_LEN as usize)
+            .map(|entry| {
+                let entry = SortedEntry::decode(entry);
+                (entry.value, entry.offset)
+            })
+            .collect())
+    }
+
+    // Primera entrada cuyo valor no cumple `before` (búsqueda binaria en disco)
+    fn partition_point<F>(&self, before: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: Fn(f64) -> bool,
+    {
+        let (mut low, mut high) = (0, self.header.entries);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (value, _) = self.read_entries(mid, 1)?[0];
+            if before(value) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    // Rango de entradas [inicio, fin) con valores entre min y max (inclusive)
+    fn bounds(&self, min: Option<f64>, max: Option<f64>) -> Result<(u64, u64), Box<dyn Error>> {
+        let start = match min.map(normalize) {
+            Some(min) => self.partition_point(|value| value.total_cmp(&min).is_lt())?,
+            None => 0,
+        };
+        let end = match max.map(normalize) {
+            Some(max) => self.partition_point(|value| value.total_cmp(&max).is_le())?,
+            None => self.header.entries,
+        };
+        Ok((start, end.max(start)))
+    }
+}
+
+// Índices ordenados de un CSV (total_amount y trip_distance)
+pub struct SortedIndexes {
+    indexes: Vec<OpenSortedIndex>,
+}
+
+impl SortedIndexes {
+    // Abre los índices del directorio; None si falta alguno
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Option<Self>, Box<dyn Error>> {
+        let dir = dir.as_ref();
+        let mut indexes = Vec::new();
+
+        for field in SortedField::ALL {
+            let path = dir.join(field.file_name());
+            if !path.exists() {
+                return Ok(None);
+            }
+
+            let mut file = File::open(&path)?;
+            let invalid = || format!("Índice ordenado incompatible en {}", path.display());
+            // Un archivo del formato anterior no termina en una cabecera
+            let (header, entries_len): (SortedHeader, u64) =
+                read_trailer(&mut file)?.ok_or_else(invalid)?;
+
+            if header.format_version != SORTED_FORMAT_VERSION
+                || header.field != field
+                || header.entries * ENTRY_LEN != entries_len
+            {
+                return Err(invalid().into());
+            }
+
+            indexes.push(OpenSortedIndex { file, header });
+        }
+
+        Ok(Some(Self { indexes }))
+    }
+
+    fn index(&self, field: SortedField) -> &OpenSortedIndex {
+        match field {
+            SortedField::TotalAmount => &self.indexes[0],
+            SortedField::TripDistance => &self.indexes[1],
+        }
+    }
+
+    // Verifica que todos los índices correspondan al CSV y que no haya cambiado
+    pub fn validate_source<P: AsRef<Path>>(&self, csv_path: P) -> Result<(), Box<dyn Error>> {
+        let current = SourceFingerprint::compute(csv_path)?;
+        for index in &self.indexes {
+            if index.header.source != current {
+                return Err(format!(
+                    "El índice de {} no corresponde al CSV actual",
+                    index.header.field
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // Filas indexadas (las válidas del CSV)
+    pub fn rows(&self) -> u64 {
+        self.indexes.first().map_or(0, |index| index.header.entries)
+    }
+
+    // Cantidad exacta de filas en el rango, sin leer sus posiciones
+    pub fn count_range(
+        &self,
+        field: SortedField,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> Result<u64, Box<dyn Error>> {
+        let (start, end) = self.index(field).bounds(min, max)?;
+        Ok(end - start)
+    }
+
+    // Posiciones de las filas con valor entre min y max (inclusive), en orden del
+    // archivo para leer el CSV secuencialmente
+    pub fn range(
+        &self,
+        field: SortedField,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> Result<Vec<u64>, Box<dyn Error>> {
+        let index = self.index(field);
+        if !index.header.source.is_current()? {
+            return Err("El CSV cambió desde que se construyó el índice ordenado".into());
+        }
+
+        let (start, end) = index.bounds(min, max)?;
+        let mut offsets = Vec::with_capacity((end - start) as usize);
+        let mut next = start;
+        while next < end {
+            let count = (end - next).min(READ_BATCH_ENTRIES);
+            offsets.extend(index.read_entries(next, count)?.into_iter().map(|(_, o)| o));
+            next += count;
+        }
+
+        offsets.sort_unstable();
+        Ok(offsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{TestDir, read_trips, sample_trips_csv};
+    use super::*;
+
+    #[test]
+    fn spilled_runs_merge_into_ranges() {
+        let dir = TestDir::new("sorted-index");
+        let csv = dir.write("trips.csv", sample_trips_csv(60));
+        let index_dir = dir.join("sorted");
+
+        // Importes variados, con ceros de los dos signos y negativos
+        let mut trips = read_trips(&csv);
+        for (i, trip) in trips.iter_mut().enumerate() {
+            trip.total_amount = match i % 6 {
+                0 => 0.0,
+                1 => -0.0,
+                2 => -(i as f64),
+                _ => (i % 17) as f64 + 0.5,
+            };
+        }
+        let offset = |i: usize| i as u64 * 100;
+
+        // Con corridas de 8 entradas cada columna se ordena en varios archivos
+        let mut builder = SortedIndexBuilder::with_run_records(&index_dir, 8).unwrap();
+        for (i, trip) in trips.iter().enumerate() {
+            let location = RowLocation {
+                offset: offset(i),
+                len: 100,
+            };
+            builder.add(trip, location).unwrap();
+        }
+        builder.write(&csv).unwrap();
+        let files = fs::read_dir(&index_dir).unwrap().count();
+        assert_eq!(files, 2);
+
+        let indexes = SortedIndexes::open(&index_dir).unwrap().unwrap();
+        indexes.validate_source(&csv).unwrap();
+        assert_eq!(indexes.rows(), 60);
+
+        let expected = |min: Option<f64>, max: Option<f64>| -> Vec<u64> {
+            (0..trips.len())
+                .filter(|i| {
+                    let value = trips[*i].total_amount;
+                    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+                })
+                .map(offset)
+                .collect()
+        };
+        for (min, max) in [
+            (Some(0.0), Some(0.0)),
+            (Some(-0.0), Some(-0.0)),
+            (Some(-0.0), Some(5.5)),
+            (None, Some(0.0)),
+            (Some(-10.0), Some(-2.0)),
+            (Some(3.0), None),
+            (None, None),
+            (Some(8.0), Some(2.0)),
+        ] {
+            let offsets = indexes.range(SortedField::TotalAmount, min, max).unwrap();
+            assert_eq!(offsets, expected(min, max), "rango {:?}..={:?}", min, max);
+            let count = indexes
+                .count_range(SortedField::TotalAmount, min, max)
+                .unwrap();
+            assert_eq!(count, offsets.len() as u64);
+        }
+        // Los dos ceros son el mismo valor
+        assert_eq!(
+            indexes
+                .count_range(SortedField::TotalAmount, Some(-0.0), Some(0.0))
+                .unwrap(),
+            20
+        );
+
+        let distances = indexes
+            .range(SortedField::TripDistance, Some(2.0), Some(4.5))
+            .unwrap();
+        let expected_distances: Vec<u64> = (0..trips.len())
+            .filter(|i| (2.0..=4.5).contains(&trips[*i].trip_distance))
+            .map(offset)
+            .collect();
+        assert_eq!(distances, expected_distances);
+    }
+}
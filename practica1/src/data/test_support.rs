@@ -0,0 +1,117 @@
+use super::data_lector::stream_process_csv;
+use super::filters::set_index_root;
+use super::trip_struct::Trip;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+// El directorio de índices es global: las pruebas que lo cambian no corren a la vez
+static INDEX_ROOT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Encabezado de los archivos de TLC, sin columna index
+pub const TLC_HEADER: &str = "VendorID,tpep_pickup_datetime,tpep_dropoff_datetime,passenger_count,trip_distance,RatecodeID,store_and_fwd_flag,PULocationID,DOLocationID,payment_type,fare_amount,extra,mta_tax,tip_amount,tolls_amount,improvement_surcharge,total_amount,congestion_surcharge";
+
+/// Directorio temporal de una prueba; se borra al salir de la prueba, aunque falle
+pub struct TestDir {
+    path: PathBuf,
+    _index_root: Option<MutexGuard<'static, ()>>,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        Self::create(name, None)
+    }
+
+    /// Además guarda los índices dentro del directorio, mientras dure la prueba
+    pub fn with_index_root(name: &str) -> Self {
+        // Una prueba que falló con el candado tomado no invalida las demás
+        let guard = INDEX_ROOT_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = Self::create(name, Some(guard));
+        set_index_root(dir.join("indexes"));
+        dir
+    }
+
+    fn create(name: &str, index_root: Option<MutexGuard<'static, ()>>) -> Self {
+        let path = std::env::temp_dir().join(format!("practica1-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self {
+            path,
+            _index_root: index_root,
+        }
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Escribe un archivo dentro del directorio y devuelve su ruta
+    pub fn write<C: AsRef<[u8]>>(&self, name: &str, contents: C) -> PathBuf {
+        let path = self.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// CSV de TLC con `rows` viajes variados: pasajeros de 0 a 4, 30 orígenes, 40 destinos
+/// (cada uno con ~2,5 % de los viajes) y una tarifa en 0 cada 23 viajes, que deja la
+/// propina sin porcentaje
+pub fn sample_trips_csv(rows: usize) -> String {
+    let mut text = format!("{}\n", TLC_HEADER);
+    for i in 0..rows {
+        let (fare, total) = if i % 23 == 0 {
+            (0, 20)
+        } else {
+            (5 + i % 40, 8 + i % 40)
+        };
+        text.push_str(&format!(
+            "{},2023-03-0{} {:02}:{:02}:00,2023-03-0{} {:02}:{:02}:00,{},{}.25,1,N,{},{},1,{},1,0.5,{}.5,0,0.3,{}.75,2.5\n",
+            i % 2 + 1,
+            1 + i % 7,
+            8 + i % 12,
+            i % 60,
+            1 + i % 7,
+            9 + i % 12,
+            (i * 7) % 60,
+            i % 5,
+            i % 12,
+            i % 30,
+            1 + (i * 13) % 40,
+            fare,
+            i % 6,
+            total
+        ));
+    }
+    text
+}
+
+/// Viajes del CSV en orden; falla si alguna fila es inválida
+pub fn read_trips(path: &Path) -> Vec<Trip> {
+    let mut trips = Vec::new();
+    let report = stream_process_csv(path, |trip| {
+        trips.push(trip.clone());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(report.rows_invalid, 0);
+    trips
+}
+
+/// Viajes válidos del CSV en orden, omitiendo las filas inválidas
+pub fn read_trips_allowing_errors(path: &Path) -> Vec<Trip> {
+    let mut trips = Vec::new();
+    stream_process_csv(path, |trip| {
+        trips.push(trip.clone());
+        Ok(())
+    })
+    .unwrap();
+    trips
+}
@@ -1,24 +1,908 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::StringRecord;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
 
-#[derive(Clone, Serialize, Deserialize)]
+// Formatos de fecha aceptados (TLC usa espacio, datagen.py usa ISO 8601)
+const DATETIME_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+// Formato usado al escribir fechas de vuelta a CSV
+pub const DATETIME_OUTPUT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// Tipo de pago según el diccionario de datos de TLC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PaymentType {
+    // Código 0: tarifa flexible, presente en los datos desde 2023
+    FlexFare,
+    CreditCard,
+    Cash,
+    NoCharge,
+    Dispute,
+    Unknown,
+    VoidedTrip,
+}
+
+impl PaymentType {
+    pub const ALL: [PaymentType; 7] = [
+        PaymentType::FlexFare,
+        PaymentType::CreditCard,
+        PaymentType::Cash,
+        PaymentType::NoCharge,
+        PaymentType::Dispute,
+        PaymentType::Unknown,
+        PaymentType::VoidedTrip,
+    ];
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(PaymentType::FlexFare),
+            1 => Some(PaymentType::CreditCard),
+            2 => Some(PaymentType::Cash),
+            3 => Some(PaymentType::NoCharge),
+            4 => Some(PaymentType::Dispute),
+            5 => Some(PaymentType::Unknown),
+            6 => Some(PaymentType::VoidedTrip),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            PaymentType::FlexFare => 0,
+            PaymentType::CreditCard => 1,
+            PaymentType::Cash => 2,
+            PaymentType::NoCharge => 3,
+            PaymentType::Dispute => 4,
+            PaymentType::Unknown => 5,
+            PaymentType::VoidedTrip => 6,
+        }
+    }
+
+    // Nombre legible para la interfaz
+    pub fn description(&self) -> &'static str {
+        match self {
+            PaymentType::FlexFare => "Tarifa flexible",
+            PaymentType::CreditCard => "Tarjeta de crédito",
+            PaymentType::Cash => "Efectivo",
+            PaymentType::NoCharge => "Sin cargo",
+            PaymentType::Dispute => "Disputa",
+            PaymentType::Unknown => "Desconocido",
+            PaymentType::VoidedTrip => "Viaje anulado",
+        }
+    }
+}
+
+// Se escribe como su código numérico para mantener el formato del CSV
+impl fmt::Display for PaymentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+// Tarifa aplicada al final del viaje según el diccionario de datos de TLC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RateCode {
+    Standard,
+    Jfk,
+    Newark,
+    NassauOrWestchester,
+    Negotiated,
+    GroupRide,
+    Unknown,
+}
+
+impl RateCode {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(RateCode::Standard),
+            2 => Some(RateCode::Jfk),
+            3 => Some(RateCode::Newark),
+            4 => Some(RateCode::NassauOrWestchester),
+            5 => Some(RateCode::Negotiated),
+            6 => Some(RateCode::GroupRide),
+            99 => Some(RateCode::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            RateCode::Standard => 1,
+            RateCode::Jfk => 2,
+            RateCode::Newark => 3,
+            RateCode::NassauOrWestchester => 4,
+            RateCode::Negotiated => 5,
+            RateCode::GroupRide => 6,
+            RateCode::Unknown => 99,
+        }
+    }
+}
+
+impl fmt::Display for RateCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+// Los campos Option son los que TLC deja vacíos (o nulos en Parquet) en algunos
+// viajes; None es un valor desconocido, no un valor por defecto
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Trip {
     pub vendor_id: String,
-    pub tpep_pickup_datetime: String,
-    pub tpep_dropoff_datetime: String,
-    pub passenger_count: String,
-    pub trip_distance: String,
-    pub ratecode_id: String,
-    pub store_and_fwd_flag: String,
-    pub pu_location_id: String,
-    pub do_location_id: String,
-    pub payment_type: String,
-    pub fare_amount: String,
-    pub extra: String,
-    pub mta_tax: String,
-    pub tip_amount: String,
-    pub tolls_amount: String,
-    pub improvement_surcharge: String,
-    pub total_amount: String,
-    pub congestion_surcharge: String,
+    pub tpep_pickup_datetime: NaiveDateTime,
+    pub tpep_dropoff_datetime: NaiveDateTime,
+    pub passenger_count: Option<u8>,
+    pub trip_distance: f64,
+    pub ratecode_id: Option<RateCode>,
+    pub store_and_fwd_flag: Option<bool>,
+    pub pu_location_id: u16,
+    pub do_location_id: u16,
+    pub payment_type: Option<PaymentType>,
+    pub fare_amount: f64,
+    pub extra: f64,
+    pub mta_tax: f64,
+    pub tip_amount: f64,
+    pub tolls_amount: f64,
+    pub improvement_surcharge: f64,
+    pub total_amount: f64,
+    pub congestion_surcharge: Option<f64>,
     pub index: String,
+    // Archivo del dataset de donde se leyó el viaje
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+}
+
+// Error al convertir una columna del CSV a su tipo
+#[derive(Debug, Clone)]
+pub struct TripParseError {
+    pub field: &'static str,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for TripParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "campo '{}' con valor '{}': {}",
+            self.field, self.value, self.reason
+        )
+    }
+}
+
+impl Error for TripParseError {}
+
+impl TripParseError {
+    pub fn new(field: &'static str, value: &str, reason: impl Into<String>) -> Self {
+        Self {
+            field,
+            value: value.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+fn parse_f64(field: &'static str, value: &str) -> Result<f64, TripParseError> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| TripParseError::new(field, value, e.to_string()))
+}
+
+// Acepta enteros escritos como "1" o "1.0" (algunos archivos TLC usan decimales)
+fn parse_integer<T: TryFrom<u64>>(field: &'static str, value: &str) -> Result<T, TripParseError> {
+    let trimmed = value.trim();
+    let number = match trimmed.parse::<u64>() {
+        Ok(n) => n,
+        Err(_) => match trimmed.parse::<f64>() {
+            Ok(f) if f >= 0.0 && f.fract() == 0.0 => f as u64,
+            _ => return Err(TripParseError::new(field, value, "no es un entero válido")),
+        },
+    };
+    T::try_from(number).map_err(|_| TripParseError::new(field, value, "fuera de rango"))
+}
+
+fn parse_datetime(field: &'static str, value: &str) -> Result<NaiveDateTime, TripParseError> {
+    parse_datetime_str(value)
+        .ok_or_else(|| TripParseError::new(field, value, "fecha con formato desconocido"))
+}
+
+// Interpreta una fecha y hora en cualquiera de los formatos aceptados
+pub fn parse_datetime_str(value: &str) -> Option<NaiveDateTime> {
+    let trimmed = value.trim();
+    parse_datetime_fast(trimmed).or_else(|| {
+        DATETIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(trimmed, format).ok())
+    })
+}
+
+// Los formatos de DATETIME_FORMATS con todos sus dígitos, leídos sin pasar por el
+// intérprete de formatos de chrono, que es lo más lento de convertir una fila
+fn parse_datetime_fast(value: &str) -> Option<NaiveDateTime> {
+    let bytes = value.as_bytes();
+    if bytes.len() != 19
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b' ' | b'T')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+
+    let number = |range: std::ops::Range<usize>| {
+        bytes[range].iter().try_fold(0u32, |acc, digit| {
+            digit
+                .is_ascii_digit()
+                .then(|| acc * 10 + (digit - b'0') as u32)
+        })
+    };
+    NaiveDate::from_ymd_opt(number(0..4)? as i32, number(5..7)?, number(8..10)?)?.and_hms_opt(
+        number(11..13)?,
+        number(14..16)?,
+        number(17..19)?,
+    )
+}
+
+fn parse_ratecode(value: &str) -> Result<RateCode, TripParseError> {
+    let code: u8 = parse_integer("ratecode_id", value)?;
+    RateCode::from_code(code)
+        .ok_or_else(|| TripParseError::new("ratecode_id", value, "código de tarifa desconocido"))
+}
+
+fn parse_payment_type(value: &str) -> Result<PaymentType, TripParseError> {
+    let code: u8 = parse_integer("payment_type", value)?;
+    PaymentType::from_code(code)
+        .ok_or_else(|| TripParseError::new("payment_type", value, "tipo de pago desconocido"))
+}
+
+fn parse_flag(field: &'static str, value: &str) -> Result<bool, TripParseError> {
+    match value.trim() {
+        "Y" | "y" => Ok(true),
+        "N" | "n" => Ok(false),
+        _ => Err(TripParseError::new(field, value, "se esperaba 'Y' o 'N'")),
+    }
+}
+
+impl Trip {
+    // Vista prestada del viaje, para usarlo donde se espera un TripRef
+    pub fn as_trip_ref(&self) -> TripRef<'_> {
+        TripRef {
+            vendor_id: &self.vendor_id,
+            tpep_pickup_datetime: self.tpep_pickup_datetime,
+            tpep_dropoff_datetime: self.tpep_dropoff_datetime,
+            passenger_count: self.passenger_count,
+            trip_distance: self.trip_distance,
+            ratecode_id: self.ratecode_id,
+            store_and_fwd_flag: self.store_and_fwd_flag,
+            pu_location_id: self.pu_location_id,
+            do_location_id: self.do_location_id,
+            payment_type: self.payment_type,
+            fare_amount: self.fare_amount,
+            extra: self.extra,
+            mta_tax: self.mta_tax,
+            tip_amount: self.tip_amount,
+            tolls_amount: self.tolls_amount,
+            improvement_surcharge: self.improvement_surcharge,
+            total_amount: self.total_amount,
+            congestion_surcharge: self.congestion_surcharge,
+            index: Cow::Borrowed(&self.index),
+            source_file: self.source_file.as_deref(),
+        }
+    }
+
+    // Representación de la bandera store_and_fwd_flag como en el CSV original
+    pub fn store_and_fwd_flag_str(&self) -> Option<&'static str> {
+        self.store_and_fwd_flag
+            .map(|flag| if flag { "Y" } else { "N" })
+    }
+}
+
+// Viaje que toma prestados sus textos del registro leído, para los recorridos que
+// solo filtran o acumulan y no necesitan guardar el viaje (ver mmap_reader)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripRef<'a> {
+    pub vendor_id: &'a str,
+    pub tpep_pickup_datetime: NaiveDateTime,
+    pub tpep_dropoff_datetime: NaiveDateTime,
+    pub passenger_count: Option<u8>,
+    pub trip_distance: f64,
+    pub ratecode_id: Option<RateCode>,
+    pub store_and_fwd_flag: Option<bool>,
+    pub pu_location_id: u16,
+    pub do_location_id: u16,
+    pub payment_type: Option<PaymentType>,
+    pub fare_amount: f64,
+    pub extra: f64,
+    pub mta_tax: f64,
+    pub tip_amount: f64,
+    pub tolls_amount: f64,
+    pub improvement_surcharge: f64,
+    pub total_amount: f64,
+    pub congestion_surcharge: Option<f64>,
+    // Sin columna index es el número de fila, que no está en el registro
+    pub index: Cow<'a, str>,
+    pub source_file: Option<&'a str>,
+}
+
+impl TripRef<'_> {
+    // Copia los textos para guardar el viaje
+    pub fn to_trip(&self) -> Trip {
+        Trip {
+            vendor_id: self.vendor_id.to_string(),
+            tpep_pickup_datetime: self.tpep_pickup_datetime,
+            tpep_dropoff_datetime: self.tpep_dropoff_datetime,
+            passenger_count: self.passenger_count,
+            trip_distance: self.trip_distance,
+            ratecode_id: self.ratecode_id,
+            store_and_fwd_flag: self.store_and_fwd_flag,
+            pu_location_id: self.pu_location_id,
+            do_location_id: self.do_location_id,
+            payment_type: self.payment_type,
+            fare_amount: self.fare_amount,
+            extra: self.extra,
+            mta_tax: self.mta_tax,
+            tip_amount: self.tip_amount,
+            tolls_amount: self.tolls_amount,
+            improvement_surcharge: self.improvement_surcharge,
+            total_amount: self.total_amount,
+            congestion_surcharge: self.congestion_surcharge,
+            index: self.index.to_string(),
+            source_file: self.source_file.map(str::to_string),
+        }
+    }
+
+    // Duración del viaje en minutos
+    pub fn duration_minutes(&self) -> f64 {
+        (self.tpep_dropoff_datetime - self.tpep_pickup_datetime).num_seconds() as f64 / 60.0
+    }
+
+    // Propina como porcentaje de la tarifa; None si la tarifa no es positiva
+    pub fn tip_percentage(&self) -> Option<f64> {
+        if self.fare_amount > 0.0 {
+            Some(self.tip_amount / self.fare_amount * 100.0)
+        } else {
+            None
+        }
+    }
+}
+
+// Valores numéricos de un viaje, incluidos los calculados, para histogramas y agregados
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Measure {
+    TotalAmount,
+    FareAmount,
+    Extra,
+    MtaTax,
+    TipAmount,
+    TollsAmount,
+    ImprovementSurcharge,
+    CongestionSurcharge,
+    TripDistance,
+    PassengerCount,
+    DurationMinutes,
+    TipPercentage,
+}
+
+impl Measure {
+    pub const ALL: [Measure; 12] = [
+        Measure::TotalAmount,
+        Measure::FareAmount,
+        Measure::Extra,
+        Measure::MtaTax,
+        Measure::TipAmount,
+        Measure::TollsAmount,
+        Measure::ImprovementSurcharge,
+        Measure::CongestionSurcharge,
+        Measure::TripDistance,
+        Measure::PassengerCount,
+        Measure::DurationMinutes,
+        Measure::TipPercentage,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Measure::TotalAmount => "total_amount",
+            Measure::FareAmount => "fare_amount",
+            Measure::Extra => "extra",
+            Measure::MtaTax => "mta_tax",
+            Measure::TipAmount => "tip_amount",
+            Measure::TollsAmount => "tolls_amount",
+            Measure::ImprovementSurcharge => "improvement_surcharge",
+            Measure::CongestionSurcharge => "congestion_surcharge",
+            Measure::TripDistance => "trip_distance",
+            Measure::PassengerCount => "passenger_count",
+            Measure::DurationMinutes => "duration_minutes",
+            Measure::TipPercentage => "tip_percentage",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Measure::ALL
+            .into_iter()
+            .find(|measure| measure.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    // Valor en el viaje; None si no se puede calcular (propina sin tarifa) o es
+    // desconocido
+    pub fn value(&self, trip: &TripRef) -> Option<f64> {
+        match self {
+            Measure::TotalAmount => Some(trip.total_amount),
+            Measure::FareAmount => Some(trip.fare_amount),
+            Measure::Extra => Some(trip.extra),
+            Measure::MtaTax => Some(trip.mta_tax),
+            Measure::TipAmount => Some(trip.tip_amount),
+            Measure::TollsAmount => Some(trip.tolls_amount),
+            Measure::ImprovementSurcharge => Some(trip.improvement_surcharge),
+            Measure::CongestionSurcharge => trip.congestion_surcharge,
+            Measure::TripDistance => Some(trip.trip_distance),
+            Measure::PassengerCount => trip.passenger_count.map(f64::from),
+            Measure::DurationMinutes => Some(trip.duration_minutes()),
+            Measure::TipPercentage => trip.tip_percentage(),
+        }
+    }
+}
+
+// Columnas de un viaje en el orden del CSV, más el archivo de origen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TripColumn {
+    VendorId,
+    PickupDatetime,
+    DropoffDatetime,
+    PassengerCount,
+    TripDistance,
+    RatecodeId,
+    StoreAndFwdFlag,
+    PuLocationId,
+    DoLocationId,
+    PaymentType,
+    FareAmount,
+    Extra,
+    MtaTax,
+    TipAmount,
+    TollsAmount,
+    ImprovementSurcharge,
+    TotalAmount,
+    CongestionSurcharge,
+    Index,
+    SourceFile,
+}
+
+impl TripColumn {
+    pub const ALL: [TripColumn; 20] = [
+        TripColumn::VendorId,
+        TripColumn::PickupDatetime,
+        TripColumn::DropoffDatetime,
+        TripColumn::PassengerCount,
+        TripColumn::TripDistance,
+        TripColumn::RatecodeId,
+        TripColumn::StoreAndFwdFlag,
+        TripColumn::PuLocationId,
+        TripColumn::DoLocationId,
+        TripColumn::PaymentType,
+        TripColumn::FareAmount,
+        TripColumn::Extra,
+        TripColumn::MtaTax,
+        TripColumn::TipAmount,
+        TripColumn::TollsAmount,
+        TripColumn::ImprovementSurcharge,
+        TripColumn::TotalAmount,
+        TripColumn::CongestionSurcharge,
+        TripColumn::Index,
+        TripColumn::SourceFile,
+    ];
+
+    // Nombre de la columna en el encabezado
+    pub fn name(&self) -> &'static str {
+        match self {
+            TripColumn::VendorId => "vendor_id",
+            TripColumn::PickupDatetime => "tpep_pickup_datetime",
+            TripColumn::DropoffDatetime => "tpep_dropoff_datetime",
+            TripColumn::PassengerCount => "passenger_count",
+            TripColumn::TripDistance => "trip_distance",
+            TripColumn::RatecodeId => "ratecode_id",
+            TripColumn::StoreAndFwdFlag => "store_and_fwd_flag",
+            TripColumn::PuLocationId => "pu_location_id",
+            TripColumn::DoLocationId => "do_location_id",
+            TripColumn::PaymentType => "payment_type",
+            TripColumn::FareAmount => "fare_amount",
+            TripColumn::Extra => "extra",
+            TripColumn::MtaTax => "mta_tax",
+            TripColumn::TipAmount => "tip_amount",
+            TripColumn::TollsAmount => "tolls_amount",
+            TripColumn::ImprovementSurcharge => "improvement_surcharge",
+            TripColumn::TotalAmount => "total_amount",
+            TripColumn::CongestionSurcharge => "congestion_surcharge",
+            TripColumn::Index => "index",
+            TripColumn::SourceFile => "source_file",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        TripColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+    }
+
+    // Otros nombres (ya normalizados) con que aparece la columna en archivos de TLC
+    // de distintos años o exportados con pandas
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            TripColumn::PickupDatetime => &["pickupdatetime", "lpeppickupdatetime"],
+            TripColumn::DropoffDatetime => &["dropoffdatetime", "lpepdropoffdatetime"],
+            TripColumn::RatecodeId => &["ratecode"],
+            TripColumn::Index => &["", "unnamed0"],
+            _ => &[],
+        }
+    }
+
+    // Columna que corresponde a un nombre del encabezado; VendorID, vendor_id y
+    // VENDOR_ID son la misma
+    pub fn from_header(name: &str) -> Option<Self> {
+        let normalized = normalize_header(name);
+        TripColumn::ALL.into_iter().find(|column| {
+            normalize_header(column.name()) == normalized
+                || column.aliases().contains(&normalized.as_str())
+        })
+    }
+
+    // Valor que toma la columna cuando falta en el archivo; None si es obligatoria.
+    // Las que TLC deja vacías en algunos viajes quedan en None (un valor vacío en ellas
+    // también es None), e index y source_file se completan al leer
+    pub fn default_value(&self) -> Option<&'static str> {
+        match self {
+            TripColumn::VendorId | TripColumn::Index | TripColumn::SourceFile => Some(""),
+            TripColumn::PassengerCount
+            | TripColumn::RatecodeId
+            | TripColumn::StoreAndFwdFlag
+            | TripColumn::PaymentType
+            | TripColumn::CongestionSurcharge => Some(""),
+            TripColumn::Extra
+            | TripColumn::MtaTax
+            | TripColumn::TipAmount
+            | TripColumn::TollsAmount
+            | TripColumn::ImprovementSurcharge => Some("0"),
+            TripColumn::PickupDatetime
+            | TripColumn::DropoffDatetime
+            | TripColumn::TripDistance
+            | TripColumn::PuLocationId
+            | TripColumn::DoLocationId
+            | TripColumn::FareAmount
+            | TripColumn::TotalAmount => None,
+        }
+    }
+
+    // Las columnas numéricas se escriben sin comillas en JSON
+    pub fn is_numeric(&self) -> bool {
+        !matches!(
+            self,
+            TripColumn::VendorId
+                | TripColumn::PickupDatetime
+                | TripColumn::DropoffDatetime
+                | TripColumn::StoreAndFwdFlag
+                | TripColumn::Index
+                | TripColumn::SourceFile
+        )
+    }
+
+    // Valor de la columna como se escribe en el CSV; vacío si es desconocido
+    pub fn format(&self, trip: &Trip) -> String {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map(|value| value.to_string()).unwrap_or_default()
+        }
+
+        match self {
+            TripColumn::VendorId => trip.vendor_id.clone(),
+            TripColumn::PickupDatetime => trip
+                .tpep_pickup_datetime
+                .format(DATETIME_OUTPUT_FORMAT)
+                .to_string(),
+            TripColumn::DropoffDatetime => trip
+                .tpep_dropoff_datetime
+                .format(DATETIME_OUTPUT_FORMAT)
+                .to_string(),
+            TripColumn::PassengerCount => optional(trip.passenger_count),
+            TripColumn::TripDistance => trip.trip_distance.to_string(),
+            TripColumn::RatecodeId => optional(trip.ratecode_id),
+            TripColumn::StoreAndFwdFlag => optional(trip.store_and_fwd_flag_str()),
+            TripColumn::PuLocationId => trip.pu_location_id.to_string(),
+            TripColumn::DoLocationId => trip.do_location_id.to_string(),
+            TripColumn::PaymentType => optional(trip.payment_type),
+            TripColumn::FareAmount => trip.fare_amount.to_string(),
+            TripColumn::Extra => trip.extra.to_string(),
+            TripColumn::MtaTax => trip.mta_tax.to_string(),
+            TripColumn::TipAmount => trip.tip_amount.to_string(),
+            TripColumn::TollsAmount => trip.tolls_amount.to_string(),
+            TripColumn::ImprovementSurcharge => trip.improvement_surcharge.to_string(),
+            TripColumn::TotalAmount => trip.total_amount.to_string(),
+            TripColumn::CongestionSurcharge => optional(trip.congestion_surcharge),
+            TripColumn::Index => trip.index.clone(),
+            TripColumn::SourceFile => trip.source_file.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Minúsculas y solo letras y dígitos (descarta `_`, espacios y el BOM de Excel)
+fn normalize_header(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Posición de cada columna de TripColumn en los registros de un CSV, tomada de su
+// encabezado; las columnas que no son de Trip (airport_fee, por ejemplo) se ignoran
+#[derive(Clone, Debug, PartialEq)]
+pub struct TripSchema {
+    positions: [Option<usize>; TripColumn::ALL.len()],
+}
+
+impl TripSchema {
+    // Columnas en el orden de TripColumn, como las escribe este programa
+    pub fn positional() -> Self {
+        let mut positions = [None; TripColumn::ALL.len()];
+        for (i, position) in positions.iter_mut().enumerate() {
+            *position = Some(i);
+        }
+        Self { positions }
+    }
+
+    pub fn from_header(header: &StringRecord) -> Result<Self, TripParseError> {
+        let mut positions = [None; TripColumn::ALL.len()];
+        for (position, name) in header.iter().enumerate() {
+            if let Some(column) = TripColumn::from_header(name) {
+                // Si una columna aparece dos veces vale la primera
+                positions[column as usize].get_or_insert(position);
+            }
+        }
+
+        // Sin ningún nombre conocido se asume el orden original de las columnas
+        if positions.iter().all(Option::is_none) {
+            return Ok(Self::positional());
+        }
+
+        let missing: Vec<&str> = TripColumn::ALL
+            .into_iter()
+            .filter(|column| {
+                column.default_value().is_none() && positions[*column as usize].is_none()
+            })
+            .map(|column| column.name())
+            .collect();
+        if !missing.is_empty() {
+            return Err(TripParseError::new(
+                "encabezado",
+                &header.iter().collect::<Vec<_>>().join(","),
+                format!("faltan columnas obligatorias: {}", missing.join(", ")),
+            ));
+        }
+
+        Ok(Self { positions })
+    }
+
+    pub fn has_column(&self, column: TripColumn) -> bool {
+        self.positions[column as usize].is_some()
+    }
+
+    // Sin columna index se usa como index el número de fila de datos (desde 0)
+    pub fn synthesizes_index(&self) -> bool {
+        !self.has_column(TripColumn::Index)
+    }
+
+    // Valor de una columna tal como viene en el registro
+    fn raw<'r, G>(&self, field: &G, column: TripColumn) -> Option<&'r str>
+    where
+        G: Fn(usize) -> Option<&'r str>,
+    {
+        self.positions[column as usize].and_then(field)
+    }
+
+    // Valor de una columna; las que faltan en el archivo toman su valor por defecto y
+    // un valor vacío es un error
+    fn value<'r, G>(&self, field: &G, column: TripColumn) -> Result<&'r str, TripParseError>
+    where
+        G: Fn(usize) -> Option<&'r str>,
+    {
+        match self.raw(field, column) {
+            Some(value) if !value.trim().is_empty() => Ok(value),
+            Some(value) => Err(TripParseError::new(column.name(), value, "valor vacío")),
+            None => column
+                .default_value()
+                .ok_or_else(|| TripParseError::new(column.name(), "", "falta la columna")),
+        }
+    }
+
+    // Valor de una columna que admite nulos; None si falta en el archivo o viene vacía
+    fn nullable<'r, G>(&self, field: &G, column: TripColumn) -> Option<&'r str>
+    where
+        G: Fn(usize) -> Option<&'r str>,
+    {
+        self.raw(field, column)
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    // Construye un viaje a partir de un registro; `row` es su número de fila de datos,
+    // que se usa como index si el archivo no tiene esa columna
+    pub fn parse(&self, record: &StringRecord, row: Option<u64>) -> Result<Trip, TripParseError> {
+        self.parse_fields(|position| record.get(position), row)
+            .map(|trip| trip.to_trip())
+    }
+
+    // Igual que parse pero sin copiar los textos: `field` entrega el campo de cada
+    // posición del registro y el viaje los toma prestados
+    pub fn parse_fields<'r, G>(
+        &self,
+        field: G,
+        row: Option<u64>,
+    ) -> Result<TripRef<'r>, TripParseError>
+    where
+        G: Fn(usize) -> Option<&'r str>,
+    {
+        let value = |column| self.value(&field, column);
+        let nullable = |column| self.nullable(&field, column);
+
+        let index = if self.synthesizes_index() {
+            Cow::Owned(row.map(|row| row.to_string()).unwrap_or_default())
+        } else {
+            Cow::Borrowed(self.raw(&field, TripColumn::Index).unwrap_or_default())
+        };
+
+        Ok(TripRef {
+            vendor_id: self.raw(&field, TripColumn::VendorId).unwrap_or_default(),
+            tpep_pickup_datetime: parse_datetime(
+                "tpep_pickup_datetime",
+                value(TripColumn::PickupDatetime)?,
+            )?,
+            tpep_dropoff_datetime: parse_datetime(
+                "tpep_dropoff_datetime",
+                value(TripColumn::DropoffDatetime)?,
+            )?,
+            passenger_count: nullable(TripColumn::PassengerCount)
+                .map(|passengers| parse_integer("passenger_count", passengers))
+                .transpose()?,
+            trip_distance: parse_f64("trip_distance", value(TripColumn::TripDistance)?)?,
+            ratecode_id: nullable(TripColumn::RatecodeId)
+                .map(parse_ratecode)
+                .transpose()?,
+            store_and_fwd_flag: nullable(TripColumn::StoreAndFwdFlag)
+                .map(|flag| parse_flag("store_and_fwd_flag", flag))
+                .transpose()?,
+            pu_location_id: parse_integer("pu_location_id", value(TripColumn::PuLocationId)?)?,
+            do_location_id: parse_integer("do_location_id", value(TripColumn::DoLocationId)?)?,
+            payment_type: nullable(TripColumn::PaymentType)
+                .map(parse_payment_type)
+                .transpose()?,
+            fare_amount: parse_f64("fare_amount", value(TripColumn::FareAmount)?)?,
+            extra: parse_f64("extra", value(TripColumn::Extra)?)?,
+            mta_tax: parse_f64("mta_tax", value(TripColumn::MtaTax)?)?,
+            tip_amount: parse_f64("tip_amount", value(TripColumn::TipAmount)?)?,
+            tolls_amount: parse_f64("tolls_amount", value(TripColumn::TollsAmount)?)?,
+            improvement_surcharge: parse_f64(
+                "improvement_surcharge",
+                value(TripColumn::ImprovementSurcharge)?,
+            )?,
+            total_amount: parse_f64("total_amount", value(TripColumn::TotalAmount)?)?,
+            congestion_surcharge: nullable(TripColumn::CongestionSurcharge)
+                .map(|surcharge| parse_f64("congestion_surcharge", surcharge))
+                .transpose()?,
+            index,
+            source_file: self
+                .raw(&field, TripColumn::SourceFile)
+                .filter(|source| !source.is_empty()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::filters::{TripFilter, parse_query};
+    use super::super::test_support::TLC_HEADER;
+    use super::*;
+
+    #[test]
+    fn payment_type_codes_round_trip() {
+        for payment in PaymentType::ALL {
+            assert_eq!(PaymentType::from_code(payment.code()), Some(payment));
+        }
+        assert_eq!(PaymentType::from_code(0), Some(PaymentType::FlexFare));
+        assert_eq!(PaymentType::from_code(7), None);
+    }
+
+    fn tlc_record(payment_type: &str) -> StringRecord {
+        StringRecord::from(vec![
+            "2",
+            "2023-03-01 10:00:00",
+            "2023-03-01 10:20:00",
+            "1",
+            "3.5",
+            "1",
+            "N",
+            "10",
+            "20",
+            payment_type,
+            "15",
+            "1",
+            "0.5",
+            "3",
+            "0",
+            "1",
+            "21",
+            "2.5",
+        ])
+    }
+
+    fn tlc_schema() -> TripSchema {
+        TripSchema::from_header(&StringRecord::from(
+            TLC_HEADER.split(',').collect::<Vec<_>>(),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn flex_fare_rows_parse_and_filter() {
+        let trip = tlc_schema().parse(&tlc_record("0"), Some(0)).unwrap();
+        assert_eq!(trip.payment_type, Some(PaymentType::FlexFare));
+
+        let filter = parse_query("payment_type = 0").unwrap();
+        assert!(matches!(
+            filter,
+            TripFilter::PaymentType(PaymentType::FlexFare)
+        ));
+        assert_eq!(filter.to_string(), "payment_type = 0");
+        assert!(filter.matches(&trip.as_trip_ref()));
+    }
+
+    // Los valores vacíos de las columnas que TLC deja vacías son desconocidos; en
+    // las demás son un error, y el valor por defecto es solo para columnas ausentes
+    #[test]
+    fn empty_values_are_unknown_or_invalid() {
+        let schema = tlc_schema();
+        let mut record: Vec<String> = tlc_record("1").iter().map(str::to_string).collect();
+        for column in [3, 5, 6, 9, 17] {
+            record[column].clear();
+        }
+        let trip = schema
+            .parse(&StringRecord::from(record.clone()), Some(0))
+            .unwrap();
+        assert_eq!(trip.passenger_count, None);
+        assert_eq!(trip.ratecode_id, None);
+        assert_eq!(trip.store_and_fwd_flag, None);
+        assert_eq!(trip.payment_type, None);
+        assert_eq!(trip.congestion_surcharge, None);
+        assert_eq!(TripColumn::PassengerCount.format(&trip), "");
+
+        // extra viene vacío
+        record[11].clear();
+        let error = schema
+            .parse(&StringRecord::from(record), Some(0))
+            .unwrap_err();
+        assert_eq!(error.field, "extra");
+
+        // Sin la columna extra se usa 0
+        let header = StringRecord::from(vec![
+            "tpep_pickup_datetime",
+            "tpep_dropoff_datetime",
+            "trip_distance",
+            "PULocationID",
+            "DOLocationID",
+            "fare_amount",
+            "total_amount",
+        ]);
+        let schema = TripSchema::from_header(&header).unwrap();
+        let record = StringRecord::from(vec![
+            "2023-03-01 10:00:00",
+            "2023-03-01 10:20:00",
+            "3.5",
+            "10",
+            "20",
+            "15",
+            "21",
+        ]);
+        let trip = schema.parse(&record, Some(0)).unwrap();
+        assert_eq!(trip.extra, 0.0);
+        assert_eq!(trip.passenger_count, None);
+        assert_eq!(trip.payment_type, None);
+    }
 }
@@ -1,9 +1,10 @@
+mod cli;
+mod config;
 mod data;
 mod visual;
 
 fn main() {
-    // Lanzar interfaz gráfica
-    if let Err(e) = visual::run_app() {
-        eprintln!("Error al iniciar la aplicación: {}", e);
-    }
+    // Sin comando se lanza la interfaz gráfica; con uno se ejecuta sin interfaz
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(cli::run(&args));
 }
@@ -0,0 +1,348 @@
+use super::visual::{parse_datetime_bound, parse_optional};
+use crate::data::filters::TripFilter;
+use crate::data::trip_struct::PaymentType;
+use eframe::egui;
+
+// Columnas que se pueden usar en una condición del árbol
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConditionField {
+    Price,
+    Index,
+    Destination,
+    PickupLocation,
+    PickupTime,
+    PaymentType,
+    Vendor,
+    Passengers,
+    Distance,
+    TipPercentage,
+    Duration,
+}
+
+impl ConditionField {
+    const ALL: [ConditionField; 11] = [
+        ConditionField::Price,
+        ConditionField::Index,
+        ConditionField::Destination,
+        ConditionField::PickupLocation,
+        ConditionField::PickupTime,
+        ConditionField::PaymentType,
+        ConditionField::Vendor,
+        ConditionField::Passengers,
+        ConditionField::Distance,
+        ConditionField::TipPercentage,
+        ConditionField::Duration,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            ConditionField::Price => "Precio",
+            ConditionField::Index => "Índice",
+            ConditionField::Destination => "Destino",
+            ConditionField::PickupLocation => "Origen",
+            ConditionField::PickupTime => "Fecha de recogida",
+            ConditionField::PaymentType => "Tipo de pago",
+            ConditionField::Vendor => "Proveedor",
+            ConditionField::Passengers => "Pasajeros",
+            ConditionField::Distance => "Distancia",
+            ConditionField::TipPercentage => "Propina (%)",
+            ConditionField::Duration => "Duración (min)",
+        }
+    }
+
+    // Las columnas de rango se editan con un mínimo y un máximo
+    fn is_range(&self) -> bool {
+        matches!(
+            self,
+            ConditionField::Price
+                | ConditionField::PickupTime
+                | ConditionField::Passengers
+                | ConditionField::Distance
+                | ConditionField::TipPercentage
+                | ConditionField::Duration
+        )
+    }
+}
+
+// Condición simple editable; los valores se guardan como texto hasta construir el filtro
+#[derive(Clone, Debug)]
+pub struct Condition {
+    field: ConditionField,
+    value: String,
+    min: String,
+    max: String,
+    payment_type: PaymentType,
+}
+
+impl Default for Condition {
+    fn default() -> Self {
+        Self {
+            field: ConditionField::Price,
+            value: String::new(),
+            min: String::new(),
+            max: String::new(),
+            payment_type: PaymentType::CreditCard,
+        }
+    }
+}
+
+impl Condition {
+    // Filtro de la condición; None si está vacía o sus valores son inválidos
+    fn to_filter(&self) -> Option<TripFilter> {
+        let value = self.value.trim();
+        let label = self.field.label();
+        match self.field {
+            ConditionField::Index => {
+                (!value.is_empty()).then(|| TripFilter::Index(value.to_string()))
+            }
+            ConditionField::Vendor => {
+                (!value.is_empty()).then(|| TripFilter::Vendor(value.to_string()))
+            }
+            ConditionField::Destination => {
+                parse_optional(value, label).map(TripFilter::Destination)
+            }
+            ConditionField::PickupLocation => {
+                parse_optional(value, label).map(TripFilter::PickupLocation)
+            }
+            ConditionField::PaymentType => Some(TripFilter::PaymentType(self.payment_type)),
+            ConditionField::PickupTime => {
+                let from = parse_datetime_bound(&self.min, false);
+                let to = parse_datetime_bound(&self.max, true);
+                (from.is_some() || to.is_some()).then_some(TripFilter::PickupTime { from, to })
+            }
+            ConditionField::Passengers => {
+                let (min, max) = self.parse_range(label)?;
+                Some(TripFilter::Passengers { min, max })
+            }
+            ConditionField::Price => {
+                let (min, max) = self.parse_range(label)?;
+                Some(TripFilter::Price { min, max })
+            }
+            ConditionField::Distance => {
+                let (min, max) = self.parse_range(label)?;
+                Some(TripFilter::Distance { min, max })
+            }
+            ConditionField::TipPercentage => {
+                let (min, max) = self.parse_range(label)?;
+                Some(TripFilter::TipPercentage { min, max })
+            }
+            ConditionField::Duration => {
+                let (min, max) = self.parse_range(label)?;
+                Some(TripFilter::Duration { min, max })
+            }
+        }
+    }
+
+    // Límites del rango; None si no hay ninguno válido
+    fn parse_range<T: std::str::FromStr>(&self, label: &str) -> Option<(Option<T>, Option<T>)> {
+        let min = parse_optional(&self.min, &format!("{} mínimo", label));
+        let max = parse_optional(&self.max, &format!("{} máximo", label));
+        (min.is_some() || max.is_some()).then_some((min, max))
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, id: egui::Id) {
+        egui::ComboBox::from_id_salt(id.with("field"))
+            .selected_text(self.field.label())
+            .show_ui(ui, |ui| {
+                for field in ConditionField::ALL {
+                    ui.selectable_value(&mut self.field, field, field.label());
+                }
+            });
+
+        if self.field == ConditionField::PaymentType {
+            egui::ComboBox::from_id_salt(id.with("payment_type"))
+                .selected_text(self.payment_type.description())
+                .show_ui(ui, |ui| {
+                    for payment in PaymentType::ALL {
+                        ui.selectable_value(&mut self.payment_type, payment, payment.description());
+                    }
+                });
+        } else if self.field.is_range() {
+            let (min_hint, max_hint) = if self.field == ConditionField::PickupTime {
+                ("AAAA-MM-DD [HH:MM:SS]", "AAAA-MM-DD [HH:MM:SS]")
+            } else {
+                ("Mínimo", "Máximo")
+            };
+            ui.label("desde");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.min)
+                    .hint_text(min_hint)
+                    .desired_width(110.0),
+            );
+            ui.label("hasta");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.max)
+                    .hint_text(max_hint)
+                    .desired_width(110.0),
+            );
+        } else {
+            ui.label("=");
+            ui.add(egui::TextEdit::singleline(&mut self.value).desired_width(120.0));
+        }
+    }
+}
+
+// Nodo del árbol de filtros: un grupo AND/OR con hijos o una condición simple
+#[derive(Clone, Debug)]
+pub enum FilterNode {
+    Group {
+        use_and: bool,
+        negated: bool,
+        children: Vec<FilterNode>,
+    },
+    Condition {
+        negated: bool,
+        condition: Condition,
+    },
+}
+
+impl Default for FilterNode {
+    fn default() -> Self {
+        FilterNode::Group {
+            use_and: true,
+            negated: false,
+            children: Vec::new(),
+        }
+    }
+}
+
+// Acción pedida por un hijo; se aplica al terminar de dibujar el grupo
+enum NodeAction {
+    MoveUp,
+    MoveDown,
+    Remove,
+}
+
+impl FilterNode {
+    fn new_condition() -> Self {
+        FilterNode::Condition {
+            negated: false,
+            condition: Condition::default(),
+        }
+    }
+
+    // Filtro del árbol completo; sin condiciones acepta todas las filas
+    pub fn build(&self) -> TripFilter {
+        self.to_filter().unwrap_or(TripFilter::Price {
+            min: None,
+            max: None,
+        })
+    }
+
+    // Los grupos y condiciones vacíos no filtran y se omiten
+    fn to_filter(&self) -> Option<TripFilter> {
+        let (filter, negated) = match self {
+            FilterNode::Condition { negated, condition } => (condition.to_filter()?, *negated),
+            FilterNode::Group {
+                use_and,
+                negated,
+                children,
+            } => {
+                let mut filters: Vec<TripFilter> =
+                    children.iter().filter_map(FilterNode::to_filter).collect();
+                let filter = match filters.len() {
+                    0 => return None,
+                    1 => filters.remove(0),
+                    _ if *use_and => TripFilter::And(filters),
+                    _ => TripFilter::Or(filters),
+                };
+                (filter, *negated)
+            }
+        };
+
+        if negated {
+            Some(TripFilter::Not(Box::new(filter)))
+        } else {
+            Some(filter)
+        }
+    }
+
+    // Dibuja el árbol; la raíz no se puede mover ni eliminar
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let id = ui.id().with("filter_tree");
+        self.show_node(ui, id, None);
+    }
+
+    // `position` es (índice, hermanos) para los nodos que no son la raíz
+    fn show_node(
+        &mut self,
+        ui: &mut egui::Ui,
+        id: egui::Id,
+        position: Option<(usize, usize)>,
+    ) -> Option<NodeAction> {
+        let mut action = None;
+
+        match self {
+            FilterNode::Condition { negated, condition } => {
+                ui.horizontal(|ui| {
+                    ui.checkbox(negated, "NOT");
+                    condition.show(ui, id);
+                    action = node_buttons(ui, position);
+                });
+            }
+            FilterNode::Group {
+                use_and,
+                negated,
+                children,
+            } => {
+                ui.horizontal(|ui| {
+                    ui.checkbox(negated, "NOT");
+                    ui.radio_value(use_and, true, "AND");
+                    ui.radio_value(use_and, false, "OR");
+                    if ui.button("+ Condición").clicked() {
+                        children.push(FilterNode::new_condition());
+                    }
+                    if ui.button("+ Grupo").clicked() {
+                        children.push(FilterNode::default());
+                    }
+                    action = node_buttons(ui, position);
+                });
+
+                let mut child_action = None;
+                ui.indent(id.with("children"), |ui| {
+                    if children.is_empty() {
+                        ui.label("(grupo vacío: acepta todos los viajes)");
+                    }
+                    let siblings = children.len();
+                    for (i, child) in children.iter_mut().enumerate() {
+                        if let Some(requested) =
+                            child.show_node(ui, id.with(i), Some((i, siblings)))
+                        {
+                            child_action = Some((i, requested));
+                        }
+                    }
+                });
+
+                match child_action {
+                    Some((i, NodeAction::MoveUp)) => children.swap(i - 1, i),
+                    Some((i, NodeAction::MoveDown)) => children.swap(i, i + 1),
+                    Some((i, NodeAction::Remove)) => {
+                        children.remove(i);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        action
+    }
+}
+
+// Botones para mover y eliminar un nodo entre sus hermanos
+fn node_buttons(ui: &mut egui::Ui, position: Option<(usize, usize)>) -> Option<NodeAction> {
+    let (index, siblings) = position?;
+    let mut action = None;
+    if ui.add_enabled(index > 0, egui::Button::new("↑")).clicked() {
+        action = Some(NodeAction::MoveUp);
+    }
+    if ui
+        .add_enabled(index + 1 < siblings, egui::Button::new("↓"))
+        .clicked()
+    {
+        action = Some(NodeAction::MoveDown);
+    }
+    if ui.button("✕").clicked() {
+        action = Some(NodeAction::Remove);
+    }
+    action
+}
@@ -1,2 +1,4 @@
+mod filter_tree;
+#[allow(clippy::module_inception)]
 pub mod visual;
 pub use visual::run_app;
@@ -1,16 +1,23 @@
-use crate::data::filters::{self, TripFilter};
-use crate::data::trip_struct::Trip;
+use super::filter_tree::FilterNode;
+use crate::config::AppConfig;
+use crate::data::aggregate::{Aggregate, GroupBySpec, GroupKey, ResultTable, Value};
+use crate::data::data_lector;
+use crate::data::dataset::Dataset;
+use crate::data::export::{ExportFormat, ExportOptions};
+use crate::data::filters::{self, FileOutput, Histogram, HistogramSpec, QueryOutputs, TripFilter};
+use crate::data::trip_struct::{self, Measure, PaymentType, Trip, TripColumn};
+use chrono::{NaiveDate, NaiveDateTime};
 use eframe::{self, egui};
 use egui_extras::{Column, TableBuilder};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-// Rutas corregidas
-const CSV_PATH: &str = "src/data/data.csv";
-const TMP_DIR: &str = "tmp";
 const MAX_DISPLAYED_ROWS: usize = 1000; // Para limitar la cantidad de filas mostradas a la vez
+const TOP_DESTINATIONS: usize = 20; // Destinos que se muestran en la pestaña de destinos
+const DEFAULT_GROUP_AGGREGATES: &str =
+    "count, avg(total_amount), median(total_amount), p95(trip_distance)";
 
 // Estructura para compartir datos entre hilos
 #[derive(Default)]
@@ -20,7 +27,7 @@ struct FilterState {
     is_filtering: bool,
     filter_error: Option<String>,
     stats: Option<std::collections::HashMap<String, f64>>,
-    popular_destinations: Option<Vec<(String, usize)>>,
+    popular_destinations: Option<Vec<(u16, usize)>>,
     export_status: Option<String>,
     // Nuevos campos para seguimiento de tareas completadas
     statistics_loaded: bool,
@@ -30,16 +37,52 @@ struct FilterState {
     current_page: usize,
     total_pages: usize,
     // Campo para almacenar el archivo temporal activo
-    temp_file: Option<String>,
+    temp_file: Option<PathBuf>,
+    // Plan de consulta del último filtro explicado
+    query_plan: Option<String>,
+    // Histogramas de la pestaña de estadísticas
+    histograms: Vec<Histogram>,
+    // Filtro con que se calcularon los resultados cargados
+    results_filter: Option<TripFilter>,
+    // Agrupación de la pestaña de estadísticas, la que se pidió y el orden elegido
+    // (columna y si es descendente)
+    group_table: Option<ResultTable>,
+    results_group_by: Option<GroupBySpec>,
+    group_sort: Option<(usize, bool)>,
 }
 
-struct FilterApp {
-    // Estado de los filtros
+// Valores escritos en el panel de filtros; los campos vacíos no filtran
+#[derive(Clone, Default)]
+struct FilterInputs {
     min_price: String,
     max_price: String,
     index_filter: String,
     destination_filter: String,
+    pickup_filter: String,
+    pickup_from: String,
+    pickup_to: String,
+    payment_type: Option<PaymentType>,
+    vendor_filter: String,
+    min_passengers: String,
+    max_passengers: String,
+    min_distance: String,
+    max_distance: String,
+    min_tip_percentage: String,
+    max_tip_percentage: String,
+    min_duration: String,
+    max_duration: String,
+}
+
+struct FilterApp {
+    // Rutas del dataset actual y de los archivos temporales
+    config: AppConfig,
+
+    // Estado de los filtros
+    inputs: FilterInputs,
     use_and: bool,
+    filter_mode: FilterMode,
+    filter_tree: FilterNode,
+    query_text: String,
 
     // Estado compartido entre hilos
     state: Arc<Mutex<FilterState>>,
@@ -49,56 +92,89 @@ struct FilterApp {
 
     // Estado para la exportación
     export_filename: String,
+    export_format: ExportFormat,
+    export_columns: Vec<TripColumn>,
+
+    // Estado para la agrupación
+    group_key: GroupKey,
+    second_group_key: Option<GroupKey>,
+    group_aggregates: String,
+}
+
+// Forma de armar el filtro en el panel
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
+enum FilterMode {
+    // Campos fijos combinados con un único AND u OR
+    #[default]
+    Simple,
+    // Árbol de condiciones con grupos anidados y negaciones
+    Tree,
+    // Consulta en texto compilada con filters::parse_query
+    Query,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Default)]
 enum Tab {
+    #[default]
     Data,
     Stats,
     PopularDestinations,
 }
 
-impl Default for Tab {
-    fn default() -> Self {
-        Tab::Data
-    }
-}
-
-impl Default for FilterApp {
-    fn default() -> Self {
+impl FilterApp {
+    fn new(config: AppConfig) -> Self {
         // Crear el directorio tmp si no existe
         println!("Inicializando aplicación de visualización de datos...");
 
-        if !Path::new(TMP_DIR).exists() {
-            println!("Creando directorio temporal: {}", TMP_DIR);
-            fs::create_dir_all(TMP_DIR).expect("No se pudo crear el directorio tmp");
-        }
-
-        // Verificar si el archivo CSV existe
-        println!("Verificando archivo de datos: {}", CSV_PATH);
-        if !Path::new(CSV_PATH).exists() {
-            println!(
-                "ADVERTENCIA: No se encontró el archivo de datos en {}",
-                CSV_PATH
-            );
-        } else {
-            println!("Archivo de datos encontrado correctamente");
+        if !config.tmp_dir.exists() {
+            println!("Creando directorio temporal: {}", config.tmp_dir.display());
+            fs::create_dir_all(&config.tmp_dir).expect("No se pudo crear el directorio tmp");
         }
 
         let app = Self {
-            min_price: String::new(),
-            max_price: String::new(),
-            index_filter: String::new(),
-            destination_filter: String::new(),
+            config,
+            inputs: FilterInputs::default(),
             use_and: true,
+            filter_mode: FilterMode::default(),
+            filter_tree: FilterNode::default(),
+            query_text: String::new(),
             state: Arc::new(Mutex::new(FilterState::default())),
             selected_tab: Tab::default(),
             export_filename: "filtered_data.csv".to_string(),
+            export_format: ExportFormat::default(),
+            export_columns: TripColumn::ALL.to_vec(),
+            group_key: GroupKey::PickupHour,
+            second_group_key: None,
+            group_aggregates: DEFAULT_GROUP_AGGREGATES.to_string(),
         };
 
+        app.load_initial_data();
+        app
+    }
+
+    // Carga las primeras filas del dataset actual sin filtros
+    fn load_initial_data(&self) {
+        // Verificar que el dataset (un CSV, un directorio o un patrón) tenga archivos
+        let csv_path = self.config.data_path.clone();
+        println!("Verificando archivo de datos: {}", csv_path.display());
+        match Dataset::open(&csv_path) {
+            Ok(dataset) if dataset.files().iter().all(|file| file.exists()) => {
+                println!(
+                    "Dataset encontrado correctamente ({} archivos)",
+                    dataset.files().len()
+                );
+            }
+            Ok(_) => println!(
+                "ADVERTENCIA: No se encontró el archivo de datos en {}",
+                csv_path.display()
+            ),
+            Err(e) => println!("ADVERTENCIA: {}", e),
+        }
+
         // Realizar una carga inicial de datos
         println!("Iniciando carga inicial de datos...");
-        let state_clone = Arc::clone(&app.state);
+        let tmp_dir = self.config.tmp_dir.clone();
+        let state_clone = Arc::clone(&self.state);
 
         thread::spawn(move || {
             // Crear un filtro que incluya todos los datos (sin restricciones)
@@ -106,485 +182,262 @@ impl Default for FilterApp {
                 min: None,
                 max: None,
             };
-            let tmp_file = format!("{}/initial_data.csv", TMP_DIR);
+            let tmp_file = tmp_dir.join("initial_data.csv");
 
             println!("Aplicando filtro inicial para cargar datos...");
-            match filters::filter_to_file(CSV_PATH, &tmp_file, filter, None) {
+            match filters::filter_to_file(&csv_path, &tmp_file, filter, None) {
                 // Sin límite de resultados
                 Ok(count) => {
                     println!("Filtro aplicado. Total de registros encontrados: {}", count);
 
                     // Cargar los datos filtrados
-                    if let Ok(file) = std::fs::File::open(&tmp_file) {
-                        println!(
-                            "Cargando datos en la interfaz (primeros {} registros)...",
-                            MAX_DISPLAYED_ROWS
-                        );
-                        let reader = std::io::BufReader::new(file);
-                        let mut csv_reader = csv::ReaderBuilder::new()
-                            .has_headers(true)
-                            .from_reader(reader);
-
-                        let mut trips = Vec::with_capacity(MAX_DISPLAYED_ROWS);
-                        for (i, result) in csv_reader.records().take(MAX_DISPLAYED_ROWS).enumerate()
-                        {
-                            if i % 100 == 0 {
-                                println!("Procesados {} registros...", i);
-                            }
-
-                            if let Ok(record) = result {
-                                if record.len() >= 19 {
-                                    trips.push(Trip {
-                                        vendor_id: record[0].to_string(),
-                                        tpep_pickup_datetime: record[1].to_string(),
-                                        tpep_dropoff_datetime: record[2].to_string(),
-                                        passenger_count: record[3].to_string(),
-                                        trip_distance: record[4].to_string(),
-                                        ratecode_id: record[5].to_string(),
-                                        store_and_fwd_flag: record[6].to_string(),
-                                        pu_location_id: record[7].to_string(),
-                                        do_location_id: record[8].to_string(),
-                                        payment_type: record[9].to_string(),
-                                        fare_amount: record[10].to_string(),
-                                        extra: record[11].to_string(),
-                                        mta_tax: record[12].to_string(),
-                                        tip_amount: record[13].to_string(),
-                                        tolls_amount: record[14].to_string(),
-                                        improvement_surcharge: record[15].to_string(),
-                                        total_amount: record[16].to_string(),
-                                        congestion_surcharge: record[17].to_string(),
-                                        index: record[18].to_string(),
-                                    });
-                                }
-                            }
-                        }
-
-                        println!("Cargados {} registros en la interfaz", trips.len());
-
-                        // Actualizar los resultados
-                        let mut state = state_clone.lock().unwrap();
-                        state.filtered_results = trips;
-                        state.results_count = count;
-                        state.is_filtering = false;
-                        // Inicializar paginación
-                        state.current_page = 0;
-                        state.total_pages = (count + MAX_DISPLAYED_ROWS - 1) / MAX_DISPLAYED_ROWS;
-                        state.temp_file = Some(tmp_file);
-
-                        println!(
-                            "Paginación configurada: {} páginas totales",
-                            state.total_pages
-                        );
-                    } else {
-                        println!("ERROR: No se pudo abrir el archivo temporal de resultados");
-                        let mut state = state_clone.lock().unwrap();
-                        state.filter_error =
-                            Some("No se pudo abrir el archivo de resultados".to_string());
-                        state.is_filtering = false;
-                    }
-                }
-                Err(e) => {
-                    println!("ERROR al aplicar el filtro inicial: {}", e);
-                    let mut state = state_clone.lock().unwrap();
-                    state.filter_error = Some(format!("Error al filtrar: {}", e));
-                    state.is_filtering = false;
-                }
-            }
-        });
-
-        app
-    }
-}
-
-impl FilterApp {
-    // Método para cargar todos los datos de una vez
-    fn load_all(&self) {
-        // Verificar si ya está filtrando
-        {
-            let mut state = self.state.lock().unwrap();
-            if state.is_filtering {
-                println!(
-                    "\n[CARGA TOTAL] Ya hay un proceso en curso, ignorando solicitud de carga completa"
-                );
-                return;
-            }
-            state.is_filtering = true;
-            state.filter_error = None;
-        }
-
-        println!("\n[CARGA TOTAL] ===== INICIANDO CARGA COMPLETA =====");
-        println!("[CARGA TOTAL] Etapa 1/3: Cargando datos filtrados...");
-
-        // Almacenamos los datos de filtro que necesitaremos recrear en cada etapa
-        let min_price = self.min_price.parse::<f64>().ok();
-        let max_price = self.max_price.parse::<f64>().ok();
-        let index_filter = self.index_filter.clone();
-        let destination_filter = self.destination_filter.clone();
-        let use_and = self.use_and;
-
-        let state_clone = Arc::clone(&self.state);
-
-        // Creamos un hilo principal para gestionar la carga secuencial
-        thread::spawn(move || {
-            // Crear filtro para la etapa 1
-            let filter = create_filter(
-                min_price,
-                max_price,
-                &index_filter,
-                &destination_filter,
-                use_and,
-            );
-
-            // ETAPA 1: Carga de datos filtrados
-            let tmp_file = format!("{}/load_all_data.csv", TMP_DIR);
-            println!("[CARGA TOTAL] Aplicando filtros a los datos...");
-
-            match filters::filter_to_file(CSV_PATH, &tmp_file, filter, None) {
-                // Sin límite para guardar todos los datos
-                Ok(count) => {
                     println!(
-                        "[CARGA TOTAL] ✓ Filtro aplicado exitosamente. Total de registros: {}",
-                        count
+                        "Cargando datos en la interfaz (primeros {} registros)...",
+                        MAX_DISPLAYED_ROWS
                     );
+                    match data_lector::read_trips_range(&tmp_file, 0, MAX_DISPLAYED_ROWS) {
+                        Ok(trips) => {
+                            println!("Cargados {} registros en la interfaz", trips.len());
 
-                    if let Ok(file) = std::fs::File::open(&tmp_file) {
-                        println!(
-                            "[CARGA TOTAL] Cargando datos en memoria (primeros {} registros)...",
-                            MAX_DISPLAYED_ROWS
-                        );
-                        let reader = std::io::BufReader::new(file);
-                        let mut csv_reader = csv::ReaderBuilder::new()
-                            .has_headers(true)
-                            .from_reader(reader);
-
-                        let mut trips = Vec::with_capacity(MAX_DISPLAYED_ROWS);
-                        let mut processed = 0;
-                        for result in csv_reader.records().take(MAX_DISPLAYED_ROWS) {
-                            processed += 1;
-                            if processed % 100 == 0 {
-                                println!("[CARGA TOTAL] Procesados {} registros...", processed);
-                            }
-
-                            if let Ok(record) = result {
-                                if record.len() >= 19 {
-                                    trips.push(Trip {
-                                        vendor_id: record[0].to_string(),
-                                        tpep_pickup_datetime: record[1].to_string(),
-                                        tpep_dropoff_datetime: record[2].to_string(),
-                                        passenger_count: record[3].to_string(),
-                                        trip_distance: record[4].to_string(),
-                                        ratecode_id: record[5].to_string(),
-                                        store_and_fwd_flag: record[6].to_string(),
-                                        pu_location_id: record[7].to_string(),
-                                        do_location_id: record[8].to_string(),
-                                        payment_type: record[9].to_string(),
-                                        fare_amount: record[10].to_string(),
-                                        extra: record[11].to_string(),
-                                        mta_tax: record[12].to_string(),
-                                        tip_amount: record[13].to_string(),
-                                        tolls_amount: record[14].to_string(),
-                                        improvement_surcharge: record[15].to_string(),
-                                        total_amount: record[16].to_string(),
-                                        congestion_surcharge: record[17].to_string(),
-                                        index: record[18].to_string(),
-                                    });
-                                }
-                            }
-                        }
-
-                        println!(
-                            "[CARGA TOTAL] ✓ Etapa 1/3 completada: {} registros cargados en memoria",
-                            trips.len()
-                        );
-
-                        // Actualizar los resultados
-                        {
+                            // Actualizar los resultados
                             let mut state = state_clone.lock().unwrap();
                             state.filtered_results = trips;
                             state.results_count = count;
+                            state.is_filtering = false;
+                            // Inicializar paginación
                             state.current_page = 0;
-                            state.total_pages =
-                                (count + MAX_DISPLAYED_ROWS - 1) / MAX_DISPLAYED_ROWS;
-                            state.temp_file = Some(tmp_file.clone());
+                            state.total_pages = count.div_ceil(MAX_DISPLAYED_ROWS);
+                            state.temp_file = Some(tmp_file);
+
                             println!(
-                                "[CARGA TOTAL] Paginación configurada: {} páginas totales",
+                                "Paginación configurada: {} páginas totales",
                                 state.total_pages
                             );
                         }
-
-                        // ETAPA 2: Cálculo de estadísticas
-                        println!("\n[CARGA TOTAL] Etapa 2/3: Calculando estadísticas...");
-
-                        // Crear un nuevo filtro para estadísticas
-                        let stats_filter = create_filter(
-                            min_price,
-                            max_price,
-                            &index_filter,
-                            &destination_filter,
-                            use_and,
-                        );
-
-                        match filters::get_filter_stats(CSV_PATH, stats_filter) {
-                            Ok(stats) => {
-                                println!("[CARGA TOTAL] ✓ Estadísticas calculadas correctamente:");
-                                println!(
-                                    "[CARGA TOTAL]   - Total registros: {}",
-                                    stats.get("count").unwrap_or(&0.0)
-                                );
-                                if let Some(count) = stats.get("count") {
-                                    if *count > 0.0 {
-                                        println!(
-                                            "[CARGA TOTAL]   - Distancia promedio: {:.2}",
-                                            stats.get("avg_distance").unwrap_or(&0.0)
-                                        );
-                                        println!(
-                                            "[CARGA TOTAL]   - Precio promedio: ${:.2}",
-                                            stats.get("avg_amount").unwrap_or(&0.0)
-                                        );
-                                        println!(
-                                            "[CARGA TOTAL]   - Pasajeros promedio: {:.1}",
-                                            stats.get("avg_passengers").unwrap_or(&0.0)
-                                        );
-                                        println!(
-                                            "[CARGA TOTAL]   - Monto total: ${:.2}",
-                                            stats.get("total_amount").unwrap_or(&0.0)
-                                        );
-                                    }
-                                }
-
-                                {
-                                    let mut state = state_clone.lock().unwrap();
-                                    state.stats = Some(stats);
-                                    state.statistics_loaded = true;
-                                }
-                                println!(
-                                    "[CARGA TOTAL] ✓ Etapa 2/3 completada: Estadísticas generadas"
-                                );
-
-                                // ETAPA 3: Destinos populares
-                                println!(
-                                    "\n[CARGA TOTAL] Etapa 3/3: Obteniendo destinos populares..."
-                                );
-
-                                match filters::get_popular_destinations(CSV_PATH, 20) {
-                                    Ok(destinations) => {
-                                        println!(
-                                            "[CARGA TOTAL] ✓ Se encontraron {} destinos populares",
-                                            destinations.len()
-                                        );
-                                        for (i, (dest, count)) in
-                                            destinations.iter().enumerate().take(5)
-                                        {
-                                            println!(
-                                                "[CARGA TOTAL]   {}. Destino {}: {} viajes",
-                                                i + 1,
-                                                dest,
-                                                count
-                                            );
-                                        }
-                                        if destinations.len() > 5 {
-                                            println!(
-                                                "[CARGA TOTAL]   ... y {} destinos más",
-                                                destinations.len() - 5
-                                            );
-                                        }
-
-                                        {
-                                            let mut state = state_clone.lock().unwrap();
-                                            state.popular_destinations = Some(destinations);
-                                            state.destinations_loaded = true;
-                                            state.is_filtering = false;
-                                        }
-                                        println!(
-                                            "[CARGA TOTAL] ✓ Etapa 3/3 completada: Destinos populares obtenidos"
-                                        );
-                                        println!(
-                                            "[CARGA TOTAL] ===== CARGA COMPLETA FINALIZADA CON ÉXITO =====\n"
-                                        );
-                                    }
-                                    Err(e) => {
-                                        println!(
-                                            "[CARGA TOTAL] ✗ ERROR en etapa 3/3: No se pudieron obtener destinos populares: {}",
-                                            e
-                                        );
-                                        let mut state = state_clone.lock().unwrap();
-                                        state.filter_error =
-                                            Some(format!("Error al obtener destinos: {}", e));
-                                        state.is_filtering = false;
-                                        println!(
-                                            "[CARGA TOTAL] === CARGA COMPLETA FINALIZADA CON ERRORES ===\n"
-                                        );
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                println!(
-                                    "[CARGA TOTAL] ✗ ERROR en etapa 2/3: No se pudieron calcular estadísticas: {}",
-                                    e
-                                );
-                                let mut state = state_clone.lock().unwrap();
-                                state.filter_error =
-                                    Some(format!("Error al obtener estadísticas: {}", e));
-                                state.is_filtering = false;
-                                println!(
-                                    "[CARGA TOTAL] === CARGA COMPLETA FINALIZADA CON ERRORES ===\n"
-                                );
-                            }
+                        Err(e) => {
+                            println!(
+                                "ERROR: No se pudo leer el archivo temporal de resultados: {}",
+                                e
+                            );
+                            let mut state = state_clone.lock().unwrap();
+                            state.filter_error =
+                                Some(format!("No se pudo leer el archivo de resultados: {}", e));
+                            state.is_filtering = false;
                         }
-                    } else {
-                        println!(
-                            "[CARGA TOTAL] ✗ ERROR en etapa 1/3: No se pudo abrir el archivo temporal"
-                        );
-                        let mut state = state_clone.lock().unwrap();
-                        state.filter_error =
-                            Some("No se pudo abrir el archivo de resultados".to_string());
-                        state.is_filtering = false;
-                        println!("[CARGA TOTAL] === CARGA COMPLETA FINALIZADA CON ERRORES ===\n");
                     }
                 }
                 Err(e) => {
-                    println!(
-                        "[CARGA TOTAL] ✗ ERROR en etapa 1/3: No se pudo aplicar el filtro: {}",
-                        e
-                    );
+                    println!("ERROR al aplicar el filtro inicial: {}", e);
                     let mut state = state_clone.lock().unwrap();
                     state.filter_error = Some(format!("Error al filtrar: {}", e));
                     state.is_filtering = false;
-                    println!("[CARGA TOTAL] === CARGA COMPLETA FINALIZADA CON ERRORES ===\n");
                 }
             }
         });
     }
 
+    // Cambia el dataset activo; sus índices se construyen o abren en su propio directorio
+    fn switch_dataset(&mut self, csv_path: PathBuf) {
+        println!("Cambiando al dataset: {}", csv_path.display());
+        self.config.data_path = csv_path;
+        *self.state.lock().unwrap() = FilterState::default();
+        self.load_initial_data();
+    }
+
     // Función auxiliar para construir filtros
     fn build_filter(&self) -> TripFilter {
+        match self.filter_mode {
+            FilterMode::Simple => {}
+            FilterMode::Tree => {
+                let filter = self.filter_tree.build();
+                println!("Construyendo filtro desde el árbol: {}", filter);
+                return filter;
+            }
+            FilterMode::Query => {
+                // Los botones se deshabilitan si la consulta tiene errores
+                let filter = parse_query_text(&self.query_text).unwrap_or_else(|e| {
+                    println!("Consulta inválida ignorada: {}", e);
+                    TripFilter::And(Vec::new())
+                });
+                println!("Construyendo filtro desde la consulta: {}", filter);
+                return filter;
+            }
+        }
+
         println!("Construyendo filtro con parámetros:");
-        println!("  - Precio mínimo: {}", self.min_price);
-        println!("  - Precio máximo: {}", self.max_price);
-        println!("  - Índice: {}", self.index_filter);
-        println!("  - Destino: {}", self.destination_filter);
+        let inputs = &self.inputs;
+        println!("  - Precio mínimo: {}", inputs.min_price);
+        println!("  - Precio máximo: {}", inputs.max_price);
+        println!("  - Índice: {}", inputs.index_filter);
+        println!("  - Destino: {}", inputs.destination_filter);
+        println!("  - Origen: {}", inputs.pickup_filter);
+        println!(
+            "  - Recogida: {} - {}",
+            inputs.pickup_from, inputs.pickup_to
+        );
+        println!(
+            "  - Tipo de pago: {}",
+            inputs.payment_type.map_or("", |p| p.description())
+        );
+        println!("  - Proveedor: {}", inputs.vendor_filter);
+        println!(
+            "  - Pasajeros: {} - {}",
+            inputs.min_passengers, inputs.max_passengers
+        );
+        println!(
+            "  - Distancia: {} - {}",
+            inputs.min_distance, inputs.max_distance
+        );
+        println!(
+            "  - Propina (%): {} - {}",
+            inputs.min_tip_percentage, inputs.max_tip_percentage
+        );
+        println!(
+            "  - Duración (min): {} - {}",
+            inputs.min_duration, inputs.max_duration
+        );
         println!("  - Operador: {}", if self.use_and { "AND" } else { "OR" });
 
-        let min_price = self.min_price.parse::<f64>().ok();
-        let max_price = self.max_price.parse::<f64>().ok();
-
-        create_filter(
-            min_price,
-            max_price,
-            &self.index_filter,
-            &self.destination_filter,
-            self.use_and,
-        )
+        create_filter(inputs, self.use_and)
     }
 
-    fn apply_filter(&self) {
-        self.apply_filter_internal(Some(Tab::Data));
+    // Agrupación elegida en la pestaña de estadísticas
+    fn group_by_spec(&self) -> Result<GroupBySpec, String> {
+        let mut aggregates = Aggregate::parse_list(&self.group_aggregates)?;
+        if aggregates.is_empty() {
+            aggregates.push(Aggregate::Count);
+        }
+        Ok(GroupBySpec {
+            keys: std::iter::once(self.group_key)
+                .chain(self.second_group_key)
+                .collect(),
+            aggregates,
+        })
     }
 
-    fn apply_filter_internal(&self, target_tab: Option<Tab>) {
+    // Calcula con un solo recorrido del dataset las filas filtradas, las estadísticas,
+    // los destinos populares, los histogramas y la agrupación del filtro actual, y
+    // muestra `target_tab`
+    fn run_query(&self, target_tab: Tab) {
         // Verificar si ya está filtrando
         {
             let mut state = self.state.lock().unwrap();
             if state.is_filtering {
-                println!("Ya hay un proceso de filtrado en curso, ignorando solicitud");
+                println!("Ya hay un proceso en curso, ignorando solicitud");
                 return;
             }
             state.is_filtering = true;
             state.filter_error = None;
-            if let Some(tab) = target_tab {
-                state.should_switch_tab = Some(tab);
-            }
+            state.should_switch_tab = Some(target_tab);
 
             // Limpiar el archivo temporal anterior si existe
-            if let Some(old_file) = &state.temp_file {
-                if Path::new(old_file).exists() {
-                    let _ = std::fs::remove_file(old_file);
-                    println!("Eliminado archivo temporal anterior: {}", old_file);
-                }
+            if let Some(old_file) = &state.temp_file
+                && old_file.exists()
+            {
+                let _ = std::fs::remove_file(old_file);
+                println!(
+                    "Eliminado archivo temporal anterior: {}",
+                    old_file.display()
+                );
             }
         }
 
         let filter = self.build_filter();
+        let group_by = self.group_by_spec().ok();
+        let csv_path = self.config.data_path.clone();
+        let tmp_dir = self.config.tmp_dir.clone();
         let state_clone = Arc::clone(&self.state);
 
-        // Ejecutar el filtrado en un hilo separado para no bloquear la UI
+        // Ejecutar la consulta en un hilo separado para no bloquear la UI
         thread::spawn(move || {
             // Crear un archivo temporal para los resultados
-            let tmp_file = format!("{}/temp_filter_results.csv", TMP_DIR);
+            let tmp_file = tmp_dir.join("temp_filter_results.csv");
 
             println!(
                 "Aplicando filtro, resultados se guardarán en: {}",
-                &tmp_file
+                tmp_file.display()
             );
 
-            // Aplicar el filtrado y guardar a archivo - Sin límite para guardar todos
-            match filters::filter_to_file(CSV_PATH, &tmp_file, filter, None) {
-                Ok(count) => {
-                    println!("Filtrado completado. Encontrados {} registros", count);
-
-                    // Cargar los primeros N registros para mostrar
-                    if let Ok(file) = std::fs::File::open(&tmp_file) {
-                        println!("Cargando datos filtrados para visualización...");
-                        let reader = std::io::BufReader::new(file);
-                        let mut csv_reader = csv::ReaderBuilder::new()
-                            .has_headers(true)
-                            .from_reader(reader);
-
-                        let mut trips = Vec::with_capacity(MAX_DISPLAYED_ROWS);
-                        for (i, result) in csv_reader.records().take(MAX_DISPLAYED_ROWS).enumerate()
-                        {
-                            if i % 100 == 0 && i > 0 {
-                                println!("Procesados {} registros...", i);
-                            }
-
-                            if let Ok(record) = result {
-                                if record.len() >= 19 {
-                                    trips.push(Trip {
-                                        vendor_id: record[0].to_string(),
-                                        tpep_pickup_datetime: record[1].to_string(),
-                                        tpep_dropoff_datetime: record[2].to_string(),
-                                        passenger_count: record[3].to_string(),
-                                        trip_distance: record[4].to_string(),
-                                        ratecode_id: record[5].to_string(),
-                                        store_and_fwd_flag: record[6].to_string(),
-                                        pu_location_id: record[7].to_string(),
-                                        do_location_id: record[8].to_string(),
-                                        payment_type: record[9].to_string(),
-                                        fare_amount: record[10].to_string(),
-                                        extra: record[11].to_string(),
-                                        mta_tax: record[12].to_string(),
-                                        tip_amount: record[13].to_string(),
-                                        tolls_amount: record[14].to_string(),
-                                        improvement_surcharge: record[15].to_string(),
-                                        total_amount: record[16].to_string(),
-                                        congestion_surcharge: record[17].to_string(),
-                                        index: record[18].to_string(),
-                                    });
-                                }
-                            }
+            let outputs = QueryOutputs {
+                file: Some(FileOutput {
+                    path: tmp_file.clone(),
+                    options: ExportOptions::default(),
+                }),
+                stats: true,
+                top_destinations: Some(TOP_DESTINATIONS),
+                histograms: stats_histograms(),
+                group_by: group_by.clone(),
+                ..QueryOutputs::default()
+            };
+            match filters::execute_query(&csv_path, &filter, &outputs) {
+                Ok(results) => {
+                    let count = results.matched;
+                    println!("Consulta completada. Encontrados {} registros", count);
+                    if let Some(stats) = &results.stats
+                        && count > 0
+                    {
+                        println!(
+                            "  - Distancia promedio: {:.2}",
+                            stats.get("avg_distance").unwrap_or(&0.0)
+                        );
+                        println!(
+                            "  - Precio promedio: ${:.2}",
+                            stats.get("avg_amount").unwrap_or(&0.0)
+                        );
+                        println!(
+                            "  - Pasajeros promedio: {:.1}",
+                            stats.get("avg_passengers").unwrap_or(&0.0)
+                        );
+                        println!(
+                            "  - Monto total: ${:.2}",
+                            stats.get("total_amount").unwrap_or(&0.0)
+                        );
+                    }
+                    if let Some(destinations) = &results.top_destinations {
+                        for (i, (dest, count)) in destinations.iter().enumerate().take(5) {
+                            println!("  {}. Destino {}: {} viajes", i + 1, dest, count);
                         }
+                    }
 
-                        println!("Se cargarán {} registros en la interfaz", trips.len());
+                    // Cargar los primeros N registros para mostrar
+                    println!("Cargando datos filtrados para visualización...");
+                    match data_lector::read_trips_range(&tmp_file, 0, MAX_DISPLAYED_ROWS) {
+                        Ok(trips) => {
+                            println!("Se cargarán {} registros en la interfaz", trips.len());
 
-                        // Actualizar los resultados
-                        let mut state = state_clone.lock().unwrap();
-                        state.filtered_results = trips;
-                        state.results_count = count;
-                        state.is_filtering = false;
-                        state.current_page = 0;
-                        state.total_pages = (count + MAX_DISPLAYED_ROWS - 1) / MAX_DISPLAYED_ROWS;
-                        state.temp_file = Some(tmp_file);
+                            // Actualizar todos los resultados a la vez
+                            let mut state = state_clone.lock().unwrap();
+                            state.filtered_results = trips;
+                            state.results_count = count;
+                            state.current_page = 0;
+                            state.total_pages = count.div_ceil(MAX_DISPLAYED_ROWS);
+                            state.temp_file = Some(tmp_file);
+                            state.stats = results.stats;
+                            state.statistics_loaded = true;
+                            state.popular_destinations = results.top_destinations;
+                            state.destinations_loaded = true;
+                            state.histograms = results.histograms;
+                            state.results_filter = Some(filter);
+                            state.group_table = results.group_by;
+                            state.results_group_by = group_by;
+                            state.group_sort = None;
+                            state.is_filtering = false;
 
-                        println!(
-                            "Paginación configurada: {} páginas totales",
-                            state.total_pages
-                        );
-                    } else {
-                        println!("ERROR: No se pudo abrir el archivo temporal de resultados");
-                        let mut state = state_clone.lock().unwrap();
-                        state.filter_error =
-                            Some("No se pudo abrir el archivo de resultados".to_string());
-                        state.is_filtering = false;
+                            println!(
+                                "Paginación configurada: {} páginas totales",
+                                state.total_pages
+                            );
+                        }
+                        Err(e) => {
+                            println!(
+                                "ERROR: No se pudo leer el archivo temporal de resultados: {}",
+                                e
+                            );
+                            let mut state = state_clone.lock().unwrap();
+                            state.filter_error =
+                                Some(format!("No se pudo leer el archivo de resultados: {}", e));
+                            state.is_filtering = false;
+                        }
                     }
                 }
                 Err(e) => {
@@ -597,6 +450,26 @@ impl FilterApp {
         });
     }
 
+    // Muestra una pestaña de resultados; si se calcularon con otro filtro se vuelve a
+    // ejecutar la consulta
+    fn show_results(&self, tab: Tab) {
+        let filter = self.build_filter();
+        let group_by = self.group_by_spec().ok();
+        let up_to_date = {
+            let mut state = self.state.lock().unwrap();
+            let up_to_date = state.results_filter.as_ref() == Some(&filter)
+                && state.results_group_by == group_by;
+            if up_to_date {
+                state.should_switch_tab = Some(tab);
+            }
+            up_to_date
+        };
+
+        if !up_to_date {
+            self.run_query(tab);
+        }
+    }
+
     // Nueva función para cargar una página específica de datos
     fn load_page(&self, page: usize) {
         // Verificar si ya está filtrando
@@ -639,204 +512,74 @@ impl FilterApp {
         thread::spawn(move || {
             println!("Cargando página {} de resultados...", page);
 
-            if let Ok(file) = std::fs::File::open(&temp_file) {
-                let reader = std::io::BufReader::new(file);
-                let mut csv_reader = csv::ReaderBuilder::new()
-                    .has_headers(true)
-                    .from_reader(reader);
-
-                // Saltarse las filas anteriores a la página actual
-                let start_index = page * MAX_DISPLAYED_ROWS;
-                let mut current_idx = 0;
-
-                // Saltar registros anteriores a la página actual
-                println!(
-                    "Saltando {} registros para llegar a la página {}...",
-                    start_index, page
-                );
-                for _ in 0..start_index {
-                    if let Err(_) = csv_reader.records().next().transpose() {
-                        break; // Final del archivo o error
-                    }
-                    current_idx += 1;
-
-                    if current_idx % 1000 == 0 {
-                        println!("Saltados {} registros...", current_idx);
-                    }
-                }
-
-                // Leer los registros de la página actual
-                println!("Leyendo registros para la página {}...", page);
-                let mut trips = Vec::with_capacity(MAX_DISPLAYED_ROWS);
-                let mut page_idx = 0;
-
-                for result in csv_reader.records().take(MAX_DISPLAYED_ROWS) {
-                    page_idx += 1;
-
-                    if page_idx % 100 == 0 {
-                        println!("Procesados {} registros de la página...", page_idx);
-                    }
-
-                    if let Ok(record) = result {
-                        if record.len() >= 19 {
-                            trips.push(Trip {
-                                vendor_id: record[0].to_string(),
-                                tpep_pickup_datetime: record[1].to_string(),
-                                tpep_dropoff_datetime: record[2].to_string(),
-                                passenger_count: record[3].to_string(),
-                                trip_distance: record[4].to_string(),
-                                ratecode_id: record[5].to_string(),
-                                store_and_fwd_flag: record[6].to_string(),
-                                pu_location_id: record[7].to_string(),
-                                do_location_id: record[8].to_string(),
-                                payment_type: record[9].to_string(),
-                                fare_amount: record[10].to_string(),
-                                extra: record[11].to_string(),
-                                mta_tax: record[12].to_string(),
-                                tip_amount: record[13].to_string(),
-                                tolls_amount: record[14].to_string(),
-                                improvement_surcharge: record[15].to_string(),
-                                total_amount: record[16].to_string(),
-                                congestion_surcharge: record[17].to_string(),
-                                index: record[18].to_string(),
-                            });
-                        }
-                    }
-                }
-
-                println!("Cargados {} registros para la página {}", trips.len(), page);
-
-                let mut state = state_clone.lock().unwrap();
-                state.filtered_results = trips;
-                state.current_page = page;
-                state.is_filtering = false;
-
-                println!("Página {} cargada correctamente", page);
-            } else {
-                println!(
-                    "ERROR: No se pudo abrir el archivo de resultados para la página {}",
-                    page
-                );
-                let mut state = state_clone.lock().unwrap();
-                state.filter_error = Some(format!("No se pudo cargar la página {}", page));
-                state.is_filtering = false;
-            }
-        });
-    }
-
-    fn get_statistics(&self) {
-        // Verificar si ya está filtrando
-        {
-            let mut state = self.state.lock().unwrap();
-            if state.is_filtering {
-                println!("Ya hay un proceso en curso, ignorando solicitud de estadísticas");
-                return;
-            }
-            state.is_filtering = true;
-            state.filter_error = None;
-            state.should_switch_tab = Some(Tab::Stats);
-        }
-
-        println!("Obteniendo estadísticas de los datos...");
-        let filter = self.build_filter();
-        let state_clone = Arc::clone(&self.state);
-
-        // Obtener estadísticas en un hilo separado
-        thread::spawn(move || {
-            println!("Calculando estadísticas...");
-            match filters::get_filter_stats(CSV_PATH, filter) {
-                Ok(stats) => {
-                    println!("Estadísticas calculadas correctamente:");
-                    println!(
-                        "  - Total registros: {}",
-                        stats.get("count").unwrap_or(&0.0)
-                    );
-                    if let Some(count) = stats.get("count") {
-                        if *count > 0.0 {
-                            println!(
-                                "  - Distancia promedio: {:.2}",
-                                stats.get("avg_distance").unwrap_or(&0.0)
-                            );
-                            println!(
-                                "  - Precio promedio: ${:.2}",
-                                stats.get("avg_amount").unwrap_or(&0.0)
-                            );
-                            println!(
-                                "  - Pasajeros promedio: {:.1}",
-                                stats.get("avg_passengers").unwrap_or(&0.0)
-                            );
-                            println!(
-                                "  - Monto total: ${:.2}",
-                                stats.get("total_amount").unwrap_or(&0.0)
-                            );
-                        }
-                    }
+            // Saltarse las filas anteriores a la página actual
+            let start_index = page * MAX_DISPLAYED_ROWS;
+            match data_lector::read_trips_range(&temp_file, start_index, MAX_DISPLAYED_ROWS) {
+                Ok(trips) => {
+                    println!("Cargados {} registros para la página {}", trips.len(), page);
 
                     let mut state = state_clone.lock().unwrap();
-                    state.stats = Some(stats);
-                    state.statistics_loaded = true;
+                    state.filtered_results = trips;
+                    state.current_page = page;
                     state.is_filtering = false;
+
+                    println!("Página {} cargada correctamente", page);
                 }
                 Err(e) => {
-                    println!("ERROR al calcular estadísticas: {}", e);
+                    println!(
+                        "ERROR: No se pudo leer el archivo de resultados para la página {}: {}",
+                        page, e
+                    );
                     let mut state = state_clone.lock().unwrap();
-                    state.filter_error = Some(format!("Error al obtener estadísticas: {}", e));
+                    state.filter_error = Some(format!("No se pudo cargar la página {}", page));
                     state.is_filtering = false;
                 }
             }
         });
     }
 
-    fn get_popular_destinations(&self) {
+    fn explain_plan(&self) {
         // Verificar si ya está filtrando
         {
             let mut state = self.state.lock().unwrap();
             if state.is_filtering {
-                println!("Ya hay un proceso en curso, ignorando solicitud de destinos populares");
+                println!("Ya hay un proceso en curso, ignorando solicitud de plan");
                 return;
             }
             state.is_filtering = true;
             state.filter_error = None;
-            state.should_switch_tab = Some(Tab::PopularDestinations);
         }
 
-        println!("Obteniendo destinos populares...");
+        let filter = self.build_filter();
+        let csv_path = self.config.data_path.clone();
         let state_clone = Arc::clone(&self.state);
 
-        // Obtener destinos populares en un hilo separado
-        thread::spawn(move || {
-            println!("Analizando destinos más frecuentes...");
-            match filters::get_popular_destinations(CSV_PATH, 20) {
-                Ok(destinations) => {
-                    println!("Se encontraron {} destinos populares", destinations.len());
-                    for (i, (dest, count)) in destinations.iter().enumerate().take(5) {
-                        println!("  {}. Destino {}: {} viajes", i + 1, dest, count);
-                    }
-                    if destinations.len() > 5 {
-                        println!("  ... y {} destinos más", destinations.len() - 5);
-                    }
-
-                    let mut state = state_clone.lock().unwrap();
-                    state.popular_destinations = Some(destinations);
-                    state.destinations_loaded = true;
-                    state.is_filtering = false;
-                }
-                Err(e) => {
-                    println!("ERROR al obtener destinos populares: {}", e);
-                    let mut state = state_clone.lock().unwrap();
-                    state.filter_error =
-                        Some(format!("Error al obtener destinos populares: {}", e));
-                    state.is_filtering = false;
-                }
+        // Planificar en un hilo separado: la primera vez puede construir los índices
+        thread::spawn(move || match filters::explain_filter(&csv_path, &filter) {
+            Ok(plan) => {
+                print!("{}", plan);
+                let mut state = state_clone.lock().unwrap();
+                state.query_plan = Some(plan);
+                state.is_filtering = false;
+            }
+            Err(e) => {
+                println!("ERROR al planificar la consulta: {}", e);
+                let mut state = state_clone.lock().unwrap();
+                state.filter_error = Some(format!("Error al planificar la consulta: {}", e));
+                state.is_filtering = false;
             }
         });
     }
 
-    fn export_results(&self) {
+    // Exporta los resultados filtrados con el formato y las columnas elegidas
+    fn export_results(&self, output_path: PathBuf) {
         // Verificar si ya está filtrando o si el nombre de archivo está vacío
         {
             let mut state = self.state.lock().unwrap();
-            if state.is_filtering || self.export_filename.is_empty() {
+            if state.is_filtering
+                || self.export_filename.is_empty()
+                || self.export_columns.is_empty()
+            {
                 println!(
                     "No se puede exportar: {} {}",
                     if state.is_filtering {
@@ -846,6 +589,8 @@ impl FilterApp {
                     },
                     if self.export_filename.is_empty() {
                         "nombre de archivo vacío"
+                    } else if self.export_columns.is_empty() {
+                        "no hay columnas seleccionadas"
                     } else {
                         ""
                     }
@@ -857,21 +602,31 @@ impl FilterApp {
         }
 
         let filter = self.build_filter();
-        let filename = self.export_filename.clone();
-        let output_path = format!("{}/{}", TMP_DIR, filename);
+        let csv_path = self.config.data_path.clone();
+        // Las columnas se exportan en el orden del CSV
+        let options = ExportOptions {
+            format: self.export_format,
+            columns: TripColumn::ALL
+                .into_iter()
+                .filter(|column| self.export_columns.contains(column))
+                .collect(),
+        };
         let state_clone = Arc::clone(&self.state);
 
-        println!("Exportando resultados a: {}", output_path);
+        println!("Exportando resultados a: {}", output_path.display());
 
         // Exportar en un hilo separado
         thread::spawn(move || {
             println!("Aplicando filtros y exportando datos...");
-            match filters::filter_to_file(CSV_PATH, &output_path, filter, None) {
+            match filters::export_to_file(&csv_path, &output_path, filter, &options) {
                 Ok(count) => {
                     println!("Exportación completada. Se exportaron {} registros", count);
                     let mut state = state_clone.lock().unwrap();
-                    state.export_status =
-                        Some(format!("Exportados {} registros a {}", count, output_path));
+                    state.export_status = Some(format!(
+                        "Exportados {} registros a {}",
+                        count,
+                        output_path.display()
+                    ));
                     state.is_filtering = false;
                 }
                 Err(e) => {
@@ -933,11 +688,7 @@ impl FilterApp {
 
             // Mostrar algunos botones de página cercanos a la página actual
             let show_pages = 5; // Número de páginas para mostrar a cada lado
-            let start_page = if current_page > show_pages {
-                current_page - show_pages
-            } else {
-                0
-            };
+            let start_page = current_page.saturating_sub(show_pages);
 
             let end_page = std::cmp::min(current_page + show_pages + 1, total_pages);
 
@@ -995,7 +746,6 @@ impl FilterApp {
                 .clicked()
             {
                 self.load_page(current_page + 1);
-                return;
             }
         });
 
@@ -1015,6 +765,7 @@ impl FilterApp {
                     .column(Column::remainder().at_least(50.0)) // Total Amount
                     .column(Column::remainder().at_least(40.0)) // Origin
                     .column(Column::remainder().at_least(40.0)) // Destination
+                    .column(Column::remainder().at_least(60.0)) // Source file
                     .header(20.0, |mut header| {
                         header.col(|ui| {
                             ui.strong("ID");
@@ -1040,6 +791,9 @@ impl FilterApp {
                         header.col(|ui| {
                             ui.strong("Destino");
                         });
+                        header.col(|ui| {
+                            ui.strong("Archivo");
+                        });
                     })
                     .body(|mut body| {
                         for trip in &results {
@@ -1048,25 +802,28 @@ impl FilterApp {
                                     ui.label(&trip.index);
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.tpep_pickup_datetime);
+                                    ui.label(trip.tpep_pickup_datetime.to_string());
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.tpep_dropoff_datetime);
+                                    ui.label(trip.tpep_dropoff_datetime.to_string());
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.passenger_count);
+                                    ui.label(TripColumn::PassengerCount.format(trip));
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.trip_distance);
+                                    ui.label(format!("{:.2}", trip.trip_distance));
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.total_amount);
+                                    ui.label(format!("{:.2}", trip.total_amount));
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.pu_location_id);
+                                    ui.label(trip.pu_location_id.to_string());
                                 });
                                 row.col(|ui| {
-                                    ui.label(&trip.do_location_id);
+                                    ui.label(trip.do_location_id.to_string());
+                                });
+                                row.col(|ui| {
+                                    ui.label(trip.source_file.as_deref().unwrap_or_default());
                                 });
                             });
                         }
@@ -1099,7 +856,7 @@ impl FilterApp {
         });
     }
 
-    fn show_stats_tab(&self, ui: &mut egui::Ui) {
+    fn show_stats_tab(&mut self, ui: &mut egui::Ui) {
         let stats_option = {
             let state = self.state.lock().unwrap();
             state.stats.clone()
@@ -1130,14 +887,151 @@ impl FilterApp {
                     "Monto total: ${:.2}",
                     stats.get("total_amount").unwrap_or(&0.0)
                 ));
+                ui.label(format!(
+                    "Tarifa mediana: ${:.2}",
+                    stats.get("median_fare").unwrap_or(&0.0)
+                ));
+                ui.label(format!(
+                    "Distancia p95: {:.2} km",
+                    stats.get("p95_distance").unwrap_or(&0.0)
+                ));
+                ui.label(format!(
+                    "Zonas de origen distintas: {}",
+                    stats.get("distinct_pickup_zones").unwrap_or(&0.0)
+                ));
             } else {
                 ui.label("No hay datos para mostrar estadísticas.");
             }
+
+            let invalid_rows = stats.get("invalid_rows").copied().unwrap_or(0.0);
+            if invalid_rows > 0.0 {
+                ui.label(
+                    egui::RichText::new(format!(
+                        "Registros omitidos por formato inválido: {}",
+                        invalid_rows as usize
+                    ))
+                    .color(egui::Color32::YELLOW),
+                );
+            }
+
+            self.show_group_by(ui);
+
+            let histograms = self.state.lock().unwrap().histograms.clone();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for histogram in &histograms {
+                    show_histogram(ui, histogram);
+                }
+            });
         } else {
             ui.label("Haz clic en 'Obtener Estadísticas' para ver datos estadísticos.");
         }
     }
 
+    // Claves y agregados de la agrupación, y la tabla de grupos; al hacer clic en un
+    // encabezado se ordena por esa columna y un segundo clic invierte el orden
+    fn show_group_by(&mut self, ui: &mut egui::Ui) {
+        ui.separator();
+        ui.strong("Agrupación");
+
+        ui.horizontal(|ui| {
+            ui.label("Agrupar por:");
+            egui::ComboBox::from_id_salt("group_key")
+                .selected_text(self.group_key.name())
+                .show_ui(ui, |ui| {
+                    for key in GroupKey::all() {
+                        ui.selectable_value(&mut self.group_key, key, key.name());
+                    }
+                });
+            egui::ComboBox::from_id_salt("second_group_key")
+                .selected_text(self.second_group_key.map_or("(ninguna)", |key| key.name()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.second_group_key, None, "(ninguna)");
+                    for key in GroupKey::all() {
+                        ui.selectable_value(&mut self.second_group_key, Some(key), key.name());
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Agregados:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.group_aggregates)
+                    .desired_width(400.0)
+                    .hint_text("count, avg(total_amount), p95(trip_distance)"),
+            );
+        });
+
+        let spec = self.group_by_spec();
+        if let Err(e) = &spec {
+            ui.colored_label(egui::Color32::RED, e);
+        }
+        let (table, sort, is_filtering) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.group_table.clone(),
+                state.group_sort,
+                state.is_filtering,
+            )
+        };
+        if ui
+            .add_enabled(spec.is_ok() && !is_filtering, egui::Button::new("Agrupar"))
+            .clicked()
+        {
+            self.show_results(Tab::Stats);
+        }
+
+        let Some(table) = table else {
+            return;
+        };
+        ui.label(format!("{} grupos", table.rows.len()));
+
+        let mut clicked_column = None;
+        TableBuilder::new(ui)
+            .id_salt("group_table")
+            .striped(true)
+            .resizable(true)
+            .max_scroll_height(300.0)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .columns(Column::auto().at_least(60.0), table.columns.len())
+            .header(20.0, |mut header| {
+                for (i, column) in table.columns.iter().enumerate() {
+                    header.col(|ui| {
+                        let arrow = match sort {
+                            Some((sorted, false)) if sorted == i => " ▲",
+                            Some((sorted, true)) if sorted == i => " ▼",
+                            _ => "",
+                        };
+                        let label =
+                            egui::RichText::new(format!("{}{}", column.name, arrow)).strong();
+                        if ui.button(label).clicked() {
+                            clicked_column = Some(i);
+                        }
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(18.0, table.rows.len(), |mut row| {
+                    let values = &table.rows[row.index()];
+                    for value in values {
+                        row.col(|ui| {
+                            ui.label(match value {
+                                Value::Float(number) => format!("{:.2}", number),
+                                _ => value.to_string(),
+                            });
+                        });
+                    }
+                });
+            });
+
+        if let Some(column) = clicked_column {
+            let descending = sort == Some((column, false));
+            let mut state = self.state.lock().unwrap();
+            if let Some(table) = state.group_table.as_mut() {
+                table.sort_by_column(column, descending);
+            }
+            state.group_sort = Some((column, descending));
+        }
+    }
+
     fn show_popular_destinations_tab(&self, ui: &mut egui::Ui) {
         let destinations_option = {
             let state = self.state.lock().unwrap();
@@ -1146,6 +1040,7 @@ impl FilterApp {
 
         if let Some(destinations) = destinations_option {
             ui.heading("Destinos Más Populares");
+            ui.label("Entre los viajes que cumplen el filtro");
 
             egui::ScrollArea::vertical()
                 .max_height(400.0)
@@ -1168,7 +1063,7 @@ impl FilterApp {
                             for (dest, count) in destinations {
                                 body.row(18.0, |mut row| {
                                     row.col(|ui| {
-                                        ui.label(&dest);
+                                        ui.label(dest.to_string());
                                     });
                                     row.col(|ui| {
                                         ui.label(format!("{}", count));
@@ -1186,8 +1081,8 @@ impl FilterApp {
     fn check_tab_switch(&mut self) {
         let switch_to = {
             let mut state = self.state.lock().unwrap();
-            let tab = state.should_switch_tab.take();
-            tab
+
+            state.should_switch_tab.take()
         };
 
         if let Some(tab) = switch_to {
@@ -1196,17 +1091,105 @@ impl FilterApp {
     }
 }
 
+// Histogramas que se calculan junto con las estadísticas
+fn stats_histograms() -> Vec<HistogramSpec> {
+    vec![
+        HistogramSpec {
+            measure: Measure::TotalAmount,
+            min: 0.0,
+            max: 100.0,
+            bins: 10,
+        },
+        HistogramSpec {
+            measure: Measure::TripDistance,
+            min: 0.0,
+            max: 20.0,
+            bins: 10,
+        },
+        HistogramSpec {
+            measure: Measure::DurationMinutes,
+            min: 0.0,
+            max: 60.0,
+            bins: 12,
+        },
+    ]
+}
+
+// Una fila por cubeta con una barra proporcional a la más alta
+fn show_histogram(ui: &mut egui::Ui, histogram: &Histogram) {
+    ui.separator();
+    ui.strong(format!("Distribución de {}", histogram.spec.measure.name()));
+
+    let highest = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+    egui::Grid::new(histogram.spec.measure.name())
+        .striped(true)
+        .show(ui, |ui| {
+            for (bin, count) in histogram.counts.iter().enumerate() {
+                let (from, to) = histogram.bin_range(bin);
+                ui.label(format!("{:.1} – {:.1}", from, to));
+                ui.add(
+                    egui::ProgressBar::new(*count as f32 / highest as f32)
+                        .desired_width(300.0)
+                        .text(count.to_string()),
+                );
+                ui.end_row();
+            }
+        });
+
+    let outside = histogram.below + histogram.above;
+    if outside > 0 || histogram.missing > 0 {
+        ui.label(format!(
+            "Fuera del rango: {} · Sin valor: {}",
+            outside, histogram.missing
+        ));
+    }
+}
+
+// Interpreta un campo opcional; los valores inválidos se ignoran con un aviso
+pub(super) fn parse_optional<T: std::str::FromStr>(value: &str, name: &str) -> Option<T> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    let parsed = value.parse::<T>().ok();
+    if parsed.is_none() {
+        println!("{} inválido ignorado: {}", name, value);
+    }
+    parsed
+}
+
+// Acepta fecha y hora o solo la fecha; una fecha sola como límite superior
+// incluye el día completo
+pub(super) fn parse_datetime_bound(value: &str, end_of_day: bool) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if let Some(datetime) = trip_struct::parse_datetime_str(value) {
+        return Some(datetime);
+    }
+    let datetime = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| {
+            if end_of_day {
+                date.and_hms_opt(23, 59, 59)
+            } else {
+                date.and_hms_opt(0, 0, 0)
+            }
+        });
+    if datetime.is_none() {
+        println!("Fecha inválida ignorada: {}", value);
+    }
+    datetime
+}
+
 // Función auxiliar para crear filtros con los mismos parámetros
-fn create_filter(
-    min_price: Option<f64>,
-    max_price: Option<f64>,
-    index: &str,
-    destination: &str,
-    use_and: bool,
-) -> TripFilter {
+fn create_filter(inputs: &FilterInputs, use_and: bool) -> TripFilter {
     let mut filters = Vec::new();
 
     // Filtro de precio
+    let min_price = parse_optional(&inputs.min_price, "Precio mínimo");
+    let max_price = parse_optional(&inputs.max_price, "Precio máximo");
     if min_price.is_some() || max_price.is_some() {
         filters.push(TripFilter::Price {
             min: min_price,
@@ -1215,13 +1198,64 @@ fn create_filter(
     }
 
     // Filtro por índice
-    if !index.is_empty() {
-        filters.push(TripFilter::Index(index.to_string()));
+    if !inputs.index_filter.is_empty() {
+        filters.push(TripFilter::Index(inputs.index_filter.clone()));
     }
 
     // Filtro por destino
-    if !destination.is_empty() {
-        filters.push(TripFilter::Destination(destination.to_string()));
+    if let Some(dest) = parse_optional(&inputs.destination_filter, "Destino") {
+        filters.push(TripFilter::Destination(dest));
+    }
+
+    // Filtro por origen
+    if let Some(pickup) = parse_optional(&inputs.pickup_filter, "Origen") {
+        filters.push(TripFilter::PickupLocation(pickup));
+    }
+
+    // Filtro por fecha de recogida
+    let from = parse_datetime_bound(&inputs.pickup_from, false);
+    let to = parse_datetime_bound(&inputs.pickup_to, true);
+    if from.is_some() || to.is_some() {
+        filters.push(TripFilter::PickupTime { from, to });
+    }
+
+    // Filtro por tipo de pago
+    if let Some(payment) = inputs.payment_type {
+        filters.push(TripFilter::PaymentType(payment));
+    }
+
+    // Filtro por proveedor
+    let vendor = inputs.vendor_filter.trim();
+    if !vendor.is_empty() {
+        filters.push(TripFilter::Vendor(vendor.to_string()));
+    }
+
+    // Filtro por cantidad de pasajeros
+    let min = parse_optional(&inputs.min_passengers, "Mínimo de pasajeros");
+    let max = parse_optional(&inputs.max_passengers, "Máximo de pasajeros");
+    if min.is_some() || max.is_some() {
+        filters.push(TripFilter::Passengers { min, max });
+    }
+
+    // Filtro por distancia
+    let min = parse_optional(&inputs.min_distance, "Distancia mínima");
+    let max = parse_optional(&inputs.max_distance, "Distancia máxima");
+    if min.is_some() || max.is_some() {
+        filters.push(TripFilter::Distance { min, max });
+    }
+
+    // Filtro por porcentaje de propina
+    let min = parse_optional(&inputs.min_tip_percentage, "Propina mínima");
+    let max = parse_optional(&inputs.max_tip_percentage, "Propina máxima");
+    if min.is_some() || max.is_some() {
+        filters.push(TripFilter::TipPercentage { min, max });
+    }
+
+    // Filtro por duración
+    let min = parse_optional(&inputs.min_duration, "Duración mínima");
+    let max = parse_optional(&inputs.max_duration, "Duración máxima");
+    if min.is_some() || max.is_some() {
+        filters.push(TripFilter::Duration { min, max });
     }
 
     // Si no hay filtros, crear uno que siempre da true
@@ -1245,6 +1279,33 @@ fn create_filter(
     }
 }
 
+// Una consulta vacía no filtra
+fn parse_query_text(query: &str) -> Result<TripFilter, filters::QueryError> {
+    if query.trim().is_empty() {
+        return Ok(TripFilter::And(Vec::new()));
+    }
+    filters::parse_query(query)
+}
+
+// Fila con un par de campos mínimo/máximo
+fn range_inputs(ui: &mut egui::Ui, label: &str, min: &mut String, max: &mut String) {
+    ui.horizontal(|ui| {
+        ui.label(format!("{} mínimo:", label));
+        ui.add(
+            egui::TextEdit::singleline(min)
+                .hint_text("Mínimo")
+                .desired_width(80.0),
+        );
+
+        ui.label(format!("{} máximo:", label));
+        ui.add(
+            egui::TextEdit::singleline(max)
+                .hint_text("Máximo")
+                .desired_width(80.0),
+        );
+    });
+}
+
 impl eframe::App for FilterApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Verificar si debemos cambiar de pestaña
@@ -1253,79 +1314,214 @@ impl eframe::App for FilterApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Filtrar y Visualizar Datos de Viajes");
 
+            // Dataset actual; cada CSV tiene su propio directorio de índices
+            let is_filtering = self.state.lock().unwrap().is_filtering;
+            ui.horizontal(|ui| {
+                ui.label(format!("Dataset: {}", self.config.data_path.display()));
+                if ui
+                    .add_enabled(!is_filtering, egui::Button::new("Abrir archivo..."))
+                    .clicked()
+                    && let Some(path) = rfd::FileDialog::new()
+                        .add_filter("CSV o Parquet", &["csv", "parquet"])
+                        .pick_file()
+                {
+                    self.switch_dataset(path);
+                }
+                // Todos los CSV de la carpeta se consultan como una sola tabla
+                if ui
+                    .add_enabled(!is_filtering, egui::Button::new("Abrir carpeta..."))
+                    .clicked()
+                    && let Some(path) = rfd::FileDialog::new().pick_folder()
+                {
+                    self.switch_dataset(path);
+                }
+            });
+
             // Panel de filtros
             egui::CollapsingHeader::new("Filtros").show(ui, |ui| {
                 ui.horizontal(|ui| {
-                    ui.label("Precio mínimo:");
-                    ui.add(
-                        egui::TextEdit::singleline(&mut self.min_price)
-                            .hint_text("Mínimo")
-                            .desired_width(80.0),
-                    );
-
-                    ui.label("Precio máximo:");
-                    ui.add(
-                        egui::TextEdit::singleline(&mut self.max_price)
-                            .hint_text("Máximo")
-                            .desired_width(80.0),
+                    ui.label("Modo:");
+                    ui.radio_value(&mut self.filter_mode, FilterMode::Simple, "Campos");
+                    ui.radio_value(
+                        &mut self.filter_mode,
+                        FilterMode::Tree,
+                        "Árbol de condiciones",
                     );
+                    ui.radio_value(&mut self.filter_mode, FilterMode::Query, "Consulta en texto");
                 });
 
-                ui.horizontal(|ui| {
-                    ui.label("Índice:");
+                // Error de la consulta en texto; mientras exista no se puede filtrar
+                let mut query_error = None;
+
+                if self.filter_mode == FilterMode::Tree {
+                    self.filter_tree.show(ui);
+
+                    let query = self.filter_tree.build().to_string();
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(format!("Consulta: {}", query)).monospace());
+                        if ui.button("Editar como texto").clicked() {
+                            self.query_text = query;
+                            self.filter_mode = FilterMode::Query;
+                        }
+                    });
+                } else if self.filter_mode == FilterMode::Query {
                     ui.add(
-                        egui::TextEdit::singleline(&mut self.index_filter)
-                            .hint_text("ID del viaje")
-                            .desired_width(120.0),
+                        egui::TextEdit::singleline(&mut self.query_text)
+                            .code_editor()
+                            .hint_text(
+                                "total_amount between 10 and 50 and (do_location_id = 236 or not payment_type = 2)",
+                            )
+                            .desired_width(f32::INFINITY),
                     );
 
-                    ui.label("Destino:");
-                    ui.add(
-                        egui::TextEdit::singleline(&mut self.destination_filter)
-                            .hint_text("ID de ubicación")
-                            .desired_width(120.0),
+                    if let Err(e) = parse_query_text(&self.query_text) {
+                        // Marca con ^ la posición del error debajo de la consulta
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{}\n{}^",
+                                self.query_text,
+                                " ".repeat(e.position)
+                            ))
+                            .monospace(),
+                        );
+                        ui.label(egui::RichText::new(e.to_string()).color(egui::Color32::RED));
+                        query_error = Some(e);
+                    }
+                } else {
+                    let inputs = &mut self.inputs;
+                    range_inputs(ui, "Precio", &mut inputs.min_price, &mut inputs.max_price);
+
+                    ui.horizontal(|ui| {
+                        ui.label("Índice:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.index_filter)
+                                .hint_text("ID del viaje")
+                                .desired_width(120.0),
+                        );
+
+                        ui.label("Destino:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.destination_filter)
+                                .hint_text("ID de ubicación")
+                                .desired_width(120.0),
+                        );
+
+                        ui.label("Origen:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.pickup_filter)
+                                .hint_text("ID de ubicación")
+                                .desired_width(120.0),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Recogida desde:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.pickup_from)
+                                .hint_text("AAAA-MM-DD [HH:MM:SS]")
+                                .desired_width(160.0),
+                        );
+
+                        ui.label("hasta:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.pickup_to)
+                                .hint_text("AAAA-MM-DD [HH:MM:SS]")
+                                .desired_width(160.0),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Tipo de pago:");
+                        egui::ComboBox::from_id_salt("payment_type")
+                            .selected_text(
+                                inputs
+                                    .payment_type
+                                    .map_or("Cualquiera", |payment| payment.description()),
+                            )
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut inputs.payment_type, None, "Cualquiera");
+                                for payment in PaymentType::ALL {
+                                    ui.selectable_value(
+                                        &mut inputs.payment_type,
+                                        Some(payment),
+                                        payment.description(),
+                                    );
+                                }
+                            });
+
+                        ui.label("Proveedor:");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut inputs.vendor_filter)
+                                .hint_text("vendor_id")
+                                .desired_width(80.0),
+                        );
+                    });
+
+                    range_inputs(
+                        ui,
+                        "Pasajeros",
+                        &mut inputs.min_passengers,
+                        &mut inputs.max_passengers,
+                    );
+                    range_inputs(
+                        ui,
+                        "Distancia",
+                        &mut inputs.min_distance,
+                        &mut inputs.max_distance,
+                    );
+                    range_inputs(
+                        ui,
+                        "Propina (%)",
+                        &mut inputs.min_tip_percentage,
+                        &mut inputs.max_tip_percentage,
+                    );
+                    range_inputs(
+                        ui,
+                        "Duración (min)",
+                        &mut inputs.min_duration,
+                        &mut inputs.max_duration,
                     );
-                });
 
-                ui.horizontal(|ui| {
-                    ui.radio_value(&mut self.use_and, true, "AND lógico");
-                    ui.radio_value(&mut self.use_and, false, "OR lógico");
-                });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.use_and, true, "AND lógico");
+                        ui.radio_value(&mut self.use_and, false, "OR lógico");
+                    });
+                }
 
                 // Verificamos si hay un proceso en curso antes de habilitar los botones
                 let is_filtering = {
                     let state = self.state.lock().unwrap();
                     state.is_filtering
                 };
+                let can_run = !is_filtering && query_error.is_none();
 
                 ui.horizontal(|ui| {
                     if ui
-                        .add_enabled(!is_filtering, egui::Button::new("Aplicar Filtros"))
+                        .add_enabled(can_run, egui::Button::new("Aplicar Filtros"))
                         .clicked()
                     {
-                        self.apply_filter();
+                        self.run_query(Tab::Data);
                     }
 
                     if ui
-                        .add_enabled(!is_filtering, egui::Button::new("Obtener Estadísticas"))
+                        .add_enabled(can_run, egui::Button::new("Obtener Estadísticas"))
                         .clicked()
                     {
-                        self.get_statistics();
+                        self.show_results(Tab::Stats);
                     }
 
                     if ui
-                        .add_enabled(!is_filtering, egui::Button::new("Ver Destinos Populares"))
+                        .add_enabled(can_run, egui::Button::new("Ver Destinos Populares"))
                         .clicked()
                     {
-                        self.get_popular_destinations();
+                        self.show_results(Tab::PopularDestinations);
                     }
 
-                    // Botón para cargar todo
                     if ui
-                        .add_enabled(!is_filtering, egui::Button::new("Cargar Todo"))
+                        .add_enabled(can_run, egui::Button::new("Explicar Plan"))
                         .clicked()
                     {
-                        self.load_all();
+                        self.explain_plan();
                     }
                 });
 
@@ -1338,12 +1534,66 @@ impl eframe::App for FilterApp {
                             .desired_width(200.0),
                     );
 
+                    let previous_format = self.export_format;
+                    egui::ComboBox::from_id_salt("export_format")
+                        .selected_text(self.export_format.label())
+                        .show_ui(ui, |ui| {
+                            for format in ExportFormat::ALL {
+                                ui.selectable_value(&mut self.export_format, format, format.label());
+                            }
+                        });
+                    // El nombre sugerido sigue la extensión del formato elegido
+                    if self.export_format != previous_format && !self.export_filename.is_empty() {
+                        self.export_filename = Path::new(&self.export_filename)
+                            .with_extension(self.export_format.extension())
+                            .to_string_lossy()
+                            .into_owned();
+                    }
+
                     if ui
-                        .add_enabled(!is_filtering, egui::Button::new("Exportar"))
+                        .add_enabled(can_run, egui::Button::new("Exportar"))
                         .clicked()
                     {
-                        self.export_results();
+                        self.export_results(self.config.tmp_dir.join(&self.export_filename));
                     }
+
+                    // Cualquier ruta elegida con el diálogo del sistema
+                    if ui
+                        .add_enabled(can_run, egui::Button::new("Guardar como..."))
+                        .clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter(
+                                self.export_format.label(),
+                                &[self.export_format.extension()],
+                            )
+                            .set_file_name(&self.export_filename)
+                            .save_file()
+                    {
+                        self.export_results(path);
+                    }
+                });
+
+                egui::CollapsingHeader::new("Columnas a exportar").show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Todas").clicked() {
+                            self.export_columns = TripColumn::ALL.to_vec();
+                        }
+                        if ui.button("Ninguna").clicked() {
+                            self.export_columns.clear();
+                        }
+                    });
+                    ui.horizontal_wrapped(|ui| {
+                        for column in TripColumn::ALL {
+                            let mut selected = self.export_columns.contains(&column);
+                            if ui.checkbox(&mut selected, column.name()).changed() {
+                                if selected {
+                                    self.export_columns.push(column);
+                                } else {
+                                    self.export_columns.retain(|c| *c != column);
+                                }
+                            }
+                        }
+                    });
                 });
 
                 // Mostrar estado de la exportación
@@ -1355,6 +1605,16 @@ impl eframe::App for FilterApp {
                 if let Some(status) = export_status {
                     ui.label(status);
                 }
+
+                // Mostrar el plan de consulta explicado
+                let query_plan = {
+                    let state = self.state.lock().unwrap();
+                    state.query_plan.clone()
+                };
+
+                if let Some(plan) = query_plan {
+                    ui.label(egui::RichText::new(plan).monospace());
+                }
             });
 
             // Mensaje de espera durante el filtrado y errores
@@ -1395,11 +1655,11 @@ impl eframe::App for FilterApp {
                     if ui
                         .selectable_value(&mut self.selected_tab, Tab::Stats, stats_text)
                         .clicked()
+                        && !stats_loaded
+                        && !is_filtering
                     {
-                        if !stats_loaded && !is_filtering {
-                            // Si se selecciona estadísticas pero no están cargadas, cargarlas
-                            self.get_statistics();
-                        }
+                        // Si se selecciona estadísticas pero no están cargadas, cargarlas
+                        self.show_results(Tab::Stats);
                     }
 
                     let dest_text = if destinations_loaded {
@@ -1414,11 +1674,11 @@ impl eframe::App for FilterApp {
                             dest_text,
                         )
                         .clicked()
+                        && !destinations_loaded
+                        && !is_filtering
                     {
-                        if !destinations_loaded && !is_filtering {
-                            // Si se selecciona destinos pero no están cargados, cargarlos
-                            self.get_popular_destinations();
-                        }
+                        // Si se selecciona destinos pero no están cargados, cargarlos
+                        self.show_results(Tab::PopularDestinations);
                     }
                 });
             }
@@ -1434,7 +1694,7 @@ impl eframe::App for FilterApp {
 }
 
 // Función principal que inicia la aplicación
-pub fn run_app() -> Result<(), eframe::Error> {
+pub fn run_app(config: AppConfig) -> Result<(), eframe::Error> {
     println!("Iniciando aplicación gráfica...");
 
     let options = eframe::NativeOptions {
@@ -1451,7 +1711,7 @@ pub fn run_app() -> Result<(), eframe::Error> {
         options,
         Box::new(|_cc| {
             println!("Inicializando aplicación");
-            Ok(Box::new(FilterApp::default()))
+            Ok(Box::new(FilterApp::new(config)))
         }),
     )
 }